@@ -1,7 +1,14 @@
+use crate::common::locale::NumberLocale;
+use crate::error_display::{DEFAULT_ERROR_TEXT, ErrorLabel};
 use crate::myparser::MyParser;
 use crate::spreadsheet::Spreadsheet;
+use std::collections::HashMap;
 use std::io::{self, BufRead, Write};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+/// Commands taking longer than this are flagged in the prompt, which usually means a large
+/// `SLEEP` was entered by mistake
+pub const SLOW_COMMAND: Duration = Duration::from_secs(1);
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CommandResult {
@@ -11,6 +18,10 @@ pub enum CommandResult {
     UnrecognizedCommand,
     CircularDependency,
     DivisionByZero,
+    /// `next_err` found no cell holding an error
+    NoErrorCell,
+    /// `run` named a macro that was never recorded
+    UnknownMacro,
     Quit,
 }
 
@@ -28,24 +39,31 @@ impl CommandResult {
     }
 
     pub fn as_str(&self) -> &'static str {
-        match self {
-            CommandResult::Ok => "ok",
-            CommandResult::InvalidCell => "Invalid cell",
-            CommandResult::InvalidRange => "Invalid range",
-            CommandResult::UnrecognizedCommand => "unrecognized cmd",
-            CommandResult::CircularDependency => "Circular dependency",
-            CommandResult::DivisionByZero => "Division_by_zero",
-            CommandResult::Quit => "quit",
-        }
+        self.label()
     }
 }
 
 pub struct CommandHandler {
     viewport_row: usize,
     viewport_col: usize,
+    /// The selected cell as `(row, col)`, zero-indexed like the viewport
+    cursor: (usize, usize),
+    /// Whether the cursor was moved yet. Until then the grid is printed without it, exactly
+    /// as before there was a cursor
+    cursor_moved: bool,
     output_enabled: bool,
     last_result: CommandResult,
-    last_instant: Instant,
+    /// How long the last command took to run, including any `SLEEP` it triggered
+    last_duration: Duration,
+    /// Every command run so far, oldest first. `!n` re-runs entry `n`, counting from 1
+    history: Vec<String>,
+    show_history: bool,
+    /// Lines printed by the last command, like the tree of `deps`
+    report: Vec<String>,
+    /// The commands captured since `record start`, None when not recording
+    recording: Option<Vec<String>>,
+    /// Macros saved by `record stop <name>`, replayed by `run <name>`
+    macros: HashMap<String, Vec<String>>,
 }
 
 impl CommandHandler {
@@ -53,29 +71,99 @@ impl CommandHandler {
         CommandHandler {
             viewport_row: 0,
             viewport_col: 0,
+            cursor: (0, 0),
+            cursor_moved: false,
             output_enabled: true,
             last_result: CommandResult::Ok,
-            last_instant: Instant::now(),
+            last_duration: Duration::ZERO,
+            history: Vec::new(),
+            show_history: false,
+            report: Vec::new(),
+            recording: None,
+            macros: HashMap::new(),
         }
     }
 
     pub fn handle_command(&mut self, command: &str, sheet: &mut Spreadsheet) -> CommandResult {
-        self.last_instant = Instant::now();
+        let start = Instant::now();
+        let result = self.run_command(command, sheet);
+        self.last_duration = start.elapsed();
+        result
+    }
 
-        let command = command.trim_end();
+    fn run_command(&mut self, command: &str, sheet: &mut Spreadsheet) -> CommandResult {
+        self.show_history = false;
+        self.report.clear();
 
-        if command.is_empty() {
+        let command = command.trim();
+
+        // Blank lines and `#` comments are no-ops, so script files can be annotated
+        if command.is_empty() || command.starts_with('#') {
             return CommandResult::Ok;
         }
 
-        let result = if command == "q" || command == "Q" {
+        // `!n` is recorded as the command it recalls, so the history never refers to itself
+        let command = match command.strip_prefix('!') {
+            Some(index) => match self.recall(index) {
+                Some(past) => past,
+                None => {
+                    self.last_result = CommandResult::UnrecognizedCommand;
+                    return self.last_result;
+                }
+            },
+            None => command.to_string(),
+        };
+        let command = command.as_str();
+        if command != "history" {
+            self.history.push(command.to_string());
+        }
+        // `run` is recorded as the commands it replays, so a macro never refers to another
+        if let Some(recording) = &mut self.recording {
+            let is_control = command.starts_with("record")
+                || command.starts_with("run ")
+                || matches!(command, "q" | "Q");
+            if !is_control {
+                recording.push(command.to_string());
+            }
+        }
+
+        let result = self.execute(command, sheet);
+        self.last_result = result;
+        result
+    }
+
+    /// Runs one command, without recording it in the history or a macro
+    fn execute(&mut self, command: &str, sheet: &mut Spreadsheet) -> CommandResult {
+        if command == "q" || command == "Q" {
             CommandResult::Quit
+        } else if command == "history" {
+            self.show_history = true;
+            CommandResult::Ok
         } else if command == "disable_output" {
             self.output_enabled = false;
             CommandResult::Ok
         } else if command == "enable_output" {
             self.output_enabled = true;
             CommandResult::Ok
+        } else if command == "record start" {
+            self.recording = Some(Vec::new());
+            CommandResult::Ok
+        } else if let Some(name) = command.strip_prefix("record stop ") {
+            self.handle_record_stop(name)
+        } else if let Some(name) = command.strip_prefix("run ") {
+            self.handle_run(name, sheet)
+        } else if let Some(text) = command.strip_prefix("error_text") {
+            self.handle_error_text(text, sheet)
+        } else if command.starts_with("locale") {
+            self.handle_locale(command, sheet)
+        } else if command == "next_err" {
+            self.handle_next_err(sheet)
+        } else if let Some(cell) = command.strip_prefix("rdeps ") {
+            self.handle_deps(cell, sheet, Spreadsheet::dependent_tree)
+        } else if let Some(cell) = command.strip_prefix("deps ") {
+            self.handle_deps(cell, sheet, Spreadsheet::precedent_tree)
+        } else if let Some(fill) = command.strip_prefix("fill ") {
+            self.handle_fill(fill, sheet)
         } else if command.starts_with("scroll_to") {
             self.handle_scroll_to(command, sheet)
         } else if command == "w" {
@@ -102,16 +190,53 @@ impl CommandHandler {
                 self.viewport_col = sheet.cols - 10;
             }
             CommandResult::Ok
+        } else if matches!(command, "W" | "A" | "S" | "D") {
+            self.move_cursor(command, sheet);
+            CommandResult::Ok
         } else if let Some(pos) = command.find('=') {
             self.handle_cell_assignment(command, pos, sheet)
         } else {
             CommandResult::UnrecognizedCommand
-        };
+        }
+    }
 
-        self.last_result = result;
+    fn handle_record_stop(&mut self, name: &str) -> CommandResult {
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return CommandResult::UnrecognizedCommand;
+        }
+        match self.recording.take() {
+            Some(commands) => {
+                self.macros.insert(name.to_string(), commands);
+                CommandResult::Ok
+            }
+            None => CommandResult::UnrecognizedCommand,
+        }
+    }
+
+    /// Replays a macro, reporting the first command that failed, if any
+    fn handle_run(&mut self, name: &str, sheet: &mut Spreadsheet) -> CommandResult {
+        let Some(commands) = self.macros.get(name.trim()).cloned() else {
+            return CommandResult::UnknownMacro;
+        };
+        if let Some(recording) = &mut self.recording {
+            recording.extend(commands.iter().cloned());
+        }
+        let mut result = CommandResult::Ok;
+        for command in &commands {
+            let outcome = self.execute(command, sheet);
+            if result == CommandResult::Ok {
+                result = outcome;
+            }
+        }
         result
     }
 
+    /// Whether `record start` is capturing commands
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
     fn handle_scroll_to(&mut self, command: &str, sheet: &Spreadsheet) -> CommandResult {
         let parts: Vec<&str> = command.split_whitespace().collect();
         if parts.len() >= 2 {
@@ -130,6 +255,112 @@ impl CommandHandler {
         CommandResult::InvalidCell
     }
 
+    /// Moves the cursor one cell for `W`, `A`, `S` or `D`, stopping at the edges of the
+    /// sheet, and scrolls the viewport just enough to keep it in view
+    fn move_cursor(&mut self, key: &str, sheet: &Spreadsheet) {
+        let (row, col) = self.cursor;
+        self.cursor_moved = true;
+        self.cursor = match key {
+            "W" => (row.saturating_sub(1), col),
+            "S" => ((row + 1).min(sheet.rows.saturating_sub(1)), col),
+            "A" => (row, col.saturating_sub(1)),
+            _ => (row, (col + 1).min(sheet.cols.saturating_sub(1))),
+        };
+        let (row, col) = self.cursor;
+        self.viewport_row = self.viewport_row.min(row).max((row + 1).saturating_sub(10));
+        self.viewport_col = self.viewport_col.min(col).max((col + 1).saturating_sub(10));
+    }
+
+    /// Reports the tree `tree` renders for the named cell
+    fn handle_deps(
+        &mut self,
+        cell: &str,
+        sheet: &Spreadsheet,
+        tree: fn(&Spreadsheet, (u16, u16)) -> Vec<String>,
+    ) -> CommandResult {
+        match MyParser::cell_name_to_coord(cell.trim()) {
+            Some((col, row))
+                if (1..=sheet.cols).contains(&(col as usize))
+                    && (1..=sheet.rows).contains(&(row as usize)) =>
+            {
+                self.report = tree(sheet, (col, row));
+                CommandResult::Ok
+            }
+            _ => CommandResult::InvalidCell,
+        }
+    }
+
+    /// Handles `fill A1:C3 = 7`, setting every cell of the range to an integer literal
+    fn handle_fill(&mut self, fill: &str, sheet: &mut Spreadsheet) -> CommandResult {
+        let Some((range, value)) = fill.split_once('=') else {
+            return CommandResult::UnrecognizedCommand;
+        };
+        let Ok(value) = value.trim().parse::<i32>() else {
+            return CommandResult::UnrecognizedCommand;
+        };
+        let Some((start, end)) = range.trim().split_once(':') else {
+            return CommandResult::InvalidRange;
+        };
+        match (
+            MyParser::cell_name_to_coord(start),
+            MyParser::cell_name_to_coord(end),
+        ) {
+            (Some(start), Some(end)) => {
+                CommandResult::from_code(sheet.fill_range(start, end, value))
+            }
+            _ => CommandResult::InvalidCell,
+        }
+    }
+
+    /// Looks up the command numbered `index` (from 1) in the history
+    fn recall(&self, index: &str) -> Option<String> {
+        let index: usize = index.trim().parse().ok()?;
+        self.history.get(index.checked_sub(1)?).cloned()
+    }
+
+    /// Scrolls the viewport so the next error cell after its top-left corner is at the top-left,
+    /// wrapping around to `A1` after the last cell
+    fn handle_next_err(&mut self, sheet: &Spreadsheet) -> CommandResult {
+        let top_left = (self.viewport_row + 1, self.viewport_col + 1);
+        match sheet
+            .next_error_cell(top_left)
+            .or_else(|| sheet.next_error_cell((1, 0)))
+        {
+            Some((row, col)) => {
+                self.viewport_row = row - 1;
+                self.viewport_col = col - 1;
+                CommandResult::Ok
+            }
+            None => CommandResult::NoErrorCell,
+        }
+    }
+
+    fn handle_locale(&mut self, command: &str, sheet: &mut Spreadsheet) -> CommandResult {
+        let parts: Vec<&str> = command.split_whitespace().collect();
+        match parts.as_slice() {
+            ["locale", "standard"] => sheet.locale = NumberLocale::Standard,
+            ["locale", "european"] => sheet.locale = NumberLocale::European,
+            _ => return CommandResult::UnrecognizedCommand,
+        }
+        CommandResult::Ok
+    }
+
+    /// Sets what error cells display as, `error_text` alone restores the default
+    fn handle_error_text(&mut self, text: &str, sheet: &mut Spreadsheet) -> CommandResult {
+        if !text.is_empty() && !text.starts_with(char::is_whitespace) {
+            return CommandResult::UnrecognizedCommand;
+        }
+        let text = text.trim();
+        if text.is_empty() {
+            sheet.error_text = DEFAULT_ERROR_TEXT.to_string();
+        } else if text.chars().any(char::is_whitespace) {
+            return CommandResult::UnrecognizedCommand;
+        } else {
+            sheet.error_text = text.to_string();
+        }
+        CommandResult::Ok
+    }
+
     fn handle_cell_assignment(
         &mut self,
         command: &str,
@@ -140,22 +371,31 @@ impl CommandHandler {
         let expr = &expr[1..]; // skip '='
 
         if let Some((col, row)) = MyParser::cell_name_to_coord(cell_str.trim()) {
-            let result_code = sheet.set_cell((col, row), expr);
-            CommandResult::from_code(result_code)
+            let result = CommandResult::from_code(sheet.set_cell((col, row), expr));
+            // a misspelt function is named in the report, as the prompt only has room for
+            // the result
+            let suggestion = expr
+                .trim()
+                .split_once('(')
+                .and_then(|(name, _)| MyParser::suggest_function(name.trim()));
+            if let (CommandResult::UnrecognizedCommand, Some(func)) = (result, suggestion) {
+                self.report.push(format!("Did you mean {}?", func));
+            }
+            result
         } else {
             CommandResult::InvalidCell
         }
     }
 
-    // Display prompt with elapsed time and status message, matching the original format exactly
+    // Display prompt with elapsed time and status message, matching the original format exactly.
+    // Only a command slower than `SLOW_COMMAND` adds a `(slow: 3.2s)` note before the `>`
     pub fn display_prompt(&self, writer: &mut impl Write) -> io::Result<()> {
-        let elapsed = self.last_instant.elapsed().as_secs_f64();
-        write!(
-            writer,
-            "[{:.1}] ({}) > ",
-            elapsed,
-            self.last_result.as_str()
-        )?;
+        let elapsed = self.last_duration.as_secs_f64();
+        write!(writer, "[{:.1}] ({}) ", elapsed, self.last_result.as_str())?;
+        if self.last_duration > SLOW_COMMAND {
+            write!(writer, "(slow: {:.1}s) ", elapsed)?;
+        }
+        write!(writer, "> ")?;
         writer.flush()
     }
 
@@ -166,6 +406,48 @@ impl CommandHandler {
     pub fn get_viewport(&self) -> (usize, usize) {
         (self.viewport_row, self.viewport_col)
     }
+
+    /// The selected cell as `(row, col)`, zero-indexed like `get_viewport`
+    pub fn get_cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    /// The cell to bracket in the grid, 1-indexed like `Spreadsheet::display`. None until the
+    /// cursor is first moved with W/A/S/D
+    pub fn shown_cursor(&self) -> Option<(usize, usize)> {
+        let (row, col) = self.cursor;
+        self.cursor_moved.then_some((row + 1, col + 1))
+    }
+
+    /// Whether the last command asked for the history to be printed
+    pub fn should_show_history(&self) -> bool {
+        self.show_history
+    }
+
+    /// The lines the last command asked to print, empty for most commands
+    pub fn report(&self) -> &[String] {
+        &self.report
+    }
+
+    /// Writes the lines of `report`, one per line
+    pub fn write_report(&self, writer: &mut impl Write) -> io::Result<()> {
+        for line in &self.report {
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Writes the history one command per line, numbered from 1 as `!n` expects
+    pub fn write_history(&self, writer: &mut impl Write) -> io::Result<()> {
+        for (i, command) in self.history.iter().enumerate() {
+            writeln!(writer, "{:>5}  {}", i + 1, command)?;
+        }
+        Ok(())
+    }
 }
 
 /// Handles user commands for interacting with the spreadsheet.
@@ -182,8 +464,22 @@ impl CommandHandler {
 /// - `disable_output`: Disable spreadsheet display updates.
 /// - `enable_output`: Enable spreadsheet display updates.
 /// - `scroll_to <cell>`: Scroll to a specific cell (e.g., `scroll_to A1`).
+/// - `next_err`: Scroll to the next cell holding an error.
+/// - `deps <cell>`: Print the cells `<cell>` reads, recursively, as a tree.
+/// - `rdeps <cell>`: Print the cells reading `<cell>`, recursively, as a tree.
+/// - `history`: List the commands run so far, numbered from 1.
+/// - `!<n>`: Run command number `n` from the history again (e.g., `!3`).
+/// - `locale standard|european`: Display numbers as `1234` or `1.234`.
+/// - `error_text [<text>]`: Display error cells as `<text>` (e.g., `error_text #DIV/0!`), or
+///   as `ERR` again when omitted.
 /// - `w`, `a`, `s`, `d`: Navigate the spreadsheet's viewport (up, left, down, right).
+/// - `W`, `A`, `S`, `D`: Move the selected cell, shown in brackets, by one cell.
 /// - `<cell>=<expression>`: Set a cell's value or formula (e.g., `A1=5+3`).
+/// - `fill <range> = <value>`: Set every cell of a range to an integer (e.g., `fill A1:C3 = 7`).
+/// - `record start`: Start capturing the commands that follow.
+/// - `record stop <name>`: Stop capturing and keep the commands as macro `<name>`.
+/// - `run <name>`: Replay macro `<name>` (e.g., `run setup`). Macros last until the program exits.
+/// - `# ...`: A comment; the line is ignored, as are blank lines.
 ///
 /// # Behavior
 /// - Displays the spreadsheet's current state in a 10x10 viewport.
@@ -212,7 +508,7 @@ pub fn handle_commands(sheet: &mut Spreadsheet) {
     let mut input = String::new();
 
     // Initial display
-    sheet.display(0, 0, 10, 10);
+    sheet.display(0, 0, 10, 10, None);
 
     loop {
         command_handler.display_prompt(&mut stdout).unwrap();
@@ -228,9 +524,20 @@ pub fn handle_commands(sheet: &mut Spreadsheet) {
             break;
         }
 
+        if command_handler.should_show_history() {
+            command_handler.write_history(&mut stdout).unwrap();
+        }
+        command_handler.write_report(&mut stdout).unwrap();
+
         if command_handler.should_display() {
             let (viewport_row, viewport_col) = command_handler.get_viewport();
-            sheet.display(viewport_row, viewport_col, 10, 10);
+            sheet.display(
+                viewport_row,
+                viewport_col,
+                10,
+                10,
+                command_handler.shown_cursor(),
+            );
         }
     }
 }
@@ -238,7 +545,7 @@ pub fn handle_commands(sheet: &mut Spreadsheet) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::spreadsheet::Spreadsheet;
+    use crate::spreadsheet::{Cell, Spreadsheet};
 
     #[test]
     fn test_command_result_from_code() {
@@ -282,7 +589,7 @@ mod tests {
             CommandResult::CircularDependency.as_str(),
             "Circular dependency"
         );
-        assert_eq!(CommandResult::DivisionByZero.as_str(), "Division_by_zero");
+        assert_eq!(CommandResult::DivisionByZero.as_str(), "#DIV/0!");
         assert_eq!(CommandResult::Quit.as_str(), "quit");
     }
 
@@ -338,6 +645,25 @@ mod tests {
         assert!(matches!(result, CommandResult::CircularDependency));
     }
 
+    #[test]
+    fn test_suggests_misspelt_function() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+
+        let result = handler.handle_command("A1=SUmm(A1:B2)", &mut sheet);
+        assert!(matches!(result, CommandResult::UnrecognizedCommand));
+        assert_eq!(handler.report(), ["Did you mean SUM?"]);
+
+        handler.handle_command("A1=STDV(B1:B2)", &mut sheet);
+        assert_eq!(handler.report(), ["Did you mean STDEV?"]);
+
+        // nothing to suggest for a name far from every function, or a valid formula
+        handler.handle_command("A1=VLOOKUP(A1:B2)", &mut sheet);
+        assert!(handler.report().is_empty());
+        handler.handle_command("C1=SUM(A1:B2)", &mut sheet);
+        assert!(handler.report().is_empty());
+    }
+
     #[test]
     fn test_output_toggle_commands() {
         let mut handler = CommandHandler::new();
@@ -459,6 +785,116 @@ mod tests {
         assert_eq!(handler.get_viewport(), (0, 0)); // Can't scroll right in small sheet
     }
 
+    #[test]
+    fn test_cursor_movement() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(30, 12);
+        assert_eq!(handler.get_cursor(), (0, 0));
+        // the grid is printed without the cursor until it is first moved
+        handler.handle_command("d", &mut sheet);
+        handler.handle_command("a", &mut sheet);
+        assert_eq!(handler.shown_cursor(), None);
+
+        // stops at the top-left corner
+        handler.handle_command("W", &mut sheet);
+        handler.handle_command("A", &mut sheet);
+        assert_eq!(handler.get_cursor(), (0, 0));
+        assert_eq!(handler.shown_cursor(), Some((1, 1)));
+
+        handler.handle_command("S", &mut sheet);
+        handler.handle_command("D", &mut sheet);
+        handler.handle_command("D", &mut sheet);
+        assert_eq!(handler.get_cursor(), (1, 2));
+        assert_eq!(handler.get_viewport(), (0, 0));
+
+        // the viewport follows once the cursor leaves it, one cell at a time
+        for _ in 0..9 {
+            handler.handle_command("S", &mut sheet);
+        }
+        assert_eq!(handler.get_cursor(), (10, 2));
+        assert_eq!(handler.get_viewport(), (1, 0));
+        for _ in 0..20 {
+            handler.handle_command("D", &mut sheet);
+        }
+        assert_eq!(handler.get_cursor(), (10, 11));
+        assert_eq!(handler.get_viewport(), (1, 2));
+
+        // scrolling the viewport leaves the cursor where it is
+        handler.handle_command("s", &mut sheet);
+        assert_eq!(handler.get_cursor(), (10, 11));
+        for _ in 0..10 {
+            handler.handle_command("W", &mut sheet);
+        }
+        assert_eq!(handler.get_cursor(), (0, 11));
+        assert_eq!(handler.get_viewport(), (0, 2));
+    }
+
+    #[test]
+    fn test_locale_command() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+
+        let result = handler.handle_command("locale european", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert_eq!(sheet.locale, NumberLocale::European);
+
+        let result = handler.handle_command("locale standard", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert_eq!(sheet.locale, NumberLocale::Standard);
+
+        let result = handler.handle_command("locale klingon", &mut sheet);
+        assert!(matches!(result, CommandResult::UnrecognizedCommand));
+        assert_eq!(sheet.locale, NumberLocale::Standard);
+    }
+
+    #[test]
+    fn test_error_text_command() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+        handler.handle_command("A1=1/0", &mut sheet);
+        let shown = |sheet: &Spreadsheet| {
+            let mut output = Vec::new();
+            sheet.display_to(&mut output, 0, 0, 1, 1, None).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert!(shown(&sheet).contains("ERR"));
+
+        let result = handler.handle_command("error_text NaN", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert!(shown(&sheet).contains("NaN"));
+        assert!(!shown(&sheet).contains("ERR"));
+
+        let result = handler.handle_command("error_text not one", &mut sheet);
+        assert!(matches!(result, CommandResult::UnrecognizedCommand));
+        let result = handler.handle_command("error_textX", &mut sheet);
+        assert!(matches!(result, CommandResult::UnrecognizedCommand));
+        assert_eq!(sheet.error_text, "NaN");
+
+        handler.handle_command("error_text", &mut sheet);
+        assert!(shown(&sheet).contains("ERR"));
+    }
+
+    #[test]
+    fn test_whitespace_and_comment_lines() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+
+        let result = handler.handle_command("   \t \n", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+
+        let result = handler.handle_command("# A1=42", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert_eq!(sheet.cells[1][1], crate::spreadsheet::Cell::Value(0));
+
+        let result = handler.handle_command("   # indented comment\n", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+
+        // surrounding whitespace does not stop a real command from running
+        let result = handler.handle_command("  A1=42  \n", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert_eq!(sheet.cells[1][1], crate::spreadsheet::Cell::Value(42));
+    }
+
     #[test]
     fn test_unrecognized_command() {
         let mut handler = CommandHandler::new();
@@ -480,4 +916,259 @@ mod tests {
         assert!(output_str.contains("ok"));
         assert!(output_str.contains(">"));
     }
+
+    #[test]
+    fn test_prompt_times_sleep() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+        let prompt = |handler: &CommandHandler| {
+            let mut output = Vec::new();
+            handler.display_prompt(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        handler.handle_command("A1=1", &mut sheet);
+        assert_eq!(prompt(&handler), "[0.0] (ok) > ");
+
+        // the time covers the whole SLEEP, however long the prompt takes to show
+        handler.handle_command("A2=SLEEP(2)", &mut sheet);
+        assert!(handler.last_duration >= Duration::from_secs(2));
+        let shown = prompt(&handler);
+        assert!(shown.starts_with("[2."), "{}", shown);
+        assert!(shown.contains("(ok) (slow: 2."), "{}", shown);
+        assert!(shown.ends_with("s) > "), "{}", shown);
+    }
+
+    #[test]
+    fn test_next_err_command() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(30, 30);
+
+        let result = handler.handle_command("next_err", &mut sheet);
+        assert!(matches!(result, CommandResult::NoErrorCell));
+        assert_eq!(handler.get_viewport(), (0, 0));
+
+        handler.handle_command("C2=1/0", &mut sheet);
+        handler.handle_command("B15=A1/0", &mut sheet);
+        handler.handle_command("D3=A1+1", &mut sheet);
+
+        let result = handler.handle_command("next_err", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert_eq!(handler.get_viewport(), (1, 2));
+
+        handler.handle_command("next_err", &mut sheet);
+        assert_eq!(handler.get_viewport(), (14, 1));
+
+        // wraps around to the first error
+        handler.handle_command("next_err", &mut sheet);
+        assert_eq!(handler.get_viewport(), (1, 2));
+    }
+
+    #[test]
+    fn test_fill_command() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+        for command in ["B2=A1+100", "A5=SUM(A1:C3)", "B5=A5*2"] {
+            handler.handle_command(command, &mut sheet);
+        }
+
+        let result = handler.handle_command("fill A1:C3 = 7", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        for row in 1..=3 {
+            for col in 1..=3 {
+                assert_eq!(sheet.cells[row][col], Cell::Value(7));
+            }
+        }
+        assert_eq!(sheet.cells[5][1], Cell::Value(63));
+        assert_eq!(sheet.cells[5][2], Cell::Value(126));
+
+        // B2 no longer follows A1
+        handler.handle_command("A1=1", &mut sheet);
+        assert_eq!(sheet.cells[2][2], Cell::Value(7));
+        assert_eq!(sheet.cells[5][1], Cell::Value(57));
+
+        // the corners may come in either order
+        handler.handle_command("fill C3:B2=-1", &mut sheet);
+        assert_eq!(sheet.cells[2][2], Cell::Value(-1));
+        assert_eq!(sheet.cells[3][3], Cell::Value(-1));
+        assert_eq!(sheet.cells[1][3], Cell::Value(7));
+
+        let result = handler.handle_command("fill A1:K1 = 7", &mut sheet);
+        assert!(matches!(result, CommandResult::InvalidCell));
+        let result = handler.handle_command("fill A1 = 7", &mut sheet);
+        assert!(matches!(result, CommandResult::InvalidRange));
+        let result = handler.handle_command("fill A1:B2 = B3", &mut sheet);
+        assert!(matches!(result, CommandResult::UnrecognizedCommand));
+    }
+
+    #[test]
+    fn test_deps_commands() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+        for command in ["A1=2", "B1=A1*3", "C1=B1+A1", "D1=SUM(A1:B2)", "E1=D1"] {
+            handler.handle_command(command, &mut sheet);
+        }
+        assert!(handler.report().is_empty());
+
+        let result = handler.handle_command("deps C1", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert_eq!(
+            handler.report(),
+            ["C1 = B1+A1", "  A1", "  B1 = A1*3", "    A1 (see above)"]
+        );
+        let mut out = Vec::new();
+        handler.write_report(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "C1 = B1+A1\n  A1\n  B1 = A1*3\n    A1 (see above)\n"
+        );
+
+        handler.handle_command("deps E1", &mut sheet);
+        assert_eq!(
+            handler.report(),
+            [
+                "E1 = D1",
+                "  D1 = SUM(A1:B2)",
+                "    A1:B2",
+                "      B1 = A1*3",
+                "        A1",
+            ]
+        );
+
+        handler.handle_command("rdeps A1", &mut sheet);
+        assert_eq!(
+            handler.report(),
+            [
+                "A1",
+                "  B1 = A1*3",
+                "    C1 = B1+A1",
+                "    D1 = SUM(A1:B2)",
+                "      E1 = D1",
+                "  C1 = B1+A1 (see above)",
+                "  D1 = SUM(A1:B2) (see above)",
+            ]
+        );
+
+        // the report only lasts for the command that produced it
+        handler.handle_command("w", &mut sheet);
+        assert!(handler.report().is_empty());
+
+        let result = handler.handle_command("deps K1", &mut sheet);
+        assert!(matches!(result, CommandResult::InvalidCell));
+        let result = handler.handle_command("rdeps 12", &mut sheet);
+        assert!(matches!(result, CommandResult::InvalidCell));
+    }
+
+    #[test]
+    fn test_history_recording_and_listing() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+
+        handler.handle_command("A1=5\n", &mut sheet);
+        handler.handle_command("   ", &mut sheet);
+        handler.handle_command("# not recorded", &mut sheet);
+        handler.handle_command("B1=A1+1", &mut sheet);
+        handler.handle_command("foo", &mut sheet);
+        assert_eq!(handler.history(), ["A1=5", "B1=A1+1", "foo"]);
+        assert!(!handler.should_show_history());
+
+        let result = handler.handle_command("history", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert!(handler.should_show_history());
+        assert_eq!(handler.history().len(), 3);
+
+        let mut out = Vec::new();
+        handler.write_history(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "    1  A1=5\n    2  B1=A1+1\n    3  foo\n"
+        );
+
+        handler.handle_command("w", &mut sheet);
+        assert!(!handler.should_show_history());
+    }
+
+    #[test]
+    fn test_history_rerun_by_index() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+
+        handler.handle_command("A1=A1", &mut sheet);
+        handler.handle_command("A1=7", &mut sheet);
+        handler.handle_command("A1=1", &mut sheet);
+        assert_eq!(sheet.cells[1][1], Cell::Value(1));
+
+        let result = handler.handle_command("!2", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert_eq!(sheet.cells[1][1], Cell::Value(7));
+        assert_eq!(handler.history().last().unwrap(), "A1=7");
+
+        // the recalled command's own result is reported
+        let result = handler.handle_command("!1", &mut sheet);
+        assert!(matches!(result, CommandResult::CircularDependency));
+
+        for bad in ["!0", "!9", "!x", "!"] {
+            let result = handler.handle_command(bad, &mut sheet);
+            assert!(matches!(result, CommandResult::UnrecognizedCommand));
+        }
+        assert_eq!(handler.history().len(), 5);
+    }
+
+    #[test]
+    fn test_record_and_run_macro() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+
+        let result = handler.handle_command("record start", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert!(handler.is_recording());
+        for command in ["A1=2", "B1=A1*3", "fill A2:B2 = 4", "C1=SUM(A1:B2)"] {
+            handler.handle_command(command, &mut sheet);
+        }
+        let result = handler.handle_command("record stop setup", &mut sheet);
+        assert!(matches!(result, CommandResult::Ok));
+        assert!(!handler.is_recording());
+
+        let mut fresh = Spreadsheet::new(10, 10);
+        let result = handler.handle_command("run setup", &mut fresh);
+        assert!(matches!(result, CommandResult::Ok));
+        assert_eq!(fresh.cells, sheet.cells);
+        assert_eq!(fresh.cells[1][3], Cell::Value(16));
+
+        // a macro run while recording is captured as the commands it ran
+        handler.handle_command("record start", &mut sheet);
+        handler.handle_command("run setup", &mut sheet);
+        handler.handle_command("D1=C1+1", &mut sheet);
+        handler.handle_command("record stop more", &mut sheet);
+        let mut fresh = Spreadsheet::new(10, 10);
+        handler.handle_command("run more", &mut fresh);
+        assert_eq!(fresh.cells[1][4], Cell::Value(17));
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let mut handler = CommandHandler::new();
+        let mut sheet = Spreadsheet::new(10, 10);
+
+        let result = handler.handle_command("run nothing", &mut sheet);
+        assert!(matches!(result, CommandResult::UnknownMacro));
+        let result = handler.handle_command("record stop early", &mut sheet);
+        assert!(matches!(result, CommandResult::UnrecognizedCommand));
+
+        handler.handle_command("record start", &mut sheet);
+        handler.handle_command("A1=A1", &mut sheet);
+        handler.handle_command("B1=3", &mut sheet);
+        for bad in ["record stop", "record stop two words"] {
+            let result = handler.handle_command(bad, &mut sheet);
+            assert!(matches!(result, CommandResult::UnrecognizedCommand));
+            assert!(handler.is_recording());
+        }
+        handler.handle_command("record stop broken", &mut sheet);
+
+        // the first failure is reported, the rest of the macro still runs
+        let mut fresh = Spreadsheet::new(10, 10);
+        let result = handler.handle_command("run broken", &mut fresh);
+        assert!(matches!(result, CommandResult::CircularDependency));
+        assert_eq!(fresh.cells[1][2], Cell::Value(3));
+    }
 }
@@ -18,6 +18,14 @@ pub enum CellError {
     DependsOnNonNumeric,
     /// Error that occurs when a cell depends on another cell containing an error.
     DependsOnErr,
+    /// Error that occurs when a function expecting equally shaped ranges is given ranges
+    /// of different dimensions.
+    ShapeMismatch,
+    /// Error that occurs when `MIN` or `MAX` is given a range holding no numbers.
+    EmptyRange,
+    /// Error that occurs when a number is too large for what it is used as, like a `SLEEP`
+    /// longer than a `Duration` can hold.
+    NumberTooLarge,
 }
 
 /// Represents the possible values a cell can contain.
@@ -34,6 +42,32 @@ pub enum CellValue {
     Number(f64),
 }
 
+/// What kind of value a cell shows, typed in or given by its formula, see
+/// `CellData::cell_type`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CellType {
+    /// The cell shows nothing.
+    Empty,
+    /// The cell shows a number.
+    Number,
+    /// The cell shows text.
+    Text,
+    /// The cell's formula evaluated to an error.
+    Error,
+}
+
+impl CellType {
+    /// The kind of a value. An empty value is `Empty`.
+    pub fn of_value(value: &Result<CellValue, CellError>) -> CellType {
+        match value {
+            Ok(CellValue::Empty) => CellType::Empty,
+            Ok(CellValue::Number(_)) => CellType::Number,
+            Ok(CellValue::String(_)) => CellType::Text,
+            Err(_) => CellType::Error,
+        }
+    }
+}
+
 /// Represents the complete data for a cell, including its value and formula.
 ///
 /// A cell can contain a computed value (or error) and optionally a formula
@@ -68,4 +102,10 @@ impl CellData {
         };
         &DEFAULT_CELL
     }
+
+    /// The kind of value the cell shows, the result of its formula if it has one. Whether it
+    /// has one is `formula.is_some()`
+    pub fn cell_type(&self) -> CellType {
+        CellType::of_value(&self.value)
+    }
 }
@@ -6,8 +6,12 @@
 //! ## Key Components:
 //!
 //! - `Operator`: Enum representing basic arithmetic operations (addition, subtraction, etc.)
+//!   and comparisons, which give `1` for true and `0` for false
 //! - `RangeFunction`: Enum for statistical operations over cell ranges (min, max, average, etc.)
 //! - `CellRange`: Structure that defines a rectangular range of cells
+//! - `SumProduct`: Expression over two ranges, summing the products of corresponding cells
+//! - `LocationFunction`: Functions giving the label of a cell in a range (argmin, argmax)
+//! - `CoercionFunction`: Functions keeping their argument only if it has a given type (N, T)
 //! - `Expression`: Core enum representing different types of expressions (numbers, cell references, operations)
 //!
 //! Expressions can be converted to string representations based on absolute cell positions,
@@ -15,6 +19,7 @@
 //! operations like Sleep (which introduces a delay).
 use crate::common::structs::{AbsCell, RelCell};
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::fmt::{self, Display};
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
@@ -23,6 +28,23 @@ pub enum Operator {
     Subtract,
     Multiply,
     Divide,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Operator {
+    /// How tightly the operator binds, operators with a higher precedence apply first
+    pub fn precedence(self) -> u8 {
+        match self {
+            Operator::Multiply | Operator::Divide => 2,
+            Operator::Add | Operator::Subtract => 1,
+            _ => 0,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
@@ -34,6 +56,22 @@ pub enum RangeFunction {
     Stdev,
 }
 
+/// Functions whose result is the label of a cell in the range, like `B3`, instead of a number
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
+pub enum LocationFunction {
+    ArgMin,
+    ArgMax,
+}
+
+/// Functions that look at the type of their argument instead of converting it, as in Excel
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
+pub enum CoercionFunction {
+    /// The argument if it is a number, otherwise `0`
+    N,
+    /// The argument if it is text, otherwise the empty string
+    T,
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
 pub struct CellRange {
     pub top_left: RelCell,
@@ -46,7 +84,19 @@ pub enum Expression {
     Cell(RelCell),
     BinaryOp(Box<Expression>, Operator, Box<Expression>),
     RangeFunction(RangeFunction, CellRange),
+    /// Sleeps for the given number of seconds, fractions included, and evaluates to it.
+    /// The integer engine's `SLEEP` only takes whole seconds
     Sleep(Box<Expression>),
+    // Saved sheets store the index of the variant, so new ones only ever go after this line
+    SumProduct(CellRange, CellRange),
+    /// Only valid as a whole formula, its text result can not be used in arithmetic
+    Location(LocationFunction, CellRange),
+    /// Like `Sleep`, but the argument is in milliseconds
+    SleepMs(Box<Expression>),
+    /// A quoted string, only valid as the argument of a `CoercionFunction`
+    Text(String),
+    /// `T` is only valid as a whole formula, like `Location`
+    Coerce(CoercionFunction, Box<Expression>),
 }
 
 impl Display for Operator {
@@ -56,6 +106,12 @@ impl Display for Operator {
             Operator::Subtract => "-",
             Operator::Multiply => "*",
             Operator::Divide => "/",
+            Operator::Equal => "=",
+            Operator::NotEqual => "<>",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
         };
         write!(f, "{}", op_str)
     }
@@ -74,7 +130,35 @@ impl Display for RangeFunction {
     }
 }
 
+impl Display for LocationFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let func_str = match self {
+            LocationFunction::ArgMin => "ARGMIN",
+            LocationFunction::ArgMax => "ARGMAX",
+        };
+        write!(f, "{}", func_str)
+    }
+}
+
+impl Display for CoercionFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let func_str = match self {
+            CoercionFunction::N => "N",
+            CoercionFunction::T => "T",
+        };
+        write!(f, "{}", func_str)
+    }
+}
+
 impl CellRange {
+    /// The `(rows, cols)` dimensions of the range
+    pub fn shape(&self) -> (i16, i16) {
+        (
+            self.bottom_right.row - self.top_left.row + 1,
+            self.bottom_right.col - self.top_left.col + 1,
+        )
+    }
+
     pub fn to_string(&self, cell: AbsCell) -> String {
         let tl = self.top_left.to_abs(cell);
         let br = self.bottom_right.to_abs(cell);
@@ -83,19 +167,289 @@ impl CellRange {
 }
 
 impl Expression {
+    /// The expression with each single cell reference replaced by what `cell` gives for it,
+    /// and each range by what `range` gives, stopping at the first error. Every rewrite of the
+    /// references of a formula, like transposing, moving or shifting them, is built on this
+    pub fn try_map_references<E>(
+        &self,
+        cell: &mut impl FnMut(RelCell) -> Result<RelCell, E>,
+        range: &mut impl FnMut(&CellRange) -> Result<CellRange, E>,
+    ) -> Result<Expression, E> {
+        Ok(match self {
+            Expression::Number(_) | Expression::Text(_) => self.clone(),
+            Expression::Cell(c) => Expression::Cell(cell(*c)?),
+            Expression::BinaryOp(left, op, right) => Expression::BinaryOp(
+                Box::new(left.try_map_references(cell, range)?),
+                *op,
+                Box::new(right.try_map_references(cell, range)?),
+            ),
+            Expression::RangeFunction(func, r) => Expression::RangeFunction(*func, range(r)?),
+            Expression::SumProduct(first, second) => {
+                Expression::SumProduct(range(first)?, range(second)?)
+            }
+            Expression::Sleep(arg) => {
+                Expression::Sleep(Box::new(arg.try_map_references(cell, range)?))
+            }
+            Expression::Location(func, r) => Expression::Location(*func, range(r)?),
+            Expression::SleepMs(arg) => {
+                Expression::SleepMs(Box::new(arg.try_map_references(cell, range)?))
+            }
+            Expression::Coerce(func, arg) => {
+                Expression::Coerce(*func, Box::new(arg.try_map_references(cell, range)?))
+            }
+        })
+    }
+
+    /// The expression with `f` applied to every reference, and to both corners of every
+    /// range. Written in the same cell, `A1 + SUM(B1:B2)` with every reference moved a row
+    /// down reads `A2 + SUM(B2:B3)`.
+    ///
+    /// The result may reach off the sheet, which the storage refuses when it is set
+    pub fn map_references(&self, f: impl Fn(RelCell) -> RelCell) -> Expression {
+        let Ok(expression) =
+            self.try_map_references::<Infallible>(&mut |c| Ok(f(c)), &mut |range| {
+                Ok(CellRange {
+                    top_left: f(range.top_left),
+                    bottom_right: f(range.bottom_right),
+                })
+            });
+        expression
+    }
+
+    /// The expression with every reference, ranges included, moved by `dr` rows and `dc`
+    /// columns. Written in the same cell, `A1 + B2` shifted by `(1, 0)` reads `A2 + B3`.
+    ///
+    /// The result may reach off the sheet, which the storage refuses when it is set
+    pub fn shift_references(&self, dr: i16, dc: i16) -> Expression {
+        self.map_references(move |c| RelCell::new(c.row + dr, c.col + dc))
+    }
+
+    /// The expression with the row and column offset of every reference swapped, so a
+    /// reference one row down becomes one a column right. This is what pasting a formula
+    /// transposed writes
+    pub fn transposed(&self) -> Expression {
+        self.map_references(|c| RelCell::new(c.col, c.row))
+    }
+
+    /// The expression, written in `cell`, with every single cell reference to `from` made to
+    /// read `to` instead. References to ranges are left as they are
+    pub fn retarget(&self, cell: AbsCell, from: AbsCell, to: AbsCell) -> Expression {
+        let Ok(expression) = self.try_map_references::<Infallible>(
+            &mut |c| {
+                Ok(if c.to_abs(cell) == from {
+                    to.to_rel(cell)
+                } else {
+                    c
+                })
+            },
+            &mut |range| Ok(range.clone()),
+        );
+        expression
+    }
+
     pub fn to_string(&self, cell: AbsCell) -> String {
+        self.render(&|rel_cell| rel_cell.to_abs(cell).to_string())
+    }
+
+    /// The formula with each reference shown as its offset from the cell holding it, in R1C1
+    /// style: `R[-1]C` is the cell just above, `RC[2]` two to the right. Unlike the labels of
+    /// `to_string`, this text is the same in every cell the formula is copied to
+    ///
+    /// # Examples
+    /// ```rust
+    /// use embedded::common::expression::Expression;
+    /// use embedded::common::structs::RelCell;
+    /// assert_eq!(Expression::Cell(RelCell::new(-1, 0)).to_relative_string(), "R[-1]C");
+    /// ```
+    pub fn to_relative_string(&self) -> String {
+        self.render(&|rel_cell| {
+            let offset = |offset: i16| match offset {
+                0 => String::new(),
+                offset => format!("[{}]", offset),
+            };
+            format!("R{}C{}", offset(rel_cell.row), offset(rel_cell.col))
+        })
+    }
+
+    /// Writes the formula out, with `reference` giving the text of each cell reference
+    fn render(&self, reference: &dyn Fn(RelCell) -> String) -> String {
+        let render_range = |range: &CellRange| {
+            format!(
+                "{}:{}",
+                reference(range.top_left),
+                reference(range.bottom_right)
+            )
+        };
+        // function arguments are plain expressions in the grammar, a comparison needs
+        // parentheses to be one
+        let argument = |expr: &Expression| match expr {
+            Expression::BinaryOp(_, op, _) if op.precedence() == 0 => {
+                format!("({})", expr.render(reference))
+            }
+            _ => expr.render(reference),
+        };
         match self {
             Expression::Number(n) => format!("{}", n),
-            Expression::Cell(c) => format!("{}", c.to_abs(cell)),
+            Expression::Cell(c) => reference(*c),
             Expression::BinaryOp(left, op, right) => {
-                format!("{} {} {}", left.to_string(cell), op, right.to_string(cell))
+                // operators are left associative, so a right operand of the same precedence
+                // needs parentheses too, as in `A1 - (B1 - C1)`
+                let operand = |expr: &Expression, needs_parens: fn(u8, u8) -> bool| match expr {
+                    Expression::BinaryOp(_, inner, _)
+                        if needs_parens(inner.precedence(), op.precedence()) =>
+                    {
+                        format!("({})", expr.render(reference))
+                    }
+                    _ => expr.render(reference),
+                };
+                format!(
+                    "{} {} {}",
+                    operand(left, |inner, outer| inner < outer),
+                    op,
+                    operand(right, |inner, outer| inner <= outer)
+                )
             }
             Expression::RangeFunction(func, range) => {
-                format!("{}({})", func, range.to_string(cell))
+                format!("{}({})", func, render_range(range))
+            }
+            Expression::SumProduct(first, second) => {
+                format!(
+                    "SUMPRODUCT({}, {})",
+                    render_range(first),
+                    render_range(second)
+                )
             }
             Expression::Sleep(inner) => {
-                format!("SLEEP({})", inner.to_string(cell))
+                format!("SLEEP({})", argument(inner))
+            }
+            Expression::Location(func, range) => {
+                format!("{}({})", func, render_range(range))
+            }
+            Expression::SleepMs(inner) => {
+                format!("SLEEP_MS({})", argument(inner))
+            }
+            Expression::Text(text) => format!("\"{}\"", text.replace('"', "\"\"")),
+            Expression::Coerce(func, inner) => {
+                format!("{}({})", func, argument(inner))
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(label: &str) -> AbsCell {
+        label.parse().unwrap()
+    }
+
+    fn reads(from: &str, target: &str) -> Expression {
+        Expression::Cell(cell(target).to_rel(cell(from)))
+    }
+
+    #[test]
+    fn test_shift_binary_op() {
+        let expression = Expression::BinaryOp(
+            Box::new(reads("C3", "A1")),
+            Operator::Add,
+            Box::new(reads("C3", "B2")),
+        );
+        let shifted = expression.shift_references(1, 2);
+        assert_eq!(shifted.to_string(cell("C3")), "C2 + D3");
+        assert_eq!(
+            shifted.shift_references(-1, -2).to_string(cell("C3")),
+            "A1 + B2"
+        );
+        assert_eq!(expression.transposed().to_string(cell("C3")), "A1 + B2");
+        assert_eq!(reads("C3", "A2").transposed().to_string(cell("C3")), "B1");
+    }
+
+    #[test]
+    fn test_shift_range_function() {
+        let range = CellRange {
+            top_left: cell("A1").to_rel(cell("D4")),
+            bottom_right: cell("B3").to_rel(cell("D4")),
+        };
+        let shape = range.shape();
+        let expression = Expression::RangeFunction(RangeFunction::Sum, range);
+        assert_eq!(
+            expression.shift_references(2, 0).to_string(cell("D4")),
+            "SUM(A3:B5)"
+        );
+        // the shape of the range is kept
+        let Expression::RangeFunction(_, shifted) = expression.shift_references(5, 5) else {
+            panic!("still a range function");
+        };
+        assert_eq!(shifted.shape(), shape);
+    }
+
+    #[test]
+    fn test_shift_nested_sleep() {
+        let expression = Expression::Sleep(Box::new(Expression::BinaryOp(
+            Box::new(reads("B2", "A1")),
+            Operator::Multiply,
+            Box::new(Expression::Number(2.0)),
+        )));
+        assert_eq!(
+            expression.shift_references(0, 3).to_string(cell("B2")),
+            "SLEEP(D1 * 2)"
+        );
+    }
+
+    #[test]
+    fn test_retarget_leaves_ranges() {
+        let expression = Expression::BinaryOp(
+            Box::new(reads("C1", "A1")),
+            Operator::Add,
+            Box::new(Expression::RangeFunction(
+                RangeFunction::Sum,
+                CellRange {
+                    top_left: cell("A1").to_rel(cell("C1")),
+                    bottom_right: cell("A2").to_rel(cell("C1")),
+                },
+            )),
+        );
+        let moved = expression.retarget(cell("C1"), cell("A1"), cell("E5"));
+        assert_eq!(moved.to_string(cell("C1")), "E5 + SUM(A1:A2)");
+    }
+
+    #[test]
+    fn test_try_map_stops_at_error() {
+        let expression = Expression::BinaryOp(
+            Box::new(reads("B2", "A1")),
+            Operator::Add,
+            Box::new(reads("B2", "C3")),
+        );
+        // refuses references above the cell
+        let result = expression.try_map_references(
+            &mut |c| if c.row < 0 { Err(c) } else { Ok(c) },
+            &mut |range| Ok(range.clone()),
+        );
+        assert_eq!(result, Err(RelCell::new(-1, -1)));
+    }
+
+    #[test]
+    fn test_relative_string() {
+        let expression = Expression::BinaryOp(
+            Box::new(reads("C3", "C2")),
+            Operator::Add,
+            Box::new(Expression::RangeFunction(
+                RangeFunction::Sum,
+                CellRange {
+                    top_left: cell("A1").to_rel(cell("C3")),
+                    bottom_right: cell("C3").to_rel(cell("C3")),
+                },
+            )),
+        );
+        assert_eq!(expression.to_string(cell("C3")), "C2 + SUM(A1:C3)");
+        assert_eq!(
+            expression.to_relative_string(),
+            "R[-1]C + SUM(R[-2]C[-2]:RC)"
+        );
+        assert_eq!(
+            Expression::Sleep(Box::new(reads("A1", "B3"))).to_relative_string(),
+            "SLEEP(R[2]C[1])"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Guessing how a column of text was derived from another, from a few examples.
+//!
+//! Only two kinds of rule are tried, in this order: splitting on a delimiter and taking one of
+//! the parts (`"Ada Lovelace"` → `"Ada"`), and stripping a fixed prefix and suffix
+//! (`"ID-042;"` → `"042"`). A rule is only used if it gives every example exactly.
+
+/// Delimiters tried when splitting, in order of preference
+const DELIMITERS: [char; 8] = [' ', ',', ';', '-', '_', '@', '.', '/'];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextRule {
+    /// Split on `delimiter` and take the part at `index`, counting from 0
+    Split { delimiter: char, index: usize },
+    /// Remove `prefix` from the start and `suffix` from the end
+    Strip { prefix: String, suffix: String },
+}
+
+impl TextRule {
+    /// The first rule that turns each `(source, output)` example's source into its output.
+    /// None if there are no examples or no rule fits all of them
+    ///
+    /// # Examples
+    /// ```rust
+    /// use embedded::common::flash_fill::TextRule;
+    /// let rule = TextRule::infer(&[("Ada Lovelace", "Lovelace")]).unwrap();
+    /// assert_eq!(rule.apply("Alan Turing"), Some("Turing".to_string()));
+    /// ```
+    pub fn infer(examples: &[(&str, &str)]) -> Option<TextRule> {
+        let (first_source, first_output) = *examples.first()?;
+        let fits = |rule: &TextRule| {
+            examples
+                .iter()
+                .all(|(source, output)| rule.apply(source).as_deref() == Some(*output))
+        };
+
+        for delimiter in DELIMITERS {
+            let Some(index) = first_source
+                .split(delimiter)
+                .position(|part| part == first_output)
+            else {
+                continue;
+            };
+            let rule = TextRule::Split { delimiter, index };
+            // a source without the delimiter "splits" into itself, which says nothing
+            if first_source.contains(delimiter) && fits(&rule) {
+                return Some(rule);
+            }
+        }
+
+        if first_output.is_empty() {
+            return None;
+        }
+        let start = first_source.find(first_output)?;
+        let rule = TextRule::Strip {
+            prefix: first_source[..start].to_string(),
+            suffix: first_source[start + first_output.len()..].to_string(),
+        };
+        fits(&rule).then_some(rule)
+    }
+
+    /// The output for `source`, None if the rule does not apply to it
+    pub fn apply(&self, source: &str) -> Option<String> {
+        match self {
+            TextRule::Split { delimiter, index } => {
+                if !source.contains(*delimiter) {
+                    return None;
+                }
+                source.split(*delimiter).nth(*index).map(str::to_string)
+            }
+            TextRule::Strip { prefix, suffix } => source
+                .strip_prefix(prefix.as_str())?
+                .strip_suffix(suffix.as_str())
+                .map(str::to_string),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_split() {
+        let rule = TextRule::infer(&[("Ada Lovelace", "Ada"), ("Grace Brewster Hopper", "Grace")])
+            .unwrap();
+        assert_eq!(
+            rule,
+            TextRule::Split {
+                delimiter: ' ',
+                index: 0
+            }
+        );
+        assert_eq!(rule.apply("Alan Turing"), Some("Alan".to_string()));
+        assert_eq!(rule.apply("Turing"), None);
+
+        let rule = TextRule::infer(&[("ada@example.com", "example.com")]).unwrap();
+        assert_eq!(
+            rule,
+            TextRule::Split {
+                delimiter: '@',
+                index: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_infer_strip() {
+        let rule = TextRule::infer(&[("ID-042;", "042"), ("ID-7;", "7")]).unwrap();
+        assert_eq!(
+            rule,
+            TextRule::Strip {
+                prefix: "ID-".to_string(),
+                suffix: ";".to_string()
+            }
+        );
+        assert_eq!(rule.apply("ID-100;"), Some("100".to_string()));
+        assert_eq!(rule.apply("100"), None);
+    }
+
+    #[test]
+    fn test_infer_nothing_fits() {
+        assert_eq!(TextRule::infer(&[]), None);
+        assert_eq!(TextRule::infer(&[("Ada Lovelace", "Lady Byron")]), None);
+        // each example alone fits a rule, but not the same one
+        assert_eq!(
+            TextRule::infer(&[("Ada Lovelace", "Ada"), ("Alan Turing", "Turing")]),
+            None
+        );
+    }
+}
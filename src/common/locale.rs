@@ -0,0 +1,88 @@
+//! Locale settings that only affect how numbers are shown to the user.
+//!
+//! Values are always stored and exported as plain `f64`s; the locale is applied at the very
+//! last step, when a number is turned into text for the grid or the terminal.
+
+use serde::{Deserialize, Serialize};
+
+/// The number formatting convention used when displaying cell values.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NumberLocale {
+    /// `1234.5` - period as the decimal separator, no grouping.
+    #[default]
+    Standard,
+    /// `1.234,5` - comma as the decimal separator, period between thousands.
+    European,
+}
+
+impl NumberLocale {
+    pub fn decimal_separator(&self) -> char {
+        match self {
+            NumberLocale::Standard => '.',
+            NumberLocale::European => ',',
+        }
+    }
+
+    pub fn thousands_separator(&self) -> Option<char> {
+        match self {
+            NumberLocale::Standard => None,
+            NumberLocale::European => Some('.'),
+        }
+    }
+
+    /// Formats a number the same way as `format!("{}", num)` but with this locale's separators
+    pub fn format_number(&self, num: f64) -> String {
+        let plain = format!("{}", num);
+        if !num.is_finite() {
+            return plain;
+        }
+
+        let (sign, unsigned) = match plain.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", plain.as_str()),
+        };
+        let (int_part, frac_part) = match unsigned.split_once('.') {
+            Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+            None => (unsigned, None),
+        };
+
+        let mut result = String::from(sign);
+        match self.thousands_separator() {
+            Some(sep) => {
+                for (i, digit) in int_part.chars().enumerate() {
+                    if i > 0 && (int_part.len() - i) % 3 == 0 {
+                        result.push(sep);
+                    }
+                    result.push(digit);
+                }
+            }
+            None => result.push_str(int_part),
+        }
+        if let Some(frac_part) = frac_part {
+            result.push(self.decimal_separator());
+            result.push_str(frac_part);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_number_locales() {
+        assert_eq!(NumberLocale::Standard.format_number(1234.5), "1234.5");
+        assert_eq!(NumberLocale::European.format_number(1234.5), "1.234,5");
+    }
+
+    #[test]
+    fn test_format_number_grouping() {
+        let eu = NumberLocale::European;
+        assert_eq!(eu.format_number(0.25), "0,25");
+        assert_eq!(eu.format_number(999.0), "999");
+        assert_eq!(eu.format_number(-1234567.0), "-1.234.567");
+        assert_eq!(eu.format_number(100000.125), "100.000,125");
+        assert_eq!(eu.format_number(f64::NAN), "NaN");
+    }
+}
@@ -1,4 +1,8 @@
 pub mod cell_data;
 pub mod cell_value;
 pub mod expression;
+pub mod flash_fill;
+pub mod locale;
+pub mod number;
 pub mod structs;
+pub mod validation;
@@ -0,0 +1,242 @@
+//! Lenient parsing of numbers typed or pasted in by the user.
+//!
+//! Numbers are often copied from documents that decorate them, like `$1,234.50` or `15%`.
+//! Those decorations are stripped before parsing so the cell still holds a number.
+//! Fractions like `3/4` and `1 1/2` are read as their decimal value, and accounting
+//! negatives like `(1,234)` as negative numbers.
+//!
+//! `OrderedF64` gives numbers the total order that sorting and grouping values need.
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+/// Currency symbols accepted in front of a number.
+const CURRENCY_SYMBOLS: [char; 5] = ['$', '€', '£', '¥', '₹'];
+
+/// Parses a number, also accepting a leading currency symbol, `,` between groups of three
+/// integer digits and a trailing `%`, which divides the value by 100. A fraction, with or
+/// without a whole part, is also accepted, and so is a number in parentheses, which is
+/// negative as in accounting.
+///
+/// Returns `None` if the input is not a number even with those stripped, including when the
+/// `,` are misplaced (`1,23`), so that such input can be kept as text.
+///
+/// # Examples
+/// ```rust
+/// use embedded::common::number::parse_number;
+/// assert_eq!(parse_number("$1,234.50"), Some(1234.5));
+/// assert_eq!(parse_number("15%"), Some(0.15));
+/// assert_eq!(parse_number("1,23"), None);
+/// assert_eq!(parse_number("1 1/2"), Some(1.5));
+/// assert_eq!(parse_number("(1,234)"), Some(-1234.0));
+/// ```
+pub fn parse_number(input: &str) -> Option<f64> {
+    let input = input.trim();
+    if let Ok(num) = input.parse::<f64>() {
+        return Some(num);
+    }
+    if let Some(num) = parse_fraction(input) {
+        return Some(num);
+    }
+    if let Some(inner) = input
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        // the parentheses already make it negative, and only one pair is accepted
+        if inner.trim_start().starts_with(['-', '(']) {
+            return None;
+        }
+        return parse_number(inner).map(|num| -num);
+    }
+
+    let (negative, rest) = match input.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, input),
+    };
+    let rest = rest
+        .strip_prefix(|c| CURRENCY_SYMBOLS.contains(&c))
+        .unwrap_or(rest);
+    let (percent, rest) = match rest.strip_suffix('%') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+
+    // only digits, a `.` and `,` are left to parse, signs and exponents must come first
+    if !rest.starts_with(|c: char| c.is_ascii_digit() || c == '.') || !has_valid_grouping(rest) {
+        return None;
+    }
+    let digits: String = rest.chars().filter(|&c| c != ',').collect();
+    let mut num = digits.parse::<f64>().ok()?;
+    if percent {
+        num /= 100.0;
+    }
+    if negative {
+        num = -num;
+    }
+    Some(num)
+}
+
+/// Parses `numerator/denominator` with an optional whole part and sign in front, as in
+/// `-2 3/4`. All parts are plain digits and the denominator is not zero
+fn parse_fraction(input: &str) -> Option<f64> {
+    let digits = |part: &str| {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        part.parse::<f64>().ok()
+    };
+    let (negative, rest) = match input.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, input),
+    };
+    let (whole, fraction) = match rest.split_once(' ') {
+        Some((whole, fraction)) => (digits(whole)?, fraction.trim_start()),
+        None => (0.0, rest),
+    };
+    let (numerator, denominator) = fraction.split_once('/')?;
+    let (numerator, denominator) = (digits(numerator)?, digits(denominator)?);
+    if denominator == 0.0 {
+        return None;
+    }
+    let num = whole + numerator / denominator;
+    Some(if negative { -num } else { num })
+}
+
+/// Checks that every `,` separates groups of exactly three integer digits
+fn has_valid_grouping(num: &str) -> bool {
+    let (int_part, frac_part) = num.split_once('.').unwrap_or((num, ""));
+    if frac_part.contains(',') {
+        return false;
+    }
+    let mut groups = int_part.split(',');
+    let first = groups.next().unwrap_or_default();
+    if int_part.contains(',') && !(1..=3).contains(&first.len()) {
+        return false;
+    }
+    groups.all(|group| group.len() == 3)
+}
+
+/// A number that can be sorted, compared for equality and hashed, which `f64` cannot.
+///
+/// Numbers keep their usual order, with `-0.0` equal to `0.0`. Every NaN is equal to every
+/// other and greater than any number, so NaNs sort last.
+#[derive(Debug, Clone, Copy)]
+pub struct OrderedF64(pub f64);
+
+impl Ord for OrderedF64 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.0.is_nan(), other.0.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            // neither is NaN, so they are ordered
+            (false, false) => self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal),
+        }
+    }
+}
+
+impl PartialOrd for OrderedF64 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for OrderedF64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OrderedF64 {}
+
+impl Hash for OrderedF64 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // equal values must hash alike: one NaN for all, and `+ 0.0` turns -0.0 into 0.0
+        let bits = if self.0.is_nan() {
+            f64::NAN.to_bits()
+        } else {
+            (self.0 + 0.0).to_bits()
+        };
+        bits.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plain_numbers() {
+        assert_eq!(parse_number("42"), Some(42.0));
+        assert_eq!(parse_number(" -3.5 "), Some(-3.5));
+        assert_eq!(parse_number("1e3"), Some(1000.0));
+        assert_eq!(parse_number("1.5E-2"), Some(0.015));
+        assert_eq!(parse_number("2E-4"), Some(0.0002));
+    }
+
+    #[test]
+    fn test_parse_decorated_numbers() {
+        assert_eq!(parse_number("$1,000"), Some(1000.0));
+        assert_eq!(parse_number("1,234.5"), Some(1234.5));
+        assert_eq!(parse_number("$1,234.50"), Some(1234.5));
+        assert_eq!(parse_number("-€12"), Some(-12.0));
+        assert_eq!(parse_number("1,234,567"), Some(1234567.0));
+        assert_eq!(parse_number("50%"), Some(0.5));
+        assert_eq!(parse_number("£.5"), Some(0.5));
+    }
+
+    #[test]
+    fn test_parse_fractions() {
+        assert_eq!(parse_number("3/4"), Some(0.75));
+        assert_eq!(parse_number("1 1/2"), Some(1.5));
+        assert_eq!(parse_number(" -2 1/4 "), Some(-2.25));
+        assert_eq!(parse_number("7/2"), Some(3.5));
+        for text in [
+            "1/0", "A1/B1", "1/", "/2", "1 /2", "1.5/2", "1 2", "1/2/3", "- 1/2",
+        ] {
+            assert_eq!(parse_number(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_accounting_negatives() {
+        assert_eq!(parse_number("(100)"), Some(-100.0));
+        assert_eq!(parse_number("(1,234.50)"), Some(-1234.5));
+        assert_eq!(parse_number(" ($12) "), Some(-12.0));
+        assert_eq!(parse_number("(15%)"), Some(-0.15));
+        for text in ["(-100)", "((100))", "(100", "100)", "()", "(A1)", "(1+2)"] {
+            assert_eq!(parse_number(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_text() {
+        for text in [
+            "", "$", "%", "abc", "1,23", "1234,567", ",123", "1,,000", "1.5,0", "$-5", "5$",
+        ] {
+            assert_eq!(parse_number(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_ordered_f64() {
+        let mut nums: Vec<OrderedF64> = [3.0, f64::NAN, -1.0, f64::INFINITY, 0.0]
+            .into_iter()
+            .map(OrderedF64)
+            .collect();
+        nums.sort();
+        let sorted: Vec<f64> = nums.iter().map(|num| num.0).collect();
+        assert_eq!(sorted[..4], [-1.0, 0.0, 3.0, f64::INFINITY]);
+        assert!(sorted[4].is_nan());
+
+        assert_eq!(OrderedF64(-0.0), OrderedF64(0.0));
+        assert_eq!(OrderedF64(f64::NAN), OrderedF64(-f64::NAN));
+        assert!(OrderedF64(f64::NAN) > OrderedF64(f64::INFINITY));
+
+        let set: std::collections::HashSet<OrderedF64> = [0.0, -0.0, f64::NAN, f64::NAN, 1.0]
+            .into_iter()
+            .map(OrderedF64)
+            .collect();
+        assert_eq!(set.len(), 3);
+    }
+}
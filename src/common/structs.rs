@@ -65,6 +65,31 @@ impl AbsCell {
             col: self.col - origin.col,
         }
     }
+
+    /// Parses a range like "A1:C3" into its `(top_left, bottom_right)` corners.
+    /// Returns an error if either side is not a valid cell reference or if the range is
+    /// reversed, like "C3:A1" or "A3:C1".
+    pub fn range_from_str(s: &str) -> Result<(AbsCell, AbsCell), String> {
+        let (start, end) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Missing ':' in range: {}", s))?;
+        let parse_corner = |corner: &str| {
+            let cell: AbsCell = corner.trim().parse()?;
+            if cell.row < 0 || cell.col < 0 {
+                return Err(format!("Invalid cell in range: {}", corner.trim()));
+            }
+            Ok::<_, String>(cell)
+        };
+        let (top_left, bottom_right) = (parse_corner(start)?, parse_corner(end)?);
+
+        if top_left.row > bottom_right.row || top_left.col > bottom_right.col {
+            return Err(format!(
+                "Range is reversed: {} comes after {}",
+                top_left, bottom_right
+            ));
+        }
+        Ok((top_left, bottom_right))
+    }
 }
 
 impl Display for AbsCell {
@@ -102,7 +127,10 @@ impl FromStr for AbsCell {
                 if !upper_c.is_ascii_uppercase() {
                     return Err(format!("Invalid column letter: {}", c));
                 }
-                col = col * 26 + ((upper_c as u8 - b'A') as i16 + 1);
+                col = col
+                    .checked_mul(26)
+                    .and_then(|col| col.checked_add((upper_c as u8 - b'A') as i16 + 1))
+                    .ok_or_else(|| format!("Column is too large: {}", s))?;
             } else if c.is_ascii_digit() {
                 row_part = s[i..].to_string();
                 break;
@@ -136,3 +164,73 @@ impl RelCell {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_bounds() {
+        for (label, cell) in [
+            ("ZZ1", AbsCell::new(0, 701)),
+            ("AAA1", AbsCell::new(0, 702)),
+            ("ZZY999", AbsCell::new(998, 18276)),
+            ("ZZZ999", AbsCell::new(998, 18277)),
+        ] {
+            assert_eq!(cell.to_string(), label);
+            assert_eq!(label.parse::<AbsCell>(), Ok(cell));
+        }
+    }
+
+    #[test]
+    fn test_label_too_long() {
+        for label in ["AAAAAA1", "ZZZZZZZZZZ1", "A99999"] {
+            assert!(label.parse::<AbsCell>().is_err(), "{}", label);
+        }
+    }
+
+    #[test]
+    fn test_range_from_str() {
+        assert_eq!(
+            AbsCell::range_from_str("A1:C3"),
+            Ok((AbsCell::new(0, 0), AbsCell::new(2, 2)))
+        );
+        assert_eq!(
+            AbsCell::range_from_str(" b2 : B2 "),
+            Ok((AbsCell::new(1, 1), AbsCell::new(1, 1)))
+        );
+    }
+
+    #[test]
+    fn test_range_from_str_reversed() {
+        for reversed in ["C3:A1", "A3:C1", "C1:A3"] {
+            let err = AbsCell::range_from_str(reversed).unwrap_err();
+            assert!(err.contains("reversed"), "{}: {}", reversed, err);
+        }
+    }
+
+    #[test]
+    fn test_range_from_str_malformed() {
+        for malformed in [
+            "",
+            "A1",
+            "A1:",
+            ":C3",
+            "A1-C3",
+            "A1:C3:D4",
+            "A0:B2",
+            "1:B2",
+            "A1:B$2",
+            "AAAAAA1:B2",
+            "A1:ZZZZZZ9",
+            "AAAAAA1:B2",
+            "A1:ZZZZZZ9",
+        ] {
+            assert!(
+                AbsCell::range_from_str(malformed).is_err(),
+                "{:?}",
+                malformed
+            );
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Data validation rules that restrict what may be entered into a cell, and column types that
+//! restrict what may be entered into a whole column.
+//!
+//! Rules only check values typed or pasted in by the user; formula results are not validated.
+
+use crate::common::cell_value::CellValue;
+use crate::common::number::parse_number;
+use crate::error_display::ErrorLabel;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// A restriction on the values a cell accepts.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ValidationRule {
+    /// A number within `min..=max`. Use infinities for an open end.
+    NumberBetween { min: f64, max: f64 },
+}
+
+/// The kind of value every cell of a column holds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColumnType {
+    Number,
+    Text,
+}
+
+/// Why a value was rejected by a `ValidationRule` or a `ColumnType`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ValidationError {
+    NotANumber,
+    OutOfRange { min: f64, max: f64 },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::NotANumber => write!(f, "{}", self.label()),
+            ValidationError::OutOfRange { min, max } => {
+                write!(f, "{}: must be between {} and {}", self.label(), min, max)
+            }
+        }
+    }
+}
+
+impl ValidationRule {
+    /// Checks a value against the rule. Clearing a cell is always allowed
+    pub fn check(&self, value: &CellValue) -> Result<(), ValidationError> {
+        match (self, value) {
+            (_, CellValue::Empty) => Ok(()),
+            (ValidationRule::NumberBetween { min, max }, CellValue::Number(num)) => {
+                if (*min..=*max).contains(num) {
+                    Ok(())
+                } else {
+                    Err(ValidationError::OutOfRange {
+                        min: *min,
+                        max: *max,
+                    })
+                }
+            }
+            (ValidationRule::NumberBetween { .. }, CellValue::String(_)) => {
+                Err(ValidationError::NotANumber)
+            }
+        }
+    }
+}
+
+impl ColumnType {
+    /// The name of the type as shown to the user
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Number => "number",
+            ColumnType::Text => "text",
+        }
+    }
+
+    /// Converts a value entered into a column of this type, where nothing is lost: text that
+    /// reads as a number in a number column, and any number in a text column.
+    /// Clearing a cell is always allowed
+    ///
+    /// returns: the converted value, or None if the value already has the type
+    pub fn coerce(&self, value: &CellValue) -> Result<Option<CellValue>, ValidationError> {
+        match (self, value) {
+            (_, CellValue::Empty)
+            | (ColumnType::Number, CellValue::Number(_))
+            | (ColumnType::Text, CellValue::String(_)) => Ok(None),
+            (ColumnType::Number, CellValue::String(text)) => parse_number(text)
+                .map(|num| Some(CellValue::Number(num)))
+                .ok_or(ValidationError::NotANumber),
+            (ColumnType::Text, CellValue::Number(num)) => {
+                Ok(Some(CellValue::String(num.to_string())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_between() {
+        let rule = ValidationRule::NumberBetween {
+            min: 0.0,
+            max: 100.0,
+        };
+        assert_eq!(rule.check(&CellValue::Number(0.0)), Ok(()));
+        assert_eq!(rule.check(&CellValue::Number(100.0)), Ok(()));
+        assert_eq!(rule.check(&CellValue::Empty), Ok(()));
+
+        let err = rule.check(&CellValue::Number(101.0)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Value out of range: must be between 0 and 100"
+        );
+        let err = rule.check(&CellValue::String("ten".to_string()));
+        assert_eq!(err, Err(ValidationError::NotANumber));
+        assert_eq!(
+            rule.check(&CellValue::Number(f64::NAN)),
+            Err(ValidationError::OutOfRange {
+                min: 0.0,
+                max: 100.0
+            })
+        );
+    }
+
+    #[test]
+    fn test_column_type_coerce() {
+        let number = ColumnType::Number;
+        assert_eq!(number.coerce(&CellValue::Number(3.0)), Ok(None));
+        assert_eq!(
+            number.coerce(&CellValue::String(" 1,250 ".to_string())),
+            Ok(Some(CellValue::Number(1250.0)))
+        );
+        assert_eq!(
+            number.coerce(&CellValue::String("n/a".to_string())),
+            Err(ValidationError::NotANumber)
+        );
+        assert_eq!(number.coerce(&CellValue::Empty), Ok(None));
+
+        assert_eq!(
+            ColumnType::Text.coerce(&CellValue::Number(2.5)),
+            Ok(Some(CellValue::String("2.5".to_string())))
+        );
+    }
+}
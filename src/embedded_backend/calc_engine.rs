@@ -1,5 +1,5 @@
 use crate::common::cell_value::{CellError, CellValue};
-use crate::common::expression::{Expression, Operator, RangeFunction};
+use crate::common::expression::{CoercionFunction, Expression, Operator, RangeFunction};
 use crate::common::structs::AbsCell;
 use crate::embedded_backend::table::Storage;
 use std::thread::sleep;
@@ -7,62 +7,304 @@ use std::time::Duration;
 
 mod functions;
 
-/// Evaluates the expression for a given cell.
-pub fn evaluate(storage: &Storage, cell: AbsCell, expr: &Expression) -> Result<f64, CellError> {
+/// Everything `evaluate` needs besides the expression itself.
+pub struct EvalContext<'a> {
+    pub storage: &'a Storage,
+    /// The cell the expression belongs to, relative references are resolved against it
+    pub cell: AbsCell,
+    /// Whether a reference to an empty cell reads as `0`. Otherwise it is a `DependsOnNonNumeric`
+    /// error, like a reference to text
+    pub empty_is_zero: bool,
+}
+
+/// A pending step of the post-order walk done by `evaluate`.
+enum Task<'a> {
+    /// Evaluate this sub-expression and push its value.
+    Visit(&'a Expression),
+    /// Pop the right then the left operand and push the result of the operator.
+    Apply(Operator),
+    /// Pop the duration, sleep for it and push it back. Holds the length of one unit of the
+    /// duration in seconds.
+    Sleep(f64),
+}
+
+/// Evaluates a whole formula for a given cell. Unlike `evaluate` this handles the functions
+/// with a text result, which are only valid at the top of a formula.
+pub fn evaluate_value(ctx: &EvalContext, expr: &Expression) -> Result<CellValue, CellError> {
     match expr {
-        Expression::Number(x) => Ok(*x),
-
-        Expression::Cell(c) => {
-            let x = storage.get_value(c.to_abs(cell));
-            match x {
-                Ok(val) => match val {
-                    CellValue::Number(n) => Ok(*n),
-                    CellValue::Empty => Ok(0.0),
-                    CellValue::String(_) => Err(CellError::DependsOnNonNumeric),
-                },
-                Err(e) => Err(*e),
-            }
+        Expression::Location(function, range) => {
+            functions::location(ctx.storage, ctx.cell, *function, range)
         }
-        Expression::BinaryOp(exp1, op, exp2) => match op {
-            Operator::Add => {
-                let x = evaluate(storage, cell, exp1)?;
-                let y = evaluate(storage, cell, exp2)?;
-                Ok(x + y)
-            }
-            Operator::Subtract => {
-                let x = evaluate(storage, cell, exp1)?;
-                let y = evaluate(storage, cell, exp2)?;
-                Ok(x - y)
-            }
-            Operator::Multiply => {
-                let x = evaluate(storage, cell, exp1)?;
-                let y = evaluate(storage, cell, exp2)?;
-                Ok(x * y)
+        Expression::Coerce(CoercionFunction::T, arg) => match operand_value(ctx, arg)? {
+            CellValue::String(text) => Ok(CellValue::String(text)),
+            _ => Ok(CellValue::String(String::new())),
+        },
+        expr => evaluate(ctx, expr).map(CellValue::Number),
+    }
+}
+
+/// The value of an argument as it is, where `evaluate_value` would only accept a formula.
+/// Text and references keep their type, everything else is evaluated as usual
+fn operand_value(ctx: &EvalContext, arg: &Expression) -> Result<CellValue, CellError> {
+    match arg {
+        Expression::Text(text) => Ok(CellValue::String(text.clone())),
+        Expression::Cell(c) => ctx.storage.get_value(c.to_abs(ctx.cell)).clone(),
+        arg => evaluate_value(ctx, arg),
+    }
+}
+
+/// Evaluates the expression for a given cell.
+///
+/// The expression tree is walked with an explicit work stack instead of recursion, so
+/// arbitrarily deep formulas cannot overflow the native stack. Operands are still evaluated
+/// left to right and the first error encountered is returned.
+pub fn evaluate(ctx: &EvalContext, expr: &Expression) -> Result<f64, CellError> {
+    let (storage, cell) = (ctx.storage, ctx.cell);
+    let mut tasks = vec![Task::Visit(expr)];
+    let mut values: Vec<f64> = Vec::new();
+
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Visit(expr) => match expr {
+                Expression::Number(x) => values.push(*x),
+
+                Expression::Cell(c) => {
+                    let x = storage.get_value(c.to_abs(cell));
+                    let x = match x {
+                        Ok(val) => match val {
+                            CellValue::Number(n) => *n,
+                            CellValue::Empty if ctx.empty_is_zero => 0.0,
+                            CellValue::Empty => return Err(CellError::DependsOnNonNumeric),
+                            CellValue::String(_) => return Err(CellError::DependsOnNonNumeric),
+                        },
+                        Err(e) => return Err(*e),
+                    };
+                    values.push(x);
+                }
+                Expression::BinaryOp(exp1, op, exp2) => {
+                    tasks.push(Task::Apply(*op));
+                    tasks.push(Task::Visit(exp2));
+                    tasks.push(Task::Visit(exp1));
+                }
+
+                Expression::RangeFunction(f, range) => {
+                    let x = match f {
+                        RangeFunction::Min => functions::min(storage, cell, range),
+                        RangeFunction::Max => functions::max(storage, cell, range),
+                        RangeFunction::Avg => functions::average(storage, cell, range),
+                        RangeFunction::Sum => functions::sum(storage, cell, range),
+                        RangeFunction::Stdev => functions::stdev(storage, cell, range),
+                    }?;
+                    values.push(x);
+                }
+                Expression::SumProduct(first, second) => {
+                    values.push(functions::sumproduct(storage, cell, first, second)?);
+                }
+                Expression::Sleep(exp) => {
+                    tasks.push(Task::Sleep(1.0));
+                    tasks.push(Task::Visit(exp));
+                }
+                Expression::SleepMs(exp) => {
+                    tasks.push(Task::Sleep(0.001));
+                    tasks.push(Task::Visit(exp));
+                }
+                Expression::Coerce(CoercionFunction::N, arg) => {
+                    let x = match operand_value(ctx, arg)? {
+                        CellValue::Number(x) => x,
+                        _ => 0.0,
+                    };
+                    values.push(x);
+                }
+                // a cell label or text is not a number
+                Expression::Location(_, _)
+                | Expression::Text(_)
+                | Expression::Coerce(CoercionFunction::T, _) => {
+                    return Err(CellError::DependsOnNonNumeric);
+                }
+            },
+            Task::Apply(op) => {
+                let y = values.pop().expect("right operand evaluated");
+                let x = values.pop().expect("left operand evaluated");
+                let res = match op {
+                    Operator::Add => x + y,
+                    Operator::Subtract => x - y,
+                    Operator::Multiply => x * y,
+                    Operator::Divide => {
+                        if y == 0.0 {
+                            return Err(CellError::DivideByZero);
+                        }
+                        x / y
+                    }
+                    Operator::Equal => (x == y) as u8 as f64,
+                    Operator::NotEqual => (x != y) as u8 as f64,
+                    Operator::Less => (x < y) as u8 as f64,
+                    Operator::LessEqual => (x <= y) as u8 as f64,
+                    Operator::Greater => (x > y) as u8 as f64,
+                    Operator::GreaterEqual => (x >= y) as u8 as f64,
+                };
+                values.push(res);
             }
-            Operator::Divide => {
-                let x = evaluate(storage, cell, exp1)?;
-                let y = evaluate(storage, cell, exp2)?;
-                if y == 0.0 {
-                    Err(CellError::DivideByZero)
-                } else {
-                    Ok(x / y)
+            Task::Sleep(unit) => {
+                let x = *values.last().expect("sleep duration evaluated");
+                if x > 0.0 {
+                    let duration = Duration::try_from_secs_f64(x * unit)
+                        .map_err(|_| CellError::NumberTooLarge)?;
+                    sleep(duration);
                 }
             }
-        },
+        }
+    }
 
-        Expression::RangeFunction(f, range) => match f {
-            RangeFunction::Min => functions::min(storage, cell, range),
-            RangeFunction::Max => functions::max(storage, cell, range),
-            RangeFunction::Avg => functions::average(storage, cell, range),
-            RangeFunction::Sum => functions::sum(storage, cell, range),
-            RangeFunction::Stdev => functions::stdev(storage, cell, range),
-        },
-        Expression::Sleep(exp) => {
-            let x = evaluate(storage, cell, exp)?;
-            if x > 0.0 {
-                sleep(Duration::from_secs_f64(x));
-            }
-            Ok(x)
+    Ok(values.pop().expect("expression produces a value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::structs::RelCell;
+
+    fn ctx(storage: &Storage, cell: AbsCell) -> EvalContext<'_> {
+        EvalContext {
+            storage,
+            cell,
+            empty_is_zero: true,
+        }
+    }
+
+    #[test]
+    fn test_evaluate_order_and_errors() {
+        let mut storage = Storage::new(10, 10);
+        storage.set_value(AbsCell::new(0, 0), CellValue::Number(8.0));
+        let cell = AbsCell::new(5, 5);
+        let a1 = || Box::new(Expression::Cell(RelCell::new(-5, -5)));
+        let num = |x| Box::new(Expression::Number(x));
+
+        // (A1 - 2) / 3
+        let expr = Expression::BinaryOp(
+            Box::new(Expression::BinaryOp(a1(), Operator::Subtract, num(2.0))),
+            Operator::Divide,
+            num(3.0),
+        );
+        assert_eq!(evaluate(&ctx(&storage, cell), &expr), Ok(2.0));
+
+        let expr = Expression::BinaryOp(a1(), Operator::Divide, num(0.0));
+        assert_eq!(
+            evaluate(&ctx(&storage, cell), &expr),
+            Err(CellError::DivideByZero)
+        );
+
+        // the left operand's error wins over a later division by zero
+        storage.set_value(AbsCell::new(0, 0), CellValue::String("x".to_string()));
+        let expr = Expression::BinaryOp(
+            a1(),
+            Operator::Add,
+            Box::new(Expression::BinaryOp(num(1.0), Operator::Divide, num(0.0))),
+        );
+        assert_eq!(
+            evaluate(&ctx(&storage, cell), &expr),
+            Err(CellError::DependsOnNonNumeric)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_deep_expression() {
+        let storage = Storage::new(10, 10);
+        let depth = 50_000;
+
+        let mut expr = Expression::Number(0.0);
+        for _ in 0..depth {
+            expr = Expression::BinaryOp(
+                Box::new(expr),
+                Operator::Add,
+                Box::new(Expression::Number(1.0)),
+            );
+        }
+        assert_eq!(
+            evaluate(&ctx(&storage, AbsCell::new(0, 0)), &expr),
+            Ok(depth as f64)
+        );
+        // dropping the tree would recurse just as deep, so take it apart one level at a time
+        while let Expression::BinaryOp(left, _, _) = expr {
+            expr = *left;
         }
     }
+
+    #[test]
+    fn test_sleep_ms() {
+        let storage = Storage::new(10, 10);
+        let context = ctx(&storage, AbsCell::new(0, 0));
+        let expr = Expression::SleepMs(Box::new(Expression::Number(10.0)));
+
+        let start = std::time::Instant::now();
+        assert_eq!(evaluate(&context, &expr), Ok(10.0));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(10));
+        assert!(elapsed < Duration::from_secs(1), "slept for {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_sleep_too_long() {
+        let storage = Storage::new(10, 10);
+        let context = ctx(&storage, AbsCell::new(0, 0));
+        for seconds in [1e300, f64::INFINITY] {
+            let expr = Expression::Sleep(Box::new(Expression::Number(seconds)));
+            assert_eq!(evaluate(&context, &expr), Err(CellError::NumberTooLarge));
+        }
+        // overflows only once turned into seconds
+        let expr = Expression::SleepMs(Box::new(Expression::Number(f64::MAX)));
+        assert_eq!(evaluate(&context, &expr), Err(CellError::NumberTooLarge));
+    }
+
+    #[test]
+    fn test_location_functions() {
+        use crate::common::expression::{CellRange, LocationFunction};
+
+        let mut storage = Storage::new(10, 10);
+        for (row, value) in [(0, 3.0), (1, -2.0), (2, 9.0), (4, -2.0)] {
+            storage.set_value(AbsCell::new(row, 0), CellValue::Number(value));
+        }
+        let cell = AbsCell::new(0, 1);
+        // A1:A6, seen from B1
+        let range = CellRange {
+            top_left: RelCell::new(0, -1),
+            bottom_right: RelCell::new(5, -1),
+        };
+        let argmax = Expression::Location(LocationFunction::ArgMax, range.clone());
+        let argmin = Expression::Location(LocationFunction::ArgMin, range);
+        let context = ctx(&storage, cell);
+        assert_eq!(
+            evaluate_value(&context, &argmax),
+            Ok(CellValue::String("A3".to_string()))
+        );
+        // ties go to the first cell
+        assert_eq!(
+            evaluate_value(&context, &argmin),
+            Ok(CellValue::String("A2".to_string()))
+        );
+        assert_eq!(
+            evaluate(&context, &argmax),
+            Err(CellError::DependsOnNonNumeric)
+        );
+    }
+
+    #[test]
+    fn test_empty_cell_setting() {
+        let storage = Storage::new(10, 10);
+        let cell = AbsCell::new(0, 1);
+        // A1 + 1, evaluated in B1 with A1 empty
+        let expr = Expression::BinaryOp(
+            Box::new(Expression::Cell(RelCell::new(0, -1))),
+            Operator::Add,
+            Box::new(Expression::Number(1.0)),
+        );
+
+        let mut context = ctx(&storage, cell);
+        assert_eq!(evaluate(&context, &expr), Ok(1.0));
+
+        context.empty_is_zero = false;
+        assert_eq!(
+            evaluate(&context, &expr),
+            Err(CellError::DependsOnNonNumeric)
+        );
+    }
 }
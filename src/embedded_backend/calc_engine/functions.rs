@@ -1,5 +1,17 @@
+//! The range functions of formulas.
+//!
+//! Empty cells in a range are skipped, and a range holding no numbers at all gives:
+//! * `SUM` and `SUMPRODUCT`: `0`, as adding nothing up does
+//! * `AVG` and `STDEV`: `DivideByZero`, as they divide by the count of numbers
+//! * `MIN` and `MAX`: `EmptyRange`, as any number given instead could be mistaken for a real one
+//! * `ARGMIN` and `ARGMAX`: an empty value, as there is no cell to name
+//!
+//! `MIN`, `MAX`, `ARGMIN` and `ARGMAX` compare numbers as `OrderedF64` orders them, so a NaN
+//! is larger than any number wherever it is in the range.
+
 use crate::common::cell_value::{CellError, CellValue};
-use crate::common::expression::CellRange;
+use crate::common::expression::{CellRange, LocationFunction};
+use crate::common::number::OrderedF64;
 use crate::common::structs::AbsCell;
 use crate::embedded_backend::table::Storage;
 
@@ -7,14 +19,13 @@ pub fn max(storage: &Storage, cell: AbsCell, range: &CellRange) -> Result<f64, C
     let top_left = range.top_left.to_abs(cell);
     let bottom_right = range.bottom_right.to_abs(cell);
 
-    let mut max_value = f64::MIN;
-    let mut is_range_empty = true;
+    let mut max_value: Option<OrderedF64> = None;
     for (_, val) in storage.get_value_range_sparse(top_left, bottom_right) {
         match val {
             Ok(val) => match val {
                 CellValue::Number(x) => {
-                    max_value = max_value.max(*x);
-                    is_range_empty = false;
+                    let x = OrderedF64(*x);
+                    max_value = Some(max_value.map_or(x, |current| current.max(x)));
                 }
                 CellValue::String(_) => return Err(CellError::DependsOnNonNumeric),
                 CellValue::Empty => {}
@@ -23,25 +34,20 @@ pub fn max(storage: &Storage, cell: AbsCell, range: &CellRange) -> Result<f64, C
         }
     }
 
-    if is_range_empty {
-        return Ok(0.0);
-    }
-
-    Ok(max_value)
+    max_value.map(|x| x.0).ok_or(CellError::EmptyRange)
 }
 
 pub fn min(storage: &Storage, cell: AbsCell, range: &CellRange) -> Result<f64, CellError> {
     let top_left = range.top_left.to_abs(cell);
     let bottom_right = range.bottom_right.to_abs(cell);
 
-    let mut min_value = f64::MAX;
-    let mut is_range_empty = true;
+    let mut min_value: Option<OrderedF64> = None;
     for (_, val) in storage.get_value_range_sparse(top_left, bottom_right) {
         match val {
             Ok(val) => match val {
                 CellValue::Number(x) => {
-                    min_value = min_value.min(*x);
-                    is_range_empty = false;
+                    let x = OrderedF64(*x);
+                    min_value = Some(min_value.map_or(x, |current| current.min(x)));
                 }
                 CellValue::String(_) => return Err(CellError::DependsOnNonNumeric),
                 CellValue::Empty => {}
@@ -50,10 +56,7 @@ pub fn min(storage: &Storage, cell: AbsCell, range: &CellRange) -> Result<f64, C
         }
     }
 
-    if is_range_empty {
-        return Ok(0.0);
-    }
-    Ok(min_value)
+    min_value.map(|x| x.0).ok_or(CellError::EmptyRange)
 }
 
 pub fn average(storage: &Storage, cell: AbsCell, range: &CellRange) -> Result<f64, CellError> {
@@ -77,7 +80,7 @@ pub fn average(storage: &Storage, cell: AbsCell, range: &CellRange) -> Result<f6
     }
 
     if count == 0 {
-        return Ok(0.0);
+        return Err(CellError::DivideByZero);
     }
 
     Ok(total / count as f64)
@@ -123,7 +126,7 @@ pub fn stdev(storage: &Storage, cell: AbsCell, range: &CellRange) -> Result<f64,
     }
 
     if count == 0 {
-        return Ok(0.0);
+        return Err(CellError::DivideByZero);
     }
 
     let mean = total / count as f64;
@@ -146,3 +149,162 @@ pub fn stdev(storage: &Storage, cell: AbsCell, range: &CellRange) -> Result<f64,
 
     Ok(variance.sqrt())
 }
+
+/// Multiplies the corresponding cells of two equally shaped ranges and sums the products.
+/// Empty cells count as zero.
+pub fn sumproduct(
+    storage: &Storage,
+    cell: AbsCell,
+    first: &CellRange,
+    second: &CellRange,
+) -> Result<f64, CellError> {
+    if first.shape() != second.shape() {
+        return Err(CellError::ShapeMismatch);
+    }
+
+    let first_top_left = first.top_left.to_abs(cell);
+    let second_top_left = second.top_left.to_abs(cell);
+    let (rows, cols) = first.shape();
+
+    let numeric = |target: AbsCell| match storage.get_value(target) {
+        Ok(CellValue::Number(x)) => Ok(*x),
+        Ok(CellValue::Empty) => Ok(0.0),
+        Ok(CellValue::String(_)) => Err(CellError::DependsOnNonNumeric),
+        Err(_) => Err(CellError::DependsOnErr),
+    };
+
+    let mut total = 0.0;
+    for row in 0..rows {
+        for col in 0..cols {
+            let x = numeric(AbsCell::new(
+                first_top_left.row + row,
+                first_top_left.col + col,
+            ))?;
+            let y = numeric(AbsCell::new(
+                second_top_left.row + row,
+                second_top_left.col + col,
+            ))?;
+            total += x * y;
+        }
+    }
+
+    Ok(total)
+}
+
+/// The label of the smallest (`ArgMin`) or largest (`ArgMax`) number in the range, the first
+/// one in row-major order on ties. Empty if the range holds no numbers.
+pub fn location(
+    storage: &Storage,
+    cell: AbsCell,
+    function: LocationFunction,
+    range: &CellRange,
+) -> Result<CellValue, CellError> {
+    let top_left = range.top_left.to_abs(cell);
+    let bottom_right = range.bottom_right.to_abs(cell);
+
+    let mut best: Option<(AbsCell, OrderedF64)> = None;
+    for (target, val) in storage.get_value_range_sparse(top_left, bottom_right) {
+        match val {
+            Ok(CellValue::Number(x)) => {
+                let x = OrderedF64(*x);
+                let better = match best {
+                    None => true,
+                    Some((_, best_value)) => match function {
+                        LocationFunction::ArgMin => x < best_value,
+                        LocationFunction::ArgMax => x > best_value,
+                    },
+                };
+                if better {
+                    best = Some((target, x));
+                }
+            }
+            Ok(CellValue::String(_)) => return Err(CellError::DependsOnNonNumeric),
+            Ok(CellValue::Empty) => {}
+            Err(_) => return Err(CellError::DependsOnErr),
+        }
+    }
+
+    Ok(match best {
+        Some((target, _)) => CellValue::String(target.to_string()),
+        None => CellValue::Empty,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::structs::RelCell;
+
+    #[test]
+    fn test_empty_range() {
+        let mut storage = Storage::new(10, 10);
+        // a number outside the range, and only empty cells inside it
+        storage.set_value(AbsCell::new(9, 9), CellValue::Number(5.0));
+        let cell = AbsCell::new(0, 0);
+        let range = CellRange {
+            top_left: RelCell::new(1, 1),
+            bottom_right: RelCell::new(4, 2),
+        };
+
+        assert_eq!(sum(&storage, cell, &range), Ok(0.0));
+        assert_eq!(sumproduct(&storage, cell, &range, &range), Ok(0.0));
+        assert_eq!(
+            average(&storage, cell, &range),
+            Err(CellError::DivideByZero)
+        );
+        assert_eq!(stdev(&storage, cell, &range), Err(CellError::DivideByZero));
+        assert_eq!(min(&storage, cell, &range), Err(CellError::EmptyRange));
+        assert_eq!(max(&storage, cell, &range), Err(CellError::EmptyRange));
+        for function in [LocationFunction::ArgMin, LocationFunction::ArgMax] {
+            assert_eq!(
+                location(&storage, cell, function, &range),
+                Ok(CellValue::Empty)
+            );
+        }
+
+        // a single number, even a zero, is no longer empty
+        storage.set_value(AbsCell::new(2, 2), CellValue::Number(0.0));
+        assert_eq!(min(&storage, cell, &range), Ok(0.0));
+        assert_eq!(max(&storage, cell, &range), Ok(0.0));
+        assert_eq!(average(&storage, cell, &range), Ok(0.0));
+        assert_eq!(stdev(&storage, cell, &range), Ok(0.0));
+    }
+
+    #[test]
+    fn test_nan_and_infinity() {
+        let mut storage = Storage::new(10, 10);
+        let cell = AbsCell::new(0, 0);
+        let column = |col| CellRange {
+            top_left: RelCell::new(0, col),
+            bottom_right: RelCell::new(2, col),
+        };
+        let label = |value: Result<CellValue, CellError>| match value {
+            Ok(CellValue::String(label)) => label,
+            other => panic!("not a label: {:?}", other),
+        };
+        // the same numbers, with the NaN first in column A and in the middle in column B
+        for (row, a, b) in [
+            (0, f64::NAN, 5.0),
+            (1, 5.0, f64::NAN),
+            (2, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ] {
+            storage.set_value(AbsCell::new(row, 0), CellValue::Number(a));
+            storage.set_value(AbsCell::new(row, 1), CellValue::Number(b));
+        }
+
+        for col in [0, 1] {
+            let range = column(col);
+            assert_eq!(min(&storage, cell, &range), Ok(f64::NEG_INFINITY));
+            assert!(max(&storage, cell, &range).unwrap().is_nan());
+            let smallest = location(&storage, cell, LocationFunction::ArgMin, &range);
+            assert_eq!(label(smallest), AbsCell::new(2, col).to_string());
+        }
+        let largest = |col| location(&storage, cell, LocationFunction::ArgMax, &column(col));
+        assert_eq!(label(largest(0)), "A1");
+        assert_eq!(label(largest(1)), "B2");
+
+        // a range of only -inf has -inf as its largest number
+        storage.set_value(AbsCell::new(0, 2), CellValue::Number(f64::NEG_INFINITY));
+        assert_eq!(max(&storage, cell, &column(2)), Ok(f64::NEG_INFINITY));
+    }
+}
@@ -2,25 +2,82 @@
 //!
 //! Most of the functions of this module are just redirected the core storage.
 //! The exceptions are the features that are unrelated to the backend, like undo and redo
-use crate::common::cell_value::{CellData, CellError, CellValue};
+use crate::common::cell_value::{CellData, CellError, CellType, CellValue};
+use crate::common::expression::Expression;
+use crate::common::flash_fill::TextRule;
+use crate::common::number::parse_number;
 use crate::common::structs::AbsCell;
-use crate::embedded_backend::structs::{Action, CellInput};
-use crate::embedded_backend::table::{Storage, StorageError};
-use crate::parser::formula_parser::FormulaParser;
+use crate::common::validation::{ColumnType, ValidationError, ValidationRule};
+use crate::embedded_backend::structs::{Action, Edit, StructuralAction};
+pub use crate::embedded_backend::structs::{CellInput, StructuralEdit};
+use crate::embedded_backend::table::StorageError;
+pub use crate::embedded_backend::table::{CellDiff, LoadError, Storage};
+use crate::error_display::ErrorLabel;
+use crate::parser::formula_parser::{FormulaParser, ParseError};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display};
 use std::fs::File;
 use std::io;
 use std::path::Path;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub enum ExpressionError {
     InvalidExpression,
     CircularReference,
+    /// A range was written bottom-right first, like `B5:A1`
+    ReversedRange,
+    /// The formula reads more cells than `set_max_referenced_cells` allows
+    RangeTooLarge,
+    /// A value breaks the validation rule or column type of a cell it would be written to
+    Rejected(ValidationError),
 }
+
+impl From<ParseError> for ExpressionError {
+    fn from(err: ParseError) -> Self {
+        match err {
+            ParseError::ReversedRange => ExpressionError::ReversedRange,
+            ParseError::Syntax | ParseError::OutOfBounds => ExpressionError::InvalidExpression,
+        }
+    }
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpressionError::Rejected(err) => write!(f, "{}", err),
+            _ => write!(f, "{}", self.label()),
+        }
+    }
+}
+/// Where `insert_totals` writes its `SUM`s relative to the data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TotalsDirection {
+    /// A row of column totals just below the data.
+    Below,
+    /// A column of row totals just right of the data.
+    Right,
+}
+
+/// How many edits can be undone unless changed with `set_undo_limit`
+pub const DEFAULT_UNDO_LIMIT: usize = 1000;
+
+/// A cell's new content, ready to be written: a value, or a formula already parsed for it
+enum Write {
+    Value(CellValue),
+    Expression(Expression),
+}
+
 pub struct EmbeddedBackend {
     storage: Storage,
     parser: FormulaParser,
-    undo_stack: Vec<Action>,
-    redo_stack: Vec<Action>,
+    /// Oldest action at the front, so the oldest can be dropped once `undo_limit` is reached
+    undo_stack: VecDeque<Edit>,
+    redo_stack: Vec<Edit>,
+    undo_limit: usize,
+    /// The cells whose formulas the last undo or redo could not put back, see
+    /// `take_failed_restore`
+    failed_restore: Vec<AbsCell>,
 }
 
 impl EmbeddedBackend {
@@ -28,24 +85,48 @@ impl EmbeddedBackend {
         EmbeddedBackend {
             storage: Storage::new(rows, cols),
             parser: FormulaParser::new(rows, cols),
-            undo_stack: Vec::new(),
+            undo_stack: VecDeque::new(),
             redo_stack: Vec::new(),
+            undo_limit: DEFAULT_UNDO_LIMIT,
+            failed_restore: Vec::new(),
         }
     }
 
-    pub fn from_file(file: &File) -> io::Result<Self> {
+    pub fn from_file(file: &File) -> Result<Self, LoadError> {
         let storage = Storage::from_file(file)?;
+        // formulas may reach anywhere on the loaded sheet, whatever size it was made with
+        let parser = FormulaParser::new(storage.rows(), storage.cols());
         Ok(EmbeddedBackend {
             storage,
-            parser: FormulaParser::new(999, 18278),
-            undo_stack: Vec::new(),
+            parser,
+            undo_stack: VecDeque::new(),
             redo_stack: Vec::new(),
+            undo_limit: DEFAULT_UNDO_LIMIT,
+            failed_restore: Vec::new(),
         })
     }
 
+    /// A read-only copy of the sheet as it is now, for rendering without holding whatever lock
+    /// guards the backend. Readers clone the `Arc` and share the copy.
+    ///
+    /// The snapshot never changes: edits made afterwards are not in it, so it is stale until
+    /// the next call. It is consistent though, every cached value in it matches the formulas
+    /// in it. Taking a snapshot copies the whole sheet.
+    pub fn snapshot(&self) -> Arc<Storage> {
+        Arc::new(self.storage.clone())
+    }
+
     pub fn save_to_file(&self, file: &File) -> io::Result<()> {
         self.storage.serialize_to_file(file)
     }
+
+    /// The bytes `save_to_file` would write, so they can be written elsewhere, e.g. from
+    /// another thread
+    pub fn save_to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.storage.serialize_to_writer(&mut bytes)?;
+        Ok(bytes)
+    }
     pub fn set_cell_empty(&mut self, cell: AbsCell) {
         self.set_cell_value(cell, CellValue::Empty);
     }
@@ -57,14 +138,354 @@ impl EmbeddedBackend {
             cell,
             old_value: old,
             new_value: new,
+            old_result: self.storage.get_value(cell).clone(),
+            new_result: Ok(value.clone()),
         };
         self.storage.set_value(cell, value);
-        self.undo_stack.push(action);
+        self.push_undo(Edit::Cell(action));
         if !self.redo_stack.is_empty() {
             self.redo_stack.clear();
         }
     }
 
+    /// Sets every cell from `top_left` to `bottom_right` to `value`, replacing any formulas,
+    /// and recalculates their dependants once. The value goes through the checks of
+    /// `set_cell_value_checked` for every cell, so a column type may convert it. The fill is
+    /// a single undoable edit.
+    ///
+    /// Fails without writing anything if the range is outside the sheet, its corners are
+    /// the wrong way round or a cell refuses the value
+    pub fn fill_range(
+        &mut self,
+        top_left: AbsCell,
+        bottom_right: AbsCell,
+        value: CellValue,
+    ) -> Result<(), ExpressionError> {
+        if !self.storage.in_bounds(top_left) || !self.storage.in_bounds(bottom_right) {
+            return Err(ExpressionError::InvalidExpression);
+        }
+        if top_left.row > bottom_right.row || top_left.col > bottom_right.col {
+            return Err(ExpressionError::ReversedRange);
+        }
+        // what each column holds, its type deciding the conversion
+        let mut columns = Vec::new();
+        for col in top_left.col..=bottom_right.col {
+            let mut column_value = value.clone();
+            for row in top_left.row..=bottom_right.row {
+                (column_value, _) = self
+                    .check_value(AbsCell::new(row, col), value.clone())
+                    .map_err(ExpressionError::Rejected)?;
+            }
+            columns.push(column_value);
+        }
+        let mut actions = Vec::new();
+        for (cell, _) in self.storage.get_value_range_full(top_left, bottom_right) {
+            let new = &columns[(cell.col - top_left.col) as usize];
+            actions.push(Action {
+                cell,
+                old_value: self.storage.get_input(cell),
+                new_value: CellInput::Value(new.clone()),
+                old_result: self.storage.get_value(cell).clone(),
+                new_result: Ok(new.clone()),
+            });
+        }
+        if columns.iter().all(|column_value| *column_value == value) {
+            self.storage.fill_range(top_left, bottom_right, value);
+        } else {
+            for (col, column_value) in (top_left.col..).zip(columns) {
+                self.storage.fill_range(
+                    AbsCell::new(top_left.row, col),
+                    AbsCell::new(bottom_right.row, col),
+                    column_value,
+                );
+            }
+        }
+        self.push_undo(Edit::Cells(actions));
+        self.redo_stack.clear();
+        Ok(())
+    }
+
+    /// Installs the formula `template`, written as if for `top_left`, in every cell of the
+    /// range with its references shifted along, like filling it down and across: `A1 * 2`
+    /// entered for B1 reads A2 in B2. A leading `=` is allowed. The whole range is a single
+    /// undoable edit.
+    ///
+    /// Changes nothing if the formula does not parse, the range leaves the sheet or has its
+    /// corners the wrong way round, a shifted reference would leave the sheet or a cell would
+    /// end up reading itself
+    pub fn apply_formula_to_range(
+        &mut self,
+        top_left: AbsCell,
+        bottom_right: AbsCell,
+        template: &str,
+    ) -> Result<(), ExpressionError> {
+        let template = template.strip_prefix('=').unwrap_or(template);
+        let expression = self.parser.parse(template, top_left)?;
+        if !self.storage.in_bounds(top_left) || !self.storage.in_bounds(bottom_right) {
+            return Err(ExpressionError::InvalidExpression);
+        }
+        if top_left.row > bottom_right.row || top_left.col > bottom_right.col {
+            return Err(ExpressionError::ReversedRange);
+        }
+
+        let cells: Vec<AbsCell> = self
+            .storage
+            .get_value_range_full(top_left, bottom_right)
+            .map(|(cell, _)| cell)
+            .collect();
+        let writes = cells
+            .into_iter()
+            .map(|cell| (cell, Write::Expression(expression.clone())))
+            .collect();
+        let actions = self.write_cells(writes)?;
+        if actions.is_empty() {
+            return Ok(());
+        }
+        self.push_undo(Edit::Cells(actions));
+        self.redo_stack.clear();
+        Ok(())
+    }
+
+    /// Moves the value or formula of `from` to `to` and empties `from`, like cut and paste.
+    /// A moved formula keeps reading the same cells. Formulas reading `from` as a single cell
+    /// are rewritten to read `to`, while ranges covering `from` are left as they are.
+    /// The move is a single undoable edit.
+    ///
+    /// Changes nothing if a formula would be refused, as when the moved formula reads a cell
+    /// that reads `to`
+    pub fn move_cell(&mut self, from: AbsCell, to: AbsCell) -> Result<(), ExpressionError> {
+        if !self.storage.in_bounds(from) || !self.storage.in_bounds(to) {
+            return Err(ExpressionError::InvalidExpression);
+        }
+        if from == to {
+            return Ok(());
+        }
+        // `to` first and `from` last, so undoing in reverse puts `from` back before the
+        // formulas that read it again
+        let mut inputs = vec![(to, self.storage.get_input(from))];
+        for (cell, formula) in self.storage.retarget_references(from, to) {
+            if cell != to {
+                inputs.push((cell, CellInput::Formula(formula.to_string(cell))));
+            }
+        }
+        inputs.push((from, CellInput::Value(CellValue::Empty)));
+        self.set_cell_inputs(inputs)
+    }
+
+    /// Sets the input of each cell in turn, as a single undoable edit. A formula is given
+    /// without its leading `=`, as written in its own cell.
+    ///
+    /// Changes nothing if a cell is off the sheet or one of the formulas does not parse or is
+    /// refused, even when the cells before it were already written
+    pub fn set_cell_inputs(
+        &mut self,
+        inputs: Vec<(AbsCell, CellInput)>,
+    ) -> Result<(), ExpressionError> {
+        if inputs
+            .iter()
+            .any(|(cell, _)| !self.storage.in_bounds(*cell))
+        {
+            return Err(ExpressionError::InvalidExpression);
+        }
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        // every formula parses before anything is written
+        let mut writes = Vec::new();
+        for (cell, input) in inputs {
+            let write = match input {
+                CellInput::Value(value) => Write::Value(value),
+                CellInput::Formula(formula) => {
+                    Write::Expression(self.parser.parse(&formula, cell)?)
+                }
+            };
+            writes.push((cell, write));
+        }
+        let actions = self.write_cells(writes)?;
+        self.push_undo(Edit::Cells(actions));
+        self.redo_stack.clear();
+        Ok(())
+    }
+
+    /// Writes each cell in turn and returns the edits made, to be recorded by the caller.
+    ///
+    /// If the sheet refuses one of the formulas, the cells already written are put back
+    /// exactly as they were, without parsing their formulas again, and its error is returned
+    fn write_cells(
+        &mut self,
+        writes: Vec<(AbsCell, Write)>,
+    ) -> Result<Vec<Action>, ExpressionError> {
+        let mut written: Vec<(CellData, Action)> = Vec::new();
+        for (cell, write) in writes {
+            let before = self.storage.get_cell_data(cell);
+            let old_value = self.storage.get_input(cell);
+            let old_result = self.storage.get_value(cell).clone();
+            let result = match write {
+                Write::Value(value) => {
+                    self.storage.set_value(cell, value);
+                    StorageError::None
+                }
+                Write::Expression(expression) => self.storage.set_expression(cell, expression),
+            };
+            let error = match result {
+                StorageError::None => {
+                    let action = Action {
+                        cell,
+                        old_value,
+                        new_value: self.storage.get_input(cell),
+                        old_result,
+                        new_result: self.storage.get_value(cell).clone(),
+                    };
+                    written.push((before, action));
+                    continue;
+                }
+                StorageError::CircularDependency => ExpressionError::CircularReference,
+                StorageError::RangeTooLarge => ExpressionError::RangeTooLarge,
+                StorageError::InvalidCell => ExpressionError::InvalidExpression,
+            };
+            // latest first, so each cell goes back to what it was when it was written
+            for (before, action) in written.into_iter().rev() {
+                self.storage.restore_cell(action.cell, before);
+            }
+            return Err(error);
+        }
+        Ok(written.into_iter().map(|(_, action)| action).collect())
+    }
+
+    /// Replaces the cell's formula with the value it currently gives, so it no longer updates,
+    /// like pasting its value in place. An undoable edit. Cells without a formula are left alone.
+    ///
+    /// Returns the cell's error without changing it if its formula does not give a value
+    pub fn freeze_cell(&mut self, cell: AbsCell) -> Result<(), CellError> {
+        if self.storage.get_cell_formula(cell).is_none() {
+            return Ok(());
+        }
+        let value = self.get_cell_value(cell).clone()?;
+        self.set_cell_value(cell, value);
+        Ok(())
+    }
+
+    /// Freezes every formula in the range that gives a value, see `freeze_cell`. Formulas
+    /// giving an error are kept. The frozen cells are a single undoable edit.
+    ///
+    /// returns: the number of cells frozen
+    pub fn freeze_range(&mut self, top_left: AbsCell, bottom_right: AbsCell) -> usize {
+        let inputs: Vec<(AbsCell, CellInput)> = self
+            .storage
+            .get_value_range_sparse(top_left, bottom_right)
+            .filter(|(cell, _)| self.storage.get_cell_formula(*cell).is_some())
+            .filter_map(|(cell, value)| Some((cell, CellInput::Value(value.clone().ok()?))))
+            .collect();
+        let frozen = inputs.len();
+        match self.set_cell_inputs(inputs) {
+            Ok(()) => frozen,
+            Err(_) => 0,
+        }
+    }
+
+    /// Scenario analysis: the value `query` would have if the `overrides` were entered, e.g.
+    /// "if A1 were 10, what is D5?". Overridden formulas are replaced by the value. Nothing is
+    /// changed and nothing goes on the undo stack, see `Storage::what_if`
+    pub fn what_if(
+        &self,
+        overrides: &[(AbsCell, CellValue)],
+        query: AbsCell,
+    ) -> Result<CellValue, CellError> {
+        self.storage.what_if(overrides, query)
+    }
+
+    /// Sets the cell from either kind of input, routing values to `set_cell_value` and
+    /// formulas to `set_cell_formula`. Values can not fail
+    pub fn set_cell_input(
+        &mut self,
+        cell: AbsCell,
+        input: CellInput,
+    ) -> Result<(), ExpressionError> {
+        match input {
+            CellInput::Value(value) => {
+                self.set_cell_value(cell, value);
+                Ok(())
+            }
+            CellInput::Formula(formula) => self.set_cell_formula(cell, &formula),
+        }
+    }
+
+    /// Sets the value like `set_cell_value`, unless it breaks the validation rule of the cell.
+    /// A value of the wrong type for the cell's column is converted first, or rejected if it
+    /// can not be. This is what user input should go through
+    ///
+    /// returns: whether the value was converted to the column's type
+    pub fn set_cell_value_checked(
+        &mut self,
+        cell: AbsCell,
+        value: CellValue,
+    ) -> Result<bool, ValidationError> {
+        let (value, coerced) = self.check_value(cell, value)?;
+        self.set_cell_value(cell, value);
+        Ok(coerced)
+    }
+
+    /// The value `set_cell_value_checked` would store in the cell, and whether it was
+    /// converted to the column's type
+    fn check_value(
+        &self,
+        cell: AbsCell,
+        value: CellValue,
+    ) -> Result<(CellValue, bool), ValidationError> {
+        let converted = self.storage.coerce(cell, &value)?;
+        let coerced = converted.is_some();
+        let value = converted.unwrap_or(value);
+        self.storage.validate(cell, &value)?;
+        Ok((value, coerced))
+    }
+
+    /// Puts the values among the inputs through the checks of `set_cell_value_checked`, giving
+    /// the inputs as they would be stored, or the first cell refused and why. Formulas are
+    /// passed on as they are. Nothing is written, pass the result to `set_cell_inputs`
+    pub fn check_inputs(
+        &self,
+        inputs: Vec<(AbsCell, CellInput)>,
+    ) -> Result<Vec<(AbsCell, CellInput)>, (AbsCell, ValidationError)> {
+        inputs
+            .into_iter()
+            .map(|(cell, input)| match input {
+                CellInput::Value(value) => match self.check_value(cell, value) {
+                    Ok((value, _)) => Ok((cell, CellInput::Value(value))),
+                    Err(err) => Err((cell, err)),
+                },
+                formula => Ok((cell, formula)),
+            })
+            .collect()
+    }
+
+    /// Restricts what `set_cell_value_checked` accepts in column `col` to one type, or lifts
+    /// the restriction with None. Like validation rules, this is not undoable
+    pub fn set_column_type(&mut self, col: i16, column_type: Option<ColumnType>) {
+        self.storage.set_column_type(col, column_type);
+    }
+
+    pub fn column_type(&self, col: i16) -> Option<ColumnType> {
+        self.storage.column_type(col)
+    }
+
+    /// Restricts what `set_cell_value_checked` accepts for the cells in the rectangle
+    pub fn add_validation(
+        &mut self,
+        top_left: AbsCell,
+        bottom_right: AbsCell,
+        rule: ValidationRule,
+    ) {
+        self.storage.add_validation(top_left, bottom_right, rule);
+    }
+
+    pub fn remove_validations_at(&mut self, cell: AbsCell) {
+        self.storage.remove_validations_at(cell);
+    }
+
+    pub fn validation_at(&self, cell: AbsCell) -> Option<&ValidationRule> {
+        self.storage.validation_at(cell)
+    }
+
     pub fn get_cell_value(&self, cell: AbsCell) -> &Result<CellValue, CellError> {
         self.storage.get_value(cell)
     }
@@ -73,6 +494,47 @@ impl EmbeddedBackend {
         self.storage.get_cell_formula(cell)
     }
 
+    /// How many rows the sheet has
+    pub fn rows(&self) -> u16 {
+        self.storage.rows()
+    }
+
+    /// How many columns the sheet has
+    pub fn cols(&self) -> u16 {
+        self.storage.cols()
+    }
+
+    pub fn is_populated(&self, cell: AbsCell) -> bool {
+        self.storage.is_populated(cell)
+    }
+
+    pub fn next_populated_in_direction(&self, cell: AbsCell, dr: i16, dc: i16) -> AbsCell {
+        self.storage.next_populated_in_direction(cell, dr, dc)
+    }
+
+    /// Returns both the value and the formula of the cell with a single lookup.
+    /// Equivalent to calling `get_cell_value` and `get_cell_formula` separately.
+    pub fn get_cell(&self, cell: AbsCell) -> (&Result<CellValue, CellError>, Option<String>) {
+        self.storage.get_cell(cell)
+    }
+
+    /// The kind of value the cell shows, the result of its formula if it has one, see
+    /// `CellData::cell_type`. Use it to lay out a cell by its value
+    pub fn cell_type(&self, cell: AbsCell) -> CellType {
+        CellType::of_value(self.storage.get_value(cell))
+    }
+
+    /// Whether the cell holds a formula, whatever its result
+    pub fn has_formula(&self, cell: AbsCell) -> bool {
+        self.storage.has_formula(cell)
+    }
+
+    /// A copy of the cell's value and formula, taken together so they always agree. The
+    /// formula is the parsed expression; `Expression::to_string` renders it for the cell
+    pub fn get_cell_data(&self, cell: AbsCell) -> CellData {
+        self.storage.get_cell_data(cell)
+    }
+
     pub fn get_cell_range(
         &self,
         top_left: AbsCell,
@@ -86,11 +548,29 @@ impl EmbeddedBackend {
         cell: AbsCell,
         formula: &str,
     ) -> Result<(), ExpressionError> {
-        let new = self
-            .parser
-            .parse(formula, cell)
-            .map_err(|_| ExpressionError::InvalidExpression)?;
+        let new = self.parser.parse(formula, cell)?;
+        self.set_cell_expression(cell, new)
+    }
+
+    /// Sets `formula`, as it was written in `written_in`, in `cell`. Relative references move
+    /// along, so `A1 + 1` written in B1 reads `A2 + 1` in B2. Undoable like `set_cell_formula`
+    pub fn set_cell_formula_relative(
+        &mut self,
+        cell: AbsCell,
+        formula: &str,
+        written_in: AbsCell,
+    ) -> Result<(), ExpressionError> {
+        let new = self.parser.parse(formula, written_in)?;
+        self.set_cell_expression(cell, new)
+    }
+
+    fn set_cell_expression(
+        &mut self,
+        cell: AbsCell,
+        new: Expression,
+    ) -> Result<(), ExpressionError> {
         let old = self.storage.get_input(cell);
+        let old_result = self.storage.get_value(cell).clone();
 
         let res = self.storage.set_expression(cell, new);
         if let StorageError::None = res {
@@ -98,14 +578,18 @@ impl EmbeddedBackend {
                 cell,
                 old_value: old,
                 new_value: self.storage.get_input(cell),
+                old_result,
+                new_result: self.storage.get_value(cell).clone(),
             };
-            self.undo_stack.push(action);
+            self.push_undo(Edit::Cell(action));
             if !self.redo_stack.is_empty() {
                 self.redo_stack.clear();
             }
             Ok(())
         } else if let StorageError::CircularDependency = res {
             Err(ExpressionError::CircularReference)
+        } else if let StorageError::RangeTooLarge = res {
+            Err(ExpressionError::RangeTooLarge)
         } else {
             Err(ExpressionError::InvalidExpression)
         }
@@ -126,46 +610,177 @@ impl EmbeddedBackend {
         // }
     }
 
+    /// Puts `input` back into `cell` for undo and redo, without recording an edit.
+    ///
+    /// A formula the sheet no longer accepts, say because it now reads more cells than
+    /// allowed, is not put back: the cell gets `shown`, what the formula gave when the edit
+    /// was recorded, as a plain value, and is reported by `take_failed_restore`
+    fn replay_input(
+        &mut self,
+        cell: AbsCell,
+        input: &CellInput,
+        shown: &Result<CellValue, CellError>,
+    ) {
+        let formula = match input {
+            CellInput::Value(value) => {
+                self.storage.set_value(cell, value.clone());
+                return;
+            }
+            CellInput::Formula(formula) => formula,
+        };
+        let restored = match self.parser.parse(formula, cell) {
+            Ok(expression) => matches!(
+                self.storage.set_expression(cell, expression),
+                StorageError::None
+            ),
+            Err(_) => false,
+        };
+        if !restored {
+            self.storage
+                .set_value(cell, shown.clone().unwrap_or(CellValue::Empty));
+            self.failed_restore.push(cell);
+        }
+    }
+
+    /// The cells whose formulas the last undo or redo could not put back, clearing them.
+    /// The undo or redo still happened, with each of them holding the value its formula gave
+    pub fn take_failed_restore(&mut self) -> Vec<AbsCell> {
+        std::mem::take(&mut self.failed_restore)
+    }
+
     /// Returns true if the undo stack was not empty and undo actually happened
     pub fn undo(&mut self) -> bool {
-        if let Some(action) = self.undo_stack.pop() {
-            let old = &action.old_value;
-            match old {
-                CellInput::Value(value) => {
-                    self.storage.set_value(action.cell, value.clone());
+        self.failed_restore.clear();
+        match self.undo_stack.pop_back() {
+            Some(Edit::Cell(action)) => {
+                self.replay_input(action.cell, &action.old_value, &action.old_result);
+                self.redo_stack.push(Edit::Cell(action));
+                true
+            }
+            Some(Edit::Cells(actions)) => {
+                for action in actions.iter().rev() {
+                    self.replay_input(action.cell, &action.old_value, &action.old_result);
                 }
-                CellInput::Formula(formula) => {
-                    self.set_cell_formula(action.cell, formula)
-                        .expect("Panic from undo not expected");
+                self.redo_stack.push(Edit::Cells(actions));
+                true
+            }
+            Some(Edit::Structural(action)) => {
+                match self.storage.undo_structural(action.edit, &action.undo) {
+                    StorageError::None => {
+                        self.redo_stack.push(Edit::Structural(action));
+                        true
+                    }
+                    _ => {
+                        self.undo_stack.push_back(Edit::Structural(action));
+                        false
+                    }
                 }
             }
-            self.redo_stack.push(action);
-            true
-        } else {
-            false
+            None => false,
         }
     }
 
     /// Returns true if the redo stack was not empty and redo actually happened
     pub fn redo(&mut self) -> bool {
-        if let Some(action) = self.redo_stack.pop() {
-            let new = &action.new_value;
-            match new {
-                CellInput::Value(value) => {
-                    self.storage.set_value(action.cell, value.clone());
-                }
-                CellInput::Formula(formula) => {
-                    self.set_cell_formula(action.cell, formula)
-                        .expect("Panic from redo not expected");
+        self.failed_restore.clear();
+        match self.redo_stack.pop() {
+            Some(Edit::Cell(action)) => {
+                self.replay_input(action.cell, &action.new_value, &action.new_result);
+                self.push_undo(Edit::Cell(action));
+                true
+            }
+            Some(Edit::Cells(actions)) => {
+                for action in &actions {
+                    self.replay_input(action.cell, &action.new_value, &action.new_result);
                 }
+                self.push_undo(Edit::Cells(actions));
+                true
             }
-            self.undo_stack.push(action);
-            true
-        } else {
-            false
+            Some(Edit::Structural(action)) => match self.storage.apply_structural(action.edit) {
+                Ok(undo) => {
+                    self.push_undo(Edit::Structural(StructuralAction {
+                        edit: action.edit,
+                        undo,
+                    }));
+                    true
+                }
+                Err(_) => {
+                    self.redo_stack.push(Edit::Structural(action));
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+
+    /// Inserts or deletes a row or column, see `Storage::apply_structural`. Undo puts back
+    /// the deleted cells and every reference exactly as it was
+    pub fn apply_structural(&mut self, edit: StructuralEdit) -> Result<(), ExpressionError> {
+        let undo = self
+            .storage
+            .apply_structural(edit)
+            .map_err(|_| ExpressionError::InvalidExpression)?;
+        self.push_undo(Edit::Structural(StructuralAction { edit, undo }));
+        self.redo_stack.clear();
+        Ok(())
+    }
+
+    /// Sets how many edits are kept for undo, dropping the oldest ones beyond that.
+    /// Redo is unaffected, it can only hold actions that were undone
+    pub fn set_undo_limit(&mut self, limit: usize) {
+        self.undo_limit = limit;
+        while self.undo_stack.len() > limit {
+            self.undo_stack.pop_front();
         }
     }
 
+    fn push_undo(&mut self, edit: Edit) {
+        self.undo_stack.push_back(edit);
+        if self.undo_stack.len() > self.undo_limit {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    /// Writes a `SUM` for every column of the rectangle in the row below it, or for every row
+    /// in the column right of it. The totals are a single undoable edit.
+    ///
+    /// Fails without writing anything if the totals would fall outside the sheet or one of
+    /// them is refused
+    pub fn insert_totals(
+        &mut self,
+        top_left: AbsCell,
+        bottom_right: AbsCell,
+        direction: TotalsDirection,
+    ) -> Result<(), ExpressionError> {
+        let totals: Vec<(AbsCell, AbsCell, AbsCell)> = match direction {
+            TotalsDirection::Below => (top_left.col..=bottom_right.col)
+                .map(|col| {
+                    (
+                        AbsCell::new(bottom_right.row + 1, col),
+                        AbsCell::new(top_left.row, col),
+                        AbsCell::new(bottom_right.row, col),
+                    )
+                })
+                .collect(),
+            TotalsDirection::Right => (top_left.row..=bottom_right.row)
+                .map(|row| {
+                    (
+                        AbsCell::new(row, bottom_right.col + 1),
+                        AbsCell::new(row, top_left.col),
+                        AbsCell::new(row, bottom_right.col),
+                    )
+                })
+                .collect(),
+        };
+        let inputs = totals
+            .into_iter()
+            .map(|(cell, first, last)| {
+                (cell, CellInput::Formula(format!("SUM({}:{})", first, last)))
+            })
+            .collect();
+        self.set_cell_inputs(inputs)
+    }
+
     pub fn copy_cell_expression(
         &mut self,
         from: AbsCell,
@@ -173,11 +788,39 @@ impl EmbeddedBackend {
     ) -> Result<(), ExpressionError> {
         match self.storage.copy_cell_expression(from, to) {
             StorageError::CircularDependency => Err(ExpressionError::CircularReference),
+            StorageError::RangeTooLarge => Err(ExpressionError::RangeTooLarge),
             StorageError::InvalidCell => Err(ExpressionError::InvalidExpression),
             StorageError::None => Ok(()),
         }
     }
 
+    /// The loop that copying the formula of `from` into `to` would create, each cell reading the
+    /// next and starting and ending at `to`. None if the copy would not create one
+    pub fn find_copy_cycle(&self, from: AbsCell, to: AbsCell) -> Option<Vec<AbsCell>> {
+        self.storage.find_copy_cycle(from, to)
+    }
+
+    /// Copies like `copy_cell_expression`, as if pasting into a transposed sheet: a formula
+    /// reading the cell one row down from `from` reads the one a column right of `to`
+    pub fn paste_transposed(&mut self, from: AbsCell, to: AbsCell) -> Result<(), ExpressionError> {
+        match self.storage.copy_cell_expression_transposed(from, to) {
+            StorageError::CircularDependency => Err(ExpressionError::CircularReference),
+            StorageError::RangeTooLarge => Err(ExpressionError::RangeTooLarge),
+            StorageError::InvalidCell => Err(ExpressionError::InvalidExpression),
+            StorageError::None => Ok(()),
+        }
+    }
+
+    /// Copies the computed value of `from` into `to` as a literal, dropping any formula.
+    /// Complements `copy_cell_expression`, which copies the formula itself.
+    ///
+    /// Returns the source's error without modifying `to` if the source does not hold a value
+    pub fn paste_values(&mut self, from: AbsCell, to: AbsCell) -> Result<(), CellError> {
+        let value = self.get_cell_value(from).clone()?;
+        self.set_cell_value(to, value);
+        Ok(())
+    }
+
     pub fn search(&self, cell: AbsCell, to_search: &str) -> Option<AbsCell> {
         self.storage.search(cell, to_search)
     }
@@ -186,8 +829,162 @@ impl EmbeddedBackend {
         self.storage.search_from_start(to_search)
     }
 
+    /// Whether formulas read empty cells as `0`. True by default
+    pub fn empty_is_zero(&self) -> bool {
+        self.storage.empty_is_zero()
+    }
+
+    /// Makes formulas read empty cells as `0`, or as an error when `false`, and recomputes
+    /// every formula. This is a setting of the sheet, not an edit, so it is not undoable
+    pub fn set_empty_is_zero(&mut self, empty_is_zero: bool) {
+        self.storage.set_empty_is_zero(empty_is_zero);
+    }
+
+    /// Refuses formulas reading more than `limit` cells from now on, see
+    /// `Storage::set_max_referenced_cells`. Not undoable, and not saved with the sheet
+    pub fn set_max_referenced_cells(&mut self, limit: Option<usize>) {
+        self.storage.set_max_referenced_cells(limit);
+    }
+
+    /// Groups of two or more cells holding the same value, see `Storage::find_duplicate_values`
+    pub fn find_duplicate_values(&self) -> Vec<Vec<AbsCell>> {
+        self.storage.find_duplicate_values()
+    }
+
+    /// How many formulas read each populated cell, see `Storage::dependent_counts`
+    pub fn dependent_counts(&self) -> HashMap<AbsCell, usize> {
+        self.storage.dependent_counts()
+    }
+
+    /// The cells that differ from this sheet to `other`, see `Storage::diff`
+    pub fn diff(&self, other: &EmbeddedBackend) -> Vec<(AbsCell, CellDiff)> {
+        self.storage.diff(&other.storage)
+    }
+
+    /// The cell an error originated in, see `Storage::trace_error`
+    pub fn trace_error(&self, cell: AbsCell) -> Option<AbsCell> {
+        self.storage.trace_error(cell)
+    }
+
+    /// The smallest rectangle holding every populated cell, see `Storage::used_range`
+    pub fn used_range(&self) -> Option<(AbsCell, AbsCell)> {
+        self.storage.used_range()
+    }
+
+    /// The cell named by a label like `B7`, checked to be on the sheet
+    pub fn parse_and_goto(&self, label: &str) -> Result<AbsCell, String> {
+        let cell: AbsCell = label.trim().parse()?;
+        if !self.storage.in_bounds(cell) {
+            return Err(format!("Cell is outside the sheet: {}", label.trim()));
+        }
+        Ok(cell)
+    }
+
+    /// The `(top_left, bottom_right)` of a range like `A1:C3`, checked to be on the sheet.
+    /// See `AbsCell::range_from_str` for the accepted ranges
+    pub fn parse_and_select(&self, range: &str) -> Result<(AbsCell, AbsCell), String> {
+        let (top_left, bottom_right) = AbsCell::range_from_str(range)?;
+        if !self.storage.in_bounds(top_left) || !self.storage.in_bounds(bottom_right) {
+            return Err(format!("Range is outside the sheet: {}", range.trim()));
+        }
+        Ok((top_left, bottom_right))
+    }
+
+    /// Guesses how the examples were derived from the text in `source_col`, see `TextRule`, and
+    /// gives the output it would produce for the rest of the column. Each example is an output
+    /// cell with the text it should hold, all in one column.
+    ///
+    /// Only rows with text in `source_col` and nothing yet in the output column are filled.
+    /// Nothing is written, the caller decides whether to apply the result
+    pub fn flash_fill(
+        &self,
+        source_col: i16,
+        examples: &[(AbsCell, String)],
+    ) -> Vec<(AbsCell, String)> {
+        let source_text = |row: i16| match self.get_cell_value(AbsCell::new(row, source_col)) {
+            Ok(CellValue::String(text)) => Some(text.as_str()),
+            _ => None,
+        };
+        let Some(output_col) = examples.first().map(|(cell, _)| cell.col) else {
+            return Vec::new();
+        };
+        if examples.iter().any(|(cell, _)| cell.col != output_col) {
+            return Vec::new();
+        }
+        let Some(pairs) = examples
+            .iter()
+            .map(|(cell, output)| Some((source_text(cell.row)?, output.as_str())))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Vec::new();
+        };
+        let Some(rule) = TextRule::infer(&pairs) else {
+            return Vec::new();
+        };
+        let Some((top_left, bottom_right)) = self.used_range() else {
+            return Vec::new();
+        };
+
+        (top_left.row..=bottom_right.row)
+            .map(|row| AbsCell::new(row, output_col))
+            .filter(|cell| !self.is_populated(*cell))
+            .filter(|cell| examples.iter().all(|(example, _)| example != cell))
+            .filter_map(|cell| Some((cell, rule.apply(source_text(cell.row)?)?)))
+            .collect()
+    }
+
+    /// The number the cell's text reads as, see `parse_number`. None unless the cell holds
+    /// typed in text, not the result of a formula
+    pub fn numeric_text(&self, cell: AbsCell) -> Option<f64> {
+        match self.storage.get_input(cell) {
+            CellInput::Value(CellValue::String(text)) => parse_number(&text),
+            _ => None,
+        }
+    }
+
+    /// Replaces text that reads as a number, like `"42"` or `"$1,200"`, with the number, so
+    /// formulas reading the cell compute with it. Returns whether the cell changed
+    pub fn convert_text_to_number(&mut self, cell: AbsCell) -> bool {
+        let Some(number) = self.numeric_text(cell) else {
+            return false;
+        };
+        self.set_cell_value(cell, CellValue::Number(number));
+        true
+    }
+
+    /// `convert_text_to_number` for every cell of the column, as a single undoable edit.
+    /// Returns how many cells changed
+    pub fn convert_column_to_numbers(&mut self, col: i16) -> usize {
+        let Some((top_left, bottom_right)) = self.used_range() else {
+            return 0;
+        };
+        let inputs: Vec<_> = (top_left.row..=bottom_right.row)
+            .map(|row| AbsCell::new(row, col))
+            .filter_map(|cell| {
+                let number = self.numeric_text(cell)?;
+                Some((cell, CellInput::Value(CellValue::Number(number))))
+            })
+            .collect();
+        let converted = inputs.len();
+        match self.set_cell_inputs(inputs) {
+            Ok(()) => converted,
+            Err(_) => 0,
+        }
+    }
+
+    /// Returns the next cell after `cell` holding an error, wrapping around to the top of the
+    /// sheet. `cell` itself is checked last
+    pub fn next_error_cell(&self, cell: AbsCell) -> Option<AbsCell> {
+        self.storage
+            .next_error_cell(cell)
+            .or_else(|| self.storage.next_error_cell(AbsCell::new(0, -1)))
+    }
+
     /// Saves a rectangular range of cells to a CSV file.
     ///
+    /// Numbers are always written with `.` as the decimal separator, independent of any display
+    /// locale, so the comma delimiter stays unambiguous.
+    ///
     /// # Arguments
     /// * `top_left` - The top-left cell of the range.
     /// * `bottom_right` - The bottom-right cell of the range.
@@ -212,7 +1009,7 @@ impl EmbeddedBackend {
                     Ok(CellValue::Empty) => "".to_string(),
                     Ok(CellValue::Number(num)) => num.to_string(),
                     Ok(CellValue::String(text)) => text.clone(),
-                    Err(_) => "#ERROR".to_string(),
+                    Err(err) => err.label().to_string(),
                 };
                 csv_row.push(cell_content);
             }
@@ -229,6 +1026,7 @@ mod tests {
     use super::*;
     use crate::common::cell_value::CellValue;
     use crate::common::structs::AbsCell;
+    use std::io::Seek;
     use std::str::FromStr;
     //
     // #[test]
@@ -255,4 +1053,1335 @@ mod tests {
         println!("{:?}", backend.get_cell_formula(cell));
         assert_eq!(backend.get_cell_value(cell), &Ok(CellValue::Number(42.0)));
     }
+
+    #[test]
+    fn test_get_cell_matches_separate_getters() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        backend.set_cell_value(AbsCell::from_str("A1").unwrap(), CellValue::Number(3.0));
+        backend
+            .set_cell_formula(AbsCell::from_str("B1").unwrap(), "A1/0")
+            .unwrap();
+        backend
+            .set_cell_formula(AbsCell::from_str("C1").unwrap(), "A1*2")
+            .unwrap();
+
+        for label in ["A1", "B1", "C1", "D1"] {
+            let cell = AbsCell::from_str(label).unwrap();
+            let (value, formula) = backend.get_cell(cell);
+            assert_eq!(value, backend.get_cell_value(cell));
+            assert_eq!(formula, backend.get_cell_formula(cell));
+        }
+    }
+
+    #[test]
+    fn test_paste_values() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let a1 = AbsCell::from_str("A1").unwrap();
+        let b1 = AbsCell::from_str("B1").unwrap();
+        let c1 = AbsCell::from_str("C1").unwrap();
+        backend.set_cell_value(a1, CellValue::Number(21.0));
+        backend.set_cell_formula(b1, "A1*2").unwrap();
+
+        backend.paste_values(b1, c1).unwrap();
+        assert_eq!(backend.get_cell_value(c1), &Ok(CellValue::Number(42.0)));
+        assert_eq!(backend.get_cell_formula(c1), None);
+
+        // the pasted value is detached from the source's inputs
+        backend.set_cell_value(a1, CellValue::Number(1.0));
+        assert_eq!(backend.get_cell_value(c1), &Ok(CellValue::Number(42.0)));
+    }
+
+    #[test]
+    fn test_sumproduct() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        for (i, (x, y)) in [(1.0, 4.0), (2.0, 5.0), (3.0, 6.0)].iter().enumerate() {
+            backend.set_cell_value(AbsCell::new(0, i as i16), CellValue::Number(*x));
+            backend.set_cell_value(AbsCell::new(1, i as i16), CellValue::Number(*y));
+        }
+        let target = AbsCell::from_str("E5").unwrap();
+        backend
+            .set_cell_formula(target, "SUMPRODUCT(A1:C1, A2:C2)")
+            .unwrap();
+        assert_eq!(backend.get_cell_value(target), &Ok(CellValue::Number(32.0)));
+
+        // both ranges are tracked as dependencies
+        backend.set_cell_value(AbsCell::from_str("B2").unwrap(), CellValue::Number(0.0));
+        assert_eq!(backend.get_cell_value(target), &Ok(CellValue::Number(22.0)));
+    }
+
+    #[test]
+    fn test_sumproduct_shape_mismatch() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let target = AbsCell::from_str("E5").unwrap();
+        backend
+            .set_cell_formula(target, "SUMPRODUCT(A1:C1, A2:B2)")
+            .unwrap();
+        assert_eq!(
+            backend.get_cell_value(target),
+            &Err(CellError::ShapeMismatch)
+        );
+    }
+
+    #[test]
+    fn test_reversed_range_error() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = AbsCell::from_str("C1").unwrap();
+        let err = backend.set_cell_formula(cell, "SUM(B5:A1)").unwrap_err();
+        assert!(matches!(err, ExpressionError::ReversedRange));
+        assert_eq!(err.to_string(), "Range start must be above-left of end");
+
+        let err = backend.set_cell_formula(cell, "SUM(A1:").unwrap_err();
+        assert!(matches!(err, ExpressionError::InvalidExpression));
+    }
+
+    #[test]
+    fn test_reads_do_not_recalculate() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let sleeper = AbsCell::from_str("B2").unwrap();
+        let start = std::time::Instant::now();
+        backend.set_cell_formula(sleeper, "SLEEP(0.3)").unwrap();
+        assert!(start.elapsed().as_secs_f64() >= 0.3);
+
+        // simulate many repaints of a viewport containing the SLEEP cell
+        let start = std::time::Instant::now();
+        for _ in 0..100 {
+            let top_left = AbsCell::new(0, 0);
+            let bottom_right = AbsCell::new(9, 9);
+            for (cell, _) in backend.get_cell_range(top_left, bottom_right) {
+                let _ = backend.get_cell(cell);
+            }
+        }
+        assert!(start.elapsed().as_secs_f64() < 0.3);
+        assert_eq!(backend.get_cell_value(sleeper), &Ok(CellValue::Number(0.3)));
+    }
+
+    #[test]
+    fn test_next_error_cell() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let c2 = AbsCell::from_str("C2").unwrap();
+        let b4 = AbsCell::from_str("B4").unwrap();
+        backend.set_cell_value(AbsCell::from_str("A1").unwrap(), CellValue::Number(0.0));
+        backend.set_cell_formula(b4, "1/A1").unwrap();
+        backend.set_cell_formula(c2, "5/A1").unwrap();
+        backend
+            .set_cell_formula(AbsCell::from_str("D3").unwrap(), "A1+1")
+            .unwrap();
+        assert_eq!(backend.get_cell_value(c2), &Err(CellError::DivideByZero));
+
+        let first = backend.next_error_cell(AbsCell::new(0, 0));
+        assert_eq!(first, Some(c2));
+        assert_eq!(backend.next_error_cell(c2), Some(b4));
+        // wraps around back to the first error
+        assert_eq!(backend.next_error_cell(b4), Some(c2));
+
+        backend.set_cell_value(AbsCell::from_str("A1").unwrap(), CellValue::Number(1.0));
+        assert_eq!(backend.next_error_cell(AbsCell::new(0, 0)), None);
+    }
+
+    #[test]
+    fn test_empty_is_zero_setting() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let b1 = AbsCell::from_str("B1").unwrap();
+        let c1 = AbsCell::from_str("C1").unwrap();
+        assert!(backend.empty_is_zero());
+        backend.set_cell_formula(b1, "A1+1").unwrap();
+        backend.set_cell_formula(c1, "B1*2").unwrap();
+        assert_eq!(backend.get_cell_value(b1), &Ok(CellValue::Number(1.0)));
+
+        backend.set_empty_is_zero(false);
+        assert_eq!(
+            backend.get_cell_value(b1),
+            &Err(CellError::DependsOnNonNumeric)
+        );
+        // the error propagates to C1 unchanged
+        assert_eq!(
+            backend.get_cell_value(c1),
+            &Err(CellError::DependsOnNonNumeric)
+        );
+
+        backend.set_cell_value(AbsCell::from_str("A1").unwrap(), CellValue::Number(4.0));
+        assert_eq!(backend.get_cell_value(c1), &Ok(CellValue::Number(10.0)));
+
+        backend.set_cell_empty(AbsCell::from_str("A1").unwrap());
+        backend.set_empty_is_zero(true);
+        assert_eq!(backend.get_cell_value(c1), &Ok(CellValue::Number(2.0)));
+    }
+
+    #[test]
+    fn test_move_cell() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        let formula = |backend: &EmbeddedBackend, label| backend.get_cell_formula(cell(label));
+        backend.set_cell_value(cell("A1"), CellValue::Number(5.0));
+        backend.set_cell_formula(cell("B1"), "A1*2").unwrap();
+        backend.set_cell_formula(cell("B2"), "A1+A1").unwrap();
+        backend.set_cell_formula(cell("C1"), "SUM(A1:A2)").unwrap();
+
+        backend.move_cell(cell("A1"), cell("D4")).unwrap();
+        assert_eq!(backend.get_cell_value(cell("A1")), &Ok(CellValue::Empty));
+        assert_eq!(
+            backend.get_cell_value(cell("D4")),
+            &Ok(CellValue::Number(5.0))
+        );
+        // single references follow the cell, ranges stay where they are
+        assert_eq!(formula(&backend, "B1"), Some("D4 * 2".to_string()));
+        assert_eq!(formula(&backend, "B2"), Some("D4 + D4".to_string()));
+        assert_eq!(
+            backend.get_cell_value(cell("B1")),
+            &Ok(CellValue::Number(10.0))
+        );
+        assert_eq!(formula(&backend, "C1"), Some("SUM(A1:A2)".to_string()));
+        assert_eq!(
+            backend.get_cell_value(cell("C1")),
+            &Ok(CellValue::Number(0.0))
+        );
+
+        // a moved formula keeps reading the same cells
+        backend.move_cell(cell("B1"), cell("E1")).unwrap();
+        assert_eq!(formula(&backend, "E1"), Some("D4 * 2".to_string()));
+
+        // each move is undone in one step
+        assert!(backend.undo());
+        assert_eq!(formula(&backend, "B1"), Some("D4 * 2".to_string()));
+        assert!(backend.undo());
+        assert_eq!(
+            backend.get_cell_value(cell("A1")),
+            &Ok(CellValue::Number(5.0))
+        );
+        assert_eq!(backend.get_cell_value(cell("D4")), &Ok(CellValue::Empty));
+        assert_eq!(formula(&backend, "B1"), Some("A1 * 2".to_string()));
+        assert_eq!(
+            backend.get_cell_value(cell("C1")),
+            &Ok(CellValue::Number(5.0))
+        );
+        assert!(backend.redo());
+        assert_eq!(formula(&backend, "B2"), Some("D4 + D4".to_string()));
+        assert!(backend.take_failed_restore().is_empty());
+    }
+
+    #[test]
+    fn test_move_cell_refused() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        backend.set_cell_formula(cell("A1"), "B1").unwrap();
+        backend.set_cell_formula(cell("B1"), "C1").unwrap();
+
+        // at C1, the formula of A1 would read B1, which reads C1
+        assert!(matches!(
+            backend.move_cell(cell("A1"), cell("C1")),
+            Err(ExpressionError::CircularReference)
+        ));
+        assert_eq!(backend.get_cell_formula(cell("A1")), Some("B1".to_string()));
+        assert_eq!(backend.get_cell_formula(cell("C1")), None);
+        assert!(matches!(
+            backend.move_cell(cell("A1"), AbsCell::new(10, 0)),
+            Err(ExpressionError::InvalidExpression)
+        ));
+
+        // refused after `to` was written, when rewriting a formula that reads `from`
+        let mut backend = EmbeddedBackend::new(10, 10);
+        backend.set_cell_value(cell("A1"), CellValue::Number(5.0));
+        backend
+            .set_cell_formula(cell("B1"), "A1 + SUM(C1:D10)")
+            .unwrap();
+        backend.set_max_referenced_cells(Some(10));
+        let before = backend.snapshot();
+        assert!(matches!(
+            backend.move_cell(cell("A1"), cell("E1")),
+            Err(ExpressionError::RangeTooLarge)
+        ));
+        assert!(backend.snapshot().diff(&before).is_empty());
+        assert_eq!(
+            backend.get_cell_value(cell("A1")),
+            &Ok(CellValue::Number(5.0))
+        );
+        assert_eq!(backend.get_cell_value(cell("E1")), &Ok(CellValue::Empty));
+    }
+
+    #[test]
+    fn test_set_cell_inputs_is_all_or_nothing() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        let formula = |text: &str| CellInput::Formula(text.to_string());
+        backend.set_cell_formula(cell("A1"), "1e999").unwrap();
+        // entered before the limit, and above it
+        backend.set_cell_formula(cell("C5"), "SUM(A1:B10)").unwrap();
+        backend.set_max_referenced_cells(Some(10));
+        let before = backend.snapshot();
+
+        // refused at the last cell, after C5 and A1 were written
+        let batch = vec![
+            (cell("C5"), formula("1 + 1")),
+            (cell("A1"), CellInput::Value(CellValue::Number(3.0))),
+            (cell("D1"), formula("SUM(A1:J10)")),
+        ];
+        assert!(matches!(
+            backend.set_cell_inputs(batch),
+            Err(ExpressionError::RangeTooLarge)
+        ));
+        assert!(backend.snapshot().diff(&before).is_empty());
+        assert_eq!(
+            backend.get_cell_formula(cell("C5")),
+            Some("SUM(A1:B10)".to_string())
+        );
+
+        // a formula that does not parse is caught before anything is written
+        let batch = vec![
+            (cell("C5"), formula("1 + 1")),
+            (cell("D2"), formula("A1 +")),
+        ];
+        assert!(backend.set_cell_inputs(batch).is_err());
+        assert!(backend.snapshot().diff(&before).is_empty());
+
+        // nothing was recorded, undo takes back the formula of C5
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_formula(cell("C5")), None);
+    }
+
+    #[test]
+    fn test_load_keeps_sheet_bounds() {
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        let mut file = tempfile::tempfile().unwrap();
+        let mut backend = EmbeddedBackend::new(2000, 20);
+        backend.set_cell_value(cell("T2000"), CellValue::Number(4.0));
+        backend.save_to_file(&file).unwrap();
+        file.rewind().unwrap();
+
+        let Ok(mut loaded) = EmbeddedBackend::from_file(&file) else {
+            panic!("the sheet loads");
+        };
+        loaded.set_cell_formula(cell("A1"), "T2000*2").unwrap();
+        assert_eq!(
+            loaded.get_cell_value(cell("A1")),
+            &Ok(CellValue::Number(8.0))
+        );
+        // and the bounds are still those of the sheet
+        assert!(loaded.set_cell_formula(cell("A2"), "U1").is_err());
+    }
+
+    #[test]
+    fn test_parse_and_goto() {
+        let backend = EmbeddedBackend::new(10, 5);
+        assert_eq!(backend.parse_and_goto("E10"), Ok(AbsCell::new(9, 4)));
+        assert_eq!(backend.parse_and_goto(" a1 "), Ok(AbsCell::new(0, 0)));
+        assert!(backend.parse_and_goto("F1").is_err());
+        assert!(backend.parse_and_goto("A11").is_err());
+        assert!(backend.parse_and_goto("A0").is_err());
+        assert!(backend.parse_and_goto("1A").is_err());
+        assert!(backend.parse_and_goto("AAAAAA1").is_err());
+        assert!(backend.parse_and_select("AAAAAA1:B2").is_err());
+
+        assert_eq!(
+            backend.parse_and_select("B2:E10"),
+            Ok((AbsCell::new(1, 1), AbsCell::new(9, 4)))
+        );
+        assert!(backend.parse_and_select("B2:F10").is_err());
+        assert!(backend.parse_and_select("C3:A1").is_err());
+        assert!(backend.parse_and_select("A1").is_err());
+    }
+
+    #[test]
+    fn test_cell_type() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        backend.set_cell_value(cell("A1"), CellValue::Number(2.0));
+        backend.set_cell_value(cell("A2"), CellValue::String("x".to_string()));
+        backend.set_cell_formula(cell("A3"), "A1*2").unwrap();
+        backend.set_cell_formula(cell("A4"), "A1/0").unwrap();
+
+        assert_eq!(backend.cell_type(cell("A1")), CellType::Number);
+        assert_eq!(backend.cell_type(cell("A2")), CellType::Text);
+        backend
+            .set_cell_formula(cell("A6"), "T(\"label\")")
+            .unwrap();
+        // a formula has the type of its result
+        assert_eq!(backend.cell_type(cell("A3")), CellType::Number);
+        assert_eq!(backend.cell_type(cell("A4")), CellType::Error);
+        assert_eq!(backend.cell_type(cell("A5")), CellType::Empty);
+        assert_eq!(backend.cell_type(cell("A6")), CellType::Text);
+        assert!(backend.has_formula(cell("A3")));
+        assert!(!backend.has_formula(cell("A1")));
+        assert!(!backend.has_formula(cell("A5")));
+        backend.set_cell_empty(cell("A1"));
+        assert_eq!(backend.cell_type(cell("A1")), CellType::Empty);
+        assert_eq!(backend.cell_type(cell("A3")), CellType::Number);
+    }
+
+    #[test]
+    fn test_convert_text_to_number() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        backend.set_cell_value(cell("A1"), CellValue::String("42".to_string()));
+        backend.set_cell_formula(cell("B1"), "A1*2").unwrap();
+        assert!(backend.get_cell_value(cell("B1")).is_err());
+        assert_eq!(backend.numeric_text(cell("A1")), Some(42.0));
+
+        assert!(backend.convert_text_to_number(cell("A1")));
+        assert_eq!(
+            backend.get_cell_value(cell("A1")),
+            &Ok(CellValue::Number(42.0))
+        );
+        assert_eq!(
+            backend.get_cell_value(cell("B1")),
+            &Ok(CellValue::Number(84.0))
+        );
+        // already a number, a formula, or text that is not a number
+        assert!(!backend.convert_text_to_number(cell("A1")));
+        assert!(!backend.convert_text_to_number(cell("B1")));
+        backend.set_cell_value(cell("A2"), CellValue::String("n/a".to_string()));
+        assert!(!backend.convert_text_to_number(cell("A2")));
+
+        backend.set_cell_value(cell("C1"), CellValue::String("$1,200".to_string()));
+        backend.set_cell_value(cell("C2"), CellValue::String("x".to_string()));
+        backend.set_cell_value(cell("C3"), CellValue::String("15%".to_string()));
+        assert_eq!(backend.convert_column_to_numbers(2), 2);
+        assert_eq!(
+            backend.get_cell_value(cell("C1")),
+            &Ok(CellValue::Number(1200.0))
+        );
+        assert_eq!(
+            backend.get_cell_value(cell("C3")),
+            &Ok(CellValue::Number(0.15))
+        );
+        // the column converts as one edit
+        backend.undo();
+        assert_eq!(
+            backend.get_cell_value(cell("C1")),
+            &Ok(CellValue::String("$1,200".to_string()))
+        );
+        assert_eq!(
+            backend.get_cell_value(cell("A1")),
+            &Ok(CellValue::Number(42.0))
+        );
+    }
+
+    #[test]
+    fn test_get_cell_data() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        backend.set_cell_value(cell("A1"), CellValue::Number(2.0));
+        backend.set_cell_formula(cell("B1"), "A1*3").unwrap();
+        backend.set_cell_formula(cell("C1"), "B1/0").unwrap();
+
+        for label in ["A1", "B1", "C1", "D1"] {
+            let data = backend.get_cell_data(cell(label));
+            assert_eq!(&data.value, backend.get_cell_value(cell(label)));
+            assert_eq!(
+                data.formula.map(|formula| formula.to_string(cell(label))),
+                backend.get_cell_formula(cell(label))
+            );
+        }
+        assert_eq!(backend.get_cell_data(cell("D1")), CellData::default());
+    }
+
+    #[test]
+    fn test_flash_fill_split() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        let names = ["Ada Lovelace", "Alan Turing", "Grace Hopper", "Turing"];
+        for (row, name) in names.iter().enumerate() {
+            backend.set_cell_value(
+                AbsCell::new(row as i16, 0),
+                CellValue::String(name.to_string()),
+            );
+        }
+        backend.set_cell_value(cell("B3"), CellValue::String("kept".to_string()));
+
+        let filled = backend.flash_fill(0, &[(cell("B1"), "Lovelace".to_string())]);
+        // B3 already holds something and A4 has no space to split on
+        assert_eq!(filled, vec![(cell("B2"), "Turing".to_string())]);
+
+        // examples that fit no rule fill nothing
+        assert!(
+            backend
+                .flash_fill(0, &[(cell("B1"), "Byron".to_string())])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_undo_formula_edits() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = AbsCell::from_str("A1").unwrap();
+        backend.set_cell_formula(cell, "1+1").unwrap();
+        backend.set_cell_formula(cell, "2+2").unwrap();
+
+        // undoing a formula edit is not itself recorded as an edit
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_formula(cell), Some("1 + 1".to_string()));
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_value(cell), &Ok(CellValue::Empty));
+        assert!(!backend.undo());
+        assert!(backend.redo());
+        assert!(backend.redo());
+        assert_eq!(backend.get_cell_formula(cell), Some("2 + 2".to_string()));
+        assert!(backend.take_failed_restore().is_empty());
+    }
+
+    #[test]
+    fn test_undo_formula_no_longer_accepted() {
+        let mut backend = EmbeddedBackend::new(100, 10);
+        let (a1, b1) = (
+            AbsCell::from_str("A1").unwrap(),
+            AbsCell::from_str("B1").unwrap(),
+        );
+        backend.set_cell_value(b1, CellValue::Number(3.0));
+        backend.set_cell_formula(a1, "SUM(B1:B100)").unwrap();
+        backend.set_cell_formula(a1, "B1*2").unwrap();
+        backend.set_max_referenced_cells(Some(10));
+
+        // the SUM now reads too many cells, so A1 gets the value the SUM gave
+        assert!(backend.undo());
+        assert_eq!(backend.take_failed_restore(), vec![a1]);
+        assert!(backend.take_failed_restore().is_empty());
+        assert_eq!(backend.get_cell_formula(a1), None);
+        assert_eq!(backend.get_cell_value(a1), &Ok(CellValue::Number(3.0)));
+
+        // the rest of the history is intact
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_value(a1), &Ok(CellValue::Empty));
+        assert!(backend.redo());
+        assert_eq!(backend.take_failed_restore(), vec![a1]);
+        assert!(backend.redo());
+        assert_eq!(backend.get_cell_formula(a1), Some("B1 * 2".to_string()));
+
+        // every cell of a batch that can not be put back is reported
+        let (a2, a3) = (
+            AbsCell::from_str("A2").unwrap(),
+            AbsCell::from_str("A3").unwrap(),
+        );
+        backend.set_max_referenced_cells(None);
+        backend
+            .set_cell_inputs(vec![
+                (a2, CellInput::Formula("SUM(B1:B50)".to_string())),
+                (a3, CellInput::Formula("SUM(B1:B60) + 1".to_string())),
+            ])
+            .unwrap();
+        backend
+            .set_cell_inputs(vec![
+                (a2, CellInput::Value(CellValue::Number(0.0))),
+                (a3, CellInput::Value(CellValue::Number(0.0))),
+            ])
+            .unwrap();
+        backend.set_max_referenced_cells(Some(10));
+        assert!(backend.undo());
+        let mut failed = backend.take_failed_restore();
+        failed.sort_by_key(|cell| cell.row);
+        assert_eq!(failed, vec![a2, a3]);
+        assert_eq!(backend.get_cell_value(a2), &Ok(CellValue::Number(3.0)));
+        assert_eq!(backend.get_cell_value(a3), &Ok(CellValue::Number(4.0)));
+    }
+
+    #[test]
+    fn test_undo_limit() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = AbsCell::from_str("A1").unwrap();
+        backend.set_undo_limit(3);
+        for i in 1..=5 {
+            backend.set_cell_value(cell, CellValue::Number(i as f64));
+        }
+
+        // only the last three edits can be undone
+        for expected in [4.0, 3.0, 2.0] {
+            assert!(backend.undo());
+            assert_eq!(
+                backend.get_cell_value(cell),
+                &Ok(CellValue::Number(expected))
+            );
+        }
+        assert!(!backend.undo());
+
+        for expected in [3.0, 4.0, 5.0] {
+            assert!(backend.redo());
+            assert_eq!(
+                backend.get_cell_value(cell),
+                &Ok(CellValue::Number(expected))
+            );
+        }
+
+        // shrinking the limit drops the oldest actions straight away
+        backend.set_undo_limit(1);
+        assert!(backend.undo());
+        assert!(!backend.undo());
+        assert_eq!(backend.get_cell_value(cell), &Ok(CellValue::Number(4.0)));
+    }
+
+    #[test]
+    fn test_whole_column_sum() {
+        let mut backend = EmbeddedBackend::new(5, 3);
+        for row in 0..5 {
+            backend.set_cell_value(AbsCell::new(row, 0), CellValue::Number(row as f64 + 1.0));
+        }
+        let total = AbsCell::from_str("B1").unwrap();
+        backend.set_cell_formula(total, "SUM(A:A)").unwrap();
+        assert_eq!(backend.get_cell_value(total), &Ok(CellValue::Number(15.0)));
+
+        // the last row is tracked as a dependency
+        backend.set_cell_value(AbsCell::from_str("A5").unwrap(), CellValue::Number(10.0));
+        assert_eq!(backend.get_cell_value(total), &Ok(CellValue::Number(20.0)));
+
+        let err = backend.set_cell_formula(AbsCell::from_str("A3").unwrap(), "SUM(A:A)");
+        assert!(matches!(err, Err(ExpressionError::CircularReference)));
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = EmbeddedBackend::new(10, 10);
+        let a1 = AbsCell::from_str("A1").unwrap();
+        let b1 = AbsCell::from_str("B1").unwrap();
+        let c1 = AbsCell::from_str("C1").unwrap();
+        old.set_cell_value(a1, CellValue::Number(2.0));
+        old.set_cell_formula(b1, "A1+1").unwrap();
+        old.set_cell_value(c1, CellValue::String("same".to_string()));
+
+        let mut new = EmbeddedBackend::new(10, 10);
+        new.set_cell_value(a1, CellValue::Number(5.0));
+        new.set_cell_formula(b1, "A1*2").unwrap();
+        new.set_cell_value(c1, CellValue::String("same".to_string()));
+
+        let diffs = old.diff(&new);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0].0, a1);
+        assert_eq!(diffs[0].1.describe(a1), "A1: changed 2 -> 5");
+        assert_eq!(diffs[1].0, b1);
+        assert_eq!(
+            diffs[1].1.describe(b1),
+            "B1: changed =A1 + 1 (3) -> =A1 * 2 (10)"
+        );
+
+        new.set_cell_empty(c1);
+        new.set_cell_value(AbsCell::from_str("A2").unwrap(), CellValue::Number(1.0));
+        let diffs = old.diff(&new);
+        let described: Vec<String> = diffs.iter().map(|(cell, d)| d.describe(*cell)).collect();
+        assert_eq!(
+            described,
+            [
+                "A1: changed 2 -> 5",
+                "B1: changed =A1 + 1 (3) -> =A1 * 2 (10)",
+                "C1: removed \"same\"",
+                "A2: added 1",
+            ]
+        );
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn test_validation_rejects_input() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let b2 = AbsCell::from_str("B2").unwrap();
+        let rule = ValidationRule::NumberBetween {
+            min: 0.0,
+            max: 100.0,
+        };
+        backend.add_validation(
+            AbsCell::from_str("A1").unwrap(),
+            AbsCell::from_str("C3").unwrap(),
+            rule,
+        );
+        assert_eq!(backend.validation_at(b2), Some(&rule));
+        assert_eq!(
+            backend.validation_at(AbsCell::from_str("D1").unwrap()),
+            None
+        );
+
+        assert!(
+            backend
+                .set_cell_value_checked(b2, CellValue::Number(50.0))
+                .is_ok()
+        );
+        let err = backend.set_cell_value_checked(b2, CellValue::Number(150.0));
+        assert!(matches!(err, Err(ValidationError::OutOfRange { .. })));
+        let err = backend.set_cell_value_checked(b2, CellValue::String("high".to_string()));
+        assert_eq!(err, Err(ValidationError::NotANumber));
+        // rejected values leave the cell and the undo history alone
+        assert_eq!(backend.get_cell_value(b2), &Ok(CellValue::Number(50.0)));
+        assert!(backend.undo());
+        assert!(!backend.undo());
+
+        backend.remove_validations_at(b2);
+        assert!(
+            backend
+                .set_cell_value_checked(b2, CellValue::Number(150.0))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_check_inputs() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        backend.add_validation(
+            cell("A1"),
+            cell("A3"),
+            ValidationRule::NumberBetween {
+                min: 0.0,
+                max: 10.0,
+            },
+        );
+        backend.set_column_type(1, Some(ColumnType::Number));
+
+        let inputs = vec![
+            (cell("A1"), CellInput::Value(CellValue::Number(5.0))),
+            (cell("A2"), CellInput::Formula("A1 * 100".to_string())),
+            (
+                cell("B1"),
+                CellInput::Value(CellValue::String("7".to_string())),
+            ),
+            (
+                cell("C1"),
+                CellInput::Value(CellValue::String("x".to_string())),
+            ),
+        ];
+        let checked = backend.check_inputs(inputs).unwrap();
+        // B1 is converted to the column's type, the formula is not checked
+        assert!(matches!(
+            &checked[2],
+            (at, CellInput::Value(CellValue::Number(7.0))) if *at == cell("B1")
+        ));
+        assert!(matches!(&checked[1], (_, CellInput::Formula(f)) if f == "A1 * 100"));
+
+        let refused = backend.check_inputs(vec![
+            (cell("C2"), CellInput::Value(CellValue::Number(50.0))),
+            (cell("A3"), CellInput::Value(CellValue::Number(50.0))),
+        ]);
+        assert!(matches!(
+            refused,
+            Err((at, ValidationError::OutOfRange { .. })) if at == cell("A3")
+        ));
+    }
+
+    #[test]
+    fn test_paste_transposed() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        for (label, value) in [("A1", 1.0), ("A2", 2.0), ("B1", 10.0)] {
+            backend.set_cell_value(cell(label), CellValue::Number(value));
+        }
+        backend.set_cell_formula(cell("A3"), "A1+A2").unwrap();
+
+        // the references two and one rows up become two and one columns left
+        backend.paste_transposed(cell("A3"), cell("C1")).unwrap();
+        assert_eq!(
+            backend.get_cell_formula(cell("C1")),
+            Some("A1 + B1".to_string())
+        );
+        assert_eq!(
+            backend.get_cell_value(cell("C1")),
+            &Ok(CellValue::Number(11.0))
+        );
+        // a plain paste keeps the references' axes
+        backend
+            .copy_cell_expression(cell("A3"), cell("C3"))
+            .unwrap();
+        assert_eq!(
+            backend.get_cell_formula(cell("C3")),
+            Some("C1 + C2".to_string())
+        );
+
+        backend.set_cell_formula(cell("A4"), "SUM(A1:B2)").unwrap();
+        backend.paste_transposed(cell("A4"), cell("D1")).unwrap();
+        assert_eq!(
+            backend.get_cell_formula(cell("D1")),
+            Some("SUM(A1:B2)".to_string())
+        );
+        backend.set_cell_formula(cell("A5"), "SUM(A1:A3)").unwrap();
+        backend.paste_transposed(cell("A5"), cell("E1")).unwrap();
+        assert_eq!(
+            backend.get_cell_formula(cell("E1")),
+            Some("SUM(A1:C1)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_what_if() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        backend.set_cell_value(cell("A1"), CellValue::Number(2.0));
+        backend.set_cell_formula(cell("B1"), "A1 * 3").unwrap();
+        backend.set_cell_formula(cell("D5"), "B1 + 1").unwrap();
+
+        assert_eq!(
+            backend.what_if(&[(cell("A1"), CellValue::Number(10.0))], cell("D5")),
+            Ok(CellValue::Number(31.0))
+        );
+        // overriding a formula cell replaces its formula
+        assert_eq!(
+            backend.what_if(&[(cell("B1"), CellValue::Number(0.5))], cell("D5")),
+            Ok(CellValue::Number(1.5))
+        );
+        assert_eq!(
+            backend.what_if(
+                &[(cell("A1"), CellValue::String("x".to_string()))],
+                cell("D5")
+            ),
+            Err(CellError::DependsOnNonNumeric)
+        );
+
+        // nothing changed, not even the undo stack
+        assert_eq!(
+            backend.get_cell_value(cell("D5")),
+            &Ok(CellValue::Number(7.0))
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("B1")),
+            Some("A1 * 3".to_string())
+        );
+        backend.set_cell_value(cell("A1"), CellValue::Number(1.0));
+        assert_eq!(
+            backend.get_cell_value(cell("D5")),
+            &Ok(CellValue::Number(4.0))
+        );
+        assert!(backend.undo());
+        assert!(backend.undo());
+        assert!(backend.undo());
+        assert!(backend.undo());
+        assert!(!backend.undo());
+    }
+
+    #[test]
+    fn test_set_cell_input() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let a1 = AbsCell::new(0, 0);
+        let b1 = AbsCell::new(0, 1);
+
+        backend
+            .set_cell_input(a1, CellInput::Value(CellValue::Number(4.0)))
+            .unwrap();
+        backend
+            .set_cell_input(b1, CellInput::Formula("A1 * 2".to_string()))
+            .unwrap();
+        assert_eq!(backend.get_cell_value(b1), &Ok(CellValue::Number(8.0)));
+        assert_eq!(backend.get_cell_formula(b1), Some("A1 * 2".to_string()));
+
+        let result = backend.set_cell_input(a1, CellInput::Formula("B1 + 1".to_string()));
+        assert!(matches!(result, Err(ExpressionError::CircularReference)));
+        let result = backend.set_cell_input(a1, CellInput::Formula("B1 +".to_string()));
+        assert!(matches!(result, Err(ExpressionError::InvalidExpression)));
+        assert_eq!(backend.get_cell_value(a1), &Ok(CellValue::Number(4.0)));
+
+        // both kinds go on the undo stack
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_formula(b1), None);
+    }
+
+    #[test]
+    fn test_fill_range() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let a1 = AbsCell::new(0, 0);
+        let b2 = AbsCell::new(1, 1);
+        let c3 = AbsCell::new(2, 2);
+        let total = AbsCell::new(5, 0);
+        let doubled = AbsCell::new(5, 1);
+        // fewer undo steps than the fill has cells
+        backend.set_undo_limit(4);
+        backend.set_cell_formula(b2, "A1 + 100").unwrap();
+        backend.set_cell_formula(total, "SUM(A1:C3)").unwrap();
+        backend.set_cell_formula(doubled, "A6 * 2").unwrap();
+
+        backend.fill_range(a1, c3, CellValue::Number(7.0)).unwrap();
+        for (cell, data) in backend.get_cell_range(a1, c3) {
+            assert_eq!(data.value, Ok(CellValue::Number(7.0)), "{}", cell);
+        }
+        // the fill replaces formulas instead of being recomputed away
+        assert_eq!(backend.get_cell_formula(b2), None);
+        assert_eq!(backend.get_cell_value(total), &Ok(CellValue::Number(63.0)));
+        assert_eq!(
+            backend.get_cell_value(doubled),
+            &Ok(CellValue::Number(126.0))
+        );
+
+        // undone in one step, leaving the edits before it on the stack
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_value(c3), &Ok(CellValue::Empty));
+        assert_eq!(backend.get_cell_formula(b2), Some("A1 + 100".to_string()));
+        assert_eq!(backend.get_cell_value(total), &Ok(CellValue::Number(100.0)));
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_formula(doubled), None);
+
+        let result = backend.fill_range(a1, AbsCell::new(10, 0), CellValue::Number(1.0));
+        assert!(matches!(result, Err(ExpressionError::InvalidExpression)));
+        for (from, to) in [(b2, AbsCell::new(2, 0)), (b2, a1)] {
+            let result = backend.fill_range(from, to, CellValue::Number(1.0));
+            assert!(matches!(result, Err(ExpressionError::ReversedRange)));
+        }
+        assert_eq!(
+            backend.get_cell_value(AbsCell::new(9, 0)),
+            &Ok(CellValue::Empty)
+        );
+
+        // every cell checks the value, and a column type converts it for its column
+        backend.add_validation(
+            AbsCell::new(8, 1),
+            AbsCell::new(8, 1),
+            ValidationRule::NumberBetween { min: 0.0, max: 5.0 },
+        );
+        let result = backend.fill_range(
+            AbsCell::new(7, 0),
+            AbsCell::new(8, 2),
+            CellValue::Number(7.0),
+        );
+        assert!(matches!(
+            result,
+            Err(ExpressionError::Rejected(
+                ValidationError::OutOfRange { .. }
+            ))
+        ));
+        assert_eq!(
+            backend.get_cell_value(AbsCell::new(7, 0)),
+            &Ok(CellValue::Empty)
+        );
+        backend.set_column_type(2, Some(ColumnType::Text));
+        backend
+            .fill_range(
+                AbsCell::new(7, 0),
+                AbsCell::new(7, 2),
+                CellValue::Number(7.0),
+            )
+            .unwrap();
+        assert_eq!(
+            backend.get_cell_value(AbsCell::new(7, 1)),
+            &Ok(CellValue::Number(7.0))
+        );
+        assert_eq!(
+            backend.get_cell_value(AbsCell::new(7, 2)),
+            &Ok(CellValue::String("7".to_string()))
+        );
+        assert!(backend.undo());
+        assert_eq!(
+            backend.get_cell_value(AbsCell::new(7, 2)),
+            &Ok(CellValue::Empty)
+        );
+        assert!(backend.redo());
+        assert_eq!(
+            backend.get_cell_value(AbsCell::new(7, 2)),
+            &Ok(CellValue::String("7".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_apply_formula_to_range() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label: &str| AbsCell::from_str(label).unwrap();
+        for row in 0..5 {
+            backend.set_cell_value(AbsCell::new(row, 0), CellValue::Number(row as f64 + 1.0));
+        }
+
+        backend
+            .apply_formula_to_range(cell("B1"), cell("B5"), "=A1*2")
+            .unwrap();
+        for row in 1..=5 {
+            let target = cell(&format!("B{}", row));
+            assert_eq!(
+                backend.get_cell_formula(target),
+                Some(format!("A{} * 2", row))
+            );
+            assert_eq!(
+                backend.get_cell_value(target),
+                &Ok(CellValue::Number(row as f64 * 2.0))
+            );
+        }
+
+        // the whole range is one undo step
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_formula(cell("B5")), None);
+        assert_eq!(backend.get_cell_formula(cell("B1")), None);
+        assert_eq!(
+            backend.get_cell_value(cell("A5")),
+            &Ok(CellValue::Number(5.0))
+        );
+    }
+
+    #[test]
+    fn test_apply_formula_to_range_is_all_or_nothing() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label: &str| AbsCell::from_str(label).unwrap();
+        backend.set_cell_formula(cell("C3"), "B1 + 1").unwrap();
+        let before = backend.snapshot();
+
+        // B10 would read A11, off the sheet
+        let result = backend.apply_formula_to_range(cell("B1"), cell("B10"), "A2");
+        assert!(matches!(result, Err(ExpressionError::InvalidExpression)));
+        // A1 reads B3, but B1 would read C3, which reads B1
+        let result = backend.apply_formula_to_range(cell("A1"), cell("B1"), "B3");
+        assert!(matches!(result, Err(ExpressionError::CircularReference)));
+        let result = backend.apply_formula_to_range(cell("C1"), cell("C5"), "C3 +");
+        assert!(result.is_err());
+        for (top_left, bottom_right) in [("B1", "A2"), ("B2", "A1"), ("A2", "B1")] {
+            let result = backend.apply_formula_to_range(cell(top_left), cell(bottom_right), "=1");
+            assert!(matches!(result, Err(ExpressionError::ReversedRange)));
+        }
+
+        assert!(backend.snapshot().diff(&before).is_empty());
+        // nothing was recorded either
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_formula(cell("C3")), None);
+    }
+
+    #[test]
+    fn test_failed_apply_keeps_formulas_that_do_not_parse_back() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label: &str| AbsCell::from_str(label).unwrap();
+        // the number overflows, and its formula renders as text the parser refuses
+        backend.set_cell_formula(cell("A1"), "1e999").unwrap();
+        backend.set_cell_formula(cell("C2"), "A2 + 1").unwrap();
+        let before = backend.snapshot();
+
+        // A1 is written first, then A2 would read C2, which reads A2
+        let result = backend.apply_formula_to_range(cell("A1"), cell("A2"), "C1");
+        assert!(matches!(result, Err(ExpressionError::CircularReference)));
+        assert!(backend.snapshot().diff(&before).is_empty());
+        assert_eq!(
+            backend.get_cell_value(cell("A1")),
+            &Ok(CellValue::Number(f64::INFINITY))
+        );
+    }
+
+    #[test]
+    fn test_insert_row_undo_and_redo() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label: &str| AbsCell::from_str(label).unwrap();
+        for (row, value) in (0..4).zip([1.0, 2.0, 3.0, 4.0]) {
+            backend.set_cell_value(AbsCell::new(row, 0), CellValue::Number(value));
+        }
+        backend.set_cell_formula(cell("A5"), "SUM(A1:A4)").unwrap();
+        backend.set_cell_formula(cell("B1"), "A3 * 2").unwrap();
+        backend.set_cell_formula(cell("C3"), "A1 + A4").unwrap();
+        let before = backend.snapshot();
+
+        backend
+            .apply_structural(StructuralEdit::InsertRow(1))
+            .unwrap();
+        assert_eq!(backend.get_cell_value(cell("A2")), &Ok(CellValue::Empty));
+        assert_eq!(
+            backend.get_cell_value(cell("A3")),
+            &Ok(CellValue::Number(2.0))
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("A6")),
+            Some("SUM(A1:A5)".to_string())
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("B1")),
+            Some("A4 * 2".to_string())
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("C4")),
+            Some("A1 + A5".to_string())
+        );
+        assert_eq!(
+            backend.get_cell_value(cell("C4")),
+            &Ok(CellValue::Number(5.0))
+        );
+        assert!(!backend.is_populated(cell("C3")));
+
+        assert!(backend.undo());
+        assert!(backend.storage.diff(&before).is_empty());
+        assert_eq!(
+            backend.get_cell_formula(cell("A5")),
+            Some("SUM(A1:A4)".to_string())
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("B1")),
+            Some("A3 * 2".to_string())
+        );
+        assert!(!backend.is_populated(cell("A6")));
+
+        assert!(backend.redo());
+        assert_eq!(
+            backend.get_cell_formula(cell("A6")),
+            Some("SUM(A1:A5)".to_string())
+        );
+        assert!(backend.undo());
+        assert!(backend.storage.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_delete_row_and_column_undo() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = |label: &str| AbsCell::from_str(label).unwrap();
+        for (row, value) in (0..4).zip([1.0, 2.0, 3.0, 4.0]) {
+            backend.set_cell_value(AbsCell::new(row, 0), CellValue::Number(value));
+        }
+        backend.set_cell_formula(cell("A6"), "SUM(A1:A4)").unwrap();
+        backend.set_cell_formula(cell("B6"), "SUM(A4:A5)").unwrap();
+        let before = backend.snapshot();
+
+        // both ranges end on the deleted row, so they shrink
+        backend
+            .apply_structural(StructuralEdit::DeleteRow(3))
+            .unwrap();
+        assert_eq!(
+            backend.get_cell_formula(cell("A5")),
+            Some("SUM(A1:A3)".to_string())
+        );
+        assert_eq!(
+            backend.get_cell_value(cell("A5")),
+            &Ok(CellValue::Number(6.0))
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("B5")),
+            Some("SUM(A4:A4)".to_string())
+        );
+        assert!(!backend.is_populated(cell("A4")));
+
+        // shifting back alone would give SUM(A1:A3) and SUM(A5:A5)
+        assert!(backend.undo());
+        assert!(backend.storage.diff(&before).is_empty());
+        assert_eq!(
+            backend.get_cell_formula(cell("A6")),
+            Some("SUM(A1:A4)".to_string())
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("B6")),
+            Some("SUM(A4:A5)".to_string())
+        );
+
+        backend
+            .apply_structural(StructuralEdit::InsertCol(0))
+            .unwrap();
+        assert_eq!(
+            backend.get_cell_value(cell("B2")),
+            &Ok(CellValue::Number(2.0))
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("C6")),
+            Some("SUM(B4:B5)".to_string())
+        );
+        assert!(backend.undo());
+        assert!(backend.storage.diff(&before).is_empty());
+
+        // a deleted cell can not still be referenced, and an insert can not push data off
+        backend.set_cell_formula(cell("C1"), "A2 * 2").unwrap();
+        let result = backend.apply_structural(StructuralEdit::DeleteRow(1));
+        assert!(matches!(result, Err(ExpressionError::InvalidExpression)));
+        backend.set_cell_value(cell("A10"), CellValue::Number(1.0));
+        let result = backend.apply_structural(StructuralEdit::InsertRow(0));
+        assert!(matches!(result, Err(ExpressionError::InvalidExpression)));
+        assert_eq!(
+            backend.get_cell_value(cell("A2")),
+            &Ok(CellValue::Number(2.0))
+        );
+        assert_eq!(
+            backend.get_cell_formula(cell("C1")),
+            Some("A2 * 2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_freeze_cell() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let (a1, b1, c1, d1) = (
+            AbsCell::new(0, 0),
+            AbsCell::new(0, 1),
+            AbsCell::new(0, 2),
+            AbsCell::new(0, 3),
+        );
+        backend.set_cell_value(a1, CellValue::Number(2.0));
+        backend.set_cell_value(b1, CellValue::Number(3.0));
+        backend.set_cell_formula(c1, "A1 + B1").unwrap();
+        backend.set_cell_formula(d1, "C1 * 10").unwrap();
+
+        backend.freeze_cell(c1).unwrap();
+        assert_eq!(backend.get_cell_formula(c1), None);
+        backend.set_cell_value(a1, CellValue::Number(100.0));
+        assert_eq!(backend.get_cell_value(c1), &Ok(CellValue::Number(5.0)));
+        // cells reading the frozen one still follow it
+        assert_eq!(backend.get_cell_value(d1), &Ok(CellValue::Number(50.0)));
+
+        assert!(backend.undo());
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_formula(c1), Some("A1 + B1".to_string()));
+        assert!(backend.redo());
+        assert_eq!(backend.get_cell_formula(c1), None);
+        assert_eq!(backend.get_cell_value(c1), &Ok(CellValue::Number(5.0)));
+
+        // an error can not be frozen, the formula stays
+        backend.set_cell_formula(b1, "1 / 0").unwrap();
+        backend.set_cell_formula(c1, "A1 + B1").unwrap();
+        assert_eq!(backend.freeze_cell(c1), Err(CellError::DivideByZero));
+        backend.set_cell_formula(d1, "A1 * 10").unwrap();
+        assert_eq!(backend.freeze_range(a1, d1), 1);
+        assert_eq!(backend.get_cell_value(d1), &Ok(CellValue::Number(20.0)));
+        assert_eq!(backend.get_cell_formula(c1), Some("A1 + B1".to_string()));
+        assert_eq!(backend.get_cell_formula(b1), Some("1 / 0".to_string()));
+        assert_eq!(backend.get_cell_formula(d1), None);
+
+        // the whole range is frozen, and thawed, in one step
+        backend.set_cell_value(b1, CellValue::Number(1.0));
+        backend.set_cell_formula(d1, "A1 * 10").unwrap();
+        assert_eq!(backend.freeze_range(a1, d1), 2);
+        assert_eq!(backend.get_cell_formula(c1), None);
+        assert!(backend.undo());
+        assert_eq!(backend.get_cell_formula(c1), Some("A1 + B1".to_string()));
+        assert_eq!(backend.get_cell_formula(d1), Some("A1 * 10".to_string()));
+    }
+
+    #[test]
+    fn test_snapshot_is_not_affected_by_later_writes() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let a1 = AbsCell::new(0, 0);
+        let b1 = AbsCell::new(0, 1);
+        backend.set_cell_value(a1, CellValue::Number(1.0));
+        backend.set_cell_formula(b1, "A1 * 10").unwrap();
+
+        let snapshot = backend.snapshot();
+        backend.set_cell_value(a1, CellValue::Number(2.0));
+        backend
+            .set_cell_formula(AbsCell::new(5, 5), "B1 + 1")
+            .unwrap();
+        assert_eq!(backend.get_cell_value(b1), &Ok(CellValue::Number(20.0)));
+
+        let reader = {
+            let snapshot = Arc::clone(&snapshot);
+            std::thread::spawn(move || snapshot.get_value(b1).clone())
+        };
+        assert_eq!(reader.join().unwrap(), Ok(CellValue::Number(10.0)));
+        assert_eq!(snapshot.get_value(a1), &Ok(CellValue::Number(1.0)));
+        assert!(!snapshot.is_populated(AbsCell::new(5, 5)));
+    }
+
+    #[test]
+    fn test_scalar_comparisons() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        backend.set_cell_value(AbsCell::new(0, 0), CellValue::Number(7.0));
+        backend.set_cell_value(AbsCell::new(0, 1), CellValue::Number(3.0));
+        let cell = AbsCell::new(5, 5);
+        for (formula, expected) in [
+            ("(A1 > 5) + (B1 > 5)", 1.0),
+            ("(A1 > 1) + (B1 > 1)", 2.0),
+            ("A1 = 7", 1.0),
+            ("A1 <> 7", 0.0),
+            ("B1 <= 3", 1.0),
+            ("B1 < 3", 0.0),
+            ("A1 + 1 > B1 * 2", 1.0),
+            ("SUM(A1:B1) >= 10", 1.0),
+            // chained comparisons compare the previous result, as in Excel
+            ("1 < 2 < 3", 1.0),
+            ("3 > 2 > 1", 0.0),
+            ("(A1 > 5) * 100", 100.0),
+        ] {
+            backend.set_cell_formula(cell, formula).unwrap();
+            assert_eq!(
+                backend.get_cell_value(cell),
+                &Ok(CellValue::Number(expected)),
+                "{}",
+                formula
+            );
+        }
+    }
+
+    #[test]
+    fn test_n_and_t_functions() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        let cell = AbsCell::new(5, 5);
+        let check = |backend: &mut EmbeddedBackend, formula: &str, expected: CellValue| {
+            backend.set_cell_formula(cell, formula).unwrap();
+            assert_eq!(backend.get_cell_value(cell), &Ok(expected), "{}", formula);
+        };
+
+        check(&mut backend, "N(\"abc\")", CellValue::Number(0.0));
+        check(&mut backend, "N(5)", CellValue::Number(5.0));
+        check(&mut backend, "T(5)", CellValue::String(String::new()));
+        check(
+            &mut backend,
+            "T(\"abc\")",
+            CellValue::String("abc".to_string()),
+        );
+
+        // references are inspected, not coerced
+        let a1 = AbsCell::new(0, 0);
+        backend.set_cell_value(a1, CellValue::String("text".to_string()));
+        check(&mut backend, "N(A1) + 1", CellValue::Number(1.0));
+        check(&mut backend, "T(A1)", CellValue::String("text".to_string()));
+        backend.set_cell_value(a1, CellValue::Number(2.5));
+        check(&mut backend, "N(A1) + 1", CellValue::Number(3.5));
+        check(&mut backend, "T(A1)", CellValue::String(String::new()));
+        check(&mut backend, "N(B1)", CellValue::Number(0.0));
+
+        // errors pass through both
+        backend.set_cell_formula(a1, "1 / 0").unwrap();
+        backend.set_cell_formula(cell, "N(A1)").unwrap();
+        assert_eq!(backend.get_cell_value(cell), &Err(CellError::DivideByZero));
+        backend.set_cell_formula(cell, "T(A1 + 1)").unwrap();
+        assert_eq!(backend.get_cell_value(cell), &Err(CellError::DivideByZero));
+    }
+
+    #[test]
+    fn test_argmax_location() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        for (label, value) in [("B2", 4.0), ("C2", 7.5), ("B3", 12.0), ("C4", -1.0)] {
+            let cell = AbsCell::from_str(label).unwrap();
+            backend.set_cell_value(cell, CellValue::Number(value));
+        }
+        let result = AbsCell::from_str("E1").unwrap();
+        backend.set_cell_formula(result, "ARGMAX(B2:C4)").unwrap();
+        assert_eq!(
+            backend.get_cell_value(result),
+            &Ok(CellValue::String("B3".to_string()))
+        );
+
+        // the whole range is a dependency, including cells that were empty
+        backend.set_cell_value(AbsCell::from_str("C3").unwrap(), CellValue::Number(20.0));
+        assert_eq!(
+            backend.get_cell_value(result),
+            &Ok(CellValue::String("C3".to_string()))
+        );
+
+        backend.set_cell_formula(result, "ARGMIN(B2:C4)").unwrap();
+        assert_eq!(
+            backend.get_cell_value(result),
+            &Ok(CellValue::String("C4".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_insert_totals() {
+        let mut backend = EmbeddedBackend::new(10, 10);
+        for row in 0..3 {
+            for col in 0..3 {
+                let value = (row * 3 + col + 1) as f64;
+                backend.set_cell_value(AbsCell::new(row + 1, col + 1), CellValue::Number(value));
+            }
+        }
+        let top_left = AbsCell::from_str("B2").unwrap();
+        let bottom_right = AbsCell::from_str("D4").unwrap();
+
+        backend
+            .insert_totals(top_left, bottom_right, TotalsDirection::Below)
+            .unwrap();
+        for (label, total) in [("B5", 12.0), ("C5", 15.0), ("D5", 18.0)] {
+            let cell = AbsCell::from_str(label).unwrap();
+            assert_eq!(backend.get_cell_value(cell), &Ok(CellValue::Number(total)));
+        }
+        assert_eq!(
+            backend.get_cell_formula(AbsCell::from_str("C5").unwrap()),
+            Some("SUM(C2:C4)".to_string())
+        );
+
+        backend
+            .insert_totals(top_left, bottom_right, TotalsDirection::Right)
+            .unwrap();
+        let e3 = AbsCell::from_str("E3").unwrap();
+        assert_eq!(backend.get_cell_value(e3), &Ok(CellValue::Number(15.0)));
+
+        // the totals update with the data
+        backend.set_cell_value(AbsCell::from_str("C3").unwrap(), CellValue::Number(0.0));
+        assert_eq!(backend.get_cell_value(e3), &Ok(CellValue::Number(10.0)));
+
+        // each set of totals is undone in one step
+        assert!(backend.undo());
+        assert!(backend.undo());
+        for label in ["E2", "E3", "E4"] {
+            assert!(!backend.is_populated(AbsCell::from_str(label).unwrap()));
+        }
+        assert!(backend.is_populated(AbsCell::from_str("D5").unwrap()));
+
+        let err = backend.insert_totals(
+            AbsCell::from_str("A8").unwrap(),
+            AbsCell::from_str("B10").unwrap(),
+            TotalsDirection::Below,
+        );
+        assert!(err.is_err());
+        assert!(!backend.is_populated(AbsCell::from_str("A11").unwrap()));
+    }
 }
@@ -1,8 +1,9 @@
 //! The structs in this module are used to model the single interaction of the user with the spreadsheet.
 //! This can be used to implement collaboration, where interactions are send and each client calculates the
 //! effect of that interaction on its own end
-use crate::common::cell_value::CellValue;
+use crate::common::cell_value::{CellError, CellValue};
 use crate::common::structs::AbsCell;
+use crate::embedded_backend::table::StructuralUndo;
 
 pub enum CellInput {
     Value(CellValue),
@@ -13,4 +14,44 @@ pub struct Action {
     pub cell: AbsCell,
     pub old_value: CellInput,
     pub new_value: CellInput,
+    /// What the cell showed before the edit, kept in case its formula can not be put back
+    pub old_result: Result<CellValue, CellError>,
+    /// What the cell showed after the edit
+    pub new_result: Result<CellValue, CellError>,
+}
+
+/// Inserting or deleting a whole row or column, by its 0-based index. Inserting moves the
+/// row or column at the index and everything after it one step down or right
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StructuralEdit {
+    InsertRow(i16),
+    DeleteRow(i16),
+    InsertCol(i16),
+    DeleteCol(i16),
+}
+
+impl StructuralEdit {
+    /// The edit that moves every remaining cell back where it was
+    pub fn inverse(self) -> Self {
+        match self {
+            StructuralEdit::InsertRow(row) => StructuralEdit::DeleteRow(row),
+            StructuralEdit::DeleteRow(row) => StructuralEdit::InsertRow(row),
+            StructuralEdit::InsertCol(col) => StructuralEdit::DeleteCol(col),
+            StructuralEdit::DeleteCol(col) => StructuralEdit::InsertCol(col),
+        }
+    }
+}
+
+/// A structural edit along with what undoing it has to put back
+pub struct StructuralAction {
+    pub edit: StructuralEdit,
+    pub undo: StructuralUndo,
+}
+
+/// An entry of the undo and redo stacks
+pub enum Edit {
+    Cell(Action),
+    /// Cell edits undone and redone together, in reverse order for undo
+    Cells(Vec<Action>),
+    Structural(StructuralAction),
 }
@@ -4,48 +4,287 @@
 //!
 //! Each storage is a separate spreadsheet, multiple storages can be created
 //! and used independently.
+//!
+//! Every cell caches its evaluated value. Formulas are evaluated only when `set_value` or
+//! `set_expression` changes a cell they depend on; all the getters read the cached values,
+//! so reading (e.g. rendering every frame) never re-runs a formula or a `SLEEP`.
 
 use crate::common::cell_data::CellMetadata;
 use crate::common::cell_value::{CellData, CellError, CellValue};
-use crate::common::expression::Expression;
+use crate::common::expression::{CellRange, Expression};
+use crate::common::number::OrderedF64;
 use crate::common::structs::AbsCell;
-use crate::embedded_backend::calc_engine::evaluate;
-use crate::embedded_backend::structs::CellInput;
-use bincode;
+use crate::common::validation::{ColumnType, ValidationError, ValidationRule};
+use crate::embedded_backend::calc_engine::{EvalContext, evaluate_value};
+use crate::embedded_backend::structs::{CellInput, StructuralEdit};
+use crate::error_display::ErrorLabel;
+use bincode::{self, Options};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{self, Display};
 use std::fs::File;
-use std::io::{self};
+use std::io::{self, Read};
+use std::ops::Bound::{Excluded, Unbounded};
 
 /// The storage internally uses a BTreeMap to store the cell values and
 /// a HashMap to store the cell dependencies.
 /// The BTreeMap allows for efficient range queries and the HashMap allows for
 /// efficient dependency tracking.
 ///
+/// Ranges read by `SUM(A1:A1000000)` and the like are kept as a single entry in
+/// `range_dependents` instead of an edge from every cell they cover, so declaring a huge
+/// range over sparse data stays cheap.
+///
 /// Using a BTreeMap allows to return a range iterator which can skip all the empty cells.
 /// This greatly improves performance when dealing with sparse data.
 /// Also allows incremental searching for a string in the cells. The order the search is done
 /// is left to right, top to bottom. This is the same order as the user would expect
 /// when searching for a string in a spreadsheet.
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Storage {
     rows: u16,
     cols: u16,
     values: BTreeMap<AbsCell, CellData>,
     graph: HashMap<AbsCell, CellMetadata>,
+    /// The ranges read by formulas, as `(top_left, bottom_right, dependent)`.
+    /// Not saved with the sheet, rebuilt from the formulas on load
+    #[serde(skip)]
+    range_dependents: Vec<(AbsCell, AbsCell, AbsCell)>,
+    /// How formulas read empty cells, see `EvalContext::empty_is_zero`.
+    /// Not saved with the sheet, so files from before the setting existed still load
+    #[serde(skip, default = "default_empty_is_zero")]
+    empty_is_zero: bool,
+    /// The most cells a formula may read, counting every cell of its ranges. None for no
+    /// limit. Not saved with the sheet
+    #[serde(skip)]
+    max_referenced_cells: Option<usize>,
+    /// Validation rules and the rectangle each applies to. Later rules take precedence
+    validations: Vec<(AbsCell, AbsCell, ValidationRule)>,
+    /// The type each constrained column holds, by column index
+    column_types: BTreeMap<i16, ColumnType>,
+    // New saved fields go at the end, and are read by `deserialize_older` too
 }
 
-static EMPTY_HASHSET: once_cell::sync::Lazy<HashSet<AbsCell>> =
-    once_cell::sync::Lazy::new(HashSet::new);
+fn default_empty_is_zero() -> bool {
+    true
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage::new(0, 0)
+    }
+}
+
+/// The cells and ranges an expression reads, as absolute positions
+#[derive(Default)]
+struct References {
+    cells: HashSet<AbsCell>,
+    ranges: Vec<(AbsCell, AbsCell)>,
+}
+
+impl References {
+    /// How many cells are read, counting every cell of each range and overlaps more than once
+    fn cell_count(&self) -> usize {
+        let area = |(top_left, bottom_right): &(AbsCell, AbsCell)| {
+            let rows = (bottom_right.row - top_left.row + 1).max(0) as usize;
+            let cols = (bottom_right.col - top_left.col + 1).max(0) as usize;
+            rows * cols
+        };
+        self.cells.len() + self.ranges.iter().map(area).sum::<usize>()
+    }
+
+    fn reads(&self, cell: AbsCell) -> bool {
+        self.cells.contains(&cell)
+            || self.ranges.iter().any(|(top_left, bottom_right)| {
+                Storage::in_rectangle(cell, *top_left, *bottom_right)
+            })
+    }
+}
+
+/// What `undo_structural` needs to reverse a structural edit exactly: the cells the edit
+/// removed or could not move losslessly, as they were before it, the validation rules and the
+/// column types
+#[derive(Debug, Clone)]
+pub struct StructuralUndo {
+    cells: Vec<(AbsCell, CellData)>,
+    validations: Vec<(AbsCell, AbsCell, ValidationRule)>,
+    column_types: BTreeMap<i16, ColumnType>,
+}
+
+/// How a structural edit moves positions along its axis
+struct Shift {
+    rows: bool,
+    index: i16,
+    insert: bool,
+    /// The last index on the sheet along the axis
+    last: i16,
+}
+
+/// What shifting a formula's references did besides moving them
+#[derive(Default)]
+struct ShiftNotes {
+    /// A range end landed where shifting back does not restore it
+    lossy: bool,
+    /// A range gained or lost a line, so the formula has to be evaluated again
+    resized: bool,
+}
+
+impl Shift {
+    fn new(edit: StructuralEdit, rows: u16, cols: u16) -> Self {
+        let (along_rows, index, insert) = match edit {
+            StructuralEdit::InsertRow(index) => (true, index, true),
+            StructuralEdit::DeleteRow(index) => (true, index, false),
+            StructuralEdit::InsertCol(index) => (false, index, true),
+            StructuralEdit::DeleteCol(index) => (false, index, false),
+        };
+        let size = if along_rows { rows } else { cols };
+        Shift {
+            rows: along_rows,
+            index,
+            insert,
+            last: size as i16 - 1,
+        }
+    }
+
+    fn along(&self, cell: AbsCell) -> i16 {
+        if self.rows { cell.row } else { cell.col }
+    }
+
+    fn with(&self, cell: AbsCell, along: i16) -> AbsCell {
+        if self.rows {
+            AbsCell::new(along, cell.col)
+        } else {
+            AbsCell::new(cell.row, along)
+        }
+    }
+
+    /// Where the cell ends up, None if it is deleted or pushed off the sheet
+    fn cell(&self, cell: AbsCell) -> Option<AbsCell> {
+        let x = self.along(cell);
+        let x = if self.insert {
+            x + (x >= self.index) as i16
+        } else if x == self.index {
+            return None;
+        } else {
+            x - (x > self.index) as i16
+        };
+        (x <= self.last).then(|| self.with(cell, x))
+    }
+
+    /// Where the corners of a range end up. A range grows when a line is inserted inside
+    /// it and shrinks when one of its lines is deleted. Its end is kept on the sheet when
+    /// pushed off it. None if nothing of the range is left
+    fn range(
+        &self,
+        top_left: AbsCell,
+        bottom_right: AbsCell,
+        notes: &mut ShiftNotes,
+    ) -> Option<(AbsCell, AbsCell)> {
+        let (lo, hi) = (self.along(top_left), self.along(bottom_right));
+        let (new_lo, new_hi) = if self.insert {
+            (
+                lo + (lo >= self.index) as i16,
+                hi + (hi >= self.index) as i16,
+            )
+        } else {
+            (
+                lo - (lo > self.index) as i16,
+                hi - (hi >= self.index) as i16,
+            )
+        };
+        if new_lo > new_hi || new_lo > self.last {
+            return None;
+        }
+        let clamped = new_hi.min(self.last);
+        notes.lossy |=
+            clamped != new_hi || (!self.insert && (lo == self.index || hi == self.index));
+        notes.resized |= clamped - new_lo != hi - lo;
+        Some((
+            self.with(top_left, new_lo),
+            self.with(bottom_right, clamped),
+        ))
+    }
+}
 
 /// Error types that can occur during set operations.
 /// The storage can reject a formula if it causes a circular dependency or contains an out of bounds cell.
 pub enum StorageError {
     CircularDependency,
     InvalidCell,
+    /// The formula reads more cells than `Storage::max_referenced_cells` allows
+    RangeTooLarge,
     None,
 }
 
+/// Why a saved sheet could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file was read but does not hold a sheet: it is truncated, corrupt or was saved by
+    /// an incompatible version.
+    Corrupt(bincode::Error),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}: {}", self.label(), e),
+            LoadError::Corrupt(e) => write!(f, "{}: {}", self.label(), e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// How a cell differs between two sheets, see `Storage::diff`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellDiff {
+    /// The cell is only populated in the new sheet.
+    Added(CellData),
+    /// The cell is only populated in the old sheet.
+    Removed(CellData),
+    /// The cell is populated in both, with a different value and/or formula.
+    Changed { old: CellData, new: CellData },
+}
+
+impl CellDiff {
+    /// One line summary of the difference at `cell`, like `B2: changed 4 -> =A1 * 2 (6)`
+    pub fn describe(&self, cell: AbsCell) -> String {
+        let show = |data: &CellData| {
+            let value = match &data.value {
+                Ok(CellValue::Number(num)) => num.to_string(),
+                Ok(CellValue::String(text)) => format!("{:?}", text),
+                Ok(CellValue::Empty) => String::new(),
+                Err(err) => err.label().to_string(),
+            };
+            match &data.formula {
+                Some(formula) => format!("={} ({})", formula.to_string(cell), value),
+                None => value,
+            }
+        };
+        match self {
+            CellDiff::Added(new) => format!("{}: added {}", cell, show(new)),
+            CellDiff::Removed(old) => format!("{}: removed {}", cell, show(old)),
+            CellDiff::Changed { old, new } => {
+                format!("{}: changed {} -> {}", cell, show(old), show(new))
+            }
+        }
+    }
+}
+
+impl From<bincode::Error> for LoadError {
+    fn from(err: bincode::Error) -> Self {
+        match *err {
+            // running out of bytes mid-sheet means the file is truncated, not unreadable
+            bincode::ErrorKind::Io(e) if e.kind() != io::ErrorKind::UnexpectedEof => {
+                LoadError::Io(e)
+            }
+            kind => LoadError::Corrupt(Box::new(kind)),
+        }
+    }
+}
+
 impl Storage {
     pub fn new(rows: u16, cols: u16) -> Self {
         Storage {
@@ -53,13 +292,158 @@ impl Storage {
             cols,
             values: BTreeMap::new(),
             graph: HashMap::new(),
+            range_dependents: Vec::new(),
+            empty_is_zero: default_empty_is_zero(),
+            max_referenced_cells: None,
+            validations: Vec::new(),
+            column_types: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a validation rule for every cell in the rectangle, taking precedence over
+    /// rules added before. Values already in the cells are not checked
+    pub fn add_validation(
+        &mut self,
+        top_left: AbsCell,
+        bottom_right: AbsCell,
+        rule: ValidationRule,
+    ) {
+        self.validations.push((top_left, bottom_right, rule));
+    }
+
+    /// Removes every validation rule that applies to `cell`
+    pub fn remove_validations_at(&mut self, cell: AbsCell) {
+        self.validations.retain(|(top_left, bottom_right, _)| {
+            !Self::in_rectangle(cell, *top_left, *bottom_right)
+        });
+    }
+
+    /// The rule in effect for `cell`, if any
+    pub fn validation_at(&self, cell: AbsCell) -> Option<&ValidationRule> {
+        self.validations
+            .iter()
+            .rev()
+            .find(|(top_left, bottom_right, _)| Self::in_rectangle(cell, *top_left, *bottom_right))
+            .map(|(_, _, rule)| rule)
+    }
+
+    /// Checks `value` against the rule in effect for `cell`. Does not set anything
+    pub fn validate(&self, cell: AbsCell, value: &CellValue) -> Result<(), ValidationError> {
+        match self.validation_at(cell) {
+            Some(rule) => rule.check(value),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes every value entered into column `col` have the type, or lifts the constraint
+    /// with None. Values already in the column are not checked
+    pub fn set_column_type(&mut self, col: i16, column_type: Option<ColumnType>) {
+        match column_type {
+            Some(column_type) => self.column_types.insert(col, column_type),
+            None => self.column_types.remove(&col),
+        };
+    }
+
+    pub fn column_type(&self, col: i16) -> Option<ColumnType> {
+        self.column_types.get(&col).copied()
+    }
+
+    /// Converts `value` to the type of the column of `cell`, see `ColumnType::coerce`.
+    /// Does not set anything
+    ///
+    /// returns: the converted value, or None if it needs no conversion
+    pub fn coerce(
+        &self,
+        cell: AbsCell,
+        value: &CellValue,
+    ) -> Result<Option<CellValue>, ValidationError> {
+        match self.column_type(cell.col) {
+            Some(column_type) => column_type.coerce(value),
+            None => Ok(None),
         }
     }
+
+    fn in_rectangle(cell: AbsCell, top_left: AbsCell, bottom_right: AbsCell) -> bool {
+        (top_left.row..=bottom_right.row).contains(&cell.row)
+            && (top_left.col..=bottom_right.col).contains(&cell.col)
+    }
+
+    pub fn empty_is_zero(&self) -> bool {
+        self.empty_is_zero
+    }
+
+    pub fn max_referenced_cells(&self) -> Option<usize> {
+        self.max_referenced_cells
+    }
+
+    /// Limits how many cells a formula set from now on may read, counting every cell of its
+    /// ranges, so a formula like `SUM(A1:ZZ999)` can be refused up front. Formulas already
+    /// set are kept. None, the default, removes the limit
+    pub fn set_max_referenced_cells(&mut self, limit: Option<usize>) {
+        self.max_referenced_cells = limit;
+    }
+
+    /// Sets whether formulas read empty cells as `0` or as an error, and recomputes every
+    /// formula under the new setting
+    pub fn set_empty_is_zero(&mut self, empty_is_zero: bool) {
+        if self.empty_is_zero == empty_is_zero {
+            return;
+        }
+        self.empty_is_zero = empty_is_zero;
+        self.recalculate_all();
+    }
+
+    /// Re-evaluates every formula, dependencies first, replacing all the cached values
+    pub fn recalculate_all(&mut self) {
+        let formula_cells: Vec<AbsCell> = self
+            .values
+            .iter()
+            .filter(|(_, data)| data.formula.is_some())
+            .map(|(cell, _)| *cell)
+            .collect();
+        self.update_cells(&formula_cells);
+    }
+    /// Returns the cached value of the cell. Never evaluates the cell's formula
     pub fn get_value(&self, cell: AbsCell) -> &Result<CellValue, CellError> {
         let x = self.values.get(&cell).map(|cell_data| &cell_data.value);
         x.unwrap_or(&Ok(CellValue::Empty))
     }
 
+    /// How many rows the sheet has
+    pub fn rows(&self) -> u16 {
+        self.rows
+    }
+
+    /// How many columns the sheet has
+    pub fn cols(&self) -> u16 {
+        self.cols
+    }
+
+    /// Whether the cell lies on the sheet
+    pub fn in_bounds(&self, cell: AbsCell) -> bool {
+        (0..self.rows as i16).contains(&cell.row) && (0..self.cols as i16).contains(&cell.col)
+    }
+
+    /// Whether the cell holds anything, as opposed to never having been set or having been
+    /// cleared. A cell set to `0` is populated
+    pub fn is_populated(&self, cell: AbsCell) -> bool {
+        self.values.contains_key(&cell)
+    }
+
+    /// The smallest rectangle holding every populated cell, as `(top_left, bottom_right)`.
+    /// None if the sheet is empty
+    pub fn used_range(&self) -> Option<(AbsCell, AbsCell)> {
+        // keys are ordered by row first, so only the columns need a scan
+        let first_row = self.values.keys().next()?.row;
+        let last_row = self.values.keys().next_back()?.row;
+        let first_col = self.values.keys().map(|cell| cell.col).min()?;
+        let last_col = self.values.keys().map(|cell| cell.col).max()?;
+        Some((
+            AbsCell::new(first_row, first_col),
+            AbsCell::new(last_row, last_col),
+        ))
+    }
+
     /// Returns the formula of the cell if it exists, otherwise returns None
     /// This string is not cached, it is generated on the fly from the expression
     pub fn get_cell_formula(&self, cell: AbsCell) -> Option<String> {
@@ -68,16 +452,118 @@ impl Storage {
         Some(x.to_string(cell))
     }
 
-    /// Sets the value of the cell and recomputes its dependants
+    /// Returns the value and the formula of the cell from a single lookup.
+    /// The formula string is generated on the fly, as in `get_cell_formula`
+    pub fn get_cell(&self, cell: AbsCell) -> (&Result<CellValue, CellError>, Option<String>) {
+        match self.values.get(&cell) {
+            Some(data) => (
+                &data.value,
+                data.formula.as_ref().map(|formula| formula.to_string(cell)),
+            ),
+            None => (&Ok(CellValue::Empty), None),
+        }
+    }
+
+    /// Whether the cell holds a formula, without rendering it like `get_cell_formula`
+    pub fn has_formula(&self, cell: AbsCell) -> bool {
+        self.values
+            .get(&cell)
+            .is_some_and(|data| data.formula.is_some())
+    }
+
+    /// A copy of the value and formula expression of the cell, empty if it is not populated
+    pub fn get_cell_data(&self, cell: AbsCell) -> CellData {
+        self.values
+            .get(&cell)
+            .unwrap_or(CellData::default_instance())
+            .clone()
+    }
+
+    /// Sets the value of the cell, replacing its formula if it has one, and recomputes its
+    /// dependants
     pub fn set_value(&mut self, cell: AbsCell, value: CellValue) {
+        self.replace_with_value(cell, value);
+        self.update_cells(&[cell]);
+    }
+
+    /// Puts the cell back as `get_cell_data` gave it, formula and all, and recomputes it and
+    /// its dependants. Unlike `set_expression`, the formula is not checked: it must be one the
+    /// sheet held before, as when rolling back edits refused partway through
+    pub fn restore_cell(&mut self, cell: AbsCell, data: CellData) {
+        let Some(formula) = data.formula else {
+            self.set_value(cell, data.value.unwrap_or(CellValue::Empty));
+            return;
+        };
+        self.replace_with_value(cell, CellValue::Empty);
+        self.add_edges(cell, &Self::collect_references(&formula, cell));
+        self.values.insert(
+            cell,
+            CellData {
+                value: data.value,
+                formula: Some(formula),
+            },
+        );
+        self.update_cells(&[cell]);
+    }
+
+    /// Evaluates `query` as if each cell in `overrides` held the given value instead of its
+    /// value or formula. Works on a copy, so the storage itself is never changed
+    pub fn what_if(
+        &self,
+        overrides: &[(AbsCell, CellValue)],
+        query: AbsCell,
+    ) -> Result<CellValue, CellError> {
+        let mut scenario = self.clone();
+        for (cell, value) in overrides {
+            scenario.replace_with_value(*cell, value.clone());
+        }
+        let cells: Vec<AbsCell> = overrides.iter().map(|(cell, _)| *cell).collect();
+        scenario.update_cells(&cells);
+        scenario.get_value(query).clone()
+    }
+
+    /// Sets every cell of the closed rectangle to `value`, dropping their formulas, then
+    /// recomputes the dependants of all of them in a single pass, so a cell reading several
+    /// of the filled cells is evaluated once.
+    ///
+    /// Changes nothing if the rectangle is not inside the sheet
+    pub fn fill_range(
+        &mut self,
+        top_left: AbsCell,
+        bottom_right: AbsCell,
+        value: CellValue,
+    ) -> StorageError {
+        if !self.in_bounds(top_left) || !self.in_bounds(bottom_right) {
+            return StorageError::InvalidCell;
+        }
+        let mut cells = Vec::new();
+        for row in top_left.row..=bottom_right.row {
+            for col in top_left.col..=bottom_right.col {
+                let cell = AbsCell::new(row, col);
+                self.replace_with_value(cell, value.clone());
+                cells.push(cell);
+            }
+        }
+        self.update_cells(&cells);
+        StorageError::None
+    }
+
+    /// Stores `value` in the cell without recomputing anything. Any formula is dropped, as it
+    /// would otherwise recompute the cell and undo the new value
+    fn replace_with_value(&mut self, cell: AbsCell, value: CellValue) {
+        let formula = self
+            .values
+            .get_mut(&cell)
+            .and_then(|data| data.formula.take());
+        if let Some(formula) = formula {
+            let references = Self::collect_references(&formula, cell);
+            self.remove_edges(cell, &references);
+        }
         if value == CellValue::Empty {
             self.values.remove(&cell);
         } else {
-            let cell_data = self.values.entry(cell).or_default();
-            cell_data.value = Ok(value);
+            self.values.entry(cell).or_default().value = Ok(value);
         }
-        //        self.graph.remove(&cell);
-        self.update_cells(cell);
     }
 
     /// Gives a sparse iterator over a closed rectangle of cells. Returns only the cells that
@@ -121,11 +607,30 @@ impl Storage {
         FullRangeIter::new(top_left, bottom_right, &self.values)
     }
 
-    fn get_dep(&self, cell: AbsCell) -> &HashSet<AbsCell> {
-        self.graph
+    /// The populated cells of column `col` with their rows, top to bottom
+    pub fn column_iter(&self, col: i16) -> impl Iterator<Item = (i16, &CellData)> {
+        self.values
+            .iter()
+            .filter(move |(cell, _)| cell.col == col)
+            .map(|(cell, data)| (cell.row, data))
+    }
+
+    /// The cells whose formulas read `cell`, directly or through a range. A formula reading
+    /// it more than one way, like `A1 + SUM(A1:A3)`, comes up once for each
+    fn get_dep(&self, cell: AbsCell) -> impl Iterator<Item = AbsCell> + '_ {
+        let direct = self
+            .graph
             .get(&cell)
-            .map(|x| &x.dependents)
-            .unwrap_or(&EMPTY_HASHSET)
+            .into_iter()
+            .flat_map(|metadata| metadata.dependents.iter().copied());
+        let through_ranges = self
+            .range_dependents
+            .iter()
+            .filter(move |(top_left, bottom_right, _)| {
+                Self::in_rectangle(cell, *top_left, *bottom_right)
+            })
+            .map(|(_, _, dependent)| *dependent);
+        direct.chain(through_ranges)
     }
 
     /// Simply recalculates the value of the cell, if it has a formula.
@@ -134,44 +639,83 @@ impl Storage {
         let exp = self.values.get(&cell);
         if let Some(exp) = exp {
             if let Some(exp) = &exp.formula {
-                let res = evaluate(self, cell, exp).map(CellValue::Number);
+                let ctx = EvalContext {
+                    storage: self,
+                    cell,
+                    empty_is_zero: self.empty_is_zero,
+                };
+                let res = evaluate_value(&ctx, exp);
                 self.values.entry(cell).or_default().value = res;
             }
         }
     }
 
-    fn update_cells(&mut self, cell: AbsCell) {
-        let mut stack = vec![cell];
-        let mut dirty_parents = HashMap::new();
-        dirty_parents.insert(cell, 0u8);
+    /// Recalculates the given cells and everything depending on them, each cell only after all
+    /// of its dirty parents
+    fn update_cells(&mut self, roots: &[AbsCell]) {
+        #[cfg(feature = "recalc-log")]
+        let start = std::time::Instant::now();
+        let order = self.recalc_order_from(roots);
+        #[cfg(feature = "recalc-log")]
+        let dirty = order.len();
+        for cell in order {
+            self.recalculate_cell(cell);
+        }
+        #[cfg(feature = "recalc-log")]
+        log::debug!(
+            target: "embedded::recalc",
+            "recalculated {} cells from {:?} in {:?}",
+            dirty,
+            roots,
+            start.elapsed()
+        );
+    }
+
+    /// The order in which editing `start` recomputes cells: `start` itself, then every cell
+    /// that reads it, directly or not, each after all the cells it reads. Nothing is
+    /// recomputed
+    pub fn recalc_order(&self, start: AbsCell) -> Vec<AbsCell> {
+        self.recalc_order_from(&[start])
+    }
+
+    fn recalc_order_from(&self, roots: &[AbsCell]) -> Vec<AbsCell> {
+        let mut order = Vec::new();
+        let mut stack = roots.to_vec();
+        let mut visited: HashSet<AbsCell> = roots.iter().copied().collect();
+        let mut dirty_parents: HashMap<AbsCell, u32> = HashMap::new();
 
         //dirty marking
+        // a cell read more than one way counts once per way, here and below alike
         while let Some(top) = stack.pop() {
             for x in self.get_dep(top) {
-                let count = dirty_parents.entry(*x).or_default();
-                if *count == 0 {
-                    stack.push(*x);
+                *dirty_parents.entry(x).or_default() += 1;
+                if visited.insert(x) {
+                    stack.push(x);
                 }
-                *count += 1;
             }
         }
 
-        //now start recalculation from here
-        stack.push(cell);
+        //now start recalculation from the roots that no other dirty cell feeds into
+        stack.extend(
+            roots
+                .iter()
+                .filter(|root| !dirty_parents.contains_key(root)),
+        );
 
         while let Some(top) = stack.pop() {
-            self.recalculate_cell(top);
+            order.push(top);
 
             for x in self.get_dep(top) {
                 let cnt = dirty_parents
-                    .get_mut(x)
+                    .get_mut(&x)
                     .expect("complete chain already inserted");
                 *cnt -= 1;
                 if *cnt == 0 {
-                    stack.push(*x);
+                    stack.push(x);
                 }
             }
         }
+        order
     }
 
     /// Updates the graph according to the new expression.
@@ -186,101 +730,168 @@ impl Storage {
     ///
     /// returns: bool
     pub fn set_expression(&mut self, cell: AbsCell, expression: Expression) -> StorageError {
-        let cell_data = self.values.get(&cell);
+        #[cfg(feature = "recalc-log")]
+        log::debug!(target: "embedded::recalc", "setting the formula of {}", cell);
+        let new_references = Self::collect_references(&expression, cell);
+        let out_of_bounds = new_references.cells.iter().any(|x| !self.in_bounds(*x))
+            || new_references
+                .ranges
+                .iter()
+                .any(|(top_left, bottom_right)| {
+                    !self.in_bounds(*top_left) || !self.in_bounds(*bottom_right)
+                });
+        if out_of_bounds {
+            return StorageError::InvalidCell;
+        }
+        let too_large = self
+            .max_referenced_cells
+            .is_some_and(|limit| new_references.cell_count() > limit);
+        if too_large {
+            return StorageError::RangeTooLarge;
+        }
 
         //remove old edges
-        if let Some(cell_data) = cell_data {
-            if let Some(old_exp) = &cell_data.formula {
-                //remove
-                let mut referenced_cells = HashSet::new();
-                Self::collect_referenced_cells(old_exp, cell, &mut referenced_cells);
-
-                for referenced_cell in referenced_cells {
-                    if let Some(metadata) = self.graph.get_mut(&referenced_cell) {
-                        metadata.dependents.remove(&cell);
-                    }
-                }
-            }
+        let old_references = self
+            .values
+            .get(&cell)
+            .and_then(|cell_data| cell_data.formula.as_ref())
+            .map(|old_exp| Self::collect_references(old_exp, cell));
+        if let Some(old_references) = &old_references {
+            self.remove_edges(cell, old_references);
         }
 
         //add new
-        let mut referenced_cells = HashSet::new();
-        Self::collect_referenced_cells(&expression, cell, &mut referenced_cells);
+        self.add_edges(cell, &new_references);
 
-        if referenced_cells.iter().any(|x| {
-            x.row < 0 || x.col < 0 || x.row >= self.rows as i16 || x.col >= self.cols as i16
-        }) {
-            return StorageError::InvalidCell;
+        if self.check_circular(cell) {
+            self.remove_edges(cell, &new_references);
+            if let Some(old_references) = &old_references {
+                self.add_edges(cell, old_references);
+            }
+            return StorageError::CircularDependency;
         }
 
-        for referenced_cell in referenced_cells {
+        let cell_data = self.values.entry(cell).or_default();
+        cell_data.formula = Some(expression);
+        self.update_cells(&[cell]);
+        StorageError::None
+    }
+
+    fn add_edges(&mut self, cell: AbsCell, references: &References) {
+        for referenced_cell in &references.cells {
             self.graph
-                .entry(referenced_cell)
+                .entry(*referenced_cell)
                 .or_default()
                 .dependents
                 .insert(cell);
         }
+        for (top_left, bottom_right) in &references.ranges {
+            self.range_dependents.push((*top_left, *bottom_right, cell));
+        }
+    }
 
-        if self.check_circular(cell) {
-            //remove
-            let mut referenced_cells = HashSet::new();
-            Self::collect_referenced_cells(&expression, cell, &mut referenced_cells);
-
-            for referenced_cell in referenced_cells {
-                if let Some(metadata) = self.graph.get_mut(&referenced_cell) {
-                    metadata.dependents.remove(&cell);
-                }
-            }
-            if let Some(cell_data) = cell_data {
-                if let Some(old_exp) = &cell_data.formula {
-                    //add old
-                    let mut referenced_cells = HashSet::new();
-                    Self::collect_referenced_cells(old_exp, cell, &mut referenced_cells);
-
-                    for referenced_cell in referenced_cells {
-                        self.graph
-                            .entry(referenced_cell)
-                            .or_default()
-                            .dependents
-                            .insert(cell);
-                    }
-                }
+    fn remove_edges(&mut self, cell: AbsCell, references: &References) {
+        for referenced_cell in &references.cells {
+            if let Some(metadata) = self.graph.get_mut(referenced_cell) {
+                metadata.dependents.remove(&cell);
             }
-            return StorageError::CircularDependency;
         }
+        if !references.ranges.is_empty() {
+            self.range_dependents
+                .retain(|(_, _, dependent)| *dependent != cell);
+        }
+    }
 
-        let cell_data = self.values.entry(cell).or_default();
-        cell_data.formula = Some(expression);
-        self.update_cells(cell);
-        StorageError::None
+    /// Rebuilds the dependency graph and the range entries from the formulas. Sheets saved
+    /// before ranges were tracked as single entries hold an edge for every cell in a range,
+    /// this replaces them
+    fn rebuild_dependencies(&mut self) {
+        self.graph.clear();
+        self.range_dependents.clear();
+        let formulas: Vec<(AbsCell, References)> = self
+            .values
+            .iter()
+            .filter_map(|(cell, data)| {
+                let formula = data.formula.as_ref()?;
+                Some((*cell, Self::collect_references(formula, *cell)))
+            })
+            .collect();
+        for (cell, references) in &formulas {
+            self.add_edges(*cell, references);
+        }
+    }
+
+    fn collect_references(expression: &Expression, cell: AbsCell) -> References {
+        let mut references = References::default();
+        Self::collect_referenced_cells(expression, cell, &mut references);
+        references
     }
 
     fn collect_referenced_cells(
         expression: &Expression,
         cell: AbsCell,
-        referenced_cells: &mut HashSet<AbsCell>,
+        references: &mut References,
     ) {
         match expression {
             Expression::Cell(rel_cell) => {
-                referenced_cells.insert(rel_cell.to_abs(cell));
+                references.cells.insert(rel_cell.to_abs(cell));
             }
             Expression::BinaryOp(lhs, _, rhs) => {
-                Self::collect_referenced_cells(lhs, cell, referenced_cells);
-                Self::collect_referenced_cells(rhs, cell, referenced_cells);
+                Self::collect_referenced_cells(lhs, cell, references);
+                Self::collect_referenced_cells(rhs, cell, references);
             }
-            Expression::RangeFunction(_, range) => {
-                let top_left = range.top_left.to_abs(cell);
-                let bottom_right = range.bottom_right.to_abs(cell);
-                for row in top_left.row..=bottom_right.row {
-                    for col in top_left.col..=bottom_right.col {
-                        referenced_cells.insert(AbsCell::new(row, col));
-                    }
+            Expression::RangeFunction(_, range) | Expression::Location(_, range) => {
+                references
+                    .ranges
+                    .push((range.top_left.to_abs(cell), range.bottom_right.to_abs(cell)));
+            }
+            Expression::SumProduct(first, second) => {
+                for range in [first, second] {
+                    references
+                        .ranges
+                        .push((range.top_left.to_abs(cell), range.bottom_right.to_abs(cell)));
                 }
             }
-            Expression::Sleep(inner) => {
-                Self::collect_referenced_cells(inner, cell, referenced_cells);
+            Expression::Sleep(inner)
+            | Expression::SleepMs(inner)
+            | Expression::Coerce(_, inner) => {
+                Self::collect_referenced_cells(inner, cell, references);
+            }
+            Expression::Number(_) | Expression::Text(_) => {}
+        }
+    }
+
+    /// Follows an error back to the cell it originated in: the formula that divided by zero
+    /// or read a non-number, as opposed to one that only read another error.
+    /// Of several erroring precedents the first in row-major order is followed.
+    ///
+    /// returns: the originating cell, `cell` itself if it is the origin, None if `cell` does
+    /// not hold an error
+    pub fn trace_error(&self, cell: AbsCell) -> Option<AbsCell> {
+        self.get_value(cell).as_ref().err()?;
+        let mut current = cell;
+        loop {
+            let Some(formula) = self.values.get(&current).and_then(|x| x.formula.as_ref()) else {
+                return Some(current);
+            };
+            let references = Self::collect_references(formula, current);
+            let from_cells = references
+                .cells
+                .iter()
+                .copied()
+                .filter(|x| self.get_value(*x).is_err());
+            let from_ranges = references
+                .ranges
+                .iter()
+                .filter_map(|(top_left, bottom_right)| {
+                    self.get_value_range_sparse(*top_left, *bottom_right)
+                        .find(|(_, value)| value.is_err())
+                        .map(|(x, _)| x)
+                });
+            match from_cells.chain(from_ranges).min() {
+                Some(precedent) => current = precedent,
+                None => return Some(current),
             }
-            Expression::Number(_) => {}
         }
     }
 
@@ -293,7 +904,7 @@ impl Storage {
         let mut stack = vec![cell];
         let mut found = HashSet::new();
         while let Some(top) = stack.pop() {
-            for &x in self.get_dep(top) {
+            for x in self.get_dep(top) {
                 if x == cell {
                     return true;
                 }
@@ -308,6 +919,64 @@ impl Storage {
         false
     }
 
+    /// Finds the loop that setting `expression` at `cell` would close, without changing anything.
+    ///
+    /// returns: the cells of the shortest such loop, each reading the next, starting and ending
+    /// at `cell`, or None if the expression would not create one
+    pub fn find_cycle(&self, cell: AbsCell, expression: &Expression) -> Option<Vec<AbsCell>> {
+        let references = Self::collect_references(expression, cell);
+        if references.reads(cell) {
+            return Some(vec![cell, cell]);
+        }
+
+        // walk outwards through the cells reading `cell` until one the expression would read
+        let mut reads = HashMap::new();
+        let mut queue = VecDeque::from([cell]);
+        while let Some(top) = queue.pop_front() {
+            for dependent in self.get_dep(top) {
+                if dependent == cell || reads.contains_key(&dependent) {
+                    continue;
+                }
+                reads.insert(dependent, top);
+                if references.reads(dependent) {
+                    let mut cycle = vec![cell, dependent];
+                    let mut current = dependent;
+                    while current != cell {
+                        current = reads[&current];
+                        cycle.push(current);
+                    }
+                    return Some(cycle);
+                }
+                queue.push_back(dependent);
+            }
+        }
+        None
+    }
+
+    /// `find_cycle` for copying the formula of `from` into `to`, None if `from` has no formula
+    pub fn find_copy_cycle(&self, from: AbsCell, to: AbsCell) -> Option<Vec<AbsCell>> {
+        let formula = self.values.get(&from)?.formula.as_ref()?;
+        self.find_cycle(to, formula)
+    }
+
+    /// The formulas reading `cell` as a single cell, not through a range, rewritten to read
+    /// `to` instead, see `Expression::retarget`. Sorted by the cell each formula is in
+    pub fn retarget_references(&self, cell: AbsCell, to: AbsCell) -> Vec<(AbsCell, Expression)> {
+        let mut dependents: Vec<AbsCell> = self
+            .graph
+            .get(&cell)
+            .map(|metadata| metadata.dependents.iter().copied().collect())
+            .unwrap_or_default();
+        dependents.sort();
+        dependents
+            .into_iter()
+            .filter_map(|dependent| {
+                let formula = self.values.get(&dependent)?.formula.as_ref()?;
+                Some((dependent, formula.retarget(dependent, cell, to)))
+            })
+            .collect()
+    }
+
     pub fn get_input(&self, cell: AbsCell) -> CellInput {
         let val = self.values.get(&cell);
         match val {
@@ -344,6 +1013,140 @@ impl Storage {
         }
     }
 
+    /// Copies like `copy_cell_expression`, but a formula is written with its references
+    /// transposed, see `Expression::transposed`
+    pub fn copy_cell_expression_transposed(&mut self, from: AbsCell, to: AbsCell) -> StorageError {
+        match self
+            .values
+            .get(&from)
+            .and_then(|data| data.formula.as_ref())
+        {
+            Some(formula) => self.set_expression(to, formula.transposed()),
+            None => self.copy_cell_expression(from, to),
+        }
+    }
+
+    /// Inserts or deletes a row or column, moving the cells after it and rewriting every
+    /// reference to follow the cells it pointed at. Ranges grow or shrink with their lines.
+    ///
+    /// Changes nothing and fails with `InvalidCell` if the index is off the sheet, if an
+    /// insert would push a value or a single cell reference off the sheet, or if a deleted
+    /// cell is still referenced, as there is no error to leave in its place
+    ///
+    /// returns: what `undo_structural` needs to reverse the edit
+    pub fn apply_structural(
+        &mut self,
+        edit: StructuralEdit,
+    ) -> Result<StructuralUndo, StorageError> {
+        let shift = Shift::new(edit, self.rows, self.cols);
+        if !(0..=shift.last).contains(&shift.index) {
+            return Err(StorageError::InvalidCell);
+        }
+        let mut undo = StructuralUndo {
+            cells: Vec::new(),
+            validations: self.validations.clone(),
+            column_types: self.column_types.clone(),
+        };
+        let mut values = BTreeMap::new();
+        let mut changed = Vec::new();
+        for (cell, data) in &self.values {
+            let Some(new_cell) = shift.cell(*cell) else {
+                if shift.insert {
+                    return Err(StorageError::InvalidCell);
+                }
+                undo.cells.push((*cell, data.clone()));
+                continue;
+            };
+            let mut new_data = data.clone();
+            if let Some(formula) = &data.formula {
+                let mut notes = ShiftNotes::default();
+                let formula = Self::shift_expression(formula, *cell, new_cell, &shift, &mut notes)
+                    .ok_or(StorageError::InvalidCell)?;
+                if notes.lossy {
+                    undo.cells.push((*cell, data.clone()));
+                }
+                if notes.lossy || notes.resized {
+                    changed.push(new_cell);
+                }
+                new_data.formula = Some(formula);
+            }
+            values.insert(new_cell, new_data);
+        }
+
+        self.values = values;
+        self.validations = undo
+            .validations
+            .iter()
+            .filter_map(|(top_left, bottom_right, rule)| {
+                let (top_left, bottom_right) =
+                    shift.range(*top_left, *bottom_right, &mut ShiftNotes::default())?;
+                Some((top_left, bottom_right, *rule))
+            })
+            .collect();
+        if !shift.rows {
+            self.column_types = undo
+                .column_types
+                .iter()
+                .filter_map(|(col, column_type)| {
+                    let moved = shift.cell(AbsCell::new(0, *col))?;
+                    Some((moved.col, *column_type))
+                })
+                .collect();
+        }
+        self.rebuild_dependencies();
+        self.update_cells(&changed);
+        Ok(undo)
+    }
+
+    /// Reverses `apply_structural(edit)`, given what it returned. Every edit made since has to
+    /// be reversed first, else this can fail like `apply_structural` and change nothing
+    pub fn undo_structural(&mut self, edit: StructuralEdit, undo: &StructuralUndo) -> StorageError {
+        if let Err(err) = self.apply_structural(edit.inverse()) {
+            return err;
+        }
+        for (cell, data) in &undo.cells {
+            self.values.insert(*cell, data.clone());
+        }
+        self.validations = undo.validations.clone();
+        self.column_types = undo.column_types.clone();
+        self.rebuild_dependencies();
+        let restored: Vec<AbsCell> = undo.cells.iter().map(|(cell, _)| *cell).collect();
+        self.update_cells(&restored);
+        StorageError::None
+    }
+
+    /// `expression` as written in `to` after a structural edit moved it there from `from`.
+    /// None if it references a cell the edit removes
+    fn shift_expression(
+        expression: &Expression,
+        from: AbsCell,
+        to: AbsCell,
+        shift: &Shift,
+        notes: &mut ShiftNotes,
+    ) -> Option<Expression> {
+        expression
+            .try_map_references::<()>(
+                &mut |rel_cell| {
+                    let moved = shift.cell(rel_cell.to_abs(from)).ok_or(())?;
+                    Ok(moved.to_rel(to))
+                },
+                &mut |range| {
+                    let (top_left, bottom_right) = shift
+                        .range(
+                            range.top_left.to_abs(from),
+                            range.bottom_right.to_abs(from),
+                            notes,
+                        )
+                        .ok_or(())?;
+                    Ok(CellRange {
+                        top_left: top_left.to_rel(to),
+                        bottom_right: bottom_right.to_rel(to),
+                    })
+                },
+            )
+            .ok()
+    }
+
     /// Serializes the Storage struct to a file using binary serialization.
     ///
     /// # Arguments
@@ -354,24 +1157,82 @@ impl Storage {
     ///
     /// * `Result<(), io::Error>` - Ok if successful, Err if an error occurs.
     pub fn serialize_to_file(&self, file_path: &File) -> io::Result<()> {
-        let writer = io::BufWriter::new(file_path);
+        self.serialize_to_writer(io::BufWriter::new(file_path))
+    }
+
+    /// Serializes the Storage struct in the same format as `serialize_to_file`, to any writer
+    pub fn serialize_to_writer(&self, writer: impl io::Write) -> io::Result<()> {
         bincode::serialize_into(writer, self)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         Ok(())
     }
 
+    /// Deserializes the Storage struct from a file using binary deserialization, then
+    /// recalculates every formula, see `from_file_with`.
+    pub fn from_file(file: &File) -> Result<Self, LoadError> {
+        Self::from_file_with(file, true)
+    }
+
     /// Deserializes the Storage struct from a file using binary deserialization.
     ///
+    /// The file holds the values computed when it was saved. With `recalculate` they are
+    /// recomputed with `recalculate_all`, so a sheet saved by a version with a calculation bug
+    /// shows what the formulas evaluate to now; without it they are kept as saved.
+    /// Recalculating runs every `SLEEP` in the sheet again.
+    ///
     /// # Arguments
     ///
     /// * `file` - The file from which the data will be read.
     ///
     /// # Returns
     ///
-    /// * `Result<Self, io::Error>` - Ok with the deserialized Storage if successful, Err if an error occurs.
-    pub fn from_file(file: &File) -> io::Result<Self> {
-        let reader = io::BufReader::new(file);
-        bincode::deserialize_from(reader).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    /// * `Result<Self, LoadError>` - Ok with the deserialized Storage if successful.
+    ///   `LoadError::Corrupt` if the file does not hold a valid sheet, `LoadError::Io` if it
+    ///   could not be read at all.
+    pub fn from_file_with(file: &File, recalculate: bool) -> Result<Self, LoadError> {
+        let mut bytes = Vec::new();
+        io::BufReader::new(file)
+            .read_to_end(&mut bytes)
+            .map_err(LoadError::Io)?;
+        // Same encoding as `bincode::deserialize_from`, but a corrupt length prefix can not make
+        // it allocate more than the file could possibly hold
+        let options = bincode::options()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(bytes.len() as u64);
+        let mut storage: Self = match options.deserialize(&bytes) {
+            Ok(storage) => storage,
+            // sheets saved by older versions end where the newer fields would start,
+            // anything left over means the file is damaged rather than old
+            Err(err) => match Self::deserialize_older(&bytes, options) {
+                Some(storage) => storage,
+                None => return Err(err.into()),
+            },
+        };
+        storage.rebuild_dependencies();
+        if recalculate {
+            storage.recalculate_all();
+        }
+        Ok(storage)
+    }
+
+    /// Reads a sheet saved before some of the fields of `Storage` existed. Those fields are
+    /// added at the end, so such a sheet is a current one cut short: the fields it has are
+    /// read in order and the rest keep their defaults. None if it is not that either
+    fn deserialize_older(bytes: &[u8], options: impl Options + Copy) -> Option<Self> {
+        let mut rest = bytes;
+        let rows = options.deserialize_from(&mut rest).ok()?;
+        let cols = options.deserialize_from(&mut rest).ok()?;
+        let mut storage = Storage::new(rows, cols);
+        storage.values = options.deserialize_from(&mut rest).ok()?;
+        storage.graph = options.deserialize_from(&mut rest).ok()?;
+        if !rest.is_empty() {
+            storage.validations = options.deserialize_from(&mut rest).ok()?;
+        }
+        if !rest.is_empty() {
+            storage.column_types = options.deserialize_from(&mut rest).ok()?;
+        }
+        rest.is_empty().then_some(storage)
     }
 
     /// Searches for a string in the storage starting from the top-left cell (0, 0).
@@ -383,15 +1244,17 @@ impl Storage {
     /// Searches for a string in the storage starting from, and excluding the given cell.
     /// The search is done in a left-to-right, top-to-bottom order.
     pub fn search(&self, start: AbsCell, to_search: &str) -> Option<AbsCell> {
+        // widened, so a sheet with no rows or columns cannot underflow
+        let (rows, cols) = (i32::from(self.rows), i32::from(self.cols));
         let next_cell = {
-            if start.col >= (self.cols - 1) as i16 {
+            if i32::from(start.col) >= cols - 1 {
                 AbsCell::new(start.row + 1, 0)
             } else {
                 AbsCell::new(start.row, start.col + 1)
             }
         };
 
-        if next_cell.row >= (self.rows - 1) as i16 {
+        if cols == 0 || i32::from(next_cell.row) >= rows {
             return None;
         }
 
@@ -412,6 +1275,119 @@ impl Storage {
         }
         None
     }
+
+    /// Finds the nearest populated cell from `cell`, excluding it, stepping in the direction
+    /// given by the signs of `dr` and `dc`, one of which must be zero. Returns the cell at the
+    /// edge of the sheet in that direction if nothing is populated in between, like Ctrl+Arrow
+    pub fn next_populated_in_direction(&self, cell: AbsCell, dr: i16, dc: i16) -> AbsCell {
+        let last_row = self.rows as i16 - 1;
+        let last_col = self.cols as i16 - 1;
+        let found = match (dr.signum(), dc.signum()) {
+            (0, 1) if cell.col < last_col => self
+                .values
+                .range(AbsCell::new(cell.row, cell.col + 1)..=AbsCell::new(cell.row, last_col))
+                .next(),
+            (0, -1) => self
+                .values
+                .range(AbsCell::new(cell.row, 0)..cell)
+                .next_back(),
+            // the map is ordered row by row, so a column is scanned by skipping the other columns
+            (1, 0) => self
+                .values
+                .range(AbsCell::new(cell.row + 1, 0)..)
+                .find(|(c, _)| c.col == cell.col),
+            (-1, 0) => self
+                .values
+                .range(..AbsCell::new(cell.row, 0))
+                .rev()
+                .find(|(c, _)| c.col == cell.col),
+            _ => None,
+        };
+        match (found, dr.signum(), dc.signum()) {
+            (Some((found, _)), _, _) => *found,
+            (None, 1, _) => AbsCell::new(last_row, cell.col),
+            (None, -1, _) => AbsCell::new(0, cell.col),
+            (None, _, 1) => AbsCell::new(cell.row, last_col),
+            (None, _, -1) => AbsCell::new(cell.row, 0),
+            (None, _, _) => cell,
+        }
+    }
+
+    /// Groups the populated cells holding the same value, numbers by exact equality and strings
+    /// by exact match. Only groups of two or more cells are returned; errors are never grouped.
+    ///
+    /// The groups, and the cells in each, are in the usual left-to-right, top-to-bottom order
+    pub fn find_duplicate_values(&self) -> Vec<Vec<AbsCell>> {
+        #[derive(PartialEq, Eq, Hash)]
+        enum Key<'a> {
+            Number(OrderedF64),
+            String(&'a str),
+        }
+
+        let mut groups: Vec<Vec<AbsCell>> = Vec::new();
+        let mut group_of: HashMap<Key, usize> = HashMap::new();
+        for (cell, data) in &self.values {
+            let key = match &data.value {
+                // NaN equals nothing, not even another NaN
+                Ok(CellValue::Number(num)) if num.is_nan() => continue,
+                Ok(CellValue::Number(num)) => Key::Number(OrderedF64(*num)),
+                Ok(CellValue::String(text)) => Key::String(text),
+                Ok(CellValue::Empty) | Err(_) => continue,
+            };
+            let index = *group_of.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[index].push(*cell);
+        }
+        groups.retain(|group| group.len() > 1);
+        groups
+    }
+
+    /// How many formulas read each populated cell, directly or through a range. Cells nothing
+    /// reads are left out
+    pub fn dependent_counts(&self) -> HashMap<AbsCell, usize> {
+        self.values
+            .keys()
+            .map(|&cell| (cell, self.get_dep(cell).collect::<HashSet<_>>().len()))
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    /// Lists the cells that differ from `self` (the old sheet) to `other` (the new sheet),
+    /// comparing both the value and the formula, in left-to-right, top-to-bottom order
+    pub fn diff(&self, other: &Storage) -> Vec<(AbsCell, CellDiff)> {
+        let mut diffs: Vec<(AbsCell, CellDiff)> = Vec::new();
+        for (cell, old) in &self.values {
+            match other.values.get(cell) {
+                None => diffs.push((*cell, CellDiff::Removed(old.clone()))),
+                Some(new) if new != old => diffs.push((
+                    *cell,
+                    CellDiff::Changed {
+                        old: old.clone(),
+                        new: new.clone(),
+                    },
+                )),
+                Some(_) => {}
+            }
+        }
+        for (cell, new) in &other.values {
+            if !self.values.contains_key(cell) {
+                diffs.push((*cell, CellDiff::Added(new.clone())));
+            }
+        }
+        diffs.sort_by_key(|(cell, _)| *cell);
+        diffs
+    }
+
+    /// Finds the first cell after, and excluding, `start` whose value is an error.
+    /// Only populated cells are scanned, in the same left-to-right, top-to-bottom order as `search`.
+    pub fn next_error_cell(&self, start: AbsCell) -> Option<AbsCell> {
+        self.values
+            .range((Excluded(start), Unbounded))
+            .find(|(_, data)| data.value.is_err())
+            .map(|(cell, _)| *cell)
+    }
 }
 
 struct SparseRangeIter<'a> {
@@ -492,7 +1468,8 @@ impl<'a> FullRangeIter<'a> {
             values.range(bottom_right..bottom_right) //empty range
         };
 
-        let current_cell = if is_valid { top_left } else { bottom_right }; //will stop on the first instance of .next
+        // a reversed range is empty, which `next` sees from the corners
+        let current_cell = top_left;
         let next_value = value_iter.next();
         FullRangeIter {
             top_left,
@@ -509,8 +1486,10 @@ impl<'a> Iterator for FullRangeIter<'a> {
     type Item = (AbsCell, &'a CellData);
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Check if we've gone beyond the bottom-right boundary
-        if self.current_cell.row > self.bottom_right.row {
+        // Check if we've gone beyond the bottom-right boundary, or the range is reversed
+        if self.current_cell.row > self.bottom_right.row
+            || self.top_left.col > self.bottom_right.col
+        {
             return None;
         }
 
@@ -541,3 +1520,696 @@ impl<'a> Iterator for FullRangeIter<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::expression::{CellRange, Operator, RangeFunction};
+    use crate::common::structs::RelCell;
+    use std::io::{Seek, Write};
+
+    fn file_with(bytes: &[u8]) -> File {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(bytes).unwrap();
+        file.rewind().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_from_file_round_trip() {
+        let mut storage = Storage::new(10, 10);
+        storage.set_value(AbsCell::new(1, 2), CellValue::Number(4.0));
+        let mut file = tempfile::tempfile().unwrap();
+        storage.serialize_to_file(&file).unwrap();
+        file.rewind().unwrap();
+
+        let loaded = Storage::from_file(&file).unwrap();
+        assert_eq!(
+            loaded.get_value(AbsCell::new(1, 2)),
+            &Ok(CellValue::Number(4.0))
+        );
+    }
+
+    #[test]
+    fn test_from_file_truncated() {
+        let mut storage = Storage::new(10, 10);
+        storage.set_value(AbsCell::new(1, 2), CellValue::String("hello".to_string()));
+        let bytes = bincode::serialize(&storage).unwrap();
+
+        for len in [0, 3, bytes.len() / 2, bytes.len() - 1] {
+            let err = Storage::from_file(&file_with(&bytes[..len])).unwrap_err();
+            assert!(
+                matches!(err, LoadError::Corrupt(_)),
+                "{} bytes: {}",
+                len,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_file_garbage_length() {
+        // a map length prefix far larger than the file must not be trusted
+        let err = Storage::from_file(&file_with(&[0xff; 64])).unwrap_err();
+        assert!(matches!(err, LoadError::Corrupt(_)));
+        assert!(
+            err.to_string()
+                .starts_with("File is corrupt or from an incompatible version")
+        );
+    }
+
+    /// `SUM(top_left:bottom_right)` written in `cell`
+    fn sum(cell: AbsCell, top_left: AbsCell, bottom_right: AbsCell) -> Expression {
+        let relative = |target: AbsCell| RelCell::new(target.row - cell.row, target.col - cell.col);
+        Expression::RangeFunction(
+            RangeFunction::Sum,
+            CellRange {
+                top_left: relative(top_left),
+                bottom_right: relative(bottom_right),
+            },
+        )
+    }
+
+    #[test]
+    fn test_huge_range_is_a_single_dependency() {
+        let mut storage = Storage::new(30000, 10);
+        let total = AbsCell::new(0, 1);
+        let expression = sum(total, AbsCell::new(0, 0), AbsCell::new(29999, 0));
+        assert!(matches!(
+            storage.set_expression(total, expression),
+            StorageError::None
+        ));
+        assert!(storage.graph.is_empty());
+        assert_eq!(storage.range_dependents.len(), 1);
+
+        storage.set_value(AbsCell::new(12345, 0), CellValue::Number(2.0));
+        storage.set_value(AbsCell::new(29999, 0), CellValue::Number(3.0));
+        assert_eq!(storage.get_value(total), &Ok(CellValue::Number(5.0)));
+        // cells outside the range do not trigger it
+        storage.set_value(AbsCell::new(5, 2), CellValue::Number(100.0));
+        assert_eq!(storage.get_value(total), &Ok(CellValue::Number(5.0)));
+
+        // replacing the formula drops the range entry
+        storage.set_expression(total, Expression::Number(1.0));
+        assert!(storage.range_dependents.is_empty());
+    }
+
+    #[test]
+    fn test_range_dependencies_chain_and_detect_cycles() {
+        let mut storage = Storage::new(10, 10);
+        let total = AbsCell::new(0, 1);
+        let doubled = AbsCell::new(0, 2);
+        storage.set_expression(total, sum(total, AbsCell::new(0, 0), AbsCell::new(9, 0)));
+        storage.set_expression(
+            doubled,
+            Expression::BinaryOp(
+                Box::new(Expression::Cell(RelCell::new(0, -1))),
+                Operator::Multiply,
+                Box::new(Expression::Number(2.0)),
+            ),
+        );
+        storage.set_value(AbsCell::new(3, 0), CellValue::Number(4.0));
+        assert_eq!(storage.get_value(doubled), &Ok(CellValue::Number(8.0)));
+
+        // a range covering its own cell, directly or through another formula, is circular
+        let cycle = sum(AbsCell::new(5, 0), AbsCell::new(0, 0), AbsCell::new(9, 0));
+        assert!(matches!(
+            storage.set_expression(AbsCell::new(5, 0), cycle),
+            StorageError::CircularDependency
+        ));
+        let cycle = sum(total, AbsCell::new(0, 0), AbsCell::new(0, 2));
+        assert!(matches!(
+            storage.set_expression(total, cycle),
+            StorageError::CircularDependency
+        ));
+        // the rejected formula left the old range in place
+        storage.set_value(AbsCell::new(4, 0), CellValue::Number(1.0));
+        assert_eq!(storage.get_value(doubled), &Ok(CellValue::Number(10.0)));
+    }
+
+    #[test]
+    fn test_from_file_rebuilds_range_dependencies() {
+        let mut storage = Storage::new(10, 10);
+        let total = AbsCell::new(0, 1);
+        storage.set_expression(total, sum(total, AbsCell::new(0, 0), AbsCell::new(9, 0)));
+        // sheets from before range entries have an edge for every cell of the range
+        for row in 0..10 {
+            storage
+                .graph
+                .entry(AbsCell::new(row, 0))
+                .or_default()
+                .dependents
+                .insert(total);
+        }
+        let mut file = tempfile::tempfile().unwrap();
+        storage.serialize_to_file(&file).unwrap();
+        file.rewind().unwrap();
+
+        let mut loaded = Storage::from_file(&file).unwrap();
+        assert!(loaded.graph.values().all(|meta| meta.dependents.is_empty()));
+        assert_eq!(loaded.range_dependents.len(), 1);
+        loaded.set_value(AbsCell::new(7, 0), CellValue::Number(6.0));
+        assert_eq!(loaded.get_value(total), &Ok(CellValue::Number(6.0)));
+    }
+
+    #[test]
+    fn test_used_range() {
+        let mut storage = Storage::new(100, 100);
+        assert_eq!(storage.used_range(), None);
+
+        for (row, col) in [(40, 7), (3, 12), (55, 2), (9, 30)] {
+            storage.set_value(AbsCell::new(row, col), CellValue::Number(1.0));
+        }
+        assert_eq!(
+            storage.used_range(),
+            Some((AbsCell::new(3, 2), AbsCell::new(55, 30)))
+        );
+
+        // clearing a corner cell shrinks the range
+        storage.set_value(AbsCell::new(55, 2), CellValue::Empty);
+        assert_eq!(
+            storage.used_range(),
+            Some((AbsCell::new(3, 7), AbsCell::new(40, 30)))
+        );
+    }
+
+    #[test]
+    fn test_trace_error() {
+        let mut storage = Storage::new(10, 10);
+        let (a1, b1, c1) = (AbsCell::new(0, 0), AbsCell::new(0, 1), AbsCell::new(0, 2));
+        // B1 = A1 / 0
+        storage.set_expression(
+            b1,
+            Expression::BinaryOp(
+                Box::new(Expression::Cell(RelCell::new(0, -1))),
+                Operator::Divide,
+                Box::new(Expression::Number(0.0)),
+            ),
+        );
+        // C1 = B1
+        storage.set_expression(c1, Expression::Cell(RelCell::new(0, -1)));
+        assert_eq!(storage.get_value(c1), &Err(CellError::DivideByZero));
+
+        assert_eq!(storage.trace_error(c1), Some(b1));
+        assert_eq!(storage.trace_error(b1), Some(b1));
+        assert_eq!(storage.trace_error(a1), None);
+
+        // through a range as well
+        let total = AbsCell::new(5, 5);
+        storage.set_expression(total, sum(total, a1, AbsCell::new(0, 2)));
+        assert_eq!(storage.trace_error(total), Some(b1));
+    }
+
+    #[test]
+    fn test_is_populated() {
+        let mut storage = Storage::new(10, 10);
+        let zero = AbsCell::new(0, 0);
+        storage.set_value(zero, CellValue::Number(0.0));
+        assert!(storage.is_populated(zero));
+        assert!(!storage.is_populated(AbsCell::new(0, 1)));
+        assert_eq!(storage.get_value(AbsCell::new(0, 1)), &Ok(CellValue::Empty));
+
+        storage.set_value(zero, CellValue::Empty);
+        assert!(!storage.is_populated(zero));
+    }
+
+    #[test]
+    fn test_next_populated_in_direction() {
+        let mut storage = Storage::new(20, 10);
+        for row in [2, 3, 9] {
+            storage.set_value(AbsCell::new(row, 1), CellValue::Number(row as f64));
+        }
+        // cells in neighbouring columns must not be picked up by column scans
+        storage.set_value(AbsCell::new(5, 0), CellValue::Number(1.0));
+        storage.set_value(AbsCell::new(6, 4), CellValue::Number(1.0));
+
+        let down = |row| storage.next_populated_in_direction(AbsCell::new(row, 1), 1, 0);
+        assert_eq!(down(0), AbsCell::new(2, 1));
+        assert_eq!(down(2), AbsCell::new(3, 1));
+        assert_eq!(down(3), AbsCell::new(9, 1));
+        assert_eq!(down(9), AbsCell::new(19, 1));
+
+        let up = |row| storage.next_populated_in_direction(AbsCell::new(row, 1), -1, 0);
+        assert_eq!(up(19), AbsCell::new(9, 1));
+        assert_eq!(up(9), AbsCell::new(3, 1));
+        assert_eq!(up(2), AbsCell::new(0, 1));
+
+        let across = |col, dc| storage.next_populated_in_direction(AbsCell::new(6, col), 0, dc);
+        assert_eq!(across(0, 1), AbsCell::new(6, 4));
+        assert_eq!(across(4, 1), AbsCell::new(6, 9));
+        assert_eq!(across(9, 1), AbsCell::new(6, 9));
+        assert_eq!(across(9, -1), AbsCell::new(6, 4));
+        assert_eq!(across(4, -1), AbsCell::new(6, 0));
+    }
+
+    #[test]
+    fn test_find_duplicate_values() {
+        let mut storage = Storage::new(10, 10);
+        let fives = [AbsCell::new(0, 3), AbsCell::new(2, 0), AbsCell::new(7, 7)];
+        for cell in fives {
+            storage.set_value(cell, CellValue::Number(5.0));
+        }
+        storage.set_value(AbsCell::new(1, 1), CellValue::Number(5.5));
+        storage.set_value(AbsCell::new(1, 2), CellValue::String("5".to_string()));
+        storage.set_value(AbsCell::new(4, 4), CellValue::String("x".to_string()));
+        storage.set_value(AbsCell::new(5, 5), CellValue::String("x".to_string()));
+        storage.set_value(AbsCell::new(6, 6), CellValue::String("X".to_string()));
+
+        assert_eq!(
+            storage.find_duplicate_values(),
+            vec![fives.to_vec(), vec![AbsCell::new(4, 4), AbsCell::new(5, 5)]]
+        );
+    }
+
+    #[test]
+    fn test_search_tiny_sheets() {
+        for (rows, cols) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            let storage = Storage::new(rows, cols);
+            assert_eq!(storage.search_from_start("x"), None);
+            assert_eq!(storage.search(AbsCell::new(0, 0), "x"), None);
+        }
+
+        // the last row is searched too
+        let mut storage = Storage::new(1, 1);
+        let a1 = AbsCell::new(0, 0);
+        storage.set_value(a1, CellValue::String("x".to_string()));
+        assert_eq!(storage.search_from_start("x"), Some(a1));
+        assert_eq!(storage.search(a1, "x"), None);
+    }
+
+    #[test]
+    fn test_range_too_large() {
+        let mut storage = Storage::new(999, 702);
+        let total = AbsCell::new(0, 701);
+        let huge = sum(total, AbsCell::new(0, 0), AbsCell::new(998, 700));
+        storage.set_max_referenced_cells(Some(1000));
+        assert!(matches!(
+            storage.set_expression(total, huge.clone()),
+            StorageError::RangeTooLarge
+        ));
+        assert!(storage.range_dependents.is_empty());
+        assert_eq!(storage.get_cell_formula(total), None);
+
+        // 10 x 100 cells is exactly at the limit
+        let fits = sum(total, AbsCell::new(0, 0), AbsCell::new(9, 99));
+        assert!(matches!(
+            storage.set_expression(total, fits),
+            StorageError::None
+        ));
+
+        storage.set_max_referenced_cells(None);
+        assert!(matches!(
+            storage.set_expression(total, huge),
+            StorageError::None
+        ));
+    }
+
+    #[test]
+    fn test_column_iter() {
+        let mut storage = Storage::new(10, 10);
+        for row in [7, 0, 3] {
+            storage.set_value(AbsCell::new(row, 2), CellValue::Number(row as f64));
+        }
+        for row in [1, 3, 5] {
+            storage.set_value(AbsCell::new(row, 1), CellValue::Number(-1.0));
+            storage.set_value(AbsCell::new(row, 3), CellValue::Number(-1.0));
+        }
+
+        let column: Vec<(i16, &Result<CellValue, CellError>)> = storage
+            .column_iter(2)
+            .map(|(row, data)| (row, &data.value))
+            .collect();
+        assert_eq!(
+            column,
+            [
+                (0, &Ok(CellValue::Number(0.0))),
+                (3, &Ok(CellValue::Number(3.0))),
+                (7, &Ok(CellValue::Number(7.0))),
+            ]
+        );
+        assert_eq!(storage.column_iter(9).count(), 0);
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let mut storage = Storage::new(10, 10);
+        let (a1, b1, c1, d1) = (
+            AbsCell::new(0, 0),
+            AbsCell::new(0, 1),
+            AbsCell::new(0, 2),
+            AbsCell::new(0, 3),
+        );
+        let reads = |cell: AbsCell, target: AbsCell| {
+            Expression::Cell(RelCell::new(target.row - cell.row, target.col - cell.col))
+        };
+        storage.set_value(a1, CellValue::Number(1.0));
+        storage.set_expression(b1, reads(b1, a1));
+        storage.set_expression(c1, sum(c1, b1, b1));
+
+        assert_eq!(
+            storage.find_cycle(a1, &reads(a1, c1)),
+            Some(vec![a1, c1, b1, a1])
+        );
+        assert_eq!(storage.find_cycle(a1, &reads(a1, a1)), Some(vec![a1, a1]));
+        assert_eq!(storage.find_cycle(d1, &reads(d1, c1)), None);
+        // at D1, the formula of C1 would read C1, which does not lead back to D1
+        assert_eq!(storage.find_copy_cycle(c1, d1), None);
+        assert_eq!(storage.find_copy_cycle(a1, c1), None);
+        assert_eq!(storage.get_value(a1), &Ok(CellValue::Number(1.0)));
+    }
+
+    #[test]
+    fn test_recalc_order_diamond() {
+        let mut storage = Storage::new(10, 10);
+        let (a1, b1, c1, d1) = (
+            AbsCell::new(0, 0),
+            AbsCell::new(0, 1),
+            AbsCell::new(0, 2),
+            AbsCell::new(0, 3),
+        );
+        let reads = |cell: AbsCell, target: AbsCell| {
+            Expression::Cell(RelCell::new(target.row - cell.row, target.col - cell.col))
+        };
+        storage.set_value(a1, CellValue::Number(1.0));
+        storage.set_expression(b1, reads(b1, a1));
+        storage.set_expression(c1, reads(c1, a1));
+        storage.set_expression(d1, sum(d1, b1, c1));
+
+        let order = storage.recalc_order(a1);
+        let position = |cell| order.iter().position(|&x| x == cell).unwrap();
+        assert_eq!(order.len(), 4);
+        assert_eq!(position(a1), 0);
+        assert!(position(d1) > position(b1));
+        assert!(position(d1) > position(c1));
+
+        assert_eq!(storage.recalc_order(c1), vec![c1, d1]);
+        assert_eq!(storage.recalc_order(d1), vec![d1]);
+    }
+
+    #[test]
+    fn test_cell_read_directly_and_in_range() {
+        let mut storage = Storage::new(10, 10);
+        let (a1, b1, b2, c3) = (
+            AbsCell::new(0, 0),
+            AbsCell::new(0, 1),
+            AbsCell::new(1, 1),
+            AbsCell::new(2, 2),
+        );
+        let reads = |cell: AbsCell, target: AbsCell| {
+            Expression::Cell(RelCell::new(target.row - cell.row, target.col - cell.col))
+        };
+        storage.set_value(a1, CellValue::Number(1.0));
+        // A1 + SUM(A1:B2) reads A1 twice, which is still one edge
+        storage.set_expression(
+            c3,
+            Expression::BinaryOp(
+                Box::new(reads(c3, a1)),
+                Operator::Add,
+                Box::new(sum(c3, a1, b2)),
+            ),
+        );
+        assert_eq!(storage.dependent_counts()[&a1], 1);
+        assert_eq!(storage.recalc_order(a1), vec![a1, c3]);
+        assert_eq!(storage.get_value(c3), &Ok(CellValue::Number(2.0)));
+
+        // dropping the direct read keeps the edge of the range
+        storage.set_expression(c3, sum(c3, a1, b2));
+        storage.set_value(a1, CellValue::Number(5.0));
+        assert_eq!(storage.get_value(c3), &Ok(CellValue::Number(5.0)));
+        assert_eq!(storage.dependent_counts()[&a1], 1);
+
+        // and dropping the range keeps a direct read that is still there
+        storage.set_expression(
+            c3,
+            Expression::BinaryOp(
+                Box::new(reads(c3, a1)),
+                Operator::Add,
+                Box::new(sum(c3, b1, b2)),
+            ),
+        );
+        storage.set_expression(c3, reads(c3, a1));
+        storage.set_value(a1, CellValue::Number(7.0));
+        assert_eq!(storage.get_value(c3), &Ok(CellValue::Number(7.0)));
+
+        storage.set_expression(c3, reads(c3, b1));
+        assert!(!storage.dependent_counts().contains_key(&a1));
+    }
+
+    #[cfg(feature = "recalc-log")]
+    #[test]
+    fn test_recalc_log() {
+        use std::sync::Mutex;
+
+        struct Capture(Mutex<Vec<String>>);
+        impl log::Log for Capture {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.target() == "embedded::recalc"
+            }
+            fn log(&self, record: &log::Record) {
+                if self.enabled(record.metadata()) {
+                    self.0.lock().unwrap().push(record.args().to_string());
+                }
+            }
+            fn flush(&self) {}
+        }
+        static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let mut storage = Storage::new(7, 3);
+        let (a1, c7) = (AbsCell::new(0, 0), AbsCell::new(6, 2));
+        storage.set_value(a1, CellValue::Number(1.0));
+        storage.set_expression(c7, sum(c7, a1, a1));
+        storage.set_value(a1, CellValue::Number(2.0));
+
+        let events = CAPTURE.0.lock().unwrap();
+        assert!(
+            events
+                .iter()
+                .any(|event| event == "setting the formula of C7")
+        );
+        assert!(events.iter().any(|event| {
+            event.starts_with("recalculated 2 cells from [AbsCell { row: 0, col: 0 }] in ")
+        }));
+    }
+
+    #[test]
+    fn test_dependent_counts() {
+        let mut storage = Storage::new(10, 10);
+        let (a1, b1, c1, d1) = (
+            AbsCell::new(0, 0),
+            AbsCell::new(0, 1),
+            AbsCell::new(0, 2),
+            AbsCell::new(0, 3),
+        );
+        storage.set_value(a1, CellValue::Number(1.0));
+        storage.set_expression(b1, Expression::Cell(RelCell::new(0, -1)));
+        storage.set_expression(c1, Expression::Cell(RelCell::new(0, -2)));
+        storage.set_expression(d1, sum(d1, a1, b1));
+        // an empty cell read by a formula is not populated, so not counted
+        storage.set_expression(AbsCell::new(5, 5), Expression::Cell(RelCell::new(1, 1)));
+        // reading A1 directly and through a range still counts one formula
+        let e1 = AbsCell::new(0, 4);
+        let both = Expression::BinaryOp(
+            Box::new(Expression::Cell(a1.to_rel(e1))),
+            Operator::Add,
+            Box::new(sum(e1, a1, a1)),
+        );
+        storage.set_expression(e1, both);
+
+        let counts = storage.dependent_counts();
+        assert_eq!(counts, HashMap::from([(a1, 4), (b1, 1)]));
+        // and it is recomputed once, after the cells it reads
+        let order = storage.recalc_order(a1);
+        assert_eq!(order.iter().filter(|&&cell| cell == e1).count(), 1);
+        storage.set_value(a1, CellValue::Number(2.0));
+        assert_eq!(storage.get_value(e1), &Ok(CellValue::Number(4.0)));
+    }
+
+    #[test]
+    fn test_from_file_recalculates_stale_values() {
+        let mut storage = Storage::new(10, 10);
+        let a1 = AbsCell::new(0, 0);
+        let b1 = AbsCell::new(0, 1);
+        storage.set_value(a1, CellValue::Number(2.0));
+        let formula = Expression::BinaryOp(
+            Box::new(Expression::Cell(RelCell::new(0, -1))),
+            Operator::Multiply,
+            Box::new(Expression::Number(2.0)),
+        );
+        storage.set_expression(b1, formula);
+        assert_eq!(storage.get_value(b1), &Ok(CellValue::Number(4.0)));
+
+        // as if saved by a version that computed B1 wrongly
+        storage.values.get_mut(&b1).unwrap().value = Ok(CellValue::Number(99.0));
+        let bytes = bincode::serialize(&storage).unwrap();
+
+        let kept = Storage::from_file_with(&file_with(&bytes), false).unwrap();
+        assert_eq!(kept.get_value(b1), &Ok(CellValue::Number(99.0)));
+        let loaded = Storage::from_file(&file_with(&bytes)).unwrap();
+        assert_eq!(loaded.get_value(b1), &Ok(CellValue::Number(4.0)));
+    }
+
+    #[test]
+    fn test_validations_are_saved() {
+        let mut storage = Storage::new(10, 10);
+        let rule = ValidationRule::NumberBetween { min: 1.0, max: 2.0 };
+        storage.add_validation(AbsCell::new(0, 0), AbsCell::new(2, 2), rule);
+        let bytes = bincode::serialize(&storage).unwrap();
+
+        let loaded = Storage::from_file(&file_with(&bytes)).unwrap();
+        assert_eq!(loaded.validation_at(AbsCell::new(1, 1)), Some(&rule));
+    }
+
+    #[test]
+    fn test_column_types() {
+        let mut storage = Storage::new(10, 10);
+        storage.set_column_type(1, Some(ColumnType::Number));
+        let b1 = AbsCell::new(0, 1);
+        assert_eq!(
+            storage.coerce(b1, &CellValue::String("text".to_string())),
+            Err(ValidationError::NotANumber)
+        );
+        assert_eq!(
+            storage.coerce(AbsCell::new(0, 2), &CellValue::String("text".to_string())),
+            Ok(None)
+        );
+
+        let bytes = bincode::serialize(&storage).unwrap();
+        let mut loaded = Storage::from_file(&file_with(&bytes)).unwrap();
+        assert_eq!(loaded.column_type(1), Some(ColumnType::Number));
+
+        // the type follows its column when a column is inserted before it
+        let edit = StructuralEdit::InsertCol(0);
+        let Ok(undo) = loaded.apply_structural(edit) else {
+            panic!("the inserted column fits on the sheet");
+        };
+        assert_eq!(loaded.column_type(1), None);
+        assert_eq!(loaded.column_type(2), Some(ColumnType::Number));
+        loaded.undo_structural(edit, &undo);
+        assert_eq!(loaded.column_type(1), Some(ColumnType::Number));
+        assert!(
+            loaded
+                .apply_structural(StructuralEdit::DeleteCol(1))
+                .is_ok()
+        );
+        assert_eq!(loaded.column_type(1), None);
+
+        // sheets saved before column types existed still load
+        #[derive(Serialize)]
+        struct OldStorage {
+            rows: u16,
+            cols: u16,
+            values: BTreeMap<AbsCell, CellData>,
+            graph: HashMap<AbsCell, CellMetadata>,
+            validations: Vec<(AbsCell, AbsCell, ValidationRule)>,
+        }
+        let old = OldStorage {
+            rows: storage.rows,
+            cols: storage.cols,
+            values: storage.values.clone(),
+            graph: storage.graph.clone(),
+            validations: storage.validations.clone(),
+        };
+        let loaded = Storage::from_file(&file_with(&bincode::serialize(&old).unwrap())).unwrap();
+        assert_eq!(loaded.column_type(1), None);
+    }
+
+    #[test]
+    fn test_from_file_without_validations() {
+        #[derive(Serialize)]
+        struct OldStorage {
+            rows: u16,
+            cols: u16,
+            values: BTreeMap<AbsCell, CellData>,
+            graph: HashMap<AbsCell, CellMetadata>,
+        }
+        let mut storage = Storage::new(10, 10);
+        storage.set_value(AbsCell::new(1, 2), CellValue::Number(4.0));
+        let old = OldStorage {
+            rows: storage.rows,
+            cols: storage.cols,
+            values: storage.values.clone(),
+            graph: storage.graph.clone(),
+        };
+        let bytes = bincode::serialize(&old).unwrap();
+
+        let loaded = Storage::from_file(&file_with(&bytes)).unwrap();
+        assert_eq!(
+            loaded.get_value(AbsCell::new(1, 2)),
+            &Ok(CellValue::Number(4.0))
+        );
+        assert_eq!(loaded.validation_at(AbsCell::new(1, 2)), None);
+    }
+
+    #[test]
+    fn test_reversed_range_is_empty() {
+        let mut storage = Storage::new(10, 10);
+        storage.set_value(AbsCell::new(1, 1), CellValue::Number(1.0));
+        let b1 = AbsCell::new(0, 1);
+        let a2 = AbsCell::new(1, 0);
+        let b2 = AbsCell::new(1, 1);
+        assert_eq!(storage.get_value_range_full(b1, a2).count(), 0);
+        assert_eq!(
+            storage.get_value_range_full(b2, AbsCell::new(0, 0)).count(),
+            0
+        );
+        assert_eq!(storage.get_value_range_full(a2, b2).count(), 2);
+    }
+
+    #[test]
+    fn test_from_file_baseline_sleep() {
+        // the formula variants of the first saved sheets, in their original order
+        #[derive(Serialize)]
+        #[allow(dead_code)]
+        enum OldExpression {
+            Number(f64),
+            Cell(RelCell),
+            BinaryOp(Box<OldExpression>, Operator, Box<OldExpression>),
+            RangeFunction(RangeFunction, CellRange),
+            Sleep(Box<OldExpression>),
+        }
+        #[derive(Serialize)]
+        struct OldCellData {
+            value: Result<CellValue, CellError>,
+            formula: Option<OldExpression>,
+        }
+        #[derive(Serialize)]
+        struct OldStorage {
+            rows: u16,
+            cols: u16,
+            values: BTreeMap<AbsCell, OldCellData>,
+            graph: HashMap<AbsCell, CellMetadata>,
+        }
+        let a1 = AbsCell::new(0, 0);
+        let b1 = AbsCell::new(0, 1);
+        let mut values = BTreeMap::new();
+        values.insert(
+            a1,
+            OldCellData {
+                value: Ok(CellValue::Number(0.0)),
+                formula: None,
+            },
+        );
+        values.insert(
+            b1,
+            OldCellData {
+                value: Ok(CellValue::Number(0.0)),
+                formula: Some(OldExpression::Sleep(Box::new(OldExpression::Cell(
+                    RelCell::new(0, -1),
+                )))),
+            },
+        );
+        let old = OldStorage {
+            rows: 10,
+            cols: 10,
+            values,
+            graph: HashMap::new(),
+        };
+        let bytes = bincode::serialize(&old).unwrap();
+
+        let loaded = Storage::from_file(&file_with(&bytes)).unwrap();
+        assert_eq!(loaded.get_cell_formula(b1), Some("SLEEP(A1)".to_string()));
+        assert_eq!(loaded.get_value(b1), &Ok(CellValue::Number(0.0)));
+    }
+}
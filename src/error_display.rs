@@ -0,0 +1,157 @@
+//! Canonical user-facing text for every error the spreadsheet can report.
+//!
+//! Both engines and both front ends turn errors into text through `ErrorLabel`, so the same
+//! condition reads the same wherever it is shown: dividing by zero is `#DIV/0!` in the GUI
+//! grid and in the CLI prompt alike. The other CLI status strings (`ok`,
+//! `unrecognized cmd`, ...) have no counterpart elsewhere and keep the prompt's wording.
+//!
+//! The CLI grid is the one place without a label per error: the integer engine only records
+//! that a cell failed, not why, so its error cells all show `DEFAULT_ERROR_TEXT`.
+
+use crate::commands::CommandResult;
+use crate::common::cell_value::CellError;
+use crate::common::validation::ValidationError;
+use crate::embedded_backend::simple::{ExpressionError, LoadError};
+use crate::parser::formula_parser::ParseError;
+
+/// Shown in place of an error value when one text is used for every kind of error, like the
+/// integer engine does since its cells do not record which error they hold, unless the user
+/// picks another
+pub const DEFAULT_ERROR_TEXT: &str = "ERR";
+
+pub trait ErrorLabel {
+    /// The text shown to the user for this error
+    fn label(&self) -> &'static str;
+}
+
+/// Shown in place of the value of a cell whose evaluation failed
+impl ErrorLabel for CellError {
+    fn label(&self) -> &'static str {
+        match self {
+            CellError::DivideByZero => "#DIV/0!",
+            CellError::DependsOnNonNumeric => "#VALUE!",
+            CellError::DependsOnErr => "#ERROR",
+            CellError::ShapeMismatch => "#VALUE!",
+            CellError::EmptyRange => "#N/A",
+            CellError::NumberTooLarge => "#NUM!",
+        }
+    }
+}
+
+impl ErrorLabel for ParseError {
+    fn label(&self) -> &'static str {
+        match self {
+            ParseError::Syntax => "Invalid formula syntax",
+            ParseError::OutOfBounds => "Cell reference out of bounds",
+            ParseError::ReversedRange => "Range start must be above-left of end",
+        }
+    }
+}
+
+impl ErrorLabel for ExpressionError {
+    fn label(&self) -> &'static str {
+        match self {
+            ExpressionError::InvalidExpression => "Invalid expression",
+            ExpressionError::CircularReference => CommandResult::CircularDependency.label(),
+            ExpressionError::ReversedRange => ParseError::ReversedRange.label(),
+            ExpressionError::RangeTooLarge => "Formula reads too many cells",
+            ExpressionError::Rejected(err) => err.label(),
+        }
+    }
+}
+
+impl ErrorLabel for LoadError {
+    fn label(&self) -> &'static str {
+        match self {
+            LoadError::Io(_) => "Could not read file",
+            LoadError::Corrupt(_) => "File is corrupt or from an incompatible version",
+        }
+    }
+}
+
+impl ErrorLabel for ValidationError {
+    fn label(&self) -> &'static str {
+        match self {
+            ValidationError::NotANumber => "Value must be a number",
+            ValidationError::OutOfRange { .. } => "Value out of range",
+        }
+    }
+}
+
+impl ErrorLabel for CommandResult {
+    fn label(&self) -> &'static str {
+        match self {
+            CommandResult::Ok => "ok",
+            CommandResult::InvalidCell => "Invalid cell",
+            CommandResult::InvalidRange => "Invalid range",
+            CommandResult::UnrecognizedCommand => "unrecognized cmd",
+            CommandResult::CircularDependency => "Circular dependency",
+            CommandResult::DivisionByZero => CellError::DivideByZero.label(),
+            CommandResult::NoErrorCell => "no error cells",
+            CommandResult::UnknownMacro => "no such macro",
+            CommandResult::Quit => "quit",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cell_error_labels() {
+        assert_eq!(CellError::DivideByZero.label(), "#DIV/0!");
+        assert_eq!(CellError::DependsOnNonNumeric.label(), "#VALUE!");
+        assert_eq!(CellError::DependsOnErr.label(), "#ERROR");
+        assert_eq!(CellError::ShapeMismatch.label(), "#VALUE!");
+        assert_eq!(CellError::EmptyRange.label(), "#N/A");
+        assert_eq!(CellError::NumberTooLarge.label(), "#NUM!");
+    }
+
+    #[test]
+    fn test_formula_error_labels() {
+        assert_eq!(ParseError::Syntax.label(), "Invalid formula syntax");
+        assert_eq!(
+            ParseError::OutOfBounds.label(),
+            "Cell reference out of bounds"
+        );
+        assert_eq!(
+            ParseError::ReversedRange.label(),
+            "Range start must be above-left of end"
+        );
+        assert_eq!(
+            ExpressionError::InvalidExpression.label(),
+            "Invalid expression"
+        );
+        assert_eq!(
+            ExpressionError::ReversedRange.label(),
+            ParseError::ReversedRange.label()
+        );
+        assert_eq!(
+            ExpressionError::RangeTooLarge.label(),
+            "Formula reads too many cells"
+        );
+    }
+
+    #[test]
+    fn test_command_result_labels() {
+        assert_eq!(
+            CommandResult::DivisionByZero.label(),
+            CellError::DivideByZero.label()
+        );
+        assert_eq!(CommandResult::NoErrorCell.label(), "no error cells");
+        assert_eq!(CommandResult::UnknownMacro.label(), "no such macro");
+    }
+
+    #[test]
+    fn test_circular_dependency_label_is_shared() {
+        assert_eq!(
+            ExpressionError::CircularReference.label(),
+            CommandResult::CircularDependency.label()
+        );
+        assert_eq!(
+            ExpressionError::CircularReference.to_string(),
+            "Circular dependency"
+        );
+    }
+}
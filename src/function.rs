@@ -288,6 +288,49 @@ where
     }
 }
 
+/// Evaluate a range function over fractional values, for a sheet using float division.
+///
+/// Like `eval_range`, but `AVG` and `STDEV` keep their fraction instead of being truncated
+/// and rounded to an integer. `SLEEP` never reaches here, it only takes a single cell.
+///
+/// # Examples
+/// ```rust
+/// use embedded::function::eval_range_real;
+/// let get_val = |coord: (u16, u16)| Some(coord.0 as f64 + coord.1 as f64 / 2.0);
+///
+/// assert_eq!(eval_range_real("SUM", (1, 1), (2, 2), get_val), Some(9.0));
+/// assert_eq!(eval_range_real("AVG", (1, 1), (2, 2), get_val), Some(2.25));
+/// assert_eq!(eval_range_real("MAX", (1, 1), (2, 2), get_val), Some(3.0));
+/// ```
+pub fn eval_range_real<F>(func: &str, start: (u16, u16), end: (u16, u16), get_val: F) -> Option<f64>
+where
+    F: Fn((u16, u16)) -> Option<f64>,
+{
+    let mut values = Vec::new();
+    for c in start.0..=end.0 {
+        for r in start.1..=end.1 {
+            values.push(get_val((c, r))?);
+        }
+    }
+    let count = values.len() as f64;
+    let sum: f64 = values.iter().sum();
+
+    match func.to_uppercase().as_str() {
+        "MIN" => values.into_iter().reduce(f64::min),
+        "MAX" => values.into_iter().reduce(f64::max),
+        "AVG" if values.is_empty() => Some(0.0),
+        "AVG" => Some(sum / count),
+        "SUM" => Some(sum),
+        "STDEV" if values.len() <= 1 => Some(0.0),
+        "STDEV" => {
+            let mean = sum / count;
+            let var_sum: f64 = values.iter().map(|v| (v - mean) * (v - mean)).sum();
+            Some((var_sum / count).sqrt())
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -506,4 +549,23 @@ mod tests {
         // Test invalid function name
         assert_eq!(eval_range("INVALID", (1, 1), (2, 2), get_val), None);
     }
+
+    #[test]
+    fn test_eval_range_real() {
+        let values = [((1, 1), 1.0), ((1, 2), 2.0), ((2, 1), 4.0), ((2, 2), 0.5)];
+        let get_val = |coord: (u16, u16)| -> Option<f64> {
+            values.iter().find(|(c, _)| *c == coord).map(|(_, v)| *v)
+        };
+
+        assert_eq!(eval_range_real("MIN", (1, 1), (2, 2), get_val), Some(0.5));
+        assert_eq!(eval_range_real("MAX", (1, 1), (2, 2), get_val), Some(4.0));
+        assert_eq!(eval_range_real("SUM", (1, 1), (2, 2), get_val), Some(7.5));
+        assert_eq!(eval_range_real("avg", (1, 1), (2, 2), get_val), Some(1.875));
+        assert_eq!(eval_range_real("STDEV", (1, 1), (1, 1), get_val), Some(0.0));
+        let stdev = eval_range_real("STDEV", (1, 1), (1, 2), get_val).unwrap();
+        assert!((stdev - 0.5).abs() < 1e-12);
+
+        assert_eq!(eval_range_real("SUM", (1, 1), (2, 3), get_val), None);
+        assert_eq!(eval_range_real("SLEEP", (1, 1), (1, 1), get_val), None);
+    }
 }
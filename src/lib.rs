@@ -3,7 +3,10 @@ pub mod embedded_backend;
 pub mod parser;
 
 pub mod commands;
+pub mod error_display;
 pub mod function;
 pub mod myparser;
+pub mod recent_files;
 pub mod spreadsheet;
 pub mod ui;
+pub mod view_state;
@@ -1,5 +1,6 @@
 use std::env;
 
+use embedded::embedded_backend::simple::EmbeddedBackend;
 use embedded::{commands, spreadsheet, ui};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -12,9 +13,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             ui::run_spreadsheet_app()?;
         }
 
-        // Otherwise expect two numeric args: rows and cols
+        // "diff <a> <b>" lists the cells that differ between two saved sheets
+        Some("diff") => {
+            let usage = "Usage: diff <old.sheet> <new.sheet>";
+            let old_path = args.next().ok_or(usage)?;
+            let new_path = args.next().ok_or(usage)?;
+            let old = EmbeddedBackend::from_file(&std::fs::File::open(&old_path)?)?;
+            let new = EmbeddedBackend::from_file(&std::fs::File::open(&new_path)?)?;
+
+            let diffs = old.diff(&new);
+            if diffs.is_empty() {
+                println!("No differences");
+            }
+            for (cell, diff) in diffs {
+                println!("{}", diff.describe(cell));
+            }
+        }
+
+        // Otherwise expect two numeric args: rows and cols, and optionally --float-division
         Some(rows_str) => {
-            let cols_str = args.next().ok_or("Usage: <ext1> | <rows> <cols>")?;
+            let usage = "Usage: <ext1> | <rows> <cols> [--float-division]";
+            let cols_str = args.next().ok_or(usage)?;
+            let float_division = match args.next().as_deref() {
+                None => false,
+                Some("--float-division") => true,
+                Some(_) => return Err(usage.into()),
+            };
             let rows: usize = rows_str.parse()?;
             let cols: usize = cols_str.parse()?;
 
@@ -25,14 +49,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 );
                 std::process::exit(1);
             }
-            let mut sheet = spreadsheet::Spreadsheet::new(rows, cols);
+            let mut sheet = if float_division {
+                spreadsheet::Spreadsheet::with_float_division(rows, cols)
+            } else {
+                spreadsheet::Spreadsheet::new(rows, cols)
+            };
             commands::handle_commands(&mut sheet);
         }
         // No args at all
         None => {
             eprintln!("Usage:");
             eprintln!("  cargo run --release -- ext1       # launch the GUI");
-            eprintln!("  cargo run --release -- <rows> <cols>");
+            eprintln!("  cargo run --release -- <rows> <cols> [--float-division]");
+            eprintln!("  cargo run --release -- diff <old.sheet> <new.sheet>");
             std::process::exit(1);
         }
     }
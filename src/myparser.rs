@@ -2,9 +2,18 @@
 //!
 //! The `MyParser` struct provides methods to parse and interpret common expressions
 //! encountered in spreadsheet applications. This includes converting cell names to coordinates,
-//! splitting binary expressions, and parsing range-based function calls.
+//! splitting binary expressions, and parsing range-based function calls. `parse_formula`
+//! combines them into a `Formula` that can be evaluated on its own, so the same rules apply
+//! when a cell is assigned and when it is recalculated.
+use crate::function::{eval_binary, eval_range, eval_range_real};
+use std::thread;
+use std::time::Duration;
+
 pub struct MyParser;
 
+/// The functions a formula can call, in the order `parse_range` tries them
+pub const FUNCTION_NAMES: [&str; 6] = ["MIN", "MAX", "AVG", "SUM", "STDEV", "SLEEP"];
+
 /// Represents a range in a spreadsheet as a tuple.
 ///
 /// The tuple contains:
@@ -13,6 +22,146 @@ pub struct MyParser;
 /// - The ending cell's coordinates as a tuple `(col, row)`.
 type RangeType<'a> = (&'a str, (u16, u16), (u16, u16));
 
+/// An operand of a binary formula or of `SLEEP`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Literal(i32),
+    /// A cell reference as `(col, row)`
+    Cell((u16, u16)),
+}
+
+impl Operand {
+    /// A cell name, or else an integer literal
+    fn parse(s: &str) -> Option<Self> {
+        match MyParser::cell_name_to_coord(s) {
+            Some(cell) => Some(Operand::Cell(cell)),
+            None => s.parse().ok().map(Operand::Literal),
+        }
+    }
+
+    fn value(self, get_val: &impl Fn((u16, u16)) -> Option<i32>) -> Option<i32> {
+        match self {
+            Operand::Literal(v) => Some(v),
+            Operand::Cell(cell) => get_val(cell),
+        }
+    }
+
+    fn real_value(self, get_val: &impl Fn((u16, u16)) -> Option<f64>) -> Option<f64> {
+        match self {
+            Operand::Literal(v) => Some(v as f64),
+            Operand::Cell(cell) => get_val(cell),
+        }
+    }
+}
+
+/// Sleeps for whole seconds, not at all for a negative count
+fn sleep_seconds(seconds: u64) {
+    if seconds > 0 {
+        thread::sleep(Duration::from_secs(seconds));
+    }
+}
+
+/// A formula of the integer engine, as accepted by `MyParser::parse_formula`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Formula<'a> {
+    /// `42`
+    Literal(i32),
+    /// `C5`
+    Cell((u16, u16)),
+    /// `A1+2`, with the operator as a character
+    Binary(char, Operand, Operand),
+    /// `SUM(A1:B3)`, with `start` never after `end`
+    Range(&'a str, (u16, u16), (u16, u16)),
+    /// `SLEEP(5)` or `SLEEP(A1)`, sleeping for whole seconds
+    Sleep(Operand),
+}
+
+/// The cells a formula reads, in the two shapes `Spreadsheet` tracks them in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dependencies {
+    /// Individual cells, possibly none
+    Cells(Vec<(u16, u16)>),
+    /// Every cell of an inclusive rectangle, as `(start, end)`
+    Range((u16, u16), (u16, u16)),
+}
+
+impl Formula<'_> {
+    /// Whether a cell assigned this keeps only the value and no formula: a literal, or `SLEEP`
+    /// of one, which sleeps only when assigned
+    pub fn is_value(&self) -> bool {
+        matches!(
+            self,
+            Formula::Literal(_) | Formula::Sleep(Operand::Literal(_))
+        )
+    }
+
+    pub fn dependencies(&self) -> Dependencies {
+        let cell = |operand: &Operand| match operand {
+            Operand::Cell(cell) => Some(*cell),
+            Operand::Literal(_) => None,
+        };
+        match self {
+            Formula::Literal(_) => Dependencies::Cells(Vec::new()),
+            Formula::Cell(c) => Dependencies::Cells(vec![*c]),
+            Formula::Binary(_, lhs, rhs) => {
+                Dependencies::Cells(cell(lhs).into_iter().chain(cell(rhs)).collect())
+            }
+            Formula::Range(_, start, end) => Dependencies::Range(*start, *end),
+            Formula::Sleep(arg) => Dependencies::Cells(cell(arg).into_iter().collect()),
+        }
+    }
+
+    /// Computes the formula's value, reading cells through `get_val`. `None` means the cell
+    /// holds an error, as when a cell read holds one or on division by zero. `SLEEP` sleeps
+    /// before returning.
+    pub fn evaluate(&self, get_val: impl Fn((u16, u16)) -> Option<i32>) -> Option<i32> {
+        match self {
+            Formula::Literal(v) => Some(*v),
+            Formula::Cell(c) => get_val(*c),
+            Formula::Binary(op, lhs, rhs) => {
+                let op_code = match op {
+                    '+' => 1,
+                    '-' => 2,
+                    '*' => 3,
+                    _ => 5,
+                };
+                eval_binary(op_code, lhs.value(&get_val)?, rhs.value(&get_val)?)
+            }
+            Formula::Range(func, start, end) => eval_range(func, *start, *end, get_val),
+            Formula::Sleep(arg) => {
+                let seconds = arg.value(&get_val)?;
+                sleep_seconds(seconds.max(0) as u64);
+                Some(seconds)
+            }
+        }
+    }
+
+    /// Like `evaluate`, for a sheet using float division: values are fractional and `/` does
+    /// not truncate, so `7/2` is `3.5`.
+    pub fn evaluate_real(&self, get_val: impl Fn((u16, u16)) -> Option<f64>) -> Option<f64> {
+        match self {
+            Formula::Literal(v) => Some(*v as f64),
+            Formula::Cell(c) => get_val(*c),
+            Formula::Binary(op, lhs, rhs) => {
+                let a = lhs.real_value(&get_val)?;
+                let b = rhs.real_value(&get_val)?;
+                match op {
+                    '+' => Some(a + b),
+                    '-' => Some(a - b),
+                    '*' => Some(a * b),
+                    _ => (b != 0.0).then(|| a / b),
+                }
+            }
+            Formula::Range(func, start, end) => eval_range_real(func, *start, *end, get_val),
+            Formula::Sleep(arg) => {
+                let seconds = arg.real_value(&get_val)?;
+                sleep_seconds(seconds.max(0.0) as u64);
+                Some(seconds)
+            }
+        }
+    }
+}
+
 impl MyParser {
     /// Converts a cell name (e.g., "A1") into its column and row indices.
     ///
@@ -26,7 +175,9 @@ impl MyParser {
     ///
     /// # Returns
     /// * `Some((col, row))` - The column and row indices if parsing succeeds.
-    /// * `None` - If the input string is invalid (e.g., lacks letters or numbers).
+    /// * `None` - If the input string is invalid: it must be ASCII letters followed by
+    ///   nothing but digits, so `A1B`, `A1.5`, `A 1` and `A+1` are all rejected, as is a
+    ///   column too large to represent.
     ///
     /// # Examples
     /// ```rust
@@ -38,25 +189,21 @@ impl MyParser {
     /// ```
     pub fn cell_name_to_coord(s: &str) -> Option<(u16, u16)> {
         let trimmed = s.trim();
-        let mut chars = trimmed.chars();
-        if let Some(first) = chars.next() {
-            if !first.is_alphabetic() {
-                return None;
-            }
-        } else {
-            return None;
-        }
+        let split = trimmed
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(trimmed.len());
+        let (letters, numbers) = trimmed.split_at(split);
 
-        let letters: String = trimmed.chars().take_while(|c| c.is_alphabetic()).collect();
-        let numbers: String = trimmed.chars().skip_while(|c| c.is_alphabetic()).collect();
-
-        if letters.is_empty() || numbers.is_empty() {
+        // `parse` alone would also accept a sign, as in `A+1`
+        if letters.is_empty() || numbers.is_empty() || !numbers.bytes().all(|b| b.is_ascii_digit())
+        {
             return None;
         }
 
-        let col = letters.chars().fold(0, |acc, c| {
-            acc * 26 + ((c.to_ascii_uppercase() as u16) - ('A' as u16) + 1)
-        });
+        let col = letters.bytes().try_fold(0u16, |acc, c| {
+            acc.checked_mul(26)?
+                .checked_add((c.to_ascii_uppercase() - b'A' + 1) as u16)
+        })?;
         let row = numbers.parse::<u16>().ok()?;
 
         Some((col, row))
@@ -103,6 +250,30 @@ impl MyParser {
         None
     }
 
+    /// The known function closest to `name`, for suggesting a fix to a misspelt call like
+    /// `SUmm`. Letter case is ignored, and at most two letters may be added, removed or
+    /// changed. None for a correctly written function or nothing close enough
+    ///
+    /// # Examples
+    /// ```rust
+    /// use embedded::myparser::MyParser;
+    /// assert_eq!(MyParser::suggest_function("SUmm"), Some("SUM"));
+    /// assert_eq!(MyParser::suggest_function("SUM"), None);
+    /// assert_eq!(MyParser::suggest_function("VLOOKUP"), None);
+    /// ```
+    pub fn suggest_function(name: &str) -> Option<&'static str> {
+        if FUNCTION_NAMES.contains(&name) {
+            return None;
+        }
+        let name = name.to_ascii_uppercase();
+        FUNCTION_NAMES
+            .into_iter()
+            .map(|func| (edit_distance(&name, func), func))
+            .filter(|&(distance, _)| distance <= 2)
+            .min_by_key(|&(distance, _)| distance)
+            .map(|(_, func)| func)
+    }
+
     /// Parses a range-based function call (e.g., `MAX(A1:B3)`).
     ///
     /// This method interprets functions that operate over a range of cells, such as `SUM`, `MAX`,
@@ -137,7 +308,7 @@ impl MyParser {
     /// ```
     pub fn parse_range(expr: &str) -> Option<RangeType> {
         let expr = expr.trim();
-        for &func in &["MIN", "MAX", "AVG", "SUM", "STDEV", "SLEEP"] {
+        for func in FUNCTION_NAMES {
             let open = format!("{}(", func);
             if expr.starts_with(&open) && expr.ends_with(')') {
                 let inside = &expr[open.len()..expr.len() - 1];
@@ -155,4 +326,212 @@ impl MyParser {
         }
         None
     }
+
+    /// Parses any formula a cell of the integer engine can hold.
+    ///
+    /// The forms are tried in order: `SLEEP(<literal or cell>)`, a binary operation of two
+    /// literals or cells, a range function, a single cell reference and finally an integer
+    /// literal.
+    ///
+    /// # Returns
+    /// * `None` - If the expression is none of these, like `SLEEP(A1:B2)`, `A1+x` or
+    ///   `SUM(B2:A1)` with its corners swapped.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use embedded::myparser::{Formula, MyParser, Operand};
+    /// assert_eq!(
+    ///     MyParser::parse_formula("A1*3"),
+    ///     Some(Formula::Binary('*', Operand::Cell((1, 1)), Operand::Literal(3)))
+    /// );
+    /// assert_eq!(MyParser::parse_formula(" -7 "), Some(Formula::Literal(-7)));
+    /// assert_eq!(MyParser::parse_formula("A1+"), None);
+    /// ```
+    pub fn parse_formula(expr: &str) -> Option<Formula<'_>> {
+        let expr = expr.trim();
+        if let Some(arg) = expr
+            .strip_prefix("SLEEP(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            // ranges are not allowed in SLEEP
+            if arg.contains(':') {
+                return None;
+            }
+            if let Some(arg) = Operand::parse(arg) {
+                return Some(Formula::Sleep(arg));
+            }
+        }
+        if let Some((op, lhs, rhs)) = MyParser::split_binary(expr) {
+            return Some(Formula::Binary(
+                op,
+                Operand::parse(lhs)?,
+                Operand::parse(rhs)?,
+            ));
+        }
+        if let Some((func, start, end)) = MyParser::parse_range(expr) {
+            if start.0 > end.0 || start.1 > end.1 {
+                return None;
+            }
+            return Some(Formula::Range(func, start, end));
+        }
+        if let Some(cell) = MyParser::cell_name_to_coord(expr) {
+            return Some(Formula::Cell(cell));
+        }
+        expr.parse().ok().map(Formula::Literal)
+    }
+
+    /// Parses and evaluates an expression in one go, reading cells through `get_val`.
+    ///
+    /// # Returns
+    /// * `Ok((value, dependencies))` - The value, `None` for an error value, and the cells the
+    ///   expression reads.
+    /// * `Err(3)` - If the expression does not parse, the code `Spreadsheet::set_cell` returns.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use embedded::myparser::{Dependencies, MyParser};
+    /// let get_val = |(col, row): (u16, u16)| Some((col * 10 + row) as i32);
+    /// assert_eq!(
+    ///     MyParser::evaluate_expression("B3-A1", get_val),
+    ///     Ok((Some(12), Dependencies::Cells(vec![(2, 3), (1, 1)])))
+    /// );
+    /// assert_eq!(MyParser::evaluate_expression("5/0", get_val).unwrap().0, None);
+    /// assert_eq!(MyParser::evaluate_expression("5%2", get_val), Err(3));
+    /// ```
+    pub fn evaluate_expression(
+        expr: &str,
+        get_val: impl Fn((u16, u16)) -> Option<i32>,
+    ) -> Result<(Option<i32>, Dependencies), u8> {
+        let formula = MyParser::parse_formula(expr).ok_or(3)?;
+        Ok((formula.evaluate(get_val), formula.dependencies()))
+    }
+}
+
+/// The number of single letter insertions, removals and changes turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances from a prefix of `a` to every prefix of `b`, one row at a time
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cell_name_to_coord_valid() {
+        assert_eq!(MyParser::cell_name_to_coord("A1"), Some((1, 1)));
+        assert_eq!(MyParser::cell_name_to_coord(" b2 "), Some((2, 2)));
+        assert_eq!(MyParser::cell_name_to_coord("ZZZ999"), Some((18278, 999)));
+    }
+
+    #[test]
+    fn test_cell_name_to_coord_rejects_malformed() {
+        for name in [
+            "A1B", "A1.5", "A 1", "A+1", "A-1", "1A", "A", "12", "", "é1", "A1é", "ZZZZZZ1",
+            "A99999",
+        ] {
+            assert_eq!(MyParser::cell_name_to_coord(name), None, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_formula_forms() {
+        assert_eq!(MyParser::parse_formula("42"), Some(Formula::Literal(42)));
+        assert_eq!(MyParser::parse_formula("C5"), Some(Formula::Cell((3, 5))));
+        assert_eq!(
+            MyParser::parse_formula("2-B1"),
+            Some(Formula::Binary(
+                '-',
+                Operand::Literal(2),
+                Operand::Cell((2, 1))
+            ))
+        );
+        assert_eq!(
+            MyParser::parse_formula("MAX(A1:B3)"),
+            Some(Formula::Range("MAX", (1, 1), (2, 3)))
+        );
+        assert_eq!(
+            MyParser::parse_formula("SLEEP(A2)"),
+            Some(Formula::Sleep(Operand::Cell((1, 2))))
+        );
+        assert_eq!(
+            MyParser::parse_formula("SLEEP(-1)"),
+            Some(Formula::Sleep(Operand::Literal(-1)))
+        );
+        for bad in [
+            "",
+            "A1+x",
+            "2*-3",
+            "SUM(B2:A1)",
+            "SLEEP(A1:B2)",
+            "SLEEP(x)",
+            "10%5",
+            "A1B",
+        ] {
+            assert_eq!(MyParser::parse_formula(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_formula_evaluation() {
+        let get_val = |cell: (u16, u16)| match cell {
+            (1, 1) => Some(7),
+            (1, 2) => Some(2),
+            (1, 3) => None,
+            _ => Some(0),
+        };
+        let eval = |expr| MyParser::evaluate_expression(expr, get_val).map(|(value, _)| value);
+
+        assert_eq!(eval("A1/A2"), Ok(Some(3)));
+        assert_eq!(eval("A1*A2"), Ok(Some(14)));
+        assert_eq!(eval("A1/0"), Ok(None));
+        assert_eq!(eval("A3+1"), Ok(None));
+        assert_eq!(eval("SUM(A1:A2)"), Ok(Some(9)));
+        assert_eq!(eval("MIN(A1:A3)"), Ok(None));
+        assert_eq!(eval("SLEEP(-2)"), Ok(Some(-2)));
+        assert_eq!(eval("A1+"), Err(3));
+    }
+
+    #[test]
+    fn test_formula_real_evaluation() {
+        let get_val = |cell: (u16, u16)| match cell {
+            (1, 1) => Some(7.0),
+            (1, 2) => Some(0.5),
+            _ => None,
+        };
+        let eval = |expr| {
+            MyParser::parse_formula(expr)
+                .unwrap()
+                .evaluate_real(get_val)
+        };
+
+        assert_eq!(eval("A1/2"), Some(3.5));
+        assert_eq!(eval("A1*A2"), Some(3.5));
+        assert_eq!(eval("AVG(A1:A2)"), Some(3.75));
+        assert_eq!(eval("A1/0"), None);
+        assert_eq!(eval("A3-1"), None);
+    }
+
+    #[test]
+    fn test_formula_dependencies() {
+        let deps = |expr| MyParser::parse_formula(expr).unwrap().dependencies();
+        assert_eq!(deps("A1+B2"), Dependencies::Cells(vec![(1, 1), (2, 2)]));
+        assert_eq!(deps("3*4"), Dependencies::Cells(vec![]));
+        assert_eq!(deps("AVG(A1:C3)"), Dependencies::Range((1, 1), (3, 3)));
+        assert_eq!(deps("SLEEP(B1)"), Dependencies::Cells(vec![(2, 1)]));
+
+        assert!(MyParser::parse_formula("SLEEP(3)").unwrap().is_value());
+        assert!(!MyParser::parse_formula("3*4").unwrap().is_value());
+    }
 }
@@ -1,15 +1,36 @@
 use pest::Parser;
 use pest::iterators::Pair;
 use pest_derive::Parser;
+use std::fmt::{self, Display};
 use std::str::FromStr;
 
-use crate::common::expression::{CellRange, Expression, Operator, RangeFunction};
+use crate::common::expression::{
+    CellRange, CoercionFunction, Expression, LocationFunction, Operator, RangeFunction,
+};
 use crate::common::structs::{AbsCell, RelCell};
+use crate::error_display::ErrorLabel;
 
 #[derive(Parser)]
 #[grammar = "parser/formula.pest"]
 struct PestFormulaParser;
 
+/// The reasons a formula can be rejected by the parser.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The formula does not follow the grammar.
+    Syntax,
+    /// A referenced cell lies outside the sheet.
+    OutOfBounds,
+    /// A range whose first cell is not above and to the left of its second cell, like `B5:A1`.
+    ReversedRange,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FormulaParser {
     max_rows: u16,
@@ -21,9 +42,9 @@ impl FormulaParser {
         FormulaParser { max_rows, max_cols }
     }
 
-    #[allow(clippy::result_unit_err)]
-    pub fn parse(&self, formula: &str, cell: AbsCell) -> Result<Expression, ()> {
-        let pairs = PestFormulaParser::parse(Rule::formula, formula).map_err(|_| ())?;
+    pub fn parse(&self, formula: &str, cell: AbsCell) -> Result<Expression, ParseError> {
+        let pairs =
+            PestFormulaParser::parse(Rule::formula, formula).map_err(|_| ParseError::Syntax)?;
 
         let formula_pair = pairs.peek().unwrap();
         let expr_pairs = formula_pair.into_inner().next().unwrap();
@@ -31,9 +52,24 @@ impl FormulaParser {
         self.parse_expression(expr_pairs, cell)
     }
 
-    fn parse_expression(&self, pair: Pair<Rule>, cell: AbsCell) -> Result<Expression, ()> {
+    /// Checks that `formula` parses and stays on the sheet, without choosing a cell for it.
+    /// References are absolute in the text, so the cell a formula is written in does not
+    /// change whether it is accepted
+    ///
+    /// # Examples
+    /// ```rust
+    /// use embedded::parser::formula_parser::{FormulaParser, ParseError};
+    /// let parser = FormulaParser::new(10, 10);
+    /// assert_eq!(parser.validate("SUM(A1:B2) * 2"), Ok(()));
+    /// assert_eq!(parser.validate("A1 +"), Err(ParseError::Syntax));
+    /// ```
+    pub fn validate(&self, formula: &str) -> Result<(), ParseError> {
+        self.parse(formula, AbsCell::new(0, 0)).map(|_| ())
+    }
+
+    fn parse_expression(&self, pair: Pair<Rule>, cell: AbsCell) -> Result<Expression, ParseError> {
         match pair.as_rule() {
-            Rule::expression => {
+            Rule::comparison | Rule::expression => {
                 let mut pairs = pair.into_inner();
                 let mut left = self.parse_expression(pairs.next().unwrap(), cell)?;
 
@@ -41,8 +77,12 @@ impl FormulaParser {
                     let operator = match op_pair.as_rule() {
                         Rule::add => Operator::Add,
                         Rule::subtract => Operator::Subtract,
-                        Rule::multiply => Operator::Multiply,
-                        Rule::divide => Operator::Divide,
+                        Rule::equal => Operator::Equal,
+                        Rule::not_equal => Operator::NotEqual,
+                        Rule::less => Operator::Less,
+                        Rule::less_equal => Operator::LessEqual,
+                        Rule::greater => Operator::Greater,
+                        Rule::greater_equal => Operator::GreaterEqual,
                         _ => unreachable!(),
                     };
 
@@ -74,7 +114,10 @@ impl FormulaParser {
                 self.parse_expression(inner, cell)
             }
             Rule::number => {
-                let value = pair.as_str().parse::<f64>().map_err(|_| ())?;
+                let value = pair
+                    .as_str()
+                    .parse::<f64>()
+                    .map_err(|_| ParseError::Syntax)?;
                 Ok(Expression::Number(value))
             }
             Rule::cell_ref => {
@@ -96,41 +139,120 @@ impl FormulaParser {
                     "AVG" => RangeFunction::Avg,
                     "SUM" => RangeFunction::Sum,
                     "STDEV" => RangeFunction::Stdev,
-                    _ => return Err(()),
+                    _ => return Err(ParseError::Syntax),
                 };
 
                 let cell_range = self.parse_cell_range(range_pair, cell)?;
                 Ok(Expression::RangeFunction(range_function, cell_range))
             }
+            Rule::sumproduct_function => {
+                let mut pairs = pair.into_inner();
+                let first = self.parse_cell_range(pairs.next().unwrap(), cell)?;
+                let second = self.parse_cell_range(pairs.next().unwrap(), cell)?;
+                Ok(Expression::SumProduct(first, second))
+            }
+            Rule::location_function => {
+                let mut pairs = pair.into_inner();
+                let function_name = pairs.next().unwrap();
+                let range_pair = pairs.next().unwrap();
+
+                let location_function = match function_name.as_str() {
+                    "ARGMIN" => LocationFunction::ArgMin,
+                    "ARGMAX" => LocationFunction::ArgMax,
+                    _ => return Err(ParseError::Syntax),
+                };
+
+                let cell_range = self.parse_cell_range(range_pair, cell)?;
+                Ok(Expression::Location(location_function, cell_range))
+            }
             Rule::sleep_function => {
                 let expr_pair = pair.into_inner().next().unwrap();
                 let expr = self.parse_expression(expr_pair, cell)?;
                 Ok(Expression::Sleep(Box::new(expr)))
             }
-            _ => Err(()),
+            Rule::sleep_ms_function => {
+                let expr_pair = pair.into_inner().next().unwrap();
+                let expr = self.parse_expression(expr_pair, cell)?;
+                Ok(Expression::SleepMs(Box::new(expr)))
+            }
+            Rule::n_function | Rule::t_function => {
+                let function = match pair.as_rule() {
+                    Rule::n_function => CoercionFunction::N,
+                    _ => CoercionFunction::T,
+                };
+                let expr_pair = pair.into_inner().next().unwrap();
+                let expr = self.parse_expression(expr_pair, cell)?;
+                Ok(Expression::Coerce(function, Box::new(expr)))
+            }
+            Rule::string => {
+                let quoted = pair.as_str();
+                let text = quoted[1..quoted.len() - 1].replace("\"\"", "\"");
+                Ok(Expression::Text(text))
+            }
+            _ => Err(ParseError::Syntax),
         }
     }
 
-    fn parse_cell_ref(&self, ref_str: &str, cell: AbsCell) -> Result<RelCell, ()> {
-        let c = AbsCell::from_str(ref_str).map_err(|_| ())?;
+    fn parse_cell_ref(&self, ref_str: &str, cell: AbsCell) -> Result<RelCell, ParseError> {
+        let c = AbsCell::from_str(ref_str).map_err(|_| ParseError::Syntax)?;
         if c.row >= self.max_rows as i16 || c.col >= self.max_cols as i16 {
-            Err(())
+            Err(ParseError::OutOfBounds)
         } else {
             Ok(c.to_rel(cell))
         }
     }
 
-    fn parse_cell_range(&self, range_pair: Pair<Rule>, cell: AbsCell) -> Result<CellRange, ()> {
+    /// Parses the column letters of a whole-column reference like the `C` in `C:C`
+    fn parse_column(&self, column: &str) -> Result<i16, ParseError> {
+        let c = AbsCell::from_str(&format!("{}1", column)).map_err(|_| ParseError::Syntax)?;
+        if c.col >= self.max_cols as i16 {
+            Err(ParseError::OutOfBounds)
+        } else {
+            Ok(c.col)
+        }
+    }
+
+    /// Parses the 1-based row number of a whole-row reference like the `3` in `3:3`
+    fn parse_row(&self, row: &str) -> Result<i16, ParseError> {
+        let row = row.parse::<u16>().map_err(|_| ParseError::OutOfBounds)?;
+        if row == 0 || row > self.max_rows {
+            Err(ParseError::OutOfBounds)
+        } else {
+            Ok(row as i16 - 1)
+        }
+    }
+
+    /// Parses a range. Whole columns (`A:B`) and whole rows (`1:2`) become the equivalent
+    /// explicit range clamped to the sheet, `A1:B999` and `A1:ZZZ2` on a 999 x 18278 sheet
+    fn parse_cell_range(
+        &self,
+        range_pair: Pair<Rule>,
+        cell: AbsCell,
+    ) -> Result<CellRange, ParseError> {
         let mut pairs = range_pair.into_inner();
-        let top_left_str = pairs.next().unwrap().as_str();
-        let bottom_right_str = pairs.next().unwrap().as_str();
+        let first = pairs.next().unwrap();
+        let second = pairs.next().unwrap();
 
-        let top_left = self.parse_cell_ref(top_left_str, cell)?;
-        let bottom_right = self.parse_cell_ref(bottom_right_str, cell)?;
+        let last_row = self.max_rows as i16 - 1;
+        let last_col = self.max_cols as i16 - 1;
+        let (top_left, bottom_right) = match first.as_rule() {
+            Rule::column_ref => (
+                AbsCell::new(0, self.parse_column(first.as_str())?).to_rel(cell),
+                AbsCell::new(last_row, self.parse_column(second.as_str())?).to_rel(cell),
+            ),
+            Rule::row_ref => (
+                AbsCell::new(self.parse_row(first.as_str())?, 0).to_rel(cell),
+                AbsCell::new(self.parse_row(second.as_str())?, last_col).to_rel(cell),
+            ),
+            _ => (
+                self.parse_cell_ref(first.as_str(), cell)?,
+                self.parse_cell_ref(second.as_str(), cell)?,
+            ),
+        };
 
         // Validate that the range forms a valid rectangle
         if !(top_left.row <= bottom_right.row && top_left.col <= bottom_right.col) {
-            return Err(());
+            return Err(ParseError::ReversedRange);
         }
         Ok(CellRange {
             top_left,
@@ -141,8 +263,8 @@ impl FormulaParser {
 
 #[cfg(test)]
 mod tests {
-    use super::super::formula_parser::FormulaParser;
-    use crate::common::expression::Expression;
+    use super::super::formula_parser::{FormulaParser, ParseError};
+    use crate::common::expression::{CoercionFunction, Expression, LocationFunction};
     use crate::common::structs::AbsCell;
 
     #[test]
@@ -189,6 +311,179 @@ mod tests {
         assert!(result.is_err(), "Should fail with invalid range");
     }
 
+    #[test]
+    fn test_reversed_range_error() {
+        let parser = FormulaParser::new(1000, 26);
+        let cell = AbsCell::new(1, 1);
+
+        assert_eq!(
+            parser.parse("SUM(B5:A1)", cell),
+            Err(ParseError::ReversedRange)
+        );
+        assert_eq!(
+            ParseError::ReversedRange.to_string(),
+            "Range start must be above-left of end"
+        );
+        // a broken formula is still a plain syntax error
+        assert_eq!(parser.parse("SUM(B5:", cell), Err(ParseError::Syntax));
+        assert_eq!(parser.parse("AA1", cell), Err(ParseError::OutOfBounds));
+    }
+
+    #[test]
+    fn test_sumproduct() {
+        let parser = FormulaParser::new(1000, 26);
+        let cell = AbsCell::new(5, 5);
+        let result = parser.parse("SUMPRODUCT(A1:C1, A2:C2)", cell);
+        assert!(matches!(result, Ok(Expression::SumProduct(_, _))));
+        assert_eq!(result.unwrap().to_string(cell), "SUMPRODUCT(A1:C1, A2:C2)");
+
+        // SUM must still parse as a plain range function
+        let result = parser.parse("SUM(A1:C1)", cell);
+        assert!(matches!(result, Ok(Expression::RangeFunction(_, _))));
+    }
+
+    #[test]
+    fn test_scientific_notation() {
+        let parser = FormulaParser::new(1000, 26);
+        let cell = AbsCell::new(5, 5);
+        assert_eq!(parser.parse("1e3", cell), Ok(Expression::Number(1000.0)));
+        assert_eq!(parser.parse("1.5E-2", cell), Ok(Expression::Number(0.015)));
+        assert_eq!(parser.parse("-2e+2", cell), Ok(Expression::Number(-200.0)));
+
+        let result = parser.parse("A1 * 1e6", cell).unwrap();
+        assert_eq!(result.to_string(cell), "A1 * 1000000");
+
+        // an exponent needs digits
+        assert_eq!(parser.parse("1e", cell), Err(ParseError::Syntax));
+        assert_eq!(parser.parse("1e+", cell), Err(ParseError::Syntax));
+    }
+
+    #[test]
+    fn test_sleep_functions() {
+        let parser = FormulaParser::new(1000, 26);
+        let cell = AbsCell::new(5, 5);
+        let result = parser.parse("SLEEP_MS(A1 + 10)", cell);
+        assert!(matches!(result, Ok(Expression::SleepMs(_))));
+        assert_eq!(result.unwrap().to_string(cell), "SLEEP_MS(A1 + 10)");
+        assert!(matches!(
+            parser.parse("SLEEP(0.5)", cell),
+            Ok(Expression::Sleep(_))
+        ));
+    }
+
+    #[test]
+    fn test_location_functions() {
+        let parser = FormulaParser::new(1000, 26);
+        let cell = AbsCell::new(5, 5);
+        let result = parser.parse("ARGMAX(A1:C4)", cell);
+        assert!(matches!(
+            result,
+            Ok(Expression::Location(LocationFunction::ArgMax, _))
+        ));
+        assert_eq!(result.unwrap().to_string(cell), "ARGMAX(A1:C4)");
+        assert!(matches!(
+            parser.parse("ARGMIN(B1:B9)", cell),
+            Ok(Expression::Location(LocationFunction::ArgMin, _))
+        ));
+
+        // the label can not take part in arithmetic
+        assert_eq!(
+            parser.parse("ARGMAX(A1:C4) + 1", cell),
+            Err(ParseError::Syntax)
+        );
+        assert_eq!(
+            parser.parse("SUM(A1:A2) + ARGMIN(A1:C4)", cell),
+            Err(ParseError::Syntax)
+        );
+    }
+
+    #[test]
+    fn test_comparisons_and_parentheses_round_trip() {
+        let parser = FormulaParser::new(1000, 26);
+        let cell = AbsCell::new(5, 5);
+        for formula in [
+            "(A1 > 5) + (B1 >= 5)",
+            "A1 + 1 > B1 * 2",
+            "1 < 2 < 3",
+            "A1 <> (B1 = C1)",
+            "(A1 + 1) * 2",
+            "A1 - (B1 - C1)",
+            "A1 / (B1 * C1)",
+            "A1 - B1 - C1",
+        ] {
+            let parsed = parser.parse(formula, cell).unwrap();
+            assert_eq!(parsed.to_string(cell), formula);
+        }
+        assert_eq!(
+            parser.parse("((A1+1))*2", cell).unwrap().to_string(cell),
+            "(A1 + 1) * 2"
+        );
+        assert_eq!(parser.parse("A1 => 1", cell), Err(ParseError::Syntax));
+    }
+
+    #[test]
+    fn test_function_arguments_round_trip() {
+        let parser = FormulaParser::new(1000, 26);
+        let cell = AbsCell::new(5, 5);
+        for formula in [
+            "SLEEP((A1 = 1))",
+            "SLEEP_MS((A1 < 2))",
+            "SLEEP((A1 < 2) * 3)",
+            "N((B2 = 1))",
+            "N((B2 = 1) + 1)",
+            "T((A1 <> 2))",
+            "SLEEP(N((A1 >= 0)))",
+        ] {
+            let parsed = parser.parse(formula, cell).unwrap();
+            let text = parsed.to_string(cell);
+            assert_eq!(text, formula);
+            assert_eq!(parser.parse(&text, cell), Ok(parsed));
+        }
+    }
+
+    #[test]
+    fn test_coercion_functions() {
+        let parser = FormulaParser::new(1000, 26);
+        let cell = AbsCell::new(5, 5);
+        let result = parser.parse("N(\"say \"\"hi\"\"\") + N(A1 * 2)", cell);
+        assert!(result.is_ok());
+        assert_eq!(
+            result.unwrap().to_string(cell),
+            "N(\"say \"\"hi\"\"\") + N(A1 * 2)"
+        );
+        assert!(matches!(
+            parser.parse("T(B2)", cell),
+            Ok(Expression::Coerce(CoercionFunction::T, _))
+        ));
+        // N5 is still a cell
+        assert!(matches!(parser.parse("N5", cell), Ok(Expression::Cell(_))));
+
+        // text is only allowed as an argument, and T's result only as the whole formula
+        assert_eq!(parser.parse("\"abc\"", cell), Err(ParseError::Syntax));
+        assert_eq!(
+            parser.parse("N(\"abc\" + 1)", cell),
+            Err(ParseError::Syntax)
+        );
+        assert_eq!(parser.parse("T(5) + 1", cell), Err(ParseError::Syntax));
+    }
+
+    #[test]
+    fn test_validate() {
+        let parser = FormulaParser::new(1000, 26);
+        assert_eq!(parser.validate("A1 + B2 * 3"), Ok(()));
+        assert_eq!(parser.validate("MAX(A1:Z1000)"), Ok(()));
+        assert_eq!(parser.validate("SLEEP(1)"), Ok(()));
+
+        assert_eq!(parser.validate("A1 +"), Err(ParseError::Syntax));
+        assert_eq!(parser.validate(""), Err(ParseError::Syntax));
+        assert_eq!(parser.validate("AA1"), Err(ParseError::OutOfBounds));
+        assert_eq!(parser.validate("B5:A1"), Err(ParseError::Syntax));
+        assert_eq!(
+            parser.validate("SUM(B5:A1)"),
+            Err(ParseError::ReversedRange)
+        );
+    }
+
     #[test]
     fn test_out_of_bounds() {
         let parser = FormulaParser::new(1000, 26);
@@ -198,6 +493,8 @@ mod tests {
 
         let result = parser.parse(formula, cell);
         assert!(result.is_err(), "Should fail with out of bounds error");
+        assert!(parser.parse("AAAAAA1 + B2", cell).is_err());
+        assert!(parser.parse("SUM(A1:ZZZZZZ1)", cell).is_err());
     }
 
     #[test]
@@ -210,4 +507,28 @@ mod tests {
         let result = parser.parse(formula, cell);
         assert!(result.is_err(), "Should fail with out of bounds error");
     }
+
+    #[test]
+    fn test_whole_column_and_row_ranges() {
+        let parser = FormulaParser::new(5, 4);
+        let cell = AbsCell::new(0, 3);
+
+        let result = parser.parse("SUM(A:A)", cell).unwrap();
+        assert_eq!(result.to_string(cell), "SUM(A1:A5)");
+        let result = parser.parse("MAX(A:C)", cell).unwrap();
+        assert_eq!(result.to_string(cell), "MAX(A1:C5)");
+        let result = parser.parse("SUM(2:3)", cell).unwrap();
+        assert_eq!(result.to_string(cell), "SUM(A2:D3)");
+
+        assert_eq!(parser.parse("SUM(E:E)", cell), Err(ParseError::OutOfBounds));
+        assert_eq!(parser.parse("SUM(6:6)", cell), Err(ParseError::OutOfBounds));
+        assert_eq!(parser.parse("SUM(0:1)", cell), Err(ParseError::OutOfBounds));
+        assert_eq!(
+            parser.parse("SUM(B:A)", cell),
+            Err(ParseError::ReversedRange)
+        );
+        // endpoints of different kinds do not form a range
+        assert_eq!(parser.parse("SUM(A:1)", cell), Err(ParseError::Syntax));
+        assert_eq!(parser.parse("SUM(A1:B)", cell), Err(ParseError::Syntax));
+    }
 }
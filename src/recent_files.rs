@@ -0,0 +1,112 @@
+//! The sheets listed under File → Open Recent in the GUI.
+//!
+//! The list is kept across runs in a small text file in the user's config directory, one path
+//! per line, most recent first.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many paths the list keeps, older ones are dropped
+pub const MAX_RECENT_FILES: usize = 10;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    /// Where the list is kept, None if the platform has no config directory
+    pub fn default_location() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("embedded-spreadsheet").join("recent_files"))
+    }
+
+    /// Reads the list kept at `location`, leaving out files that no longer exist. A missing or
+    /// unreadable list reads as empty
+    pub fn load(location: &Path) -> Self {
+        let contents = fs::read_to_string(location).unwrap_or_default();
+        let mut recent = Self::default();
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let path = PathBuf::from(line);
+            if path.exists() && !recent.paths.contains(&path) {
+                recent.paths.push(path);
+            }
+        }
+        recent.paths.truncate(MAX_RECENT_FILES);
+        recent
+    }
+
+    /// Writes the list to `location`, creating its directory if needed
+    pub fn save(&self, location: &Path) -> io::Result<()> {
+        if let Some(dir) = location.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = String::new();
+        for path in &self.paths {
+            contents.push_str(&path.to_string_lossy());
+            contents.push('\n');
+        }
+        fs::write(location, contents)
+    }
+
+    /// Puts `path` first, removing its earlier entry and anything past `MAX_RECENT_FILES`
+    pub fn add(&mut self, path: PathBuf) {
+        self.remove(&path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT_FILES);
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.paths.retain(|p| p != path);
+    }
+
+    /// The paths, most recent first
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_dedupes_and_caps() {
+        let mut recent = RecentFiles::default();
+        recent.add(PathBuf::from("a.sheet"));
+        recent.add(PathBuf::from("b.sheet"));
+        recent.add(PathBuf::from("a.sheet"));
+        assert_eq!(
+            recent.paths(),
+            [PathBuf::from("a.sheet"), PathBuf::from("b.sheet")]
+        );
+
+        for i in 0..MAX_RECENT_FILES {
+            recent.add(PathBuf::from(format!("{}.sheet", i)));
+        }
+        assert_eq!(recent.paths().len(), MAX_RECENT_FILES);
+        assert_eq!(recent.paths()[0], PathBuf::from("9.sheet"));
+        assert!(!recent.paths().contains(&PathBuf::from("a.sheet")));
+    }
+
+    #[test]
+    fn test_load_drops_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let kept = dir.path().join("kept.sheet");
+        fs::write(&kept, b"").unwrap();
+        let missing = dir.path().join("missing.sheet");
+
+        let mut recent = RecentFiles::default();
+        recent.add(kept.clone());
+        recent.add(missing);
+        let location = dir.path().join("config").join("recent_files");
+        recent.save(&location).unwrap();
+
+        assert_eq!(RecentFiles::load(&location).paths(), [kept]);
+        assert!(RecentFiles::load(&dir.path().join("none")).is_empty());
+    }
+}
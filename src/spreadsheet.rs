@@ -1,8 +1,8 @@
-use crate::function::{eval_binary, eval_range};
-use crate::myparser::MyParser;
+use crate::common::locale::NumberLocale;
+use crate::error_display::DEFAULT_ERROR_TEXT;
+use crate::myparser::{Dependencies, Formula, MyParser};
 use std::collections::{HashMap, HashSet};
-use std::thread;
-use std::time::Duration;
+use std::io::{BufWriter, Write};
 
 /// Represents a type alias for normal child dependencies.
 ///
@@ -25,10 +25,12 @@ type ChildRangeType = (String, (u16, u16), (u16, u16));
 ///
 /// # Variants
 /// * `Value(i32)` - Contains a numeric value.
+/// * `Real(f64)` - Contains a value with a fraction, only in a sheet using float division.
 /// * `Err` - Indicates an error state.
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum Cell {
     Value(i32),
+    Real(f64),
     Err,
 }
 
@@ -47,6 +49,16 @@ impl Cell {
     pub fn new() -> Self {
         Cell::Value(0)
     }
+
+    /// Stores a value computed with float division, as `Value` when it is a whole number that
+    /// fits and as `Real` otherwise
+    fn from_real(value: f64) -> Self {
+        if value.fract() == 0.0 && value >= i32::MIN as f64 && value <= i32::MAX as f64 {
+            Cell::Value(value as i32)
+        } else {
+            Cell::Real(value)
+        }
+    }
 }
 
 /// Converts a 1-based column index into letters.
@@ -88,6 +100,10 @@ fn col_to_letter(mut n: usize) -> String {
 /// * `child_normal` - A map linking each cell to its child dependencies and identifier (normal dependencies).
 /// * `child_range` - A map linking each cell to range-based dependencies.
 /// * `cells` - A two-dimensional vector storing the content (`Cell`) of the spreadsheet.
+/// * `set_cells` - The `(column, row)` of every cell successfully assigned by `set_cell`.
+/// * `locale` - The number format used when displaying cell values.
+/// * `error_text` - What error cells are displayed as.
+/// * `float_division` - Whether formulas are evaluated with fractions, see `with_float_division`.
 pub struct Spreadsheet {
     pub rows: usize,
     pub cols: usize,
@@ -95,6 +111,11 @@ pub struct Spreadsheet {
     pub child_normal: HashMap<(u16, u16), ChildNormalType>,
     pub child_range: HashMap<(u16, u16), ChildRangeType>,
     pub cells: Vec<Vec<Cell>>,
+    pub set_cells: HashSet<(u16, u16)>,
+    pub locale: NumberLocale,
+    /// Shown by `display` in place of the value of an error cell
+    pub error_text: String,
+    pub float_division: bool,
 }
 impl Spreadsheet {
     /// Creates a new `Spreadsheet` instance with the specified number of rows and columns.
@@ -128,6 +149,30 @@ impl Spreadsheet {
             child_normal: HashMap::new(),
             child_range: HashMap::new(),
             cells,
+            set_cells: HashSet::new(),
+            locale: NumberLocale::default(),
+            error_text: DEFAULT_ERROR_TEXT.to_string(),
+            float_division: false,
+        }
+    }
+
+    /// Creates a `Spreadsheet` like `new`, whose formulas keep fractions instead of truncating
+    /// to integers, so `7/2` is `3.5` rather than `3`. `AVG` and `STDEV` keep their fraction as
+    /// well. Whole results are still stored as `Cell::Value`, others as `Cell::Real`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use embedded::spreadsheet::{Cell, Spreadsheet};
+    /// let mut sheet = Spreadsheet::with_float_division(10, 10);
+    /// sheet.set_cell((1, 1), "7/2");
+    /// sheet.set_cell((1, 2), "A1*2");
+    /// assert_eq!(sheet.cells[1][1], Cell::Real(3.5));
+    /// assert_eq!(sheet.cells[2][1], Cell::Value(7));
+    /// ```
+    pub fn with_float_division(rows: usize, cols: usize) -> Self {
+        Spreadsheet {
+            float_division: true,
+            ..Spreadsheet::new(rows, cols)
         }
     }
 
@@ -147,12 +192,40 @@ impl Spreadsheet {
         if r as usize <= self.rows && c as usize <= self.cols {
             match &self.cells[r as usize][c as usize] {
                 Cell::Value(v) => Some(*v),
+                // only a sheet using float division holds these, and it reads `get_real`
+                Cell::Real(v) => Some(*v as i32),
                 Cell::Err => None,
             }
         } else {
             None
         }
     }
+
+    /// Like `get_val`, keeping the fraction of a `Cell::Real`.
+    fn get_real(&self, (c, r): (u16, u16)) -> Option<f64> {
+        if r as usize <= self.rows && c as usize <= self.cols {
+            match &self.cells[r as usize][c as usize] {
+                Cell::Value(v) => Some(*v as f64),
+                Cell::Real(v) => Some(*v),
+                Cell::Err => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Computes what a cell holding `formula` holds, with float division if the sheet uses it
+    fn evaluate(&self, formula: &Formula) -> Cell {
+        if self.float_division {
+            formula
+                .evaluate_real(|c| self.get_real(c))
+                .map_or(Cell::Err, Cell::from_real)
+        } else {
+            formula
+                .evaluate(|c| self.get_val(c))
+                .map_or(Cell::Err, Cell::Value)
+        }
+    }
     /// Updates a cell's value with a new expression and updates all dependencies.
     ///
     /// # Arguments
@@ -175,7 +248,7 @@ impl Spreadsheet {
     /// * Cell references: "A1"
     /// * Binary operations: "A1+2", "3*B4"
     /// * Range functions: "SUM(A1:B3)"
-    /// * Special functions: "SLEEP(5)" or "SLEEP(A1)"
+    /// * Special functions: "SLEEP(5)" or "SLEEP(A1)", sleeping for whole seconds
     ///
     /// # Dependencies
     ///
@@ -184,324 +257,144 @@ impl Spreadsheet {
     /// * Prevents cyclic dependencies by restoring previous state when detected
     /// * Recalculates dependent cells when a referenced cell changes
     pub fn set_cell(&mut self, coord: (u16, u16), expr: &str) -> u8 {
-        if coord.1 as usize > self.rows || coord.0 as usize > self.cols {
-            return 1; // Invalid cell
+        let code = self.assign_cell(coord, expr);
+        if code == 0 {
+            self.set_cells.insert(coord);
         }
-        // Check for out-of-bounds coordinates
-        //print parents and child dependencies before setting cell
-        // println!("parents: {:?}", self.parents_normal);
-        // println!("child: {:?}", self.child_normal);
-        // println!("child_range: {:?}", self.child_range);
-        // 1) clear old dependencies but save them first
-        let old_cell_value = self.cells[coord.1 as usize][coord.0 as usize];
-        let mut removed_from_parents = Vec::new();
-        let old_child_normal = self.child_normal.remove(&coord);
-        let old_child_range = self.child_range.remove(&coord);
+        code
+    }
 
-        // Track which entries we're removing from parents_normal
-        for (parent_coord, deps) in self.parents_normal.iter_mut() {
-            if deps.contains(&coord) {
-                removed_from_parents.push((*parent_coord, coord));
-                deps.remove(&coord);
+    /// Sets every cell of the rectangle between `start` and `end` (in either order) to `value`,
+    /// replacing their formulas, and then recalculates the dependents of all of them together.
+    ///
+    /// # Returns
+    ///
+    /// * `0` - Success
+    /// * `1` - Either corner is out of bounds, nothing is changed
+    ///
+    /// # Examples
+    /// ```rust
+    /// let mut sheet = embedded::spreadsheet::Spreadsheet::new(10, 10);
+    /// use embedded::spreadsheet::Cell;
+    /// sheet.set_cell((1, 4), "SUM(A1:B3)");
+    /// assert_eq!(sheet.fill_range((1, 1), (2, 3), 7), 0);
+    /// assert_eq!(sheet.cells[4][1], Cell::Value(42));
+    /// ```
+    pub fn fill_range(&mut self, start: (u16, u16), end: (u16, u16), value: i32) -> u8 {
+        let in_bounds = |(c, r): (u16, u16)| {
+            (1..=self.cols).contains(&(c as usize)) && (1..=self.rows).contains(&(r as usize))
+        };
+        if !in_bounds(start) || !in_bounds(end) {
+            return 1;
+        }
+        let (first, last) = (
+            (start.0.min(end.0), start.1.min(end.1)),
+            (start.0.max(end.0), start.1.max(end.1)),
+        );
+
+        let mut filled = Vec::new();
+        for row in first.1..=last.1 {
+            for col in first.0..=last.0 {
+                let coord = (col, row);
+                self.child_normal.remove(&coord);
+                self.child_range.remove(&coord);
+                self.cells[row as usize][col as usize] = Cell::Value(value);
+                self.set_cells.insert(coord);
+                filled.push(coord);
             }
         }
+        for deps in self.parents_normal.values_mut() {
+            deps.retain(|&cell| !is_within_range(cell, first, last));
+        }
+        self.recalc_dependents_of(&filled);
+        0
+    }
+
+    /// Checks whether a cell was ever successfully assigned, as opposed to holding the initial `0`.
+    ///
+    /// # Arguments
+    /// * `coord` - The cell coordinates as `(column, row)`, as taken by `set_cell`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// let mut sheet = embedded::spreadsheet::Spreadsheet::new(10, 10);
+    /// sheet.set_cell((1, 1), "0");
+    /// assert!(sheet.is_set((1, 1)));
+    /// assert!(!sheet.is_set((2, 1)));
+    /// ```
+    pub fn is_set(&self, coord: (u16, u16)) -> bool {
+        self.set_cells.contains(&coord)
+    }
 
+    /// Does the work of `set_cell`, without recording the cell as set.
+    fn assign_cell(&mut self, coord: (u16, u16), expr: &str) -> u8 {
+        if coord.1 as usize > self.rows || coord.0 as usize > self.cols {
+            return 1; // Invalid cell
+        }
         let expr = expr.trim();
+        let Some(formula) = MyParser::parse_formula(expr) else {
+            return 3; // unrecognized cmd
+        };
+        let out_of_bounds =
+            |(col, row): (u16, u16)| col as usize > self.cols || row as usize > self.rows;
+        if matches!(formula, Formula::Range(_, _, end) if out_of_bounds(end)) {
+            return 3;
+        }
 
-        // 1a) Check for SLEEP function with a constant value: "SLEEP(5)"
-        if expr.starts_with("SLEEP(") && expr.ends_with(")") {
-            let arg_str = &expr[6..expr.len() - 1];
-            // Check if the argument contains a colon, which indicates a range
-            if arg_str.contains(':') {
-                // Range is not allowed in SLEEP function
-                // Restore old child dependencies
-                if let Some(old_normal) = old_child_normal {
-                    self.child_normal.insert(coord, old_normal);
-                }
-                if let Some(old_range) = old_child_range {
-                    self.child_range.insert(coord, old_range);
-                }
-                // Restore parents
-                for (parent_coord, child_coord) in removed_from_parents {
-                    self.parents_normal
-                        .entry(parent_coord)
-                        .or_default()
-                        .insert(child_coord);
-                }
-                return 3; // unrecognized cmd - range not allowed in SLEEP
-            }
-            // Try to parse as a literal integer
-            if let Ok(sleep_time) = arg_str.parse::<i32>() {
-                // Direct sleep with constant
-                if sleep_time > 0 {
-                    thread::sleep(Duration::from_secs(sleep_time as u64));
-                }
-                self.cells[coord.1 as usize][coord.0 as usize] = Cell::Value(sleep_time);
-                self.recalc_dependents(coord);
-                return 0;
-            }
-            // Try to parse as a cell reference
-            else if let Some(ref_cell) = MyParser::cell_name_to_coord(arg_str) {
-                // Add dependency tracking
-                self.parents_normal
-                    .entry(ref_cell)
-                    .or_default()
-                    .insert(coord);
-                let mut refs = HashSet::new();
-                refs.insert(ref_cell);
-                self.child_normal.insert(coord, (expr.to_string(), refs));
-
-                if self.has_cycle_from(coord) {
-                    //reverse the parents normal and child normal changes done above
-                    self.parents_normal
-                        .entry(ref_cell)
-                        .or_default()
-                        .remove(&coord);
-                    self.child_normal.remove(&coord);
-                    //get the old values in these hashmaps
-                    for (parent_coord, child_coord) in removed_from_parents {
-                        self.parents_normal
-                            .entry(parent_coord)
-                            .or_default()
-                            .insert(child_coord);
-                    }
-                    // Restore old child dependencies
-                    if let Some(old_normal) = old_child_normal {
-                        self.child_normal.insert(coord, old_normal);
-                    }
-                    if let Some(old_range) = old_child_range {
-                        self.child_range.insert(coord, old_range);
-                    }
-                    return 4;
-                }
-                match self.get_val(ref_cell) {
-                    Some(sleep_time) => {
-                        // Sleep using the referenced cell's value
-                        if sleep_time > 0 {
-                            thread::sleep(Duration::from_secs(sleep_time as u64));
-                        }
-                        self.cells[coord.1 as usize][coord.0 as usize] = Cell::Value(sleep_time);
-                    }
-                    None => {
-                        self.cells[coord.1 as usize][coord.0 as usize] = Cell::Err;
-                    }
-                }
-                self.recalc_dependents(coord);
-                return 0;
+        // 1) clear old dependencies but save them first
+        let old_child_normal = self.child_normal.remove(&coord);
+        let old_child_range = self.child_range.remove(&coord);
+        let mut removed_from_parents = Vec::new();
+        for (parent_coord, deps) in self.parents_normal.iter_mut() {
+            if deps.remove(&coord) {
+                removed_from_parents.push(*parent_coord);
             }
         }
 
-        // 2a) Binary: "A1+2", "3/0", etc.
-        if let Some((op_char, lhs_s, rhs_s)) = MyParser::split_binary(expr) {
-            let op_code = match op_char {
-                '+' => 1,
-                '-' => 2,
-                '*' => 3,
-                '/' => 5,
-                _ => return 3, // unrecognized cmd  invalid operator
-            };
-
-            // Evaluate lhs
-            let a = if let Some(c) = MyParser::cell_name_to_coord(lhs_s) {
-                match self.get_val(c) {
-                    Some(val) => Cell::Value(val),
-                    //for None return Err
-                    None => Cell::Err,
-                }
-            } else {
-                match lhs_s.parse::<i32>() {
-                    Ok(val) => Cell::Value(val),
-                    Err(_) => return 3,
-                }
-            };
-
-            // Evaluate rhs
-            let b = if let Some(c) = MyParser::cell_name_to_coord(rhs_s) {
-                match self.get_val(c) {
-                    Some(val) => Cell::Value(val),
-                    None => Cell::Err,
-                }
-            } else {
-                match rhs_s.parse::<i32>() {
-                    Ok(val) => Cell::Value(val),
-                    Err(_) => return 3,
-                }
-            };
-
-            let new_cell =
-                if op_code == 5 && b == Cell::Value(0) || a == Cell::Err || b == Cell::Err {
-                    Cell::Err
-                }
-                //else if both are values
-                else if let (Cell::Value(va), Cell::Value(vb)) = (a, b) {
-                    if let Some(v) = eval_binary(op_code, va, vb) {
-                        Cell::Value(v)
-                    } else {
-                        return 5; // division by zero
-                    }
-                } else {
-                    return 3;
-                };
-            let mut updated_parents = Vec::new();
-            // adding new dependencies
-            let mut refs = HashSet::new();
-            if let Some(c) = MyParser::cell_name_to_coord(lhs_s) {
-                updated_parents.push((c, coord));
-                self.parents_normal.entry(c).or_default().insert(coord);
-                refs.insert(c);
-            }
-            if let Some(c) = MyParser::cell_name_to_coord(rhs_s) {
-                updated_parents.push((c, coord));
-                self.parents_normal.entry(c).or_default().insert(coord);
-                refs.insert(c);
+        // 2) record the new ones
+        match formula.dependencies() {
+            Dependencies::Range(start, end) => {
+                self.child_range
+                    .insert(coord, (expr.to_string(), start, end));
             }
-            self.child_normal.insert(coord, (expr.to_string(), refs));
-            // Check for cycles
-            if self.has_cycle_from(coord) {
-                // Reverse the parents_normal and child_normal changes done above
-                for (parent, child) in updated_parents {
-                    self.parents_normal
-                        .entry(parent)
-                        .or_default()
-                        .remove(&child);
-                }
-                self.child_normal.remove(&coord);
-                // Restore old child dependencies
-                if let Some(old_normal) = old_child_normal {
-                    self.child_normal.insert(coord, old_normal);
+            Dependencies::Cells(refs) => {
+                for &c in &refs {
+                    self.parents_normal.entry(c).or_default().insert(coord);
                 }
-                if let Some(old_range) = old_child_range {
-                    self.child_range.insert(coord, old_range);
+                if !formula.is_value() {
+                    self.child_normal
+                        .insert(coord, (expr.to_string(), refs.into_iter().collect()));
                 }
-                // Restore parents
-                for (parent_coord, child_coord) in removed_from_parents {
-                    self.parents_normal
-                        .entry(parent_coord)
-                        .or_default()
-                        .insert(child_coord);
-                }
-                // Keep the old cell value
-                self.cells[coord.1 as usize][coord.0 as usize] = old_cell_value;
-                return 4;
             }
-            self.cells[coord.1 as usize][coord.0 as usize] = new_cell;
-            self.recalc_dependents(coord);
-            return 0;
         }
 
-        // 2b) Range: "SUM(A1:B3)"
-        if let Some((func, start, end)) = MyParser::parse_range(expr) {
-            // if start > end => return 3, and check in bounds
-            if start.0 > end.0
-                || start.1 > end.1
-                || start.0 > self.cols as u16
-                || start.1 > self.rows as u16
-                || end.0 > self.cols as u16
-                || end.1 > self.rows as u16
-            {
-                return 3; // unrecognized cmd
-            }
-            // Add the range dependency
-            self.child_range
-                .insert(coord, (expr.to_string(), start, end));
-
-            // Check for cycles that might be created by this range reference
-            if self.has_cycle_from(coord) {
-                // Cycle detected - remove the range dependency we just added
-                self.child_range.remove(&coord);
-                // Restore old child dependencies
-                if let Some(old_normal) = old_child_normal {
-                    self.child_normal.insert(coord, old_normal);
-                }
-                if let Some(old_range) = old_child_range {
-                    self.child_range.insert(coord, old_range);
+        // 3) a cycle puts the old dependencies back and keeps the old value
+        if self.has_cycle_from(coord) {
+            if let Some((_, refs)) = self.child_normal.remove(&coord) {
+                for c in refs {
+                    self.parents_normal.entry(c).or_default().remove(&coord);
                 }
-                // Restore parents
-                for (parent_coord, child_coord) in removed_from_parents {
-                    self.parents_normal
-                        .entry(parent_coord)
-                        .or_default()
-                        .insert(child_coord);
-                }
-                // Keep the old cell value
-                self.cells[coord.1 as usize][coord.0 as usize] = old_cell_value;
-                return 4;
             }
-            // No cycle, proceed with evaluation
-            if let Some(v) = eval_range(func, start, end, |c| self.get_val(c)) {
-                self.cells[coord.1 as usize][coord.0 as usize] = Cell::Value(v);
-            } else {
-                self.cells[coord.1 as usize][coord.0 as usize] = Cell::Err;
+            self.child_range.remove(&coord);
+            if let Some(old_normal) = old_child_normal {
+                self.child_normal.insert(coord, old_normal);
             }
-            self.recalc_dependents(coord);
-            return 0;
-        }
-
-        // 2c) Single reference: "C5"
-        if let Some(c) = MyParser::cell_name_to_coord(expr) {
-            // Add dependency tracking
-            self.parents_normal.entry(c).or_default().insert(coord);
-            let mut refs = HashSet::new();
-            refs.insert(c);
-            self.child_normal.insert(coord, (expr.to_string(), refs));
-
-            // Check for cycles
-            if self.has_cycle_from(coord) {
-                // Cycle detected - remove the dependency we just added
-                self.parents_normal.entry(c).or_default().remove(&coord);
-                self.child_normal.remove(&coord);
-                // Restore old child dependencies
-                if let Some(old_normal) = old_child_normal {
-                    self.child_normal.insert(coord, old_normal);
-                }
-                if let Some(old_range) = old_child_range {
-                    self.child_range.insert(coord, old_range);
-                }
-                // Restore parents
-                for (parent_coord, child_coord) in removed_from_parents {
-                    self.parents_normal
-                        .entry(parent_coord)
-                        .or_default()
-                        .insert(child_coord);
-                }
-                // Keep the old cell value
-                self.cells[coord.1 as usize][coord.0 as usize] = old_cell_value;
-                return 4;
+            if let Some(old_range) = old_child_range {
+                self.child_range.insert(coord, old_range);
             }
-            // No cycle, proceed with evaluation
-            let v = self.get_val(c);
-            match v {
-                Some(val) => self.cells[coord.1 as usize][coord.0 as usize] = Cell::Value(val),
-                None => self.cells[coord.1 as usize][coord.0 as usize] = Cell::Err,
+            for parent_coord in removed_from_parents {
+                self.parents_normal
+                    .entry(parent_coord)
+                    .or_default()
+                    .insert(coord);
             }
-            self.recalc_dependents(coord);
-            return 0;
-        }
-
-        // 2d) Literal: "42"
-        if let Ok(v) = expr.parse::<i32>() {
-            self.cells[coord.1 as usize][coord.0 as usize] = Cell::Value(v);
-            self.recalc_dependents(coord);
-            return 0;
+            return 4;
         }
 
-        // 2e) Anything else → abort with no change
-        // RESTORE OLD CHILD DEPENDENCIES
-        if let Some(old_normal) = old_child_normal {
-            self.child_normal.insert(coord, old_normal);
-        }
-        if let Some(old_range) = old_child_range {
-            self.child_range.insert(coord, old_range);
-        }
-        // Restore parents
-        for (parent_coord, child_coord) in removed_from_parents {
-            self.parents_normal
-                .entry(parent_coord)
-                .or_default()
-                .insert(child_coord);
-        }
-
-        3 // unrecognized cmd
+        // 4) evaluate, then update everything that reads this cell
+        self.cells[coord.1 as usize][coord.0 as usize] = self.evaluate(&formula);
+        self.recalc_dependents(coord);
+        0
     }
     /// Recomputes all cells that directly or indirectly depend on the cell at `start`.
     /// This function performs a complete dependency-aware recalculation of all cells
@@ -530,12 +423,18 @@ impl Spreadsheet {
     /// * If other errors occur during formula evaluation, the cell remains unchanged
     /// * Circular dependencies are detected and skipped during topological sorting
     pub fn recalc_dependents(&mut self, start: (u16, u16)) {
+        self.recalc_dependents_of(&[start]);
+    }
+
+    /// Like `recalc_dependents`, for several cells that changed together. A dependent of more
+    /// than one of them is still recalculated only once
+    fn recalc_dependents_of(&mut self, starts: &[(u16, u16)]) {
         // Keep track of all cells that need to be recalculated
         let mut all_cells_to_update = Vec::new();
         let mut visited = HashSet::new();
 
         // Collect all cells affected by the change, including indirect dependencies
-        let mut queue = vec![start];
+        let mut queue = starts.to_vec();
         while let Some(cell) = queue.pop() {
             if !visited.insert(cell) {
                 continue; // Skip if already visited
@@ -562,6 +461,8 @@ impl Spreadsheet {
             }
         }
 
+        let already_updated: HashSet<(u16, u16)> = starts.iter().copied().collect();
+
         // Now sort these cells topologically for correct calculation order
         let mut visited = HashSet::new();
         let mut visiting = HashSet::new();
@@ -637,108 +538,27 @@ impl Spreadsheet {
 
         // Process cells in reverse topological order (dependencies before dependents)
         for cur in topo_order.iter().rev() {
-            // Skip the start cell if it was already updated (e.g., by a set_cell call)
-            if *cur == start {
+            // Skip the start cells if they were already updated (e.g., by a set_cell call)
+            if already_updated.contains(cur) {
                 //this change fixed the issue of sleep (earlier it was *cur == start && all_cells_to_update.len() > 1)
                 continue;
             }
 
-            // compute new value for `cur`
-            let new_cell = if let Some((formula, _)) = self.child_normal.get(cur).cloned() {
-                // SLEEP function handling
-                if formula.starts_with("SLEEP(") && formula.ends_with(")") {
-                    let arg_str = &formula[6..formula.len() - 1];
-
-                    // Try to parse as a literal integer
-                    if let Ok(sleep_time) = arg_str.parse::<i32>() {
-                        // Direct sleep with constant
-                        if sleep_time > 0 {
-                            thread::sleep(Duration::from_secs(sleep_time as u64));
-                        }
-                        Cell::Value(sleep_time)
-                    }
-                    // Try to parse as a cell reference
-                    else if let Some(ref_cell) = MyParser::cell_name_to_coord(arg_str) {
-                        match self.get_val(ref_cell) {
-                            Some(sleep_time) => {
-                                // Sleep using the referenced cell's value
-                                if sleep_time > 0 {
-                                    thread::sleep(Duration::from_secs(sleep_time as u64));
-                                    //this is the issue.
-                                }
-                                Cell::Value(sleep_time)
-                            }
-                            None => Cell::Err,
-                        }
-                    } else {
-                        Cell::Err
-                    }
-                }
-                // binary?
-                else if let Some((op_char, lhs_s, rhs_s)) = MyParser::split_binary(&formula) {
-                    let op_code = match op_char {
-                        '+' => 1,
-                        '-' => 2,
-                        '*' => 3,
-                        '/' => 5,
-                        _ => continue, // shouldn't happen
-                    };
-
-                    let a = if let Some(c) = MyParser::cell_name_to_coord(lhs_s) {
-                        self.get_val(c)
-                    } else {
-                        lhs_s.parse::<i32>().ok()
-                    };
-                    let b = if let Some(c) = MyParser::cell_name_to_coord(rhs_s) {
-                        self.get_val(c)
-                    } else {
-                        rhs_s.parse::<i32>().ok()
-                    };
-
-                    if op_code == 5 && b == Some(0) {
-                        Cell::Err
-                    } else if let (Some(a_val), Some(b_val)) = (a, b) {
-                        if let Some(v) = eval_binary(op_code, a_val, b_val) {
-                            Cell::Value(v)
-                        } else {
-                            Cell::Err
-                        }
-                    } else {
-                        Cell::Err
-                    }
-                }
-                // single‐cell ref?
-                else if let Some(c) = MyParser::cell_name_to_coord(&formula) {
-                    match self.get_val(c) {
-                        Some(val) => Cell::Value(val),
-                        None => Cell::Err,
-                    }
-                }
-                // literal?
-                else if let Ok(v) = formula.parse::<i32>() {
-                    Cell::Value(v)
-                } else {
-                    continue;
-                }
-            }
-            // range?
-            else if let Some((formula, range_start, range_end)) =
-                self.child_range.get(cur).cloned()
-            {
-                let func = &formula[..formula.find('(').unwrap_or(0)];
-                if let Some(v) = eval_range(func, range_start, range_end, |c| self.get_val(c)) {
-                    Cell::Value(v)
-                } else {
-                    Cell::Err
-                }
-            } else {
+            // compute new value for `cur` from its formula
+            let formula = match (self.child_normal.get(cur), self.child_range.get(cur)) {
+                (Some((formula, _)), _) | (None, Some((formula, _, _))) => formula.clone(),
+                (None, None) => continue,
+            };
+            let Some(formula) = MyParser::parse_formula(&formula) else {
                 continue;
             };
+            let new_cell = self.evaluate(&formula);
 
             self.cells[cur.1 as usize][cur.0 as usize] = new_cell;
         }
     }
 
+    /// Writes what `display` prints to `writer`
     pub fn display_to<W: std::io::Write>(
         &self,
         writer: &mut W,
@@ -746,6 +566,7 @@ impl Spreadsheet {
         start_col: usize,
         max_rows: usize,
         max_cols: usize,
+        selected: Option<(usize, usize)>,
     ) -> std::io::Result<()> {
         write!(writer, "    ")?;
         for c in (start_col + 1)..=(start_col + max_cols).min(self.cols) {
@@ -756,9 +577,15 @@ impl Spreadsheet {
         for r in (start_row + 1)..=(start_row + max_rows).min(self.rows) {
             write!(writer, "{:>3} ", r)?;
             for c in (start_col + 1)..=(start_col + max_cols).min(self.cols) {
-                match &self.cells[r][c] {
-                    Cell::Value(v) => write!(writer, "{:>8}", v)?,
-                    Cell::Err => write!(writer, "{:>8}", "ERR")?,
+                let text = match &self.cells[r][c] {
+                    Cell::Value(v) => self.locale.format_number(*v as f64),
+                    Cell::Real(v) => self.locale.format_number(*v),
+                    Cell::Err => self.error_text.clone(),
+                };
+                if selected == Some((r, c)) {
+                    write!(writer, "{:>8}", format!("[{}]", text))?;
+                } else {
+                    write!(writer, "{:>8}", text)?;
                 }
             }
             writeln!(writer)?;
@@ -778,32 +605,189 @@ impl Spreadsheet {
     /// * `start_col` - The starting column index (0-indexed internally, but displayed as letters)
     /// * `max_rows` - Maximum number of rows to display
     /// * `max_cols` - Maximum number of columns to display
+    /// * `selected` - The `(row, column)` of the selected cell, 1-indexed, if any
     ///
     /// # Output Format
     ///
     /// * Column headers are displayed as letters (A, B, C, ...)
     /// * Row headers are displayed as numbers (1, 2, 3, ...)
     /// * Cell values are right-aligned and displayed in 8-character width columns
-    /// * Error cells are displayed as "ERR"
+    /// * Error cells are displayed as `self.error_text`, "ERR" by default
+    /// * The selected cell is wrapped in brackets
+    /// * Numbers are formatted according to `self.locale`
+    ///
+    /// # Buffering
+    ///
+    /// Output is collected in a `BufWriter` over the locked stdout and flushed once at the end,
+    /// instead of issuing a write for every formatted field.
     ///
     /// # Example Output
     ///
     /// ```text
     ///         A       B       C
     ///   1     42       5       3
-    ///   2      7     ERR      12
+    ///   2      7   [ERR]      12
     ///   3     10      15      20
     /// ```
-    pub fn display(&self, start_row: usize, start_col: usize, max_rows: usize, max_cols: usize) {
+    pub fn display(
+        &self,
+        start_row: usize,
+        start_col: usize,
+        max_rows: usize,
+        max_cols: usize,
+        selected: Option<(usize, usize)>,
+    ) {
+        let stdout = std::io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
         self.display_to(
-            &mut std::io::stdout(),
+            &mut writer,
             start_row,
             start_col,
             max_rows,
             max_cols,
+            selected,
         )
+        .and_then(|_| writer.flush())
         .expect("Failed to write to stdout");
     }
+
+    /// Finds the first cell holding `Cell::Err` after, and excluding, the given cell.
+    ///
+    /// Cells are scanned left to right, top to bottom, without wrapping around.
+    ///
+    /// # Arguments
+    /// * `after` - The 1-based (row, column) to start after. `(1, 0)` scans from `A1`.
+    ///
+    /// # Returns
+    /// * `Some((row, col))` - The 1-based coordinates of the next error cell.
+    /// * `None` - If no error cell follows `after`.
+    pub fn next_error_cell(&self, after: (usize, usize)) -> Option<(usize, usize)> {
+        let (start_row, start_col) = after;
+        (start_row..=self.rows)
+            .flat_map(|row| {
+                let first_col = if row == start_row { start_col + 1 } else { 1 };
+                (first_col..=self.cols).map(move |col| (row, col))
+            })
+            .find(|&(row, col)| self.cells[row][col] == Cell::Err)
+    }
+    /// Renders the cells `coord` reads as an indented tree, one line per cell, for the CLI's
+    /// `deps` command. A range is one line, under which only its formula cells are expanded
+    ///
+    /// # Arguments
+    /// * `coord` - The cell coordinates as `(column, row)`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// let mut sheet = embedded::spreadsheet::Spreadsheet::new(10, 10);
+    /// sheet.set_cell((1, 2), "A1*2");
+    /// sheet.set_cell((2, 1), "A2+1");
+    /// assert_eq!(sheet.precedent_tree((2, 1)), ["B1 = A2+1", "  A2 = A1*2", "    A1"]);
+    /// ```
+    pub fn precedent_tree(&self, coord: (u16, u16)) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.write_tree(coord, 0, &mut HashSet::new(), &mut lines, &|cell| {
+            self.precedent_nodes(cell)
+        });
+        lines
+    }
+
+    /// Renders the cells reading `coord`, directly or through a range, as an indented tree
+    /// like `precedent_tree`, for the CLI's `rdeps` command
+    ///
+    /// # Arguments
+    /// * `coord` - The cell coordinates as `(column, row)`.
+    pub fn dependent_tree(&self, coord: (u16, u16)) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.write_tree(coord, 0, &mut HashSet::new(), &mut lines, &|cell| {
+            let mut dependents: Vec<(u16, u16)> = self
+                .parents_normal
+                .get(&cell)
+                .into_iter()
+                .flatten()
+                .copied()
+                .chain(
+                    self.child_range
+                        .iter()
+                        .filter(|(_, (_, start, end))| is_within_range(cell, *start, *end))
+                        .map(|(range_cell, _)| *range_cell),
+                )
+                .collect();
+            sort_cells(&mut dependents);
+            dependents.dedup();
+            dependents.into_iter().map(TreeNode::Cell).collect()
+        });
+        lines
+    }
+
+    /// The direct precedents of `cell`: its referenced cells, then its range
+    fn precedent_nodes(&self, cell: (u16, u16)) -> Vec<TreeNode> {
+        let mut refs: Vec<(u16, u16)> = self
+            .child_normal
+            .get(&cell)
+            .into_iter()
+            .flat_map(|(_, refs)| refs.iter().copied())
+            .collect();
+        sort_cells(&mut refs);
+        let mut nodes: Vec<TreeNode> = refs.into_iter().map(TreeNode::Cell).collect();
+        if let Some((_, start, end)) = self.child_range.get(&cell) {
+            nodes.push(TreeNode::Range(*start, *end));
+        }
+        nodes
+    }
+
+    /// Appends the line for `coord` and then, one level deeper, its children. A cell reached a
+    /// second time is marked instead of being expanded again
+    fn write_tree(
+        &self,
+        coord: (u16, u16),
+        depth: usize,
+        seen: &mut HashSet<(u16, u16)>,
+        lines: &mut Vec<String>,
+        children: &dyn Fn((u16, u16)) -> Vec<TreeNode>,
+    ) {
+        let indent = "  ".repeat(depth);
+        let mut line = format!("{}{}", indent, cell_label(coord));
+        let formula = self
+            .child_normal
+            .get(&coord)
+            .map(|(expr, _)| expr)
+            .or_else(|| self.child_range.get(&coord).map(|(expr, _, _)| expr));
+        if let Some(expr) = formula {
+            line.push_str(&format!(" = {}", expr));
+        }
+        if !seen.insert(coord) {
+            lines.push(format!("{} (see above)", line));
+            return;
+        }
+        lines.push(line);
+
+        for node in children(coord) {
+            match node {
+                TreeNode::Cell(child) => self.write_tree(child, depth + 1, seen, lines, children),
+                TreeNode::Range(start, end) => {
+                    lines.push(format!(
+                        "{}  {}:{}",
+                        indent,
+                        cell_label(start),
+                        cell_label(end)
+                    ));
+                    let mut formulas: Vec<(u16, u16)> = self
+                        .child_normal
+                        .keys()
+                        .chain(self.child_range.keys())
+                        .copied()
+                        .filter(|cell| is_within_range(*cell, start, end))
+                        .collect();
+                    sort_cells(&mut formulas);
+                    formulas.dedup();
+                    for child in formulas {
+                        self.write_tree(child, depth + 2, seen, lines, children);
+                    }
+                }
+            }
+        }
+    }
+
     /// Checks if a cell is part of a circular dependency chain.
     ///
     /// This function determines whether the cell at the specified coordinates
@@ -907,6 +891,23 @@ impl Spreadsheet {
     }
 }
 
+/// A child in the trees of `precedent_tree` and `dependent_tree`
+enum TreeNode {
+    Cell((u16, u16)),
+    /// A range read by a formula, as its start and end `(column, row)`
+    Range((u16, u16), (u16, u16)),
+}
+
+/// The label of a `(column, row)` cell, like `B3`
+fn cell_label((col, row): (u16, u16)) -> String {
+    format!("{}{}", col_to_letter(col as usize), row)
+}
+
+/// Sorts `(column, row)` cells top to bottom, then left to right
+fn sort_cells(cells: &mut [(u16, u16)]) {
+    cells.sort_by_key(|&(col, row)| (row, col));
+}
+
 /// Determines if a cell is contained within a specified range.
 ///
 /// This utility function checks whether the given cell coordinates fall within
@@ -956,6 +957,9 @@ mod tests {
         assert_eq!(col_to_letter(53), "BA");
         assert_eq!(col_to_letter(702), "ZZ");
         assert_eq!(col_to_letter(703), "AAA");
+        assert_eq!(col_to_letter(18277), "ZZY");
+        assert_eq!(col_to_letter(18278), "ZZZ");
+        assert_eq!(col_to_letter(18279), "AAAA");
     }
 
     #[test]
@@ -1256,6 +1260,51 @@ mod tests {
         assert!(a.is_none());
     }
 
+    #[test]
+    fn test_integer_and_float_division() {
+        let mut sheet = Spreadsheet::new(10, 10);
+        sheet.set_cell((1, 1), "7");
+        sheet.set_cell((2, 1), "A1/2");
+        sheet.set_cell((3, 1), "AVG(A1:B1)");
+        assert_eq!(sheet.cells[1][2], Cell::Value(3));
+        assert_eq!(sheet.cells[1][3], Cell::Value(5));
+
+        let mut sheet = Spreadsheet::with_float_division(10, 10);
+        sheet.set_cell((1, 1), "7");
+        sheet.set_cell((2, 1), "A1/2");
+        sheet.set_cell((3, 1), "AVG(A1:B1)");
+        sheet.set_cell((4, 1), "B1*2");
+        sheet.set_cell((5, 1), "B1/0");
+        assert_eq!(sheet.cells[1][2], Cell::Real(3.5));
+        assert_eq!(sheet.cells[1][3], Cell::Real(5.25));
+        assert_eq!(sheet.cells[1][4], Cell::Value(7));
+        assert_eq!(sheet.cells[1][5], Cell::Err);
+
+        // dependents are recalculated with fractions too
+        sheet.set_cell((1, 1), "3");
+        assert_eq!(sheet.cells[1][2], Cell::Real(1.5));
+        assert_eq!(sheet.cells[1][3], Cell::Real(2.25));
+        assert_eq!(sheet.cells[1][4], Cell::Value(3));
+
+        let mut out = Vec::new();
+        sheet.display_to(&mut out, 0, 0, 1, 3, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().nth(1).unwrap().ends_with("3     1.5    2.25"));
+    }
+
+    #[test]
+    fn test_rejected_formula_keeps_dependencies() {
+        let mut sheet = Spreadsheet::new(10, 10);
+        sheet.set_cell((2, 2), "A1*2");
+        for bad in ["A1+x", "SUM(A1:Z99)", "SLEEP(A1:A2)"] {
+            assert_eq!(sheet.set_cell((2, 2), bad), 3, "{:?}", bad);
+        }
+
+        sheet.set_cell((1, 1), "4");
+        assert_eq!(sheet.get_val((2, 2)), Some(8));
+        assert_eq!(sheet.child_normal[&(2, 2)].0, "A1*2");
+    }
+
     #[test]
     fn test_is_within_range() {
         // Test cell inside range
@@ -1410,7 +1459,7 @@ fn test_display_function() {
 
     // Capture output in a string buffer
     let mut output = Vec::new();
-    sheet.display_to(&mut output, 0, 0, 5, 5).unwrap();
+    sheet.display_to(&mut output, 0, 0, 5, 5, None).unwrap();
     let output_str = String::from_utf8(output).unwrap();
 
     // Verify expected content
@@ -1421,3 +1470,102 @@ fn test_display_function() {
     assert!(output_str.contains("ERR"));
     assert!(output_str.contains("50"));
 }
+
+#[test]
+fn test_display_buffered_output_unchanged() {
+    let mut sheet = Spreadsheet::new(3, 3);
+    sheet.set_cell((1, 1), "10");
+    sheet.set_cell((2, 3), "A1*7");
+    sheet.set_cell((3, 3), "A1/0");
+
+    let mut buffered = BufWriter::new(Vec::new());
+    sheet.display_to(&mut buffered, 0, 0, 3, 3, None).unwrap();
+    let buffered = String::from_utf8(buffered.into_inner().unwrap()).unwrap();
+
+    // the whole grid comes through the buffer, exactly as laid out field by field
+    let expected = concat!(
+        "           A       B       C\n",
+        "  1       10       0       0\n",
+        "  2        0       0       0\n",
+        "  3        0      70     ERR\n",
+    );
+    assert_eq!(buffered, expected);
+}
+
+#[test]
+fn test_display_with_locale() {
+    let mut sheet = Spreadsheet::new(3, 3);
+    sheet.set_cell((1, 1), "1234567");
+
+    let mut output = Vec::new();
+    sheet.display_to(&mut output, 0, 0, 3, 3, None).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains(" 1234567"));
+
+    sheet.locale = NumberLocale::European;
+    let mut output = Vec::new();
+    sheet.display_to(&mut output, 0, 0, 3, 3, None).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("1.234.567"));
+}
+
+#[test]
+fn test_display_error_text() {
+    let mut sheet = Spreadsheet::new(3, 3);
+    sheet.set_cell((1, 1), "1/0");
+
+    let mut output = Vec::new();
+    sheet.display_to(&mut output, 0, 0, 3, 3, None).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("  1      ERR"));
+
+    sheet.error_text = "#DIV/0!".to_string();
+    let mut output = Vec::new();
+    sheet.display_to(&mut output, 0, 0, 3, 3, None).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("  1  #DIV/0!"));
+    assert!(!output.contains("ERR"));
+}
+
+#[test]
+fn test_display_marks_selected_cell() {
+    let mut sheet = Spreadsheet::new(3, 3);
+    sheet.set_cell((2, 1), "5"); // B1
+    sheet.set_cell((1, 2), "A1/0"); // A2
+
+    let mut output = Vec::new();
+    sheet
+        .display_to(&mut output, 0, 0, 3, 3, Some((1, 2)))
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[1], "  1        0     [5]       0");
+    assert_eq!(output.matches('[').count(), 1);
+
+    let mut output = Vec::new();
+    sheet
+        .display_to(&mut output, 0, 0, 3, 3, Some((2, 1)))
+        .unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("  2    [ERR]"));
+
+    // a selection outside the window is not shown
+    let mut output = Vec::new();
+    sheet
+        .display_to(&mut output, 0, 0, 2, 2, Some((3, 3)))
+        .unwrap();
+    assert!(!String::from_utf8(output).unwrap().contains('['));
+}
+
+#[test]
+fn test_is_set_distinguishes_zero_from_untouched() {
+    let mut sheet = Spreadsheet::new(10, 10);
+    sheet.set_cell((1, 1), "0");
+    sheet.set_cell((2, 1), "A1*5");
+    assert_eq!(sheet.cells[1][1], sheet.cells[1][3]);
+    assert!(sheet.is_set((1, 1)));
+    assert!(sheet.is_set((2, 1)));
+    assert!(!sheet.is_set((3, 1)));
+
+    // rejected assignments do not count
+    assert_eq!(sheet.set_cell((4, 4), "D4+1"), 4);
+    assert_eq!(sheet.set_cell((5, 5), "FOO"), 3);
+    assert!(!sheet.is_set((4, 4)));
+    assert!(!sheet.is_set((5, 5)));
+}
@@ -1,30 +1,221 @@
-use crate::common::cell_value::CellValue;
+use crate::common::cell_value::{CellType, CellValue};
+use crate::common::locale::NumberLocale;
+use crate::common::number::parse_number;
 use crate::common::structs::AbsCell;
-use crate::embedded_backend::simple::EmbeddedBackend;
+use crate::common::validation::{ColumnType, ValidationRule};
+use crate::embedded_backend::simple::{
+    CellInput, EmbeddedBackend, StructuralEdit, TotalsDirection,
+};
+use crate::error_display::{DEFAULT_ERROR_TEXT, ErrorLabel};
+use crate::recent_files::RecentFiles;
+use crate::view_state::{SEARCH_DEBOUNCE, ViewState};
 use egui::{Color32, FontId, Key, RichText, TextEdit};
 use rfd::FileDialog;
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Tints for "Highlight Duplicates", cycled through by group
+const DUPLICATE_TINTS: [Color32; 4] = [
+    Color32::from_rgba_premultiplied(60, 30, 0, 60),
+    Color32::from_rgba_premultiplied(0, 50, 10, 60),
+    Color32::from_rgba_premultiplied(45, 0, 45, 60),
+    Color32::from_rgba_premultiplied(50, 45, 0, 60),
+];
+
+/// The tint of a cell `count` formulas read, out of at most `max`, for View → Shade by
+/// Dependents. More dependents give a stronger tint
+fn dependents_tint(count: usize, max: usize) -> Color32 {
+    let strength = count as f32 / max.max(1) as f32;
+    Color32::from_rgba_unmultiplied(230, 80, 0, (30.0 + 150.0 * strength) as u8)
+}
+
+/// Roughly how wide a character of the grid font is, to fit cell text to the column width
+const CHAR_WIDTH: f32 = 7.0;
+
+/// `text` cut down to at most `max_chars` characters, the last one an ellipsis if anything
+/// was cut, so a long string does not widen its column. Borrowed exactly when nothing was cut
+fn truncate_text(text: &str, max_chars: usize) -> Cow<'_, str> {
+    if text.chars().count() <= max_chars {
+        return Cow::Borrowed(text);
+    }
+    match max_chars.checked_sub(1) {
+        Some(kept) => Cow::Owned(text.chars().take(kept).chain(['…']).collect()),
+        None => Cow::Owned(String::new()),
+    }
+}
+
+/// The choices offered in File → Auto-save
+const AUTO_SAVE_INTERVALS: [(Option<Duration>, &str); 4] = [
+    (None, "Off"),
+    (Some(Duration::from_secs(30)), "Every 30 seconds"),
+    (Some(Duration::from_secs(60)), "Every minute"),
+    (Some(Duration::from_secs(300)), "Every 5 minutes"),
+];
+
+/// An action that replaces the current sheet, held back while the user confirms discarding
+/// unsaved changes
+#[derive(Debug, Clone, PartialEq)]
+enum DiscardAction {
+    NewSheet,
+    Open,
+    /// Opens a file picked from File → Open Recent
+    OpenRecent(PathBuf),
+}
+
+/// Every keyboard shortcut handled in `update`, as listed in Help → Keyboard Shortcuts. Add a
+/// row here along with any new shortcut
+const SHORTCUTS: [(&str, &str); 26] = [
+    ("Arrow keys", "Move the selection"),
+    ("Tab / Shift+Tab", "Move the selection right / left"),
+    (
+        "Ctrl+Arrow keys",
+        "Jump to the next populated cell or the edge of the sheet",
+    ),
+    ("Page Up / Page Down", "Move the selection by a screen"),
+    ("Enter", "Edit the selected cell"),
+    ("F2", "Edit the selected cell"),
+    (
+        "Any character",
+        "Start editing the selected cell with that character",
+    ),
+    ("Enter (while editing)", "Commit the edit"),
+    (
+        "Tab / Shift+Tab (while editing)",
+        "Commit and move right / left",
+    ),
+    ("Escape (while editing)", "Cancel the edit"),
+    ("Ctrl+C", "Copy the selected cell"),
+    (
+        "Ctrl+V",
+        "Paste into the selected cell, spilling a copied block of cells",
+    ),
+    ("Ctrl+Z", "Undo"),
+    ("Ctrl+Y", "Redo"),
+    ("Ctrl+S", "Save"),
+    ("Ctrl+O", "Open a sheet"),
+    ("Ctrl+E", "Export the visible cells to CSV"),
+    ("Ctrl+F", "Show or hide the search panel"),
+    ("F3", "Open the search panel, or find the next match"),
+    ("Shift+F3", "Search from the beginning of the sheet"),
+    ("Escape (in search)", "Close the search panel"),
+    ("F8", "Jump to the next cell holding an error"),
+    ("F4", "Repeat the last edit on the selected cell"),
+    (
+        "Alt+=",
+        "Sum the numbers above, or to the left of, the empty selected cell",
+    ),
+    ("F1", "Show this list"),
+    ("Escape (in this list)", "Close this list"),
+];
+
+/// How a cell's text is laid out by the type of its value, as in other spreadsheets: numbers
+/// and errors to the right, so digits line up down a column, and text to the left
+fn cell_layout(value_type: CellType) -> egui::Layout {
+    match value_type {
+        CellType::Number | CellType::Error => egui::Layout::right_to_left(egui::Align::Center),
+        CellType::Text | CellType::Empty => egui::Layout::left_to_right(egui::Align::Center),
+    }
+}
+
+/// Writes a saved sheet unless a save started later already went through, so a slow
+/// background auto-save can never overwrite a newer manual save.
+/// `generation` is larger for every save started
+fn write_sheet(
+    path: &Path,
+    bytes: &[u8],
+    generation: u64,
+    last_written: &Mutex<u64>,
+) -> io::Result<()> {
+    let mut last = last_written.lock().unwrap_or_else(PoisonError::into_inner);
+    if *last > generation {
+        return Ok(());
+    }
+    std::fs::write(path, bytes)?;
+    *last = generation;
+    Ok(())
+}
 
 pub struct SpreadsheetApp {
     backend: EmbeddedBackend,
-    view_top_left: AbsCell,
-    selected_cell: AbsCell,
+    /// What is shown and selected
+    view: ViewState,
     editing: bool,
     inline_editing: bool,
     inline_edit_value: String,
     formula_input: String,
     status_message: String,
-    display_rows: i16,
-    display_cols: i16,
+    locale: NumberLocale,
+    /// View menu setting: shown in place of every error value. None shows the kind of error,
+    /// like `#DIV/0!`
+    error_text: Option<String>,
     col_width: f32,
     row_height: f32,
     show_save_dialog: bool,
     show_load_dialog: bool,
+    /// Set when loading a file failed, shown until the user picks how to continue
+    load_error: Option<String>,
+    /// Debug menu toggle: hovering a cell shows its label next to the raw 0-indexed `AbsCell`
+    show_cell_coordinates: bool,
+    /// Debug menu toggle: hovering an error cell shows the cell the error originated in
+    show_error_sources: bool,
+    /// Debug menu toggle: headers and the formula bar number rows and columns from 0, as in
+    /// `AbsCell`, instead of A1 labels
+    zero_based_labels: bool,
+    /// View menu toggle: next to the formula bar, the selected formula is also shown with its
+    /// references as offsets from the cell, in R1C1 style
+    show_relative_refs: bool,
+    /// View menu toggle: the grid shows sheet rows as columns and columns as rows. Only the
+    /// drawing changes, `view_top_left`, the selection and the sheet keep true coordinates
+    transposed: bool,
+    /// View menu settings: whether every other grid row is tinted, and with which color.
+    /// None keeps the theme's color
+    striped: bool,
+    stripe_color: Option<Color32>,
+    /// The duplicate group of every highlighted cell, as of the last "Highlight Duplicates"
+    duplicate_groups: HashMap<AbsCell, usize>,
+    /// View menu toggle: how many formulas read each cell, with the largest count, as of when
+    /// it was turned on. None when off
+    dependents_heat_map: Option<(HashMap<AbsCell, usize>, usize)>,
+    show_validation_dialog: bool,
+    /// Help → Keyboard Shortcuts window, also toggled with F1
+    show_shortcuts: bool,
+    /// Bounds typed into the Validation dialog, blank for no limit
+    validation_min: String,
+    validation_max: String,
     save_path: Option<PathBuf>,
+    /// Listed in File → Open Recent
+    recent_files: RecentFiles,
+    /// Where `recent_files` is kept across runs, None to keep it in memory only
+    recent_files_location: Option<PathBuf>,
+    /// Set by every edit, cleared once the sheet is written to `save_path`
+    dirty: bool,
+    /// How often unsaved changes are written to `save_path` in the background, None for never
+    auto_save_interval: Option<Duration>,
+    last_auto_save: Instant,
+    /// The generation of the last save started, see `write_sheet`
+    save_generation: u64,
+    /// The generation of the last save written, shared with the auto-save threads
+    last_written: Arc<Mutex<u64>>,
+    /// Reports the outcome of the running auto-save
+    auto_save_result: Option<Receiver<io::Result<()>>>,
+    /// Waiting for the user to confirm losing unsaved changes
+    confirm_discard: Option<DiscardAction>,
+    /// The window title last sent to the viewport
+    window_title: String,
     copied_cell: Option<AbsCell>,
+    /// The text `copy_cell` last put on the clipboard, to tell a paste of it from text
+    /// copied in another program since
+    copied_text: Option<String>,
+    /// The last input committed to a cell and the cell it went to, repeated by F4
+    last_edit: Option<(AbsCell, String)>,
     search_value: String,
     show_search_panel: bool,
-    last_search_position: Option<AbsCell>,
 }
 
 impl Default for SpreadsheetApp {
@@ -34,30 +225,58 @@ impl Default for SpreadsheetApp {
 }
 
 impl SpreadsheetApp {
+    /// The size of a new sheet, up to cell `ZZZ999`
+    const DEFAULT_ROWS: u16 = 999;
+    const DEFAULT_COLS: u16 = 18278;
+
     pub fn new() -> Self {
-        let backend = EmbeddedBackend::new(999, 18278);
+        let backend = EmbeddedBackend::new(Self::DEFAULT_ROWS, Self::DEFAULT_COLS);
 
         Self {
             backend,
             copied_cell: None,
-            view_top_left: AbsCell::new(0, 0),
-            selected_cell: AbsCell::new(0, 0),
+            copied_text: None,
+            last_edit: None,
+            view: ViewState::new(Self::DEFAULT_ROWS as i16, Self::DEFAULT_COLS as i16),
             editing: false,
             inline_editing: false,
             inline_edit_value: String::new(),
             formula_input: String::new(),
             status_message: String::from("Ready"),
-            display_rows: 10,
-            display_cols: 10,
+            locale: NumberLocale::default(),
+            error_text: None,
             col_width: 100.0,
             row_height: 30.0,
             show_save_dialog: false,
             show_load_dialog: false,
+            load_error: None,
+            show_cell_coordinates: false,
+            show_error_sources: false,
+            zero_based_labels: false,
+            show_relative_refs: false,
+            transposed: false,
+            striped: true,
+            stripe_color: None,
+            duplicate_groups: HashMap::new(),
+            dependents_heat_map: None,
+            show_validation_dialog: false,
+            show_shortcuts: false,
+            validation_min: String::new(),
+            validation_max: String::new(),
             save_path: None,
+            recent_files: RecentFiles::default(),
+            recent_files_location: None,
+            dirty: false,
+            auto_save_interval: None,
+            last_auto_save: Instant::now(),
+            save_generation: 0,
+            last_written: Arc::new(Mutex::new(0)),
+            auto_save_result: None,
+            confirm_discard: None,
+            window_title: String::new(),
             // Initialize new search fields
             search_value: String::new(),
             show_search_panel: false,
-            last_search_position: None,
         }
     }
 
@@ -66,8 +285,9 @@ impl SpreadsheetApp {
         self.show_search_panel = !self.show_search_panel;
         if self.show_search_panel {
             self.search_value = String::new();
-            self.last_search_position = None;
+            self.view.last_search_position = None;
         }
+        self.view.take_pending_search();
     }
 
     fn search_next(&mut self) {
@@ -76,36 +296,22 @@ impl SpreadsheetApp {
             return;
         }
 
-        let start_cell = if let Some(last_pos) = self.last_search_position {
-            last_pos
-        } else {
-            self.selected_cell
-        };
-
-        match self.backend.search(start_cell, &self.search_value) {
+        let found = self
+            .view
+            .search_next(|start| self.backend.search(start, &self.search_value));
+        match found {
             Some(found_cell) => {
-                self.selected_cell = found_cell;
-                self.last_search_position = Some(found_cell);
                 self.status_message = format!(
                     "Found match at {}{}",
                     Self::cell_to_label(found_cell.col),
                     found_cell.row + 1
                 );
 
-                // Ensure the found cell is visible in the viewport
-                self.ensure_cell_visible(found_cell);
-
                 // Update formula input for the selected cell
-                if let Some(formula) = self.backend.get_cell_formula(self.selected_cell) {
-                    self.formula_input = format!("={}", formula);
-                } else {
-                    self.formula_input = self.render_cell_value(self.selected_cell);
-                }
+                self.formula_input = self.render_cell_input(found_cell);
             }
             None => {
                 self.status_message = format!("No more matches found for '{}'", self.search_value);
-                // Reset search position to start from beginning next time
-                self.last_search_position = None;
             }
         }
     }
@@ -116,87 +322,271 @@ impl SpreadsheetApp {
             return;
         }
 
-        match self.backend.search_from_start(&self.search_value) {
+        let found = self
+            .view
+            .search_from_start(|| self.backend.search_from_start(&self.search_value));
+        match found {
             Some(found_cell) => {
-                self.selected_cell = found_cell;
-                self.last_search_position = Some(found_cell);
                 self.status_message = format!(
                     "Found match at {}{}",
                     Self::cell_to_label(found_cell.col),
                     found_cell.row + 1
                 );
 
-                // Ensure the found cell is visible in the viewport
-                self.ensure_cell_visible(found_cell);
-
                 // Update formula input for the selected cell
-                if let Some(formula) = self.backend.get_cell_formula(self.selected_cell) {
-                    self.formula_input = format!("={}", formula);
-                } else {
-                    self.formula_input = self.render_cell_value(self.selected_cell);
-                }
+                self.formula_input = self.render_cell_input(found_cell);
             }
             None => {
                 self.status_message = format!("No matches found for '{}'", self.search_value);
-                self.last_search_position = None;
             }
         }
     }
 
-    // Helper method to ensure a cell is visible in the viewport
-    fn ensure_cell_visible(&mut self, cell: AbsCell) {
-        // Check if cell is outside visible area and adjust view if needed
-        if cell.row < self.view_top_left.row {
-            self.view_top_left.row = cell.row;
-        } else if cell.row >= self.view_top_left.row + self.display_rows {
-            self.view_top_left.row = cell.row - self.display_rows + 1;
-        }
-
-        if cell.col < self.view_top_left.col {
-            self.view_top_left.col = cell.col;
-        } else if cell.col >= self.view_top_left.col + self.display_cols {
-            self.view_top_left.col = cell.col - self.display_cols + 1;
+    fn find_next_error(&mut self) {
+        match self.backend.next_error_cell(self.view.selected_cell) {
+            Some(found_cell) => {
+                self.view.selected_cell = found_cell;
+                self.view.selection_range = None;
+                self.status_message = format!(
+                    "Error at {}{}",
+                    Self::cell_to_label(found_cell.col),
+                    found_cell.row + 1
+                );
+                self.view.ensure_cell_visible(found_cell);
+                self.formula_input = self.render_cell_input(self.view.selected_cell);
+            }
+            None => {
+                self.status_message = "No error cells found".to_string();
+            }
         }
     }
 
-    fn copy_cell(&mut self) {
-        self.copied_cell = Some(self.selected_cell);
+    fn copy_cell(&mut self, ctx: &egui::Context) {
+        let text = self.render_cell_input(self.view.selected_cell);
+        ctx.copy_text(text.clone());
+        self.copied_cell = Some(self.view.selected_cell);
+        self.copied_text = Some(text);
         self.status_message = format!(
             "Copied cell {}{}",
-            Self::cell_to_label(self.selected_cell.col),
-            self.selected_cell.row + 1
+            Self::cell_to_label(self.view.selected_cell.col),
+            self.view.selected_cell.row + 1
         );
     }
 
     fn paste_cell(&mut self) {
         if let Some(source_cell) = self.copied_cell {
-            if source_cell == self.selected_cell {
+            if source_cell == self.view.selected_cell {
                 self.status_message = "Cannot paste to same cell".to_string();
                 return;
             }
+            // a value is checked as if typed in, only formulas are copied over as they are
+            if !self.backend.has_formula(source_cell) {
+                let value = self
+                    .backend
+                    .get_cell_value(source_cell)
+                    .clone()
+                    .unwrap_or(CellValue::Empty);
+                let done = format!("Pasted from {} to {}", source_cell, self.view.selected_cell);
+                self.set_input_value(value, &done);
+                self.formula_input = self.render_cell_input(self.view.selected_cell);
+                return;
+            }
 
             match self
                 .backend
-                .copy_cell_expression(source_cell, self.selected_cell)
+                .copy_cell_expression(source_cell, self.view.selected_cell)
             {
                 Ok(_) => {
+                    self.dirty = true;
                     self.status_message = format!(
                         "Pasted from {}{} to {}{}",
                         Self::cell_to_label(source_cell.col),
                         source_cell.row + 1,
-                        Self::cell_to_label(self.selected_cell.col),
-                        self.selected_cell.row + 1
+                        Self::cell_to_label(self.view.selected_cell.col),
+                        self.view.selected_cell.row + 1
                     );
 
                     // Update formula input for the selected cell
-                    if let Some(formula) = self.backend.get_cell_formula(self.selected_cell) {
-                        self.formula_input = format!("={}", formula);
-                    } else {
-                        self.formula_input = self.render_cell_value(self.selected_cell);
-                    }
+                    self.formula_input = self.render_cell_input(self.view.selected_cell);
+                }
+                Err(err) => {
+                    self.status_message = match self
+                        .backend
+                        .find_copy_cycle(source_cell, self.view.selected_cell)
+                    {
+                        Some(cycle) => format!(
+                            "Paste would create a cycle: {}; {} left unchanged",
+                            cycle
+                                .iter()
+                                .map(|cell| cell.to_string())
+                                .collect::<Vec<_>>()
+                                .join(" → "),
+                            self.view.selected_cell
+                        ),
+                        None => format!("Paste error: {}", err),
+                    };
+                }
+            }
+        } else {
+            self.status_message = "Nothing to paste".to_string();
+        }
+    }
+
+    /// Splits pasted text into rows of cells. Cells are separated by tabs, as spreadsheets
+    /// copy them, or else by commas, with CSV quoting either way. A final line break is not
+    /// an extra row
+    fn parse_block(text: &str) -> Vec<Vec<String>> {
+        let delimiter = if text.contains('\t') { b'\t' } else { b',' };
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .from_reader(text.as_bytes());
+        reader
+            .records()
+            .map_while(Result::ok)
+            .map(|record| record.iter().map(str::to_string).collect())
+            .collect()
+    }
+
+    /// How typed or pasted text is stored: a leading `=` makes a formula, a leading apostrophe
+    /// keeps the rest as text, and anything else is a number if it reads as one
+    fn input_from_text(text: &str) -> CellInput {
+        if let Some(literal) = text.strip_prefix('\'') {
+            CellInput::Value(CellValue::String(literal.to_string()))
+        } else if let Some(formula) = text.strip_prefix('=') {
+            CellInput::Formula(formula.to_string())
+        } else if text.is_empty() {
+            CellInput::Value(CellValue::Empty)
+        } else if let Some(num) = parse_number(text) {
+            CellInput::Value(CellValue::Number(num))
+        } else {
+            CellInput::Value(CellValue::String(text.to_string()))
+        }
+    }
+
+    /// The cells a pasted block fills, its top left going to `start`
+    fn place_block(start: AbsCell, block: &[Vec<String>]) -> Vec<(AbsCell, CellInput)> {
+        let mut inputs = Vec::new();
+        for (row, cells) in block.iter().enumerate() {
+            for (col, text) in cells.iter().enumerate() {
+                let cell = AbsCell::new(start.row + row as i16, start.col + col as i16);
+                inputs.push((cell, Self::input_from_text(text)));
+            }
+        }
+        inputs
+    }
+
+    /// Pastes the clipboard's text. If it is still what `copy_cell` put there the copied cell
+    /// is pasted, moving its references, otherwise the text fills the cells from the
+    /// selection on
+    fn paste_text(&mut self, text: &str) {
+        if self.copied_cell.is_some() && self.copied_text.as_deref() == Some(text) {
+            self.paste_cell();
+            return;
+        }
+        let block = Self::parse_block(text);
+        if block.is_empty() {
+            self.status_message = "Nothing to paste".to_string();
+        } else {
+            self.paste_block(&block);
+        }
+    }
+
+    /// Fills the cells from the selection on with a pasted block, as one undoable edit
+    fn paste_block(&mut self, block: &[Vec<String>]) {
+        let start = self.view.selected_cell;
+        let cols = block.iter().map(Vec::len).max().unwrap_or(0);
+        let inputs = match self.backend.check_inputs(Self::place_block(start, block)) {
+            Ok(inputs) => inputs,
+            Err((cell, err)) => {
+                self.status_message = format!("Paste rejected at {}: {}", cell, err);
+                return;
+            }
+        };
+        match self.backend.set_cell_inputs(inputs) {
+            Ok(()) => {
+                self.dirty = true;
+                self.status_message =
+                    format!("Pasted {} × {} cells at {}", block.len(), cols, start);
+                self.formula_input = self.render_cell_input(start);
+            }
+            Err(err) => self.status_message = format!("Paste error: {}", err),
+        }
+    }
+
+    fn undo(&mut self) {
+        let undone = self.backend.undo();
+        self.after_history_step(undone, "Undo");
+    }
+
+    fn redo(&mut self) {
+        let redone = self.backend.redo();
+        self.after_history_step(redone, "Redo");
+    }
+
+    fn after_history_step(&mut self, stepped: bool, name: &str) {
+        if !stepped {
+            self.status_message = format!("Nothing to {}", name.to_lowercase());
+            return;
+        }
+        self.dirty = true;
+        let failed = self.backend.take_failed_restore();
+        self.status_message = match failed.as_slice() {
+            [] => format!("{} successful", name),
+            [cell] => format!(
+                "{} could not restore the formula of {}, it keeps its last value",
+                name, cell
+            ),
+            cells => format!(
+                "{} could not restore the formulas of {}, they keep their last values",
+                name,
+                cells
+                    .iter()
+                    .map(|cell| cell.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        // Update formula input for selected cell
+        self.formula_input = self.render_cell_input(self.view.selected_cell);
+    }
+
+    fn paste_transposed(&mut self) {
+        let Some(source_cell) = self.copied_cell else {
+            self.status_message = "Nothing to paste".to_string();
+            return;
+        };
+        self.status_message = match self
+            .backend
+            .paste_transposed(source_cell, self.view.selected_cell)
+        {
+            Ok(()) => {
+                self.dirty = true;
+                self.formula_input = self.render_cell_input(self.view.selected_cell);
+                format!(
+                    "Pasted transposed from {} to {}",
+                    source_cell, self.view.selected_cell
+                )
+            }
+            Err(err) => format!("Paste error: {}", err),
+        };
+    }
+
+    fn paste_values(&mut self) {
+        if let Some(source_cell) = self.copied_cell {
+            // checked as if typed in, like any other value
+            match self.backend.get_cell_value(source_cell).clone() {
+                Ok(value) => {
+                    let done = format!(
+                        "Pasted value from {} to {}",
+                        source_cell, self.view.selected_cell
+                    );
+                    self.set_input_value(value, &done);
+                    self.formula_input = self.render_cell_input(self.view.selected_cell);
                 }
                 Err(err) => {
-                    self.status_message = format!("Paste error: {:?}", err);
+                    self.status_message = format!("Paste error: {}", err.label());
                 }
             }
         } else {
@@ -204,9 +594,219 @@ impl SpreadsheetApp {
         }
     }
 
+    /// The range spanning every row of `col`, as selected by clicking its header
+    fn column_selection(&self, col: i16) -> (AbsCell, AbsCell) {
+        let last_row = self.backend.rows() as i16 - 1;
+        (AbsCell::new(0, col), AbsCell::new(last_row, col))
+    }
+
+    /// The range spanning every column of `row`, as selected by clicking its number
+    fn row_selection(&self, row: i16) -> (AbsCell, AbsCell) {
+        let last_col = self.backend.cols() as i16 - 1;
+        (AbsCell::new(row, 0), AbsCell::new(row, last_col))
+    }
+
+    fn select_range(&mut self, range: (AbsCell, AbsCell)) {
+        if self.inline_editing {
+            self.handle_cell_edit(&self.inline_edit_value.clone());
+        }
+        self.view.selection_range = Some(range);
+        self.view.selected_cell = range.0;
+        self.editing = false;
+        self.formula_input = self.render_cell_input(self.view.selected_cell);
+        self.status_message = format!(
+            "Selected {}{}:{}{}",
+            Self::cell_to_label(range.0.col),
+            range.0.row + 1,
+            Self::cell_to_label(range.1.col),
+            range.1.row + 1
+        );
+    }
+
+    /// The block of data around `cell`: the smallest rectangle containing it whose bordering
+    /// rows and columns are empty alongside it. None if `cell` is empty and isolated
+    fn current_region(&self, cell: AbsCell) -> Option<(AbsCell, AbsCell)> {
+        let (rows, cols) = (self.backend.rows() as i16, self.backend.cols() as i16);
+        let populated = |row: i16, col: i16| {
+            (0..rows).contains(&row)
+                && (0..cols).contains(&col)
+                && self.backend.is_populated(AbsCell::new(row, col))
+        };
+        let (mut top, mut left, mut bottom, mut right) = (cell.row, cell.col, cell.row, cell.col);
+        loop {
+            // diagonal neighbours count, so rows and columns include the corners
+            let grow_top = (left - 1..=right + 1).any(|col| populated(top - 1, col));
+            let grow_bottom = (left - 1..=right + 1).any(|col| populated(bottom + 1, col));
+            let grow_left = (top - 1..=bottom + 1).any(|row| populated(row, left - 1));
+            let grow_right = (top - 1..=bottom + 1).any(|row| populated(row, right + 1));
+            if !(grow_top || grow_bottom || grow_left || grow_right) {
+                break;
+            }
+            top -= grow_top as i16;
+            bottom += grow_bottom as i16;
+            left -= grow_left as i16;
+            right += grow_right as i16;
+        }
+        let region = (AbsCell::new(top, left), AbsCell::new(bottom, right));
+        if region.0 == region.1 && !self.backend.is_populated(cell) {
+            None
+        } else {
+            Some(region)
+        }
+    }
+
+    fn insert_totals(&mut self, direction: TotalsDirection) {
+        let Some((top_left, bottom_right)) = self.current_region(self.view.selected_cell) else {
+            self.status_message = "Select a cell inside a block of data".to_string();
+            return;
+        };
+        self.status_message = match self
+            .backend
+            .insert_totals(top_left, bottom_right, direction)
+        {
+            Ok(()) => {
+                self.dirty = true;
+                format!("Inserted totals for {}:{}", top_left, bottom_right)
+            }
+            Err(_) => "No room for totals at the edge of the sheet".to_string(),
+        };
+        self.formula_input = self.render_cell_input(self.view.selected_cell);
+    }
+
+    /// The range AutoSum adds up for `cell`: the unbroken run of populated cells directly above
+    /// it, or to its left if the cell above is empty. None if both neighbours are empty
+    fn auto_sum_range(&self, cell: AbsCell) -> Option<(AbsCell, AbsCell)> {
+        let run = |dr: i16, dc: i16| {
+            let mut first = cell;
+            loop {
+                let next = AbsCell::new(first.row + dr, first.col + dc);
+                if !self.backend.is_populated(next) {
+                    break;
+                }
+                first = next;
+            }
+            (first != cell).then(|| (first, AbsCell::new(cell.row + dr, cell.col + dc)))
+        };
+        run(-1, 0).or_else(|| run(0, -1))
+    }
+
+    fn auto_sum(&mut self) {
+        let cell = self.view.selected_cell;
+        if self.backend.is_populated(cell) {
+            self.status_message = "AutoSum needs an empty cell".to_string();
+            return;
+        }
+        let Some((first, last)) = self.auto_sum_range(cell) else {
+            self.status_message = "Nothing above or to the left to sum".to_string();
+            return;
+        };
+        self.status_message = match self
+            .backend
+            .set_cell_formula(cell, &format!("SUM({}:{})", first, last))
+        {
+            Ok(()) => {
+                self.dirty = true;
+                format!("Summed {}:{} into {}", first, last, cell)
+            }
+            Err(err) => format!("AutoSum error: {}", err),
+        };
+        self.formula_input = self.render_cell_input(cell);
+    }
+
+    /// Inserts or deletes the selected cell's row or column
+    fn apply_structural(&mut self, edit: StructuralEdit) {
+        self.status_message = match self.backend.apply_structural(edit) {
+            Ok(()) => {
+                self.dirty = true;
+                // the highlighted cells have moved
+                self.duplicate_groups.clear();
+                self.dependents_heat_map = None;
+                match edit {
+                    StructuralEdit::InsertRow(_) => "Inserted row",
+                    StructuralEdit::DeleteRow(_) => "Deleted row",
+                    StructuralEdit::InsertCol(_) => "Inserted column",
+                    StructuralEdit::DeleteCol(_) => "Deleted column",
+                }
+                .to_string()
+            }
+            Err(_) => match edit {
+                StructuralEdit::InsertRow(_) | StructuralEdit::InsertCol(_) => {
+                    "No room to insert, the last row or column is in use".to_string()
+                }
+                StructuralEdit::DeleteRow(_) | StructuralEdit::DeleteCol(_) => {
+                    "Cannot delete cells that other formulas reference".to_string()
+                }
+            },
+        };
+        self.formula_input = self.render_cell_input(self.view.selected_cell);
+    }
+
+    /// Replaces the formulas in the selection with their current values
+    fn freeze_selection(&mut self) {
+        let (top_left, bottom_right) = self.selected_range();
+        let frozen = self.backend.freeze_range(top_left, bottom_right);
+        if frozen > 0 {
+            self.dirty = true;
+        }
+        self.status_message = format!("Froze {} formulas", frozen);
+        self.formula_input = self.render_cell_input(self.view.selected_cell);
+    }
+
+    fn highlight_duplicates(&mut self) {
+        let groups = self.backend.find_duplicate_values();
+        self.status_message = match groups.len() {
+            0 => "No duplicate values found".to_string(),
+            n => format!("Found {} groups of duplicate values", n),
+        };
+        self.duplicate_groups = groups
+            .into_iter()
+            .enumerate()
+            .flat_map(|(group, cells)| cells.into_iter().map(move |cell| (cell, group)))
+            .collect();
+    }
+
+    /// Turns the selected cell's numeric text into a number, or that of its whole column
+    fn convert_to_numbers(&mut self, whole_column: bool) {
+        let cell = self.view.selected_cell;
+        let converted = if whole_column {
+            self.backend.convert_column_to_numbers(cell.col)
+        } else {
+            self.backend.convert_text_to_number(cell) as usize
+        };
+        self.status_message = match converted {
+            0 => "No text that reads as a number".to_string(),
+            1 => "Converted 1 cell to a number".to_string(),
+            n => format!("Converted {} cells to numbers", n),
+        };
+        if converted > 0 {
+            self.dirty = true;
+        }
+        self.formula_input = self.render_cell_input(cell);
+    }
+
+    fn toggle_dependents_heat_map(&mut self, on: bool) {
+        self.dependents_heat_map = on.then(|| {
+            let counts = self.backend.dependent_counts();
+            let max = counts.values().copied().max().unwrap_or(0);
+            (counts, max)
+        });
+    }
+
+    fn is_in_selection(&self, cell: AbsCell) -> bool {
+        match self.view.selection_range {
+            Some((top_left, bottom_right)) => {
+                (top_left.row..=bottom_right.row).contains(&cell.row)
+                    && (top_left.col..=bottom_right.col).contains(&cell.col)
+            }
+            None => false,
+        }
+    }
+
+    /// The letters of a 0-indexed column, `ZZZ` for the last column 18277. Widened first, so
+    /// no column, not even a negative one, can overflow the arithmetic
     fn cell_to_label(col: i16) -> String {
         let mut result = String::new();
-        let mut n = col as u32 + 1;
+        let mut n = i32::from(col) + 1;
 
         while n > 0 {
             n -= 1;
@@ -221,33 +821,144 @@ impl SpreadsheetApp {
     fn render_cell_value(&self, cell: AbsCell) -> String {
         match self.backend.get_cell_value(cell) {
             Ok(CellValue::Empty) => String::new(),
-            Ok(CellValue::Number(num)) => format!("{}", num),
+            Ok(CellValue::Number(num)) => self.locale.format_number(*num),
             Ok(CellValue::String(text)) => text.clone(),
-            Err(_) => "#ERROR".to_string(),
+            Err(err) => match &self.error_text {
+                Some(text) => text.clone(),
+                None => err.label().to_string(),
+            },
         }
     }
 
-    fn handle_cell_edit(&mut self, new_value: &str) {
-        #[allow(clippy::manual_strip)]
-        if new_value.starts_with('=') {
-            match self
-                .backend
-                .set_cell_formula(self.selected_cell, &new_value[1..])
+    /// The formula of `cell` with its references as offsets, see
+    /// `Expression::to_relative_string`. None if the cell holds no formula
+    fn relative_formula(&self, cell: AbsCell) -> Option<String> {
+        let formula = self.backend.get_cell_data(cell).formula?;
+        Some(format!("={}", formula.to_relative_string()))
+    }
+
+    /// Text shown when the user starts editing a cell: the formula prefixed with `=`,
+    /// or the value itself. Text that would otherwise be read back as a formula or a
+    /// number is escaped with a leading apostrophe so re-committing it is lossless.
+    fn render_cell_input(&self, cell: AbsCell) -> String {
+        let (value, formula) = self.backend.get_cell(cell);
+        if let Some(formula) = formula {
+            return format!("={}", formula);
+        }
+        match value {
+            Ok(CellValue::String(text))
+                if text.starts_with('=')
+                    || text.starts_with('\'')
+                    || parse_number(text).is_some() =>
             {
-                Ok(_) => self.status_message = "Formula updated".to_string(),
-                Err(err) => self.status_message = format!("Formula error: {:?}", err),
+                format!("'{}", text)
             }
-        } else if new_value.is_empty() {
-            self.backend.set_cell_empty(self.selected_cell);
-            self.status_message = "Cell cleared".to_string();
-        } else if let Ok(num) = new_value.parse::<f64>() {
-            self.backend
-                .set_cell_value(self.selected_cell, CellValue::Number(num));
-            self.status_message = "Number set".to_string();
-        } else {
-            self.backend
-                .set_cell_value(self.selected_cell, CellValue::String(new_value.to_string()));
-            self.status_message = "Text set".to_string();
+            Ok(CellValue::String(text)) => text.clone(),
+            // Edit text must parse back as a number whatever the display locale is
+            Ok(CellValue::Number(num)) => format!("{}", num),
+            Ok(CellValue::Empty) => String::new(),
+            Err(err) => err.label().to_string(),
+        }
+    }
+
+    /// Stores a typed value in the selected cell unless the cell's validation rule or column
+    /// type rejects it. Warns when the value was converted to the column's type, and returns
+    /// whether the value was stored
+    fn set_input_value(&mut self, value: CellValue, done: &str) -> bool {
+        let cell = self.view.selected_cell;
+        match self.backend.set_cell_value_checked(cell, value) {
+            Ok(converted) => {
+                self.dirty = true;
+                self.status_message = match self.backend.column_type(cell.col) {
+                    Some(column_type) if converted => format!(
+                        "Converted to {} for column {}",
+                        column_type.name(),
+                        Self::cell_to_label(cell.col)
+                    ),
+                    _ => done.to_string(),
+                };
+                true
+            }
+            Err(err) => {
+                self.status_message = format!("Rejected: {}", err);
+                false
+            }
+        }
+    }
+
+    fn set_column_type(&mut self, column_type: Option<ColumnType>) {
+        let col = self.view.selected_cell.col;
+        self.backend.set_column_type(col, column_type);
+        self.dirty = true;
+        self.status_message = match column_type {
+            Some(column_type) => format!(
+                "Column {} only takes {} values",
+                Self::cell_to_label(col),
+                column_type.name()
+            ),
+            None => format!("Column {} takes any value", Self::cell_to_label(col)),
+        };
+    }
+
+    /// The cells the Validation dialog and Freeze Values apply to: the selected range, or else
+    /// the selected cell
+    fn selected_range(&self) -> (AbsCell, AbsCell) {
+        self.view
+            .selection_range
+            .unwrap_or((self.view.selected_cell, self.view.selected_cell))
+    }
+
+    fn apply_validation(&mut self) {
+        let bound = |text: &str, open: f64| match text.trim() {
+            "" => Some(open),
+            text => parse_number(text),
+        };
+        let (Some(min), Some(max)) = (
+            bound(&self.validation_min, f64::NEG_INFINITY),
+            bound(&self.validation_max, f64::INFINITY),
+        ) else {
+            self.status_message = "Validation bounds must be numbers".to_string();
+            return;
+        };
+        let (top_left, bottom_right) = self.selected_range();
+        self.backend.add_validation(
+            top_left,
+            bottom_right,
+            ValidationRule::NumberBetween { min, max },
+        );
+        self.dirty = true;
+        self.status_message = "Validation rule added".to_string();
+        self.show_validation_dialog = false;
+    }
+
+    fn handle_cell_edit(&mut self, new_value: &str) {
+        let cell = self.view.selected_cell;
+        let applied = match Self::input_from_text(new_value) {
+            CellInput::Formula(formula) => match self.backend.set_cell_formula(cell, &formula) {
+                Ok(_) => {
+                    self.dirty = true;
+                    self.status_message = "Formula updated".to_string();
+                    true
+                }
+                Err(err) => {
+                    self.status_message = format!("Formula error: {}", err);
+                    false
+                }
+            },
+            CellInput::Value(CellValue::Empty) => {
+                self.backend.set_cell_empty(cell);
+                self.dirty = true;
+                self.status_message = "Cell cleared".to_string();
+                true
+            }
+            CellInput::Value(value @ CellValue::Number(_)) => {
+                self.set_input_value(value, "Number set")
+            }
+            CellInput::Value(value) => self.set_input_value(value, "Text set"),
+        };
+        // F4 repeats what went in, not an edit the sheet refused
+        if applied {
+            self.last_edit = Some((cell, new_value.to_string()));
         }
         self.formula_input = String::new();
         self.editing = false;
@@ -257,85 +968,335 @@ impl SpreadsheetApp {
         // This ensures that any formulas dependent on the edited cell are updated
         // self.refresh_viewport_cells();
     }
-    fn move_view(&mut self, row_delta: i16, col_delta: i16) {
-        let new_row = self.view_top_left.row + row_delta;
-        let new_col = self.view_top_left.col + col_delta;
-
-        self.view_top_left.row = new_row.max(0).min(999 - self.display_rows);
-        self.view_top_left.col = new_col.max(0).min(18278 - self.display_cols);
+    /// Enters the last committed input again in the selected cell. A formula's relative
+    /// references move along with it, like a paste
+    fn repeat_last_edit(&mut self) {
+        let Some((from, input)) = self.last_edit.clone() else {
+            self.status_message = "Nothing to repeat".to_string();
+            return;
+        };
+        let Some(formula) = input.strip_prefix('=') else {
+            self.handle_cell_edit(&input);
+            return;
+        };
+        match self
+            .backend
+            .set_cell_formula_relative(self.view.selected_cell, formula, from)
+        {
+            Ok(_) => {
+                self.dirty = true;
+                self.status_message = "Formula repeated".to_string();
+            }
+            Err(err) => self.status_message = format!("Formula error: {}", err),
+        }
     }
 
     fn move_selection(&mut self, row_delta: i16, col_delta: i16) {
-        // Calculate new position
-        let new_row = self.selected_cell.row + row_delta;
-        let new_col = self.selected_cell.col + col_delta;
+        self.view.move_selection(row_delta, col_delta);
 
-        // Constrain to grid bounds
-        let new_row = new_row.clamp(0, 998);
-        let new_col = new_col.clamp(0, 18277);
+        // Update formula input if not editing
+        if !self.editing {
+            self.formula_input = self.render_cell_input(self.view.selected_cell);
+        }
+    }
 
-        self.selected_cell.row = new_row;
-        self.selected_cell.col = new_col;
+    /// The true cell drawn at a grid position, counted from the top left of the grid
+    fn cell_at(&self, grid_row: i16, grid_col: i16) -> AbsCell {
+        let (row, col) = if self.transposed {
+            (grid_col, grid_row)
+        } else {
+            (grid_row, grid_col)
+        };
+        AbsCell::new(
+            self.view.view_top_left.row + row,
+            self.view.view_top_left.col + col,
+        )
+    }
 
-        // Adjust view if selection would be outside visible area
-        if self.selected_cell.row < self.view_top_left.row {
-            self.view_top_left.row = self.selected_cell.row;
-        } else if self.selected_cell.row >= self.view_top_left.row + self.display_rows {
-            self.view_top_left.row = self.selected_cell.row - self.display_rows + 1;
+    /// The number of rows and columns the grid draws
+    fn grid_shape(&self) -> (i16, i16) {
+        if self.transposed {
+            (self.view.display_cols, self.view.display_rows)
+        } else {
+            (self.view.display_rows, self.view.display_cols)
         }
+    }
 
-        if self.selected_cell.col < self.view_top_left.col {
-            self.view_top_left.col = self.selected_cell.col;
-        } else if self.selected_cell.col >= self.view_top_left.col + self.display_cols {
-            self.view_top_left.col = self.selected_cell.col - self.display_cols + 1;
+    /// Converts a movement on screen, like an arrow key, to a movement in the sheet
+    fn sheet_delta(&self, down: i16, right: i16) -> (i16, i16) {
+        if self.transposed {
+            (right, down)
+        } else {
+            (down, right)
         }
+    }
 
-        // Update formula input if not editing
-        if !self.editing {
-            if let Some(formula) = self.backend.get_cell_formula(self.selected_cell) {
-                self.formula_input = format!("={}", formula);
+    fn move_selection_on_screen(&mut self, down: i16, right: i16) {
+        let (row_delta, col_delta) = self.sheet_delta(down, right);
+        self.move_selection(row_delta, col_delta);
+    }
+
+    fn jump_selection_on_screen(&mut self, down: i16, right: i16) {
+        let (row_delta, col_delta) = self.sheet_delta(down, right);
+        self.jump_selection(row_delta, col_delta);
+    }
+
+    /// The label of a grid header and the range clicking it selects. `along_top` picks the
+    /// header above the grid columns over the one left of the grid rows
+    fn grid_header(&self, along_top: bool, index: i16) -> (String, (AbsCell, AbsCell)) {
+        if along_top != self.transposed {
+            let col = self.view.view_top_left.col + index;
+            let label = if self.zero_based_labels {
+                col.to_string()
             } else {
-                self.formula_input = self.render_cell_value(self.selected_cell);
-            }
+                Self::cell_to_label(col)
+            };
+            (label, self.column_selection(col))
+        } else {
+            let row = self.view.view_top_left.row + index;
+            let label = if self.zero_based_labels { row } else { row + 1 };
+            (label.to_string(), self.row_selection(row))
+        }
+    }
+
+    /// The label of the selected cell in the formula bar: `B3`, or `(2, 1)` as `(row, col)`
+    /// with zero-based labels
+    fn selected_cell_label(&self) -> String {
+        let cell = self.view.selected_cell;
+        if self.zero_based_labels {
+            format!("({}, {})", cell.row, cell.col)
+        } else {
+            format!("{}{}", Self::cell_to_label(cell.col), cell.row + 1)
         }
     }
 
+    /// The hover text of the coordinates overlay, e.g. `B3  AbsCell { row: 2, col: 1 }`
+    fn cell_coordinates_text(cell: AbsCell) -> String {
+        format!(
+            "{}{}  {:?}",
+            Self::cell_to_label(cell.col),
+            cell.row + 1,
+            cell
+        )
+    }
+
+    /// The hover text of the error source overlay, e.g. `#DIV/0! from B1`. None unless the
+    /// cell holds an error
+    fn error_source_text(&self, cell: AbsCell) -> Option<String> {
+        let source = self.backend.trace_error(cell)?;
+        let Err(err) = self.backend.get_cell_value(source) else {
+            return None;
+        };
+        if source == cell {
+            Some(format!("{} originates here", err.label()))
+        } else {
+            Some(format!(
+                "{} from {}{}",
+                err.label(),
+                Self::cell_to_label(source.col),
+                source.row + 1
+            ))
+        }
+    }
+
+    /// Moves the selection to the next populated cell in the given direction
+    fn jump_selection(&mut self, row_delta: i16, col_delta: i16) {
+        let target =
+            self.backend
+                .next_populated_in_direction(self.view.selected_cell, row_delta, col_delta);
+        self.move_selection(
+            target.row - self.view.selected_cell.row,
+            target.col - self.view.selected_cell.col,
+        );
+    }
+
     fn save_spreadsheet(&mut self) {
-        if let Some(path) = &self.save_path {
-            match std::fs::File::create(path) {
-                Ok(file) => {
-                    if let Err(e) = self.backend.save_to_file(&file) {
-                        self.status_message = format!("Error saving file: {}", e);
-                    } else {
-                        self.status_message = format!("File saved to {:?}", path);
-                    }
-                }
-                Err(e) => {
-                    self.status_message = format!("Error creating file: {}", e);
+        if let Some(path) = self.save_path.clone() {
+            self.save_generation += 1;
+            let saved = self.backend.save_to_bytes().and_then(|bytes| {
+                write_sheet(&path, &bytes, self.save_generation, &self.last_written)
+            });
+            match saved {
+                Ok(()) => {
+                    self.dirty = false;
+                    self.status_message = format!("File saved to {:?}", path);
                 }
+                Err(e) => self.status_message = format!("Error saving file: {}", e),
             }
         } else {
             self.show_save_dialog = true;
         }
     }
 
+    /// Reports the outcome of the last auto-save, then starts another in the background if
+    /// auto-save is on, its interval has passed since the last one and there are unsaved
+    /// changes. Only one auto-save runs at a time
+    fn auto_save_tick(&mut self, now: Instant) {
+        if let Some(result) = &self.auto_save_result {
+            match result.try_recv() {
+                Ok(Ok(())) => self.status_message = "Auto-saved".to_string(),
+                Ok(Err(e)) => {
+                    self.dirty = true;
+                    self.status_message = format!("Auto-save failed: {}", e);
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {}
+            }
+            self.auto_save_result = None;
+        }
+
+        let Some(interval) = self.auto_save_interval else {
+            return;
+        };
+        if !self.dirty || now.duration_since(self.last_auto_save) < interval {
+            return;
+        }
+        let Some(path) = self.save_path.clone() else {
+            return;
+        };
+        self.last_auto_save = now;
+        let bytes = match self.backend.save_to_bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.status_message = format!("Auto-save failed: {}", e);
+                return;
+            }
+        };
+
+        self.save_generation += 1;
+        let generation = self.save_generation;
+        let last_written = Arc::clone(&self.last_written);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // the app may have closed by the time the write finishes
+            let _ = sender.send(write_sheet(&path, &bytes, generation, &last_written));
+        });
+        self.dirty = false;
+        self.auto_save_result = Some(receiver);
+    }
+
     fn load_spreadsheet(&mut self) {
         self.show_load_dialog = true;
     }
 
+    /// Replaces the sheet with the one saved at `path`, adding it to the recent files
+    fn open_path(&mut self, path: PathBuf) {
+        match std::fs::File::open(&path) {
+            Ok(file) => match EmbeddedBackend::from_file(&file) {
+                Ok(new_backend) => {
+                    self.backend = new_backend;
+                    self.reset_view();
+                    self.formula_input = self.render_cell_input(self.view.selected_cell);
+                    self.dirty = false;
+                    self.status_message = format!("Loaded from {:?}", path);
+                    self.save_path = Some(path.clone());
+                    self.remember_recent(path);
+                }
+                Err(e) => {
+                    self.status_message = format!("Error loading file: {}", e);
+                    self.load_error = Some(format!("{:?}: {}", path, e));
+                }
+            },
+            Err(e) => {
+                self.status_message = format!("Error opening file: {}", e);
+                if e.kind() == io::ErrorKind::NotFound {
+                    self.recent_files.remove(&path);
+                    self.save_recent_files();
+                }
+            }
+        }
+    }
+
+    fn remember_recent(&mut self, path: PathBuf) {
+        self.recent_files.add(path);
+        self.save_recent_files();
+    }
+
+    fn save_recent_files(&mut self) {
+        let Some(location) = &self.recent_files_location else {
+            return;
+        };
+        if let Err(e) = self.recent_files.save(location) {
+            self.status_message = format!("Could not update recent files: {}", e);
+        }
+    }
+
+    /// Starts a new view at the top left of the sheet, sized to it, showing as many cells as
+    /// before. Called whenever the sheet is replaced
+    fn reset_view(&mut self) {
+        let (display_rows, display_cols) = (self.view.display_rows, self.view.display_cols);
+        self.view = ViewState::new(self.backend.rows() as i16, self.backend.cols() as i16);
+        self.view.set_display_size(display_rows, display_cols);
+    }
+
+    fn new_spreadsheet(&mut self) {
+        self.backend = EmbeddedBackend::new(Self::DEFAULT_ROWS, Self::DEFAULT_COLS);
+        self.reset_view();
+        self.formula_input = String::new();
+        self.save_path = None;
+        self.dirty = false;
+        self.status_message = "New spreadsheet created".to_string();
+    }
+
+    /// Runs an action replacing the sheet, or asks first if that would lose unsaved changes
+    fn request_discard(&mut self, action: DiscardAction) {
+        if self.dirty {
+            self.confirm_discard = Some(action);
+        } else {
+            self.run_discard(action);
+        }
+    }
+
+    fn run_discard(&mut self, action: DiscardAction) {
+        self.confirm_discard = None;
+        match action {
+            DiscardAction::NewSheet => self.new_spreadsheet(),
+            DiscardAction::Open => self.load_spreadsheet(),
+            DiscardAction::OpenRecent(path) => self.open_path(path),
+        }
+    }
+
+    /// The sheet's file name, with an asterisk while it has unsaved changes
+    fn title(&self) -> String {
+        let name = self
+            .save_path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map_or("Untitled".into(), |name| name.to_string_lossy());
+        let marker = if self.dirty { "*" } else { "" };
+        format!("{}{} - Spreadsheet", name, marker)
+    }
+
+    /// Exports the cells holding data, or the visible cells if the sheet is empty
     fn export_to_csv(&mut self) {
+        let (top_left, bottom_right) = self.backend.used_range().unwrap_or(self.view.view_range());
+        self.export_range_to_csv(top_left, bottom_right);
+    }
+
+    fn export_view_to_csv(&mut self) {
+        let (top_left, bottom_right) = self.view.view_range();
+        self.export_range_to_csv(top_left, bottom_right);
+    }
+
+    /// Scrolls so the cells holding data start at the top left of the view
+    fn fit_view_to_content(&mut self) {
+        match self.backend.used_range() {
+            Some((top_left, _)) => {
+                self.view.view_top_left = AbsCell::new(0, 0);
+                self.view.move_view(top_left.row, top_left.col);
+            }
+            None => self.status_message = "The sheet is empty".to_string(),
+        }
+    }
+
+    fn export_range_to_csv(&mut self, top_left: AbsCell, bottom_right: AbsCell) {
         if let Some(path) = FileDialog::new()
             .add_filter("CSV files", &["csv"])
             .save_file()
         {
-            let bottom_right = AbsCell::new(
-                self.view_top_left.row + self.display_rows - 1,
-                self.view_top_left.col + self.display_cols - 1,
-            );
-
             match self
                 .backend
-                .save_range_to_csv(self.view_top_left, bottom_right, &path)
+                .save_range_to_csv(top_left, bottom_right, &path)
             {
                 Ok(_) => self.status_message = format!("Exported to CSV: {:?}", path),
                 Err(e) => self.status_message = format!("CSV export error: {}", e),
@@ -349,25 +1310,32 @@ impl SpreadsheetApp {
             self.inline_editing = true;
             self.editing = true;
             // Initialize with current cell value or formula
-            if let Some(formula) = self.backend.get_cell_formula(self.selected_cell) {
-                self.inline_edit_value = format!("={}", formula);
-            } else {
-                self.inline_edit_value = self.render_cell_value(self.selected_cell);
-            }
+            self.inline_edit_value = self.render_cell_input(self.view.selected_cell);
         }
     }
 }
 
 impl eframe::App for SpreadsheetApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.auto_save_tick(Instant::now());
+
+        let title = self.title();
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+
         // Handle file dialogs
         if self.show_save_dialog {
             if let Some(path) = FileDialog::new()
                 .add_filter("Spreadsheet files", &["xlsx", "sheet"])
                 .save_file()
             {
-                self.save_path = Some(path);
+                self.save_path = Some(path.clone());
                 self.save_spreadsheet();
+                if !self.dirty {
+                    self.remember_recent(path);
+                }
             }
             self.show_save_dialog = false;
         }
@@ -377,25 +1345,115 @@ impl eframe::App for SpreadsheetApp {
                 .add_filter("Spreadsheet files", &["xlsx", "sheet"])
                 .pick_file()
             {
-                match std::fs::File::open(&path) {
-                    Ok(file) => match EmbeddedBackend::from_file(&file) {
-                        Ok(new_backend) => {
-                            self.backend = new_backend;
-                            self.status_message = format!("Loaded from {:?}", path);
-                            self.save_path = Some(path);
-                        }
-                        Err(e) => {
-                            self.status_message = format!("Error loading file: {}", e);
-                        }
-                    },
-                    Err(e) => {
-                        self.status_message = format!("Error opening file: {}", e);
-                    }
-                }
+                self.open_path(path);
             }
             self.show_load_dialog = false;
         }
 
+        if self.show_validation_dialog {
+            let (top_left, bottom_right) = self.selected_range();
+            egui::Window::new("Validation")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(format!("Applies to {}:{}", top_left, bottom_right));
+                    ui.label("Only allow numbers between (leave blank for no limit)");
+                    ui.horizontal(|ui| {
+                        ui.label("Minimum");
+                        ui.text_edit_singleline(&mut self.validation_min);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Maximum");
+                        ui.text_edit_singleline(&mut self.validation_max);
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Apply").clicked() {
+                            self.apply_validation();
+                        }
+                        if ui.button("Remove Rules").clicked() {
+                            self.backend.remove_validations_at(self.view.selected_cell);
+                            self.dirty = true;
+                            self.status_message = "Validation rules removed".to_string();
+                            self.show_validation_dialog = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_validation_dialog = false;
+                        }
+                    });
+                });
+        }
+
+        if let Some(message) = self.load_error.clone() {
+            egui::Window::new("Could not load sheet")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(message);
+                    ui.label("Your current sheet has not been changed.");
+                    ui.horizontal(|ui| {
+                        if ui.button("Keep current sheet").clicked() {
+                            self.load_error = None;
+                        }
+                        if ui.button("Open another file...").clicked() {
+                            self.load_error = None;
+                            self.show_load_dialog = true;
+                        }
+                    });
+                });
+        }
+
+        if let Some(action) = self.confirm_discard.clone() {
+            egui::Window::new("Unsaved changes")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label("The current sheet has changes that have not been saved.");
+                    ui.horizontal(|ui| {
+                        if self.save_path.is_some() && ui.button("Save").clicked() {
+                            self.save_spreadsheet();
+                            if self.dirty {
+                                // the save failed, its error is in the status bar
+                                self.confirm_discard = None;
+                            } else {
+                                self.run_discard(action.clone());
+                            }
+                        }
+                        if ui.button("Discard changes").clicked() {
+                            self.run_discard(action.clone());
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_discard = None;
+                        }
+                    });
+                });
+        }
+
+        if ctx.input(|i| i.key_pressed(Key::F1)) {
+            self.show_shortcuts = !self.show_shortcuts;
+        } else if self.show_shortcuts && ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.show_shortcuts = false;
+        }
+        if self.show_shortcuts {
+            egui::Window::new("Keyboard Shortcuts")
+                .open(&mut self.show_shortcuts)
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    egui::Grid::new("shortcuts_grid")
+                        .num_columns(2)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for (keys, action) in SHORTCUTS {
+                                ui.strong(keys);
+                                ui.label(action);
+                                ui.end_row();
+                            }
+                        });
+                });
+        }
+
         // Handle keyboard inputs
         if self.show_search_panel {
             // When search panel is active, handle search-specific keys
@@ -423,6 +1481,16 @@ impl eframe::App for SpreadsheetApp {
             if ctx.input(|i| i.key_pressed(Key::F3)) {
                 self.show_search_panel = true;
             }
+
+            // F8 to jump to the next cell holding an error
+            if ctx.input(|i| i.key_pressed(Key::F8)) {
+                self.find_next_error();
+            }
+
+            // F4 to repeat the last edit on the selected cell
+            if ctx.input(|i| i.key_pressed(Key::F4)) {
+                self.repeat_last_edit();
+            }
         }
 
         if self.inline_editing {
@@ -431,78 +1499,71 @@ impl eframe::App for SpreadsheetApp {
                 self.inline_editing = false;
                 self.editing = false;
                 // Restore the formula input to the original value
-                if let Some(formula) = self.backend.get_cell_formula(self.selected_cell) {
-                    self.formula_input = format!("={}", formula);
-                } else {
-                    self.formula_input = self.render_cell_value(self.selected_cell);
-                }
+                self.formula_input = self.render_cell_input(self.view.selected_cell);
             }
         } else {
             // Handle navigation keys when not editing
             if ctx.input(|i| i.key_pressed(Key::Tab))
-                || ctx.input(|i| i.key_pressed(Key::ArrowRight))
+                || ctx.input(|i| !i.modifiers.ctrl && i.key_pressed(Key::ArrowRight))
             {
-                self.move_selection(0, 1);
+                self.move_selection_on_screen(0, 1);
             }
             if ctx.input(|i| i.modifiers.shift && i.key_pressed(Key::Tab))
-                || ctx.input(|i| i.key_pressed(Key::ArrowLeft))
+                || ctx.input(|i| !i.modifiers.ctrl && i.key_pressed(Key::ArrowLeft))
             {
-                self.move_selection(0, -1);
+                self.move_selection_on_screen(0, -1);
             }
-            if ctx.input(|i| i.key_pressed(Key::ArrowUp)) {
-                self.move_selection(-1, 0);
+            if ctx.input(|i| !i.modifiers.ctrl && i.key_pressed(Key::ArrowUp)) {
+                self.move_selection_on_screen(-1, 0);
             }
-            if ctx.input(|i| i.key_pressed(Key::ArrowDown)) {
-                self.move_selection(1, 0);
+            if ctx.input(|i| !i.modifiers.ctrl && i.key_pressed(Key::ArrowDown)) {
+                self.move_selection_on_screen(1, 0);
+            }
+            // Ctrl+Arrow jumps to the next populated cell, or the edge of the sheet
+            for (key, row_delta, col_delta) in [
+                (Key::ArrowRight, 0, 1),
+                (Key::ArrowLeft, 0, -1),
+                (Key::ArrowUp, -1, 0),
+                (Key::ArrowDown, 1, 0),
+            ] {
+                if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(key)) {
+                    self.jump_selection_on_screen(row_delta, col_delta);
+                }
             }
             if ctx.input(|i| i.key_pressed(Key::Enter)) {
                 // Enter key should start editing mode instead of moving down
                 self.start_inline_editing();
             }
             if ctx.input(|i| i.key_pressed(Key::PageUp)) {
-                self.move_selection(-self.display_rows, 0);
+                self.move_selection_on_screen(-self.grid_shape().0, 0);
             }
             if ctx.input(|i| i.key_pressed(Key::PageDown)) {
-                self.move_selection(self.display_rows, 0);
+                self.move_selection_on_screen(self.grid_shape().0, 0);
             }
             //copy
             if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(Key::C)) {
-                self.copy_cell();
+                self.copy_cell(ctx);
             }
 
-            // Ctrl+V for paste
-            if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(Key::V)) {
-                self.paste_cell();
+            // Ctrl+V arrives as the clipboard's text
+            let pasted = ctx.input(|i| {
+                i.events.iter().find_map(|event| match event {
+                    egui::Event::Paste(text) => Some(text.clone()),
+                    _ => None,
+                })
+            });
+            if let Some(text) = pasted.filter(|_| !self.editing) {
+                self.paste_text(&text);
             }
 
             // Ctrl+Z for undo
             if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(Key::Z)) {
-                if self.backend.undo() {
-                    self.status_message = "Undo successful".to_string();
-                    // Update formula input for selected cell
-                    if let Some(formula) = self.backend.get_cell_formula(self.selected_cell) {
-                        self.formula_input = format!("={}", formula);
-                    } else {
-                        self.formula_input = self.render_cell_value(self.selected_cell);
-                    }
-                } else {
-                    self.status_message = "Nothing to undo".to_string();
-                }
+                self.undo();
             }
 
             // Ctrl+Y for redo
             if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(Key::Y)) {
-                if self.backend.redo() {
-                    self.status_message = "Redo successful".to_string();
-                    // Update formula input for selected cell
-                    if let Some(formula) = self.backend.get_cell_formula(self.selected_cell) {
-                        self.formula_input = format!("={}", formula);
-                    } else {
-                        self.formula_input = self.render_cell_value(self.selected_cell);
-                    }
-                } else {
-                    self.status_message = "Nothing to redo".to_string();
-                }
+                self.redo();
             }
 
             // Ctrl+S for save
@@ -512,12 +1573,17 @@ impl eframe::App for SpreadsheetApp {
 
             // Ctrl+O for open
             if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(Key::O)) {
-                self.load_spreadsheet();
+                self.request_discard(DiscardAction::Open);
             }
 
             // Ctrl+E for export current view to CSV
             if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(Key::E)) {
-                self.export_to_csv();
+                self.export_view_to_csv();
+            }
+
+            // Alt+= to sum the run of cells above or to the left
+            if ctx.input(|i| i.modifiers.alt && i.key_pressed(Key::Equals)) {
+                self.auto_sum();
             }
 
             // Start editing on F2 or when typing any printable character
@@ -556,18 +1622,27 @@ impl eframe::App for SpreadsheetApp {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
                     if ui.button("New").clicked() {
-                        self.backend = EmbeddedBackend::new(999, 18278);
-                        self.view_top_left = AbsCell::new(0, 0);
-                        self.selected_cell = AbsCell::new(0, 0);
-                        self.formula_input = String::new();
-                        self.save_path = None;
-                        self.status_message = "New spreadsheet created".to_string();
+                        self.request_discard(DiscardAction::NewSheet);
                         ui.close_menu();
                     }
                     if ui.button("Open...").clicked() {
-                        self.load_spreadsheet();
+                        self.request_discard(DiscardAction::Open);
                         ui.close_menu();
                     }
+                    ui.add_enabled_ui(!self.recent_files.is_empty(), |ui| {
+                        ui.menu_button("Open Recent", |ui| {
+                            let mut picked = None;
+                            for path in self.recent_files.paths() {
+                                if ui.button(path.display().to_string()).clicked() {
+                                    picked = Some(path.clone());
+                                }
+                            }
+                            if let Some(path) = picked {
+                                self.request_discard(DiscardAction::OpenRecent(path));
+                                ui.close_menu();
+                            }
+                        });
+                    });
                     if ui.button("Save").clicked() {
                         self.save_spreadsheet();
                         ui.close_menu();
@@ -576,11 +1651,23 @@ impl eframe::App for SpreadsheetApp {
                         self.show_save_dialog = true;
                         ui.close_menu();
                     }
+                    ui.menu_button("Auto-save", |ui| {
+                        for (interval, label) in AUTO_SAVE_INTERVALS {
+                            ui.radio_value(&mut self.auto_save_interval, interval, label);
+                        }
+                        if self.save_path.is_none() {
+                            ui.label("Applies once the sheet has been saved");
+                        }
+                    });
                     ui.separator();
                     if ui.button("Export to CSV...").clicked() {
                         self.export_to_csv();
                         ui.close_menu();
                     }
+                    if ui.button("Export View to CSV... (Ctrl+E)").clicked() {
+                        self.export_view_to_csv();
+                        ui.close_menu();
+                    }
                 });
 
                 ui.menu_button("Search", |ui| {
@@ -596,55 +1683,195 @@ impl eframe::App for SpreadsheetApp {
                         self.search_from_beginning();
                         ui.close_menu();
                     }
+                    ui.separator();
+                    if ui.button("Find Next Error (F8)").clicked() {
+                        self.find_next_error();
+                        ui.close_menu();
+                    }
                 });
 
                 ui.menu_button("Edit", |ui| {
                     if ui.button("Copy").clicked() {
-                        self.copy_cell();
+                        self.copy_cell(ui.ctx());
                         ui.close_menu();
                     }
                     if ui.button("Paste").clicked() {
                         self.paste_cell();
                         ui.close_menu();
                     }
+                    ui.menu_button("Paste Special", |ui| {
+                        if ui.button("Values").clicked() {
+                            self.paste_values();
+                            ui.close_menu();
+                        }
+                        if ui.button("Transposed").clicked() {
+                            self.paste_transposed();
+                            ui.close_menu();
+                        }
+                    });
+                    if ui.button("Freeze Values").clicked() {
+                        self.freeze_selection();
+                        ui.close_menu();
+                    }
                     ui.separator();
 
                     if ui.button("Undo").clicked() {
-                        if self.backend.undo() {
-                            self.status_message = "Undo successful".to_string();
-                            if let Some(formula) = self.backend.get_cell_formula(self.selected_cell)
-                            {
-                                self.formula_input = format!("={}", formula);
-                            } else {
-                                self.formula_input = self.render_cell_value(self.selected_cell);
-                            }
-                        } else {
-                            self.status_message = "Nothing to undo".to_string();
-                        }
+                        self.undo();
                         ui.close_menu();
                     }
                     if ui.button("Redo").clicked() {
-                        if self.backend.redo() {
-                            self.status_message = "Redo successful".to_string();
-                            if let Some(formula) = self.backend.get_cell_formula(self.selected_cell)
+                        self.redo();
+                        ui.close_menu();
+                    }
+                    ui.separator();
+
+                    let (row, col) = (self.view.selected_cell.row, self.view.selected_cell.col);
+                    for (label, edit) in [
+                        ("Insert Row Above", StructuralEdit::InsertRow(row)),
+                        ("Delete Row", StructuralEdit::DeleteRow(row)),
+                        ("Insert Column Left", StructuralEdit::InsertCol(col)),
+                        ("Delete Column", StructuralEdit::DeleteCol(col)),
+                    ] {
+                        if ui.button(label).clicked() {
+                            self.apply_structural(edit);
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                ui.menu_button("View", |ui| {
+                    if ui.button("Fit to Content").clicked() {
+                        self.fit_view_to_content();
+                        ui.close_menu();
+                    }
+                    ui.checkbox(&mut self.transposed, "Transpose (rows as columns)");
+                    ui.checkbox(&mut self.show_relative_refs, "Formulas as R1C1 offsets");
+                    let (mut rows, mut cols) = (self.view.display_rows, self.view.display_cols);
+                    let resized = ui
+                        .horizontal(|ui| {
+                            ui.label("Rows shown");
+                            let rows_changed = ui
+                                .add(egui::DragValue::new(&mut rows).range(1..=50))
+                                .changed();
+                            ui.label("Columns shown");
+                            let cols_changed = ui
+                                .add(egui::DragValue::new(&mut cols).range(1..=50))
+                                .changed();
+                            rows_changed || cols_changed
+                        })
+                        .inner;
+                    if resized {
+                        self.view.set_display_size(rows, cols);
+                    }
+                    let mut heat_map = self.dependents_heat_map.is_some();
+                    if ui.checkbox(&mut heat_map, "Shade by Dependents").changed() {
+                        self.toggle_dependents_heat_map(heat_map);
+                    }
+                    ui.separator();
+                    ui.checkbox(&mut self.striped, "Striped rows");
+                    ui.add_enabled_ui(self.striped, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Stripe color");
+                            let mut color =
+                                self.stripe_color.unwrap_or(ui.visuals().faint_bg_color);
+                            if egui::color_picker::color_edit_button_srgba(
+                                ui,
+                                &mut color,
+                                egui::color_picker::Alpha::OnlyBlend,
+                            )
+                            .changed()
                             {
-                                self.formula_input = format!("={}", formula);
-                            } else {
-                                self.formula_input = self.render_cell_value(self.selected_cell);
+                                self.stripe_color = Some(color);
+                            }
+                            if ui.button("Default").clicked() {
+                                self.stripe_color = None;
+                            }
+                        });
+                    });
+                    ui.separator();
+                    ui.label("Number format");
+                    ui.radio_value(&mut self.locale, NumberLocale::Standard, "1234.5");
+                    ui.radio_value(&mut self.locale, NumberLocale::European, "1.234,5");
+                    ui.separator();
+                    let mut same_text = self.error_text.is_some();
+                    if ui
+                        .checkbox(&mut same_text, "Same text for every error")
+                        .changed()
+                    {
+                        self.error_text = same_text.then(|| DEFAULT_ERROR_TEXT.to_string());
+                    }
+                    if let Some(text) = &mut self.error_text {
+                        ui.text_edit_singleline(text);
+                    }
+                });
+
+                ui.menu_button("Data", |ui| {
+                    if ui.button("Validation...").clicked() {
+                        self.show_validation_dialog = true;
+                        ui.close_menu();
+                    }
+                    ui.menu_button("Column Type", |ui| {
+                        let current = self.backend.column_type(self.view.selected_cell.col);
+                        for (column_type, label) in [
+                            (None, "Any"),
+                            (Some(ColumnType::Number), "Numbers"),
+                            (Some(ColumnType::Text), "Text"),
+                        ] {
+                            if ui.radio(current == column_type, label).clicked() {
+                                self.set_column_type(column_type);
+                                ui.close_menu();
                             }
-                        } else {
-                            self.status_message = "Nothing to redo".to_string();
                         }
+                    });
+                    if ui.button("Convert Column to Numbers").clicked() {
+                        self.convert_to_numbers(true);
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    if ui.button("Insert Totals Below").clicked() {
+                        self.insert_totals(TotalsDirection::Below);
+                        ui.close_menu();
+                    }
+                    if ui.button("Insert Totals Right").clicked() {
+                        self.insert_totals(TotalsDirection::Right);
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    if ui.button("Highlight Duplicates").clicked() {
+                        self.highlight_duplicates();
+                        ui.close_menu();
+                    }
+                    if ui.button("Clear Highlights").clicked() {
+                        self.duplicate_groups.clear();
                         ui.close_menu();
                     }
                 });
 
+                ui.menu_button("Debug", |ui| {
+                    ui.checkbox(
+                        &mut self.show_cell_coordinates,
+                        "Show cell coordinates on hover",
+                    );
+                    ui.checkbox(&mut self.show_error_sources, "Show error sources on hover");
+                    ui.checkbox(
+                        &mut self.zero_based_labels,
+                        "Number rows and columns from 0",
+                    );
+                });
+
                 ui.menu_button("Navigation", |ui| {
                     if ui.button("Go to Cell...").clicked() {
                         // TODO: Implement cell navigation popup
                         ui.close_menu();
                     }
                 });
+
+                ui.menu_button("Help", |ui| {
+                    if ui.button("Keyboard Shortcuts (F1)").clicked() {
+                        self.show_shortcuts = true;
+                        ui.close_menu();
+                    }
+                });
             });
         });
 
@@ -667,15 +1894,28 @@ impl eframe::App for SpreadsheetApp {
                         ui.memory_mut(|mem| mem.request_focus(response.id));
                     }
 
-                    // Update search value and handle Enter key
+                    // Update search value, searching from the top once typing pauses
                     if response.changed() {
                         self.search_value = search_text;
+                        self.view.search_edited(Instant::now());
+                    }
+                    if self.view.search_due(Instant::now()) {
+                        if !self.search_value.is_empty() {
+                            self.search_from_beginning();
+                        }
+                    } else if self.view.search_pending() {
+                        ctx.request_repaint_after(SEARCH_DEBOUNCE);
                     }
 
                     if (response.lost_focus() && ctx.input(|i| i.key_pressed(Key::Enter)))
                         || (response.has_focus() && ctx.input(|i| i.key_pressed(Key::Enter)))
                     {
-                        self.search_next();
+                        // Enter before the pause still finds the first match
+                        if self.view.take_pending_search() {
+                            self.search_from_beginning();
+                        } else {
+                            self.search_next();
+                        }
                         // Return focus to search field after searching
                         ui.memory_mut(|mem| mem.request_focus(response.id));
                     }
@@ -707,15 +1947,20 @@ impl eframe::App for SpreadsheetApp {
         // Formula bar
         egui::TopBottomPanel::top("formula_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                ui.label(format!(
-                    "{}{}:",
-                    Self::cell_to_label(self.selected_cell.col),
-                    self.selected_cell.row + 1
-                ));
+                ui.label(format!("{}:", self.selected_cell_label()));
 
+                // shown read-only beside the input, as R1C1 text does not parse back
+                let relative = self
+                    .show_relative_refs
+                    .then(|| self.relative_formula(self.view.selected_cell))
+                    .flatten();
                 let mut input = self.formula_input.clone();
+                let width = match relative {
+                    Some(_) => ui.available_width() * 0.6,
+                    None => ui.available_width(),
+                };
                 let text_edit = TextEdit::singleline(&mut input)
-                    .desired_width(ui.available_width())
+                    .desired_width(width)
                     .font(FontId::proportional(16.0));
 
                 let response = ui.add(text_edit);
@@ -731,6 +1976,18 @@ impl eframe::App for SpreadsheetApp {
                         self.handle_cell_edit(&self.formula_input.clone());
                     }
                 }
+                if let Some(relative) = relative {
+                    ui.label(RichText::new(relative).monospace().weak());
+                }
+                if !self.editing
+                    && self.backend.numeric_text(self.view.selected_cell).is_some()
+                    && ui
+                        .small_button("Convert to number")
+                        .on_hover_text("This text reads as a number, formulas cannot use it")
+                        .clicked()
+                {
+                    self.convert_to_numbers(false);
+                }
             });
         });
 
@@ -741,10 +1998,12 @@ impl eframe::App for SpreadsheetApp {
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                     ui.label(format!(
                         "View: {}{} to {}{}",
-                        Self::cell_to_label(self.view_top_left.col),
-                        self.view_top_left.row + 1,
-                        Self::cell_to_label(self.view_top_left.col + self.display_cols - 1),
-                        self.view_top_left.row + self.display_rows
+                        Self::cell_to_label(self.view.view_top_left.col),
+                        self.view.view_top_left.row + 1,
+                        Self::cell_to_label(
+                            self.view.view_top_left.col + self.view.display_cols - 1
+                        ),
+                        self.view.view_top_left.row + self.view.display_rows
                     ));
                 });
             });
@@ -755,39 +2014,45 @@ impl eframe::App for SpreadsheetApp {
             // Navigation buttons
             ui.horizontal(|ui| {
                 if ui.button("⬅️").clicked() {
-                    self.move_view(0, -1);
+                    self.view.move_view(0, -1);
                 }
                 if ui.button("➡️").clicked() {
-                    self.move_view(0, 1);
+                    self.view.move_view(0, 1);
                 }
                 if ui.button("⬆️").clicked() {
-                    self.move_view(-1, 0);
+                    self.view.move_view(-1, 0);
                 }
                 if ui.button("⬇️").clicked() {
-                    self.move_view(1, 0);
+                    self.view.move_view(1, 0);
                 }
                 if ui.button("⏮️").clicked() {
-                    self.view_top_left.col = 0;
+                    self.view.view_top_left.col = 0;
                 }
                 if ui.button("⏭️").clicked() {
-                    self.view_top_left.col = 18278 - self.display_cols;
+                    self.view.view_top_left.col = self.view.sheet_size().1 - self.view.display_cols;
                 }
                 if ui.button("⏫").clicked() {
-                    self.view_top_left.row = 0;
+                    self.view.view_top_left.row = 0;
                 }
                 if ui.button("⏬").clicked() {
-                    self.view_top_left.row = 999 - self.display_rows;
+                    self.view.view_top_left.row = self.view.sheet_size().0 - self.view.display_rows;
                 }
             });
 
+            let (grid_rows, grid_cols) = self.grid_shape();
+            // The table paints the stripes before the cells, so the selection border and the
+            // other cell highlights are always drawn on top of them
+            if let Some(color) = self.stripe_color {
+                ui.visuals_mut().faint_bg_color = color;
+            }
             let table = egui_extras::TableBuilder::new(ui)
-                .striped(true)
+                .striped(self.striped)
                 .resizable(true)
                 .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
                 .column(egui_extras::Column::auto().at_least(40.0))
                 .columns(
                     egui_extras::Column::auto().at_least(self.col_width),
-                    self.display_cols as usize,
+                    grid_cols as usize,
                 );
 
             table
@@ -796,28 +2061,34 @@ impl eframe::App for SpreadsheetApp {
                         ui.strong("");
                     });
 
-                    for col in 0..self.display_cols {
-                        let col_idx = self.view_top_left.col + col;
+                    for col in 0..grid_cols {
+                        let (text, selection) = self.grid_header(true, col);
                         header.col(|ui| {
-                            ui.strong(Self::cell_to_label(col_idx));
+                            let label = egui::Label::new(RichText::new(text).strong())
+                                .sense(egui::Sense::click());
+                            if ui.add(label).clicked() {
+                                self.select_range(selection);
+                            }
                         });
                     }
                 })
                 .body(|mut body| {
-                    for row in 0..self.display_rows {
-                        let row_idx = self.view_top_left.row + row;
+                    for grid_row in 0..grid_rows {
+                        let (text, selection) = self.grid_header(false, grid_row);
                         body.row(self.row_height, |mut row| {
                             // Row header
                             row.col(|ui| {
-                                ui.strong(format!("{}", row_idx + 1));
+                                let label = egui::Label::new(RichText::new(text).strong())
+                                    .sense(egui::Sense::click());
+                                if ui.add(label).clicked() {
+                                    self.select_range(selection);
+                                }
                             });
 
                             // Cell data
-                            for col in 0..self.display_cols {
-                                let col_idx = self.view_top_left.col + col;
-                                let cell = AbsCell::new(row_idx, col_idx);
-                                let is_selected = self.selected_cell.row == row_idx
-                                    && self.selected_cell.col == col_idx;
+                            for grid_col in 0..grid_cols {
+                                let cell = self.cell_at(grid_row, grid_col);
+                                let is_selected = self.view.selected_cell == cell;
 
                                 row.col(|ui| {
                                     // Check if this is the selected cell and we're inline editing
@@ -844,21 +2115,15 @@ impl eframe::App for SpreadsheetApp {
                                             self.inline_editing = false;
                                             self.editing = false;
                                             // Restore the formula input to the original value
-                                            if let Some(formula) =
-                                                self.backend.get_cell_formula(self.selected_cell)
-                                            {
-                                                self.formula_input = format!("={}", formula);
-                                            } else {
-                                                self.formula_input =
-                                                    self.render_cell_value(self.selected_cell);
-                                            }
+                                            self.formula_input =
+                                                self.render_cell_input(self.view.selected_cell);
                                         } else if ctx.input(|i| i.key_pressed(Key::Tab)) {
                                             // Commit changes and move to next/previous cell when Tab is pressed
                                             self.handle_cell_edit(&self.inline_edit_value.clone());
                                             if ctx.input(|i| i.modifiers.shift) {
-                                                self.move_selection(0, -1);
+                                                self.move_selection_on_screen(0, -1);
                                             } else {
-                                                self.move_selection(0, 1);
+                                                self.move_selection_on_screen(0, 1);
                                             }
                                         } else if response.lost_focus()
                                             && !ctx.input(|i| i.key_pressed(Key::Escape))
@@ -868,14 +2133,45 @@ impl eframe::App for SpreadsheetApp {
                                         }
                                     } else {
                                         let cell_value = self.render_cell_value(cell);
+                                        let shown = truncate_text(
+                                            &cell_value,
+                                            (self.col_width / CHAR_WIDTH) as usize,
+                                        );
+                                        let truncated = matches!(shown, Cow::Owned(_));
 
                                         // Get the text ready
-                                        let text = RichText::new(&cell_value);
+                                        let text = RichText::new(shown);
                                         let text = if is_selected { text.strong() } else { text };
 
                                         // Create the cell area - important: use the full rect here
                                         let rect = ui.available_rect_before_wrap();
 
+                                        let heat = self.dependents_heat_map.as_ref().and_then(
+                                            |(counts, max)| {
+                                                Some(dependents_tint(*counts.get(&cell)?, *max))
+                                            },
+                                        );
+                                        if let Some(tint) = heat {
+                                            ui.painter().rect_filled(rect, 0.0, tint);
+                                        }
+
+                                        if let Some(group) = self.duplicate_groups.get(&cell) {
+                                            ui.painter().rect_filled(
+                                                rect,
+                                                0.0,
+                                                DUPLICATE_TINTS[group % DUPLICATE_TINTS.len()],
+                                            );
+                                        }
+
+                                        // Shade cells inside a whole row/column selection
+                                        if self.is_in_selection(cell) {
+                                            ui.painter().rect_filled(
+                                                rect,
+                                                0.0,
+                                                Color32::from_rgba_unmultiplied(0, 90, 180, 40),
+                                            );
+                                        }
+
                                         // Draw cell background if selected
                                         if is_selected {
                                             // ui.painter().rect_filled(
@@ -896,8 +2192,9 @@ impl eframe::App for SpreadsheetApp {
                                             );
                                         }
 
-                                        // Add the label with its text
-                                        ui.add(egui::Label::new(text));
+                                        // Add the label with its text, aligned by its type
+                                        let layout = cell_layout(self.backend.cell_type(cell));
+                                        ui.with_layout(layout, |ui| ui.add(egui::Label::new(text)));
 
                                         // Add an invisible button over the entire cell area to capture clicks
                                         // Position it at the same place as the cell
@@ -907,6 +2204,21 @@ impl eframe::App for SpreadsheetApp {
                                                 .frame(false)  // No visible frame
                                                 .fill(Color32::TRANSPARENT) // Transparent fill
                                         );
+                                        let mut hover_lines = Vec::new();
+                                        if truncated {
+                                            hover_lines.push(cell_value);
+                                        }
+                                        if self.show_cell_coordinates {
+                                            hover_lines.push(Self::cell_coordinates_text(cell));
+                                        }
+                                        if self.show_error_sources {
+                                            hover_lines.extend(self.error_source_text(cell));
+                                        }
+                                        let response = if hover_lines.is_empty() {
+                                            response
+                                        } else {
+                                            response.on_hover_text(hover_lines.join("\n"))
+                                        };
 
                                         // Handle clicks on the invisible button covering the entire cell
                                         if response.clicked() {
@@ -917,23 +2229,19 @@ impl eframe::App for SpreadsheetApp {
                                                 );
                                             }
 
-                                            self.selected_cell = cell;
+                                            self.view.selected_cell = cell;
+                                            self.view.selection_range = None;
                                             self.inline_editing = false;
                                             self.editing = false;
 
                                             // Update formula input when selecting a cell
-                                            if let Some(formula) =
-                                                self.backend.get_cell_formula(self.selected_cell)
-                                            {
-                                                self.formula_input = format!("={}", formula);
-                                            } else {
-                                                self.formula_input = cell_value;
-                                            }
+                                            self.formula_input =
+                                                self.render_cell_input(self.view.selected_cell);
                                         }
 
                                         // Double-click starts editing
                                         if response.double_clicked() {
-                                            self.selected_cell = cell;
+                                            self.view.selected_cell = cell;
                                             self.start_inline_editing();
                                         }
                                     }
@@ -944,7 +2252,8 @@ impl eframe::App for SpreadsheetApp {
                 });
         });
 
-        // Request repaint to keep the UI responsive
+        // Request repaint to keep the UI responsive. Rendering only reads the backend's
+        // cached values, so repainting never re-evaluates formulas (or SLEEPs again)
         ctx.request_repaint();
     }
 }
@@ -958,7 +2267,14 @@ pub fn run_spreadsheet_app() -> Result<(), eframe::Error> {
     eframe::run_native(
         "Spreadsheet",
         options,
-        Box::new(|_cc| Ok(Box::new(SpreadsheetApp::new()))),
+        Box::new(|_cc| {
+            let mut app = SpreadsheetApp::new();
+            app.recent_files_location = RecentFiles::default_location();
+            if let Some(location) = &app.recent_files_location {
+                app.recent_files = RecentFiles::load(location);
+            }
+            Ok(Box::new(app))
+        }),
     )
 }
 
@@ -968,3 +2284,762 @@ fn main() -> Result<(), eframe::Error> {
     run_spreadsheet_app()
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_cell_layout() {
+        let right = egui::Layout::right_to_left(egui::Align::Center);
+        let left = egui::Layout::left_to_right(egui::Align::Center);
+        assert_eq!(cell_layout(CellType::Number), right);
+        assert_eq!(cell_layout(CellType::Error), right);
+        assert_eq!(cell_layout(CellType::Text), left);
+        assert_eq!(cell_layout(CellType::Empty), left);
+
+        let mut app = SpreadsheetApp::new();
+        let cell = AbsCell::new(0, 0);
+        app.backend.set_cell_formula(cell, "1/0").unwrap();
+        assert_eq!(cell_layout(app.backend.cell_type(cell)), right);
+        // a formula is laid out by its result
+        app.backend.set_cell_formula(cell, "2 * 3").unwrap();
+        assert_eq!(cell_layout(app.backend.cell_type(cell)), right);
+    }
+
+    #[test]
+    fn test_truncate_text() {
+        assert_eq!(truncate_text("short", 5), "short");
+        assert_eq!(truncate_text("a longer string", 8), "a longe…");
+        assert_eq!(truncate_text("ünïcödé", 4), "ünï…");
+        assert_eq!(truncate_text("", 0), "");
+        assert_eq!(truncate_text("text", 0), "");
+        assert!(matches!(truncate_text("text", 0), Cow::Owned(_)));
+        assert!(matches!(truncate_text("short", 5), Cow::Borrowed(_)));
+        // two characters over, the cut text has as many bytes as the whole, with the ellipsis
+        let cut = truncate_text("abcdefg", 5);
+        assert_eq!(cut, "abcd…");
+        assert_eq!(cut.len(), "abcdefg".len());
+        assert!(matches!(cut, Cow::Owned(_)));
+
+        // the full text is still what the cell holds
+        let mut app = SpreadsheetApp::new();
+        app.handle_cell_edit("a string far too long for a 100 pixel column");
+        assert_eq!(
+            app.render_cell_value(app.view.selected_cell),
+            "a string far too long for a 100 pixel column"
+        );
+    }
+
+    #[test]
+    fn test_apostrophe_forces_text() {
+        let mut app = SpreadsheetApp::new();
+        app.view.selected_cell = AbsCell::from_str("B1").unwrap();
+        app.handle_cell_edit("'=A1");
+
+        assert_eq!(
+            app.backend.get_cell_value(app.view.selected_cell),
+            &Ok(CellValue::String("=A1".to_string()))
+        );
+        assert_eq!(app.backend.get_cell_formula(app.view.selected_cell), None);
+        assert_eq!(app.render_cell_value(app.view.selected_cell), "=A1");
+        assert_eq!(app.render_cell_input(app.view.selected_cell), "'=A1");
+    }
+
+    #[test]
+    fn test_edit_parses_decorated_numbers() {
+        let mut app = SpreadsheetApp::new();
+        app.handle_cell_edit("$1,234.50");
+        assert_eq!(
+            app.backend.get_cell_value(app.view.selected_cell),
+            &Ok(CellValue::Number(1234.5))
+        );
+
+        // text that only looks like a number is escaped, so editing it keeps it as text
+        app.handle_cell_edit("'$5");
+        assert_eq!(app.render_cell_input(app.view.selected_cell), "'$5");
+
+        app.handle_cell_edit("1 1/2");
+        assert_eq!(
+            app.backend.get_cell_value(app.view.selected_cell),
+            &Ok(CellValue::Number(1.5))
+        );
+        assert_eq!(app.render_cell_value(app.view.selected_cell), "1.5");
+        // a division of cells is only a formula with the `=`
+        app.handle_cell_edit("A1/B1");
+        assert_eq!(
+            app.backend.get_cell_value(app.view.selected_cell),
+            &Ok(CellValue::String("A1/B1".to_string()))
+        );
+        assert_eq!(app.backend.get_cell_formula(app.view.selected_cell), None);
+    }
+
+    #[test]
+    fn test_render_cell_value_error_text() {
+        let mut app = SpreadsheetApp::new();
+        app.handle_cell_edit("=1/0");
+        assert_eq!(app.render_cell_value(app.view.selected_cell), "#DIV/0!");
+
+        app.error_text = Some("NaN".to_string());
+        assert_eq!(app.render_cell_value(app.view.selected_cell), "NaN");
+        app.error_text = None;
+        assert_eq!(app.render_cell_value(app.view.selected_cell), "#DIV/0!");
+    }
+
+    #[test]
+    fn test_render_cell_value_locale() {
+        let mut app = SpreadsheetApp::new();
+        app.handle_cell_edit("1234.5");
+        assert_eq!(app.render_cell_value(app.view.selected_cell), "1234.5");
+
+        app.locale = NumberLocale::European;
+        assert_eq!(app.render_cell_value(app.view.selected_cell), "1.234,5");
+        // the edit text stays in the parseable form
+        assert_eq!(app.render_cell_input(app.view.selected_cell), "1234.5");
+    }
+
+    #[test]
+    fn test_move_selection_single_step_scroll() {
+        let mut app = SpreadsheetApp::new();
+        app.view.selected_cell = AbsCell::new(9, 9);
+
+        app.move_selection(0, -1);
+        assert_eq!(app.view.view_top_left, AbsCell::new(0, 0));
+
+        app.move_selection(1, 1);
+        assert_eq!(app.view.selected_cell, AbsCell::new(10, 9));
+        assert_eq!(app.view.view_top_left, AbsCell::new(1, 0));
+
+        app.move_selection(0, 1);
+        assert_eq!(app.view.view_top_left, AbsCell::new(1, 1));
+        assert_eq!(
+            app.view.selected_cell.col,
+            app.view.view_top_left.col + app.view.display_cols - 1
+        );
+
+        app.move_selection(-1, 0);
+        assert_eq!(app.view.view_top_left, AbsCell::new(1, 1));
+    }
+
+    #[test]
+    fn test_header_click_selection() {
+        let mut app = SpreadsheetApp::new();
+        let (top, bottom) = app.column_selection(2);
+        assert_eq!(top, AbsCell::from_str("C1").unwrap());
+        assert_eq!(bottom, AbsCell::from_str("C999").unwrap());
+
+        let (left, right) = app.row_selection(4);
+        assert_eq!(left, AbsCell::from_str("A5").unwrap());
+        assert_eq!(right, AbsCell::new(4, 18277));
+
+        app.select_range(app.column_selection(2));
+        assert_eq!(app.view.selected_cell, top);
+        assert!(app.is_in_selection(AbsCell::from_str("C500").unwrap()));
+        assert!(!app.is_in_selection(AbsCell::from_str("D1").unwrap()));
+
+        app.move_selection(1, 0);
+        assert!(!app.is_in_selection(AbsCell::from_str("C500").unwrap()));
+    }
+
+    #[test]
+    fn test_cell_coordinates_text() {
+        assert_eq!(
+            SpreadsheetApp::cell_coordinates_text(AbsCell::from_str("B3").unwrap()),
+            "B3  AbsCell { row: 2, col: 1 }"
+        );
+        assert_eq!(
+            SpreadsheetApp::cell_coordinates_text(AbsCell::new(0, 26)),
+            "AA1  AbsCell { row: 0, col: 26 }"
+        );
+    }
+
+    #[test]
+    fn test_highlight_duplicates() {
+        let mut app = SpreadsheetApp::new();
+        for label in ["A1", "C2", "B5"] {
+            app.view.selected_cell = AbsCell::from_str(label).unwrap();
+            app.handle_cell_edit("5");
+        }
+        app.view.selected_cell = AbsCell::from_str("D1").unwrap();
+        app.handle_cell_edit("6");
+
+        app.highlight_duplicates();
+        assert_eq!(app.duplicate_groups.len(), 3);
+        assert_eq!(
+            app.duplicate_groups.get(&AbsCell::from_str("B5").unwrap()),
+            Some(&0)
+        );
+        assert!(
+            !app.duplicate_groups
+                .contains_key(&AbsCell::from_str("D1").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_validation_rejects_edit() {
+        let mut app = SpreadsheetApp::new();
+        app.validation_min = "0".to_string();
+        app.validation_max = "100".to_string();
+        app.apply_validation();
+
+        app.handle_cell_edit("150");
+        assert!(
+            app.status_message
+                .starts_with("Rejected: Value out of range")
+        );
+        assert_eq!(
+            app.backend.get_cell_value(app.view.selected_cell),
+            &Ok(CellValue::Empty)
+        );
+
+        app.handle_cell_edit("lots");
+        assert_eq!(app.status_message, "Rejected: Value must be a number");
+
+        app.handle_cell_edit("42");
+        assert_eq!(app.status_message, "Number set");
+
+        app.validation_max = "ten".to_string();
+        app.apply_validation();
+        assert_eq!(app.status_message, "Validation bounds must be numbers");
+    }
+
+    #[test]
+    fn test_repeat_last_edit() {
+        let mut app = SpreadsheetApp::new();
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        app.repeat_last_edit();
+        assert_eq!(app.status_message, "Nothing to repeat");
+
+        app.view.selected_cell = cell("B1");
+        app.handle_cell_edit("=A1+1");
+        assert_eq!(app.last_edit, Some((cell("B1"), "=A1+1".to_string())));
+        app.view.selected_cell = cell("B2");
+        app.repeat_last_edit();
+        assert_eq!(
+            app.backend.get_cell_formula(cell("B2")),
+            Some("A2 + 1".to_string())
+        );
+
+        app.view.selected_cell = cell("C1");
+        app.handle_cell_edit("5");
+        app.view.selected_cell = cell("C3");
+        app.repeat_last_edit();
+        assert_eq!(
+            app.backend.get_cell_value(cell("C3")),
+            &Ok(CellValue::Number(5.0))
+        );
+
+        // a reference that would move off the sheet is refused
+        app.view.selected_cell = cell("B2");
+        app.handle_cell_edit("=A1");
+        app.view.selected_cell = cell("A1");
+        app.repeat_last_edit();
+        assert!(app.status_message.starts_with("Formula error"));
+        assert!(!app.backend.is_populated(cell("A1")));
+
+        // refused edits are not the ones repeated
+        app.view.selected_cell = cell("D1");
+        app.handle_cell_edit("=A1 +");
+        app.backend.set_column_type(3, Some(ColumnType::Number));
+        app.handle_cell_edit("abc");
+        assert_eq!(app.last_edit, Some((cell("B2"), "=A1".to_string())));
+    }
+
+    #[test]
+    fn test_auto_sum_range() {
+        let mut app = SpreadsheetApp::new();
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        for (row, value) in ["4", "8", "15", "16", "23"].into_iter().enumerate() {
+            app.view.selected_cell = AbsCell::new(row as i16 + 1, 1);
+            app.handle_cell_edit(value);
+        }
+        app.view.selected_cell = cell("A4");
+        app.handle_cell_edit("1");
+
+        // the header row B1 is empty, so the run stops below it
+        assert_eq!(
+            app.auto_sum_range(cell("B7")),
+            Some((cell("B2"), cell("B6")))
+        );
+        // nothing above C4, so it sums the run to its left
+        assert_eq!(
+            app.auto_sum_range(cell("C4")),
+            Some((cell("A4"), cell("B4")))
+        );
+        assert_eq!(app.auto_sum_range(cell("D1")), None);
+
+        app.view.selected_cell = cell("B7");
+        app.auto_sum();
+        assert_eq!(app.status_message, "Summed B2:B6 into B7");
+        assert_eq!(
+            app.backend.get_cell_value(cell("B7")),
+            &Ok(CellValue::Number(66.0))
+        );
+        app.auto_sum();
+        assert_eq!(app.status_message, "AutoSum needs an empty cell");
+    }
+
+    #[test]
+    fn test_number_column_rejects_text() {
+        let mut app = SpreadsheetApp::new();
+        app.view.selected_cell = AbsCell::from_str("B1").unwrap();
+        app.set_column_type(Some(ColumnType::Number));
+        app.dirty = false;
+
+        app.handle_cell_edit("pending");
+        assert_eq!(app.status_message, "Rejected: Value must be a number");
+        assert_eq!(
+            app.backend.get_cell_value(app.view.selected_cell),
+            &Ok(CellValue::Empty)
+        );
+        assert!(!app.dirty);
+
+        // forced text that reads as a number is converted, with a warning
+        app.handle_cell_edit("'42");
+        assert_eq!(app.status_message, "Converted to number for column B");
+        assert_eq!(
+            app.backend.get_cell_value(app.view.selected_cell),
+            &Ok(CellValue::Number(42.0))
+        );
+
+        // other columns are unconstrained
+        app.view.selected_cell = AbsCell::from_str("C1").unwrap();
+        app.handle_cell_edit("pending");
+        assert_eq!(app.status_message, "Text set");
+    }
+
+    #[test]
+    fn test_insert_totals_uses_current_region() {
+        let mut app = SpreadsheetApp::new();
+        for label in ["A1", "B1", "A2", "B2", "C3"] {
+            app.view.selected_cell = AbsCell::from_str(label).unwrap();
+            app.handle_cell_edit("1");
+        }
+        // an isolated value further away is not part of the block
+        app.view.selected_cell = AbsCell::from_str("F1").unwrap();
+        app.handle_cell_edit("100");
+
+        app.view.selected_cell = AbsCell::from_str("B2").unwrap();
+        assert_eq!(
+            app.current_region(app.view.selected_cell),
+            Some((
+                AbsCell::from_str("A1").unwrap(),
+                AbsCell::from_str("C3").unwrap()
+            ))
+        );
+        assert_eq!(app.current_region(AbsCell::from_str("E5").unwrap()), None);
+
+        app.insert_totals(TotalsDirection::Below);
+        assert_eq!(app.status_message, "Inserted totals for A1:C3");
+        let a4 = AbsCell::from_str("A4").unwrap();
+        assert_eq!(app.backend.get_cell_value(a4), &Ok(CellValue::Number(2.0)));
+
+        // the region reaches as far as the sheet does
+        app.backend = EmbeddedBackend::new(2000, 10);
+        for label in ["A999", "A1000", "A1001"] {
+            app.view.selected_cell = AbsCell::from_str(label).unwrap();
+            app.handle_cell_edit("1");
+        }
+        assert_eq!(
+            app.current_region(AbsCell::from_str("A999").unwrap()),
+            Some((
+                AbsCell::from_str("A999").unwrap(),
+                AbsCell::from_str("A1001").unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_error_source_text() {
+        let mut app = SpreadsheetApp::new();
+        for (label, input) in [("A1", "1"), ("B1", "=A1/0"), ("C1", "=B1")] {
+            app.view.selected_cell = AbsCell::from_str(label).unwrap();
+            app.handle_cell_edit(input);
+        }
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        assert_eq!(
+            app.error_source_text(cell("C1")).as_deref(),
+            Some("#DIV/0! from B1")
+        );
+        assert_eq!(
+            app.error_source_text(cell("B1")).as_deref(),
+            Some("#DIV/0! originates here")
+        );
+        assert_eq!(app.error_source_text(cell("A1")), None);
+    }
+
+    #[test]
+    fn test_paste_text_prefers_newer_clipboard() {
+        let mut app = SpreadsheetApp::new();
+        let ctx = egui::Context::default();
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        for (label, input) in [("A1", "=B1+1"), ("B1", "2"), ("B2", "3")] {
+            app.view.selected_cell = cell(label);
+            app.handle_cell_edit(input);
+        }
+
+        app.view.selected_cell = cell("A1");
+        app.copy_cell(&ctx);
+        assert_eq!(app.copied_text.as_deref(), Some("=B1 + 1"));
+
+        // the clipboard still holds the copied cell, its references move
+        app.view.selected_cell = cell("A2");
+        app.paste_text("=B1 + 1");
+        assert_eq!(app.render_cell_input(cell("A2")), "=B2 + 1");
+
+        // text copied elsewhere since wins over the copied cell
+        app.view.selected_cell = cell("A3");
+        app.paste_text("hello");
+        assert_eq!(app.render_cell_input(cell("A3")), "hello");
+
+        app.paste_text("");
+        assert_eq!(app.status_message, "Nothing to paste");
+    }
+
+    #[test]
+    fn test_paste_checks_values() {
+        let mut app = SpreadsheetApp::new();
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        for (label, input) in [("A1", "50"), ("A2", "=A1*2"), ("A3", "abc")] {
+            app.view.selected_cell = cell(label);
+            app.handle_cell_edit(input);
+        }
+        app.backend.add_validation(
+            cell("B1"),
+            cell("B3"),
+            ValidationRule::NumberBetween {
+                min: 0.0,
+                max: 60.0,
+            },
+        );
+
+        app.copied_cell = Some(cell("A1"));
+        app.view.selected_cell = cell("B1");
+        app.paste_cell();
+        assert_eq!(app.status_message, "Pasted from A1 to B1");
+        assert_eq!(app.render_cell_input(cell("B1")), "50");
+
+        // the value of A2 is out of range for B2
+        app.copied_cell = Some(cell("A2"));
+        app.view.selected_cell = cell("B2");
+        app.paste_values();
+        assert!(app.status_message.starts_with("Rejected"));
+        assert!(!app.backend.is_populated(cell("B2")));
+
+        app.copied_cell = Some(cell("A3"));
+        app.view.selected_cell = cell("B3");
+        app.paste_cell();
+        assert_eq!(app.status_message, "Rejected: Value must be a number");
+        assert!(!app.backend.is_populated(cell("B3")));
+
+        // a column type converts a pasted value
+        app.backend.set_column_type(2, Some(ColumnType::Text));
+        app.copied_cell = Some(cell("A1"));
+        app.view.selected_cell = cell("C1");
+        app.paste_values();
+        assert_eq!(
+            app.backend.get_cell_value(cell("C1")),
+            &Ok(CellValue::String("50".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_paste_cycle_leaves_destination() {
+        let mut app = SpreadsheetApp::new();
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        for (label, input) in [("A1", "=B1"), ("A2", "7"), ("B2", "=A2")] {
+            app.view.selected_cell = cell(label);
+            app.handle_cell_edit(input);
+        }
+        app.dirty = false;
+
+        // A1 reads the cell to its right, so at A2 it would read B2, which reads A2
+        app.copied_cell = Some(cell("A1"));
+        app.view.selected_cell = cell("A2");
+        app.paste_cell();
+
+        assert_eq!(
+            app.status_message,
+            "Paste would create a cycle: A2 → B2 → A2; A2 left unchanged"
+        );
+        assert_eq!(app.render_cell_input(cell("A2")), "7");
+        assert_eq!(
+            app.backend.get_cell_value(cell("B2")),
+            &Ok(CellValue::Number(7.0))
+        );
+        assert!(!app.dirty);
+    }
+
+    #[test]
+    fn test_view_follows_replaced_sheet() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("small.sheet");
+        let mut small = EmbeddedBackend::new(20, 5);
+        small.set_cell_value(AbsCell::new(0, 0), CellValue::Number(1.0));
+        small
+            .save_to_file(&std::fs::File::create(&path).unwrap())
+            .unwrap();
+
+        let mut app = SpreadsheetApp::new();
+        app.view.selected_cell = AbsCell::from_str("Z500").unwrap();
+        app.view.ensure_cell_visible(app.view.selected_cell);
+        app.open_path(path);
+        assert_eq!(app.view.sheet_size(), (20, 5));
+        assert_eq!(app.view.selected_cell, AbsCell::new(0, 0));
+        assert_eq!(app.view.view_top_left, AbsCell::new(0, 0));
+        assert_eq!(app.formula_input, "1");
+        // the selection stops at the edge of the loaded sheet
+        app.move_selection(50, 50);
+        assert_eq!(app.view.selected_cell, AbsCell::new(19, 4));
+
+        app.new_spreadsheet();
+        assert_eq!(app.view.sheet_size(), (999, 18278));
+        assert_eq!(app.view.selected_cell, AbsCell::new(0, 0));
+    }
+
+    #[test]
+    fn test_dirty_flag_and_auto_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sheet.sheet");
+        let mut app = SpreadsheetApp::new();
+        assert!(!app.dirty);
+
+        app.handle_cell_edit("5");
+        assert!(app.dirty);
+        // a rejected edit does not change the sheet
+        app.handle_cell_edit("=A1 +");
+        app.save_path = Some(path.clone());
+        app.save_spreadsheet();
+        assert!(!app.dirty);
+        assert_eq!(app.status_message, format!("File saved to {:?}", path));
+
+        app.handle_cell_edit("=1/0");
+        assert!(app.dirty);
+        let start = Instant::now();
+        // off by default
+        app.auto_save_tick(start + Duration::from_secs(3600));
+        assert!(app.dirty && app.auto_save_result.is_none());
+
+        app.auto_save_interval = Some(Duration::from_secs(30));
+        app.last_auto_save = start;
+        app.auto_save_tick(start + Duration::from_secs(10));
+        assert!(app.dirty);
+        app.auto_save_tick(start + Duration::from_secs(30));
+        assert!(!app.dirty);
+        while app.auto_save_result.is_some() {
+            app.auto_save_tick(start + Duration::from_secs(31));
+        }
+        assert_eq!(app.status_message, "Auto-saved");
+
+        let saved = EmbeddedBackend::from_file(&std::fs::File::open(&path).unwrap()).unwrap();
+        assert!(saved.get_cell_value(AbsCell::new(0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_write_sheet_keeps_newer_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sheet.sheet");
+        let last_written = Mutex::new(0);
+        write_sheet(&path, b"manual", 2, &last_written).unwrap();
+        // an auto-save started before the manual save finishes after it
+        write_sheet(&path, b"auto", 1, &last_written).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"manual");
+    }
+
+    #[test]
+    fn test_unsaved_changes_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("budget.sheet");
+        let mut app = SpreadsheetApp::new();
+        assert_eq!(app.title(), "Untitled - Spreadsheet");
+
+        app.handle_cell_edit("1");
+        assert_eq!(app.title(), "Untitled* - Spreadsheet");
+
+        app.save_path = Some(path);
+        app.save_spreadsheet();
+        assert!(!app.dirty);
+        assert_eq!(app.title(), "budget.sheet - Spreadsheet");
+
+        app.handle_cell_edit("2");
+        assert_eq!(app.title(), "budget.sheet* - Spreadsheet");
+
+        // replacing the sheet waits for confirmation
+        app.request_discard(DiscardAction::NewSheet);
+        assert_eq!(app.confirm_discard, Some(DiscardAction::NewSheet));
+        assert_eq!(
+            app.backend.get_cell_value(AbsCell::new(0, 0)),
+            &Ok(CellValue::Number(2.0))
+        );
+        app.run_discard(DiscardAction::NewSheet);
+        assert_eq!(app.confirm_discard, None);
+        assert_eq!(app.title(), "Untitled - Spreadsheet");
+        assert_eq!(
+            app.backend.get_cell_value(AbsCell::new(0, 0)),
+            &Ok(CellValue::Empty)
+        );
+
+        // nothing to lose, nothing to confirm
+        app.request_discard(DiscardAction::Open);
+        assert_eq!(app.confirm_discard, None);
+        assert!(app.show_load_dialog);
+    }
+
+    #[test]
+    fn test_paste_block() {
+        let block = SpreadsheetApp::parse_block("1\tx\t=A1*2\n'7\t\t\"a, b\"\n");
+        assert_eq!(block, vec![vec!["1", "x", "=A1*2"], vec!["'7", "", "a, b"]]);
+        assert_eq!(
+            SpreadsheetApp::parse_block("1,2\n3"),
+            vec![vec!["1", "2"], vec!["3"]]
+        );
+
+        let mut app = SpreadsheetApp::new();
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        app.view.selected_cell = cell("B2");
+        app.backend
+            .set_cell_value(cell("C3"), CellValue::Number(9.0));
+        app.paste_block(&block);
+        assert_eq!(
+            app.backend.get_cell_value(cell("B2")),
+            &Ok(CellValue::Number(1.0))
+        );
+        assert_eq!(
+            app.backend.get_cell_value(cell("C2")),
+            &Ok(CellValue::String("x".to_string()))
+        );
+        // a formula is written as for its own cell
+        assert_eq!(
+            app.backend.get_cell_formula(cell("D2")),
+            Some("A1 * 2".to_string())
+        );
+        assert_eq!(
+            app.backend.get_cell_value(cell("B3")),
+            &Ok(CellValue::String("7".to_string()))
+        );
+        assert!(!app.backend.is_populated(cell("C3")));
+
+        // the whole block is undone at once
+        app.undo();
+        assert!(!app.backend.is_populated(cell("B2")));
+        assert_eq!(
+            app.backend.get_cell_value(cell("C3")),
+            &Ok(CellValue::Number(9.0))
+        );
+
+        // a block running off the sheet changes nothing
+        app.view.selected_cell = AbsCell::new(998, 0);
+        app.paste_block(&block);
+        assert!(app.status_message.starts_with("Paste error"));
+        assert!(!app.backend.is_populated(AbsCell::new(998, 0)));
+        // values go through the column types and validation rules, all or nothing
+        app.backend.set_column_type(1, Some(ColumnType::Number));
+        app.view.selected_cell = cell("A1");
+        app.paste_block(&[vec!["3".to_string(), "x".to_string()]]);
+        assert_eq!(
+            app.status_message,
+            "Paste rejected at B1: Value must be a number"
+        );
+        assert!(!app.backend.is_populated(cell("A1")));
+        app.backend.set_column_type(1, Some(ColumnType::Text));
+        app.paste_block(&[vec!["x".to_string(), "3".to_string()]]);
+        assert_eq!(
+            app.backend.get_cell_value(cell("B1")),
+            &Ok(CellValue::String("3".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_relative_formula() {
+        let mut app = SpreadsheetApp::new();
+        let cell = |label| AbsCell::from_str(label).unwrap();
+        app.backend.set_cell_formula(cell("B3"), "A1+B2*2").unwrap();
+        app.backend
+            .set_cell_formula(cell("C3"), "SUM(A1:B2)")
+            .unwrap();
+        app.backend
+            .set_cell_value(cell("D3"), CellValue::Number(1.0));
+
+        assert_eq!(
+            app.relative_formula(cell("B3")),
+            Some("=R[-2]C[-1] + R[-1]C * 2".to_string())
+        );
+        assert_eq!(
+            app.relative_formula(cell("C3")),
+            Some("=SUM(R[-2]C[-2]:R[-1]C[-1])".to_string())
+        );
+        assert_eq!(app.relative_formula(cell("D3")), None);
+        assert_eq!(app.relative_formula(cell("E3")), None);
+    }
+
+    #[test]
+    fn test_cell_to_label_bounds() {
+        assert_eq!(SpreadsheetApp::cell_to_label(0), "A");
+        assert_eq!(SpreadsheetApp::cell_to_label(25), "Z");
+        assert_eq!(SpreadsheetApp::cell_to_label(701), "ZZ");
+        assert_eq!(SpreadsheetApp::cell_to_label(702), "AAA");
+        assert_eq!(SpreadsheetApp::cell_to_label(18276), "ZZY");
+        assert_eq!(SpreadsheetApp::cell_to_label(18277), "ZZZ");
+        assert_eq!(SpreadsheetApp::cell_to_label(18278), "AAAA");
+        assert_eq!(SpreadsheetApp::cell_to_label(i16::MAX), "AVLH");
+        assert_eq!(SpreadsheetApp::cell_to_label(-1), "");
+    }
+
+    #[test]
+    fn test_zero_based_labels() {
+        let mut app = SpreadsheetApp::new();
+        app.view.view_top_left = AbsCell::from_str("C10").unwrap();
+        app.view.selected_cell = AbsCell::from_str("D11").unwrap();
+        assert_eq!(app.grid_header(true, 1).0, "D");
+        assert_eq!(app.grid_header(false, 1).0, "11");
+        assert_eq!(app.selected_cell_label(), "D11");
+
+        app.zero_based_labels = true;
+        assert_eq!(app.grid_header(true, 1).0, "3");
+        assert_eq!(app.grid_header(false, 1).0, "10");
+        assert_eq!(app.selected_cell_label(), "(10, 3)");
+        // only the labels change
+        assert_eq!(app.grid_header(true, 1).1, app.column_selection(3));
+
+        // the selection ends where the sheet does
+        app.backend = EmbeddedBackend::new(10, 5);
+        assert_eq!(app.column_selection(2).1, AbsCell::new(9, 2));
+        assert_eq!(app.row_selection(4).1, AbsCell::new(4, 4));
+    }
+
+    #[test]
+    fn test_transposed_grid_mapping() {
+        let mut app = SpreadsheetApp::new();
+        app.view.display_rows = 3;
+        app.view.display_cols = 5;
+        app.view.view_top_left = AbsCell::from_str("C10").unwrap();
+        assert_eq!(app.grid_shape(), (3, 5));
+        assert_eq!(app.cell_at(1, 4), AbsCell::from_str("G11").unwrap());
+
+        app.transposed = true;
+        assert_eq!(app.grid_shape(), (5, 3));
+        // grid row 4, column 1 is sheet column 4, row 1 of the view
+        assert_eq!(app.cell_at(4, 1), AbsCell::from_str("G11").unwrap());
+        let (label, selection) = app.grid_header(true, 2);
+        assert_eq!(label, "12");
+        assert_eq!(selection, app.row_selection(11));
+        let (label, selection) = app.grid_header(false, 2);
+        assert_eq!(label, "E");
+        assert_eq!(selection, app.column_selection(4));
+
+        // moving down on screen goes to the next sheet column
+        app.view.selected_cell = AbsCell::from_str("C10").unwrap();
+        app.move_selection_on_screen(1, 0);
+        assert_eq!(app.view.selected_cell, AbsCell::from_str("D10").unwrap());
+        app.move_selection_on_screen(0, 1);
+        assert_eq!(app.view.selected_cell, AbsCell::from_str("D11").unwrap());
+
+        // edits land in the true cell
+        app.handle_cell_edit("7");
+        assert_eq!(
+            app.backend.get_cell_value(AbsCell::new(10, 3)),
+            &Ok(CellValue::Number(7.0))
+        );
+    }
+}
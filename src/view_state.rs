@@ -0,0 +1,310 @@
+//! Which cells the GUI shows and selects, kept apart from egui so navigation, scrolling and
+//! search can be tested without drawing anything.
+//!
+//! All coordinates are true sheet coordinates, whatever way the grid is drawn.
+
+use crate::common::structs::AbsCell;
+use std::time::{Duration, Instant};
+
+/// How long typing in the search box has to pause before the search runs
+pub const SEARCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewState {
+    /// The cell shown at the top left of the grid
+    pub view_top_left: AbsCell,
+    pub selected_cell: AbsCell,
+    /// Inclusive `(top_left, bottom_right)` of a multi-cell selection, if any
+    pub selection_range: Option<(AbsCell, AbsCell)>,
+    /// How many sheet rows and columns are shown
+    pub display_rows: i16,
+    pub display_cols: i16,
+    /// Where the last search matched, the next one continues after it
+    pub last_search_position: Option<AbsCell>,
+    /// When the search text last changed, while a search for it is still to run
+    search_edited_at: Option<Instant>,
+    /// The size of the sheet
+    rows: i16,
+    cols: i16,
+}
+
+impl ViewState {
+    /// A 10 by 10 view of a sheet of `rows` by `cols`, at its top left
+    pub fn new(rows: i16, cols: i16) -> Self {
+        ViewState {
+            view_top_left: AbsCell::new(0, 0),
+            selected_cell: AbsCell::new(0, 0),
+            selection_range: None,
+            display_rows: 10,
+            display_cols: 10,
+            last_search_position: None,
+            search_edited_at: None,
+            rows,
+            cols,
+        }
+    }
+
+    /// How far a window starting at `start` and spanning `len` cells along one axis has to
+    /// scroll so that `target` is inside it. Zero if it already is; otherwise the smallest
+    /// shift, which leaves `target` on the window's edge.
+    pub fn viewport_delta(start: i16, len: i16, target: i16) -> i16 {
+        if target < start {
+            target - start
+        } else if target >= start + len {
+            target - (start + len - 1)
+        } else {
+            0
+        }
+    }
+
+    /// Scrolls as little as needed for `cell` to be shown
+    pub fn ensure_cell_visible(&mut self, cell: AbsCell) {
+        self.view_top_left.row +=
+            Self::viewport_delta(self.view_top_left.row, self.display_rows, cell.row);
+        self.view_top_left.col +=
+            Self::viewport_delta(self.view_top_left.col, self.display_cols, cell.col);
+    }
+
+    /// Shows `rows` by `cols` cells, each at least 1 and at most the size of the sheet. The
+    /// view stays within the sheet and keeps the selection shown
+    pub fn set_display_size(&mut self, rows: i16, cols: i16) {
+        self.display_rows = rows.clamp(1, self.rows.max(1));
+        self.display_cols = cols.clamp(1, self.cols.max(1));
+        self.ensure_cell_visible(self.selected_cell);
+        self.move_view(0, 0);
+    }
+
+    /// The `(rows, cols)` of the sheet
+    pub fn sheet_size(&self) -> (i16, i16) {
+        (self.rows, self.cols)
+    }
+
+    /// Scrolls by the given number of cells, stopping once the view reaches an edge of the
+    /// sheet. The selection stays where it is
+    pub fn move_view(&mut self, row_delta: i16, col_delta: i16) {
+        let new_row = self.view_top_left.row + row_delta;
+        let new_col = self.view_top_left.col + col_delta;
+
+        self.view_top_left.row = new_row.max(0).min(self.rows - self.display_rows);
+        self.view_top_left.col = new_col.max(0).min(self.cols - self.display_cols);
+    }
+
+    /// Moves the selection, stopping at the edges of the sheet, and drops any range
+    /// selection
+    pub fn move_selection(&mut self, row_delta: i16, col_delta: i16) {
+        self.selected_cell.row = (self.selected_cell.row + row_delta).clamp(0, self.rows - 1);
+        self.selected_cell.col = (self.selected_cell.col + col_delta).clamp(0, self.cols - 1);
+        self.selection_range = None;
+
+        // Moving within the window leaves the view alone; crossing an edge
+        // scrolls just far enough to keep the selection on that edge
+        self.ensure_cell_visible(self.selected_cell);
+    }
+
+    /// The cells shown, as `(top_left, bottom_right)`
+    pub fn view_range(&self) -> (AbsCell, AbsCell) {
+        let bottom_right = AbsCell::new(
+            self.view_top_left.row + self.display_rows - 1,
+            self.view_top_left.col + self.display_cols - 1,
+        );
+        (self.view_top_left, bottom_right)
+    }
+
+    /// Finds the next match of a search. `search` looks for one after the given cell, which is
+    /// the last match, or the selected cell for a new search.
+    ///
+    /// A match is selected and scrolled to, and the search after continues from it. Without
+    /// one, the search after starts over from the selection
+    pub fn search_next(
+        &mut self,
+        search: impl FnOnce(AbsCell) -> Option<AbsCell>,
+    ) -> Option<AbsCell> {
+        let start = self.last_search_position.unwrap_or(self.selected_cell);
+        let found = search(start);
+        self.search_found(found);
+        found
+    }
+
+    /// Like `search_next`, but `search` finds the first match in the whole sheet
+    pub fn search_from_start(
+        &mut self,
+        search: impl FnOnce() -> Option<AbsCell>,
+    ) -> Option<AbsCell> {
+        let found = search();
+        self.search_found(found);
+        found
+    }
+
+    /// Notes that the search text changed at `now`, so a search from the start is due once
+    /// typing pauses
+    pub fn search_edited(&mut self, now: Instant) {
+        self.search_edited_at = Some(now);
+    }
+
+    /// Whether the search text changed and then stayed the same for `SEARCH_DEBOUNCE` up to
+    /// `now`. True only once per change, the caller is expected to search
+    pub fn search_due(&mut self, now: Instant) -> bool {
+        match self.search_edited_at {
+            Some(edited_at) if now.duration_since(edited_at) >= SEARCH_DEBOUNCE => {
+                self.search_edited_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a search is waiting on `search_due`
+    pub fn search_pending(&self) -> bool {
+        self.search_edited_at.is_some()
+    }
+
+    /// Drops the waiting search, returning whether there was one
+    pub fn take_pending_search(&mut self) -> bool {
+        self.search_edited_at.take().is_some()
+    }
+
+    fn search_found(&mut self, found: Option<AbsCell>) {
+        self.last_search_position = found;
+        if let Some(cell) = found {
+            self.selected_cell = cell;
+            self.ensure_cell_visible(cell);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_viewport_delta() {
+        // inside the window
+        assert_eq!(ViewState::viewport_delta(10, 10, 10), 0);
+        assert_eq!(ViewState::viewport_delta(10, 10, 19), 0);
+        // one step past either edge
+        assert_eq!(ViewState::viewport_delta(10, 10, 20), 1);
+        assert_eq!(ViewState::viewport_delta(10, 10, 9), -1);
+        // jumps scroll only as far as needed
+        assert_eq!(ViewState::viewport_delta(10, 10, 35), 16);
+        assert_eq!(ViewState::viewport_delta(10, 10, 0), -10);
+    }
+
+    #[test]
+    fn test_move_selection_stops_at_edges() {
+        let mut view = ViewState::new(20, 30);
+        view.selection_range = Some((AbsCell::new(0, 0), AbsCell::new(1, 1)));
+        view.move_selection(-1, -5);
+        assert_eq!(view.selected_cell, AbsCell::new(0, 0));
+        assert_eq!(view.selection_range, None);
+
+        view.move_selection(100, 100);
+        assert_eq!(view.selected_cell, AbsCell::new(19, 29));
+        // the view followed, with the selection on its bottom right edge
+        assert_eq!(view.view_top_left, AbsCell::new(10, 20));
+        assert_eq!(
+            view.view_range(),
+            (AbsCell::new(10, 20), AbsCell::new(19, 29))
+        );
+
+        // moving back inside the window does not scroll
+        view.move_selection(-9, -9);
+        assert_eq!(view.selected_cell, AbsCell::new(10, 20));
+        assert_eq!(view.view_top_left, AbsCell::new(10, 20));
+    }
+
+    #[test]
+    fn test_move_view_clamps() {
+        let mut view = ViewState::new(20, 30);
+        view.move_view(-5, -5);
+        assert_eq!(view.view_top_left, AbsCell::new(0, 0));
+        view.move_view(3, 4);
+        assert_eq!(view.view_top_left, AbsCell::new(3, 4));
+        view.move_view(100, 100);
+        assert_eq!(view.view_top_left, AbsCell::new(10, 20));
+        assert_eq!(view.selected_cell, AbsCell::new(0, 0));
+    }
+
+    #[test]
+    fn test_search_next_advances() {
+        let matches = [AbsCell::new(2, 0), AbsCell::new(40, 3)];
+        let next_match = |start: AbsCell| matches.iter().copied().find(|&cell| cell > start);
+        let mut view = ViewState::new(100, 100);
+
+        assert_eq!(view.search_next(next_match), Some(matches[0]));
+        assert_eq!(view.selected_cell, matches[0]);
+        assert_eq!(view.search_next(next_match), Some(matches[1]));
+        assert_eq!(view.last_search_position, Some(matches[1]));
+        assert_eq!(view.view_top_left, AbsCell::new(31, 0));
+
+        // past the last match, the search after starts over from the selection
+        assert_eq!(view.search_next(next_match), None);
+        assert_eq!(view.selected_cell, matches[1]);
+        assert_eq!(view.last_search_position, None);
+
+        assert_eq!(
+            view.search_from_start(|| Some(matches[0])),
+            Some(matches[0])
+        );
+        assert_eq!(view.last_search_position, Some(matches[0]));
+        assert_eq!(view.view_top_left, AbsCell::new(2, 0));
+    }
+
+    #[test]
+    fn test_incremental_search() {
+        let matches = [AbsCell::new(5, 1), AbsCell::new(50, 2)];
+        let mut view = ViewState::new(100, 100);
+        view.selected_cell = AbsCell::new(60, 0);
+        view.ensure_cell_visible(view.selected_cell);
+        let start = Instant::now();
+
+        // each key press restarts the wait
+        view.search_edited(start);
+        view.search_edited(start + SEARCH_DEBOUNCE / 2);
+        assert!(!view.search_due(start + SEARCH_DEBOUNCE));
+        assert!(view.search_pending());
+        assert!(view.search_due(start + SEARCH_DEBOUNCE * 2));
+        assert!(!view.search_due(start + SEARCH_DEBOUNCE * 3));
+
+        // the search runs from the top, whatever is selected, and Enter continues from there
+        view.search_from_start(|| Some(matches[0]));
+        assert_eq!(view.selected_cell, matches[0]);
+        assert_eq!(view.view_top_left, AbsCell::new(5, 0));
+        let next_match = |start: AbsCell| matches.iter().copied().find(|&cell| cell > start);
+        assert_eq!(view.search_next(next_match), Some(matches[1]));
+
+        view.search_edited(start);
+        assert!(view.take_pending_search());
+        assert!(!view.search_due(start + SEARCH_DEBOUNCE));
+    }
+
+    #[test]
+    fn test_display_size_keeps_clamping() {
+        let mut view = ViewState::new(100, 50);
+        view.move_selection(95, 45);
+        assert_eq!(view.view_top_left, AbsCell::new(86, 36));
+        view.move_view(100, 100);
+        assert_eq!(view.view_top_left, AbsCell::new(90, 40));
+
+        // a larger view pulls back so it still ends at the sheet's edge
+        view.set_display_size(30, 20);
+        assert_eq!(view.view_top_left, AbsCell::new(70, 30));
+        view.move_view(100, 100);
+        assert_eq!(view.view_range().1, AbsCell::new(99, 49));
+
+        // never larger than the sheet, nor empty
+        view.set_display_size(500, 0);
+        assert_eq!((view.display_rows, view.display_cols), (100, 1));
+        assert_eq!(view.view_top_left, AbsCell::new(0, 45));
+        view.move_view(-5, 100);
+        assert_eq!(view.view_top_left, AbsCell::new(0, 49));
+
+        // shrinking keeps the selection shown
+        let mut view = ViewState::new(100, 50);
+        view.move_selection(25, 25);
+        view.set_display_size(5, 5);
+        assert_eq!(
+            view.view_range(),
+            (AbsCell::new(21, 21), AbsCell::new(25, 25))
+        );
+    }
+}